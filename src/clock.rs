@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+
+use std::fmt;
+
+/// Clock represents a source of the current time, such that the time at which users are
+/// registered may be pinned in tests.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Gets the current UTC time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// SystemClock represents the system's real-time clock. This is the clock used by default.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// FixedClock represents a clock that is stopped at a particular instant.
+///
+/// # Examples
+///
+/// ```
+/// use chrono::{TimeZone, Utc};
+/// use swaply_identity::clock::{Clock, FixedClock};
+///
+/// let pinned = Utc.timestamp(1_592_000_000, 0);
+/// assert_eq!(FixedClock(pinned).now(), pinned);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
@@ -0,0 +1,5 @@
+/// Password implements domain-separated password hashing and password strength policies.
+pub mod password;
+
+/// Token implements at-rest encryption of sensitive identity provider tokens.
+pub mod token;
@@ -0,0 +1,549 @@
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use std::{
+    array::TryFromSliceError,
+    convert::{TryFrom, TryInto},
+    error::Error,
+    fmt,
+};
+
+/// The context string used to derive password hashes by default. Per the blake3 docs, context
+/// strings should be hardcoded, globally unique, and application-specific.
+pub const DEFAULT_CONTEXT: &str = "swaply identity 2020-06-12 password hash";
+
+/// The number of bytes in a password salt.
+pub const SALT_LENGTH: usize = 16;
+
+/// The number of bytes in a password hash.
+pub const HASH_LENGTH: usize = 32;
+
+/// PasswordHash represents the hash of a user's password. Hashes are compared in constant time,
+/// are never printed, and are zeroed once dropped.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PasswordHash([u8; HASH_LENGTH]);
+
+impl PasswordHash {
+    /// Creates a new password hash from the given raw hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The bytes of the hash
+    pub const fn new(hash: [u8; HASH_LENGTH]) -> Self {
+        Self(hash)
+    }
+
+    /// Gets the raw bytes of the hash.
+    pub fn as_bytes(&self) -> &[u8; HASH_LENGTH] {
+        &self.0
+    }
+
+    /// Encodes the hash as base58, as it was stored in the users table before hashes were stored
+    /// as BLOBs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::crypto::password::PasswordHash;
+    /// use std::convert::TryFrom;
+    ///
+    /// let hash = PasswordHash::new([1; 32]);
+    ///
+    /// let decoded = bs58::decode(hash.to_base58()).into_vec().unwrap();
+    /// assert_eq!(PasswordHash::try_from(decoded.as_slice()).unwrap(), hash);
+    /// ```
+    pub fn to_base58(&self) -> String {
+        bs58::encode(self.0).into_string()
+    }
+}
+
+impl From<[u8; HASH_LENGTH]> for PasswordHash {
+    fn from(hash: [u8; HASH_LENGTH]) -> Self {
+        Self(hash)
+    }
+}
+
+impl From<blake3::Hash> for PasswordHash {
+    fn from(hash: blake3::Hash) -> Self {
+        Self(hash.into())
+    }
+}
+
+impl TryFrom<&[u8]> for PasswordHash {
+    type Error = TryFromSliceError;
+
+    /// Converts a stored hash, as decoded from the users table, into a password hash. Fails if the
+    /// hash isn't exactly HASH_LENGTH bytes long.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        bytes.try_into().map(Self)
+    }
+}
+
+impl PartialEq for PasswordHash {
+    fn eq(&self, other: &Self) -> bool {
+        // blake3 hashes implement a constant-time comparison
+        blake3::Hash::from(self.0) == other.0
+    }
+}
+
+impl Eq for PasswordHash {}
+
+// Hashes should never end up in logs
+impl fmt::Debug for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PasswordHash(<redacted>)")
+    }
+}
+
+impl Drop for PasswordHash {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// PasswordHasher represents a domain-separated blake3 password hashing scheme. Hashes produced
+/// by a PasswordHasher are derived from a context string specific to this crate, and may
+/// optionally be keyed with a secret pepper kept outside of the database.
+#[derive(Clone)]
+pub struct PasswordHasher {
+    /// The context string that hashes are derived from
+    context: String,
+
+    /// A secret key mixed into each hash, if any
+    pepper: Option<[u8; 32]>,
+}
+
+impl PasswordHasher {
+    /// Creates a new password hasher.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - The context string that hashes should be derived from
+    /// * `pepper` - A secret key that should be mixed into each hash, if any
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::crypto::password::PasswordHasher;
+    ///
+    /// let hasher = PasswordHasher::new("swaply identity test password hash", Some([1; 32]));
+    ///
+    /// let hash = hasher.hash(b"123456");
+    /// assert!(hasher.verify(b"123456", &hash));
+    /// ```
+    pub fn new(context: &str, pepper: Option<[u8; 32]>) -> Self {
+        Self {
+            context: context.to_owned(),
+            pepper,
+        }
+    }
+
+    /// Hashes the provided password.
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The password (and any prepended salt) that should be hashed
+    pub fn hash(&self, password: &[u8]) -> PasswordHash {
+        match self.pepper {
+            // Derive a key specific to this context from the pepper, such that the same pepper
+            // can't be used to forge hashes in any other context
+            Some(ref pepper) => {
+                blake3::keyed_hash(&blake3::derive_key(&self.context, pepper), password).into()
+            }
+            None => blake3::derive_key(&self.context, password).into(),
+        }
+    }
+
+    /// Determines whether or not the provided password matches a hash produced by this hasher.
+    /// The comparison is performed in constant time.
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The password (and any prepended salt) that should be verified
+    /// * `hash` - The hash that the password should produce
+    pub fn verify(&self, password: &[u8], hash: &PasswordHash) -> bool {
+        self.hash(password) == *hash
+    }
+
+    /// Determines whether or not the provided password matches either a hash produced by this
+    /// hasher, or a legacy hash produced by plain, unkeyed blake3.
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The password (and any prepended salt) that should be verified
+    /// * `hash` - The hash that the password should produce
+    pub fn verify_with_legacy(&self, password: &[u8], hash: &PasswordHash) -> bool {
+        // Both hashes are always computed, such that timing doesn't reveal the scheme in use
+        let (current, legacy) = (self.verify(password, hash), verify_legacy(password, hash));
+
+        current | legacy
+    }
+
+    /// Hashes the provided password with a salt prepended to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The plaintext password that should be hashed
+    /// * `salt` - The salt that should be prepended to the password
+    pub fn hash_salted(&self, password: &str, salt: &[u8]) -> PasswordHash {
+        self.hash(&[salt, password.as_bytes()].concat())
+    }
+
+    /// Determines whether or not the provided password, with a salt prepended to it, matches a
+    /// hash produced by this hasher.
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The plaintext password that should be verified
+    /// * `salt` - The salt that was prepended to the password when it was hashed
+    /// * `hash` - The hash that the password should produce
+    pub fn verify_salted(&self, password: &str, salt: &[u8], hash: &PasswordHash) -> bool {
+        self.verify(&[salt, password.as_bytes()].concat(), hash)
+    }
+}
+
+impl Default for PasswordHasher {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONTEXT, None)
+    }
+}
+
+// Peppers should never end up in logs
+impl fmt::Debug for PasswordHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PasswordHasher")
+            .field("context", &self.context)
+            .field("pepper", &self.pepper.map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// Determines whether or not the provided password matches a legacy hash produced by plain,
+/// unkeyed blake3.
+///
+/// # Arguments
+///
+/// * `password` - The password (and any prepended salt) that should be verified
+/// * `hash` - The hash that the password should produce
+pub fn verify_legacy(password: &[u8], hash: &PasswordHash) -> bool {
+    PasswordHash::from(blake3::hash(password)) == *hash
+}
+
+/// PasswordVerification represents the outcome of checking a password against a stored hash.
+#[derive(PartialEq, Eq, Debug)]
+pub enum PasswordVerification {
+    /// The password doesn't match the stored hash
+    Rejected,
+
+    /// The password matches the stored hash, which needn't be replaced
+    Accepted,
+
+    /// The password matches the stored hash, but the stored hash should be replaced by this
+    /// freshly salted hash of the same password
+    Upgraded {
+        hash: PasswordHash,
+        salt: [u8; SALT_LENGTH],
+    },
+}
+
+impl PasswordVerification {
+    /// Determines whether or not the password matched the stored hash.
+    pub fn is_accepted(&self) -> bool {
+        *self != Self::Rejected
+    }
+}
+
+/// A small list of passwords so common that they would be guessed almost immediately.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// PasswordPolicy represents a set of rules that a password must satisfy before it may be used.
+/// The default policy requires at least ten characters, including a lowercase letter, an
+/// uppercase letter, and a digit, and rejects commonly used passwords.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// The minimum number of characters in a password
+    pub min_length: usize,
+
+    /// Whether or not a password must contain a lowercase letter
+    pub require_lowercase: bool,
+
+    /// Whether or not a password must contain an uppercase letter
+    pub require_uppercase: bool,
+
+    /// Whether or not a password must contain a digit
+    pub require_digit: bool,
+
+    /// Whether or not a password must contain a character that is neither a letter nor a digit
+    pub require_symbol: bool,
+
+    /// Whether or not passwords on the embedded list of common passwords are rejected
+    pub reject_common: bool,
+}
+
+impl PasswordPolicy {
+    /// Creates a policy that accepts any password, which is mostly useful in testing.
+    pub fn permissive() -> Self {
+        Self {
+            min_length: 0,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            reject_common: false,
+        }
+    }
+
+    /// Ensures that a password satisfies each of the policy's rules, returning the first rule
+    /// that it fails to satisfy, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The plaintext password that should be checked
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::crypto::password::{PasswordError, PasswordPolicy};
+    ///
+    /// let policy = PasswordPolicy::default();
+    ///
+    /// assert_eq!(policy.check("123456"), Err(PasswordError::TooShort(10)));
+    /// assert!(policy.check("Tr0ub4dour&3").is_ok());
+    /// ```
+    pub fn check(&self, password: &str) -> Result<(), PasswordError> {
+        let has = |pred: fn(&char) -> bool| password.chars().any(|c| pred(&c));
+
+        if password.chars().count() < self.min_length {
+            Err(PasswordError::TooShort(self.min_length))
+        } else if self.require_lowercase && !has(char::is_ascii_lowercase) {
+            Err(PasswordError::MissingLowercase)
+        } else if self.require_uppercase && !has(char::is_ascii_uppercase) {
+            Err(PasswordError::MissingUppercase)
+        } else if self.require_digit && !has(char::is_ascii_digit) {
+            Err(PasswordError::MissingDigit)
+        } else if self.require_symbol && !has(|c| !c.is_alphanumeric()) {
+            Err(PasswordError::MissingSymbol)
+        } else if self.reject_common && is_common_password(password) {
+            Err(PasswordError::Common)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 10,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: false,
+            reject_common: true,
+        }
+    }
+}
+
+/// Determines whether or not a password appears on the embedded list of common passwords,
+/// ignoring case.
+///
+/// # Arguments
+///
+/// * `password` - The plaintext password that should be looked up
+pub fn is_common_password(password: &str) -> bool {
+    COMMON_PASSWORDS
+        .lines()
+        .any(|common| common.eq_ignore_ascii_case(password))
+}
+
+/// PasswordError represents a rule of a PasswordPolicy that a password failed to satisfy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordError {
+    /// The password has fewer than the given number of characters
+    TooShort(usize),
+    MissingLowercase,
+    MissingUppercase,
+    MissingDigit,
+    MissingSymbol,
+    Common,
+}
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "encountered an error while checking the password: ")?;
+
+        match self {
+            Self::TooShort(min) => write!(f, "the password must be at least {} characters", min),
+            Self::MissingLowercase => write!(f, "the password must contain a lowercase letter"),
+            Self::MissingUppercase => write!(f, "the password must contain an uppercase letter"),
+            Self::MissingDigit => write!(f, "the password must contain a digit"),
+            Self::MissingSymbol => write!(f, "the password must contain a symbol"),
+            Self::Common => write!(f, "the password is too common"),
+        }
+    }
+}
+
+impl Error for PasswordError {}
+
+/// Generates a random salt suitable for use with hash_password.
+pub fn generate_salt() -> [u8; SALT_LENGTH] {
+    rand::random()
+}
+
+/// Hashes a password exactly as the swaply identity service expects it to be hashed, using the
+/// default PasswordHasher. Connectors configured with a custom hasher should use
+/// PasswordHasher::hash_salted instead.
+///
+/// # Arguments
+///
+/// * `password` - The plaintext password that should be hashed
+/// * `salt` - The salt that should be prepended to the password
+///
+/// # Examples
+///
+/// ```
+/// use swaply_identity::crypto::password;
+///
+/// let salt = password::generate_salt();
+/// let hash = password::hash_password("correct horse battery staple", &salt);
+///
+/// assert!(password::verify_password("correct horse battery staple", &salt, &hash));
+/// ```
+pub fn hash_password(password: &str, salt: &[u8]) -> PasswordHash {
+    PasswordHasher::default().hash_salted(password, salt)
+}
+
+/// Determines whether or not the provided password matches a hash produced by hash_password.
+///
+/// # Arguments
+///
+/// * `password` - The plaintext password that should be verified
+/// * `salt` - The salt that was prepended to the password when it was hashed
+/// * `hash` - The hash that the password should produce
+pub fn verify_password(password: &str, salt: &[u8], hash: &PasswordHash) -> bool {
+    PasswordHasher::default().verify_salted(password, salt, hash)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_domain_separated() {
+        let hash = PasswordHasher::default().hash(b"123456");
+
+        assert_ne!(hash, PasswordHash::from(blake3::hash(b"123456")));
+        assert_ne!(
+            hash,
+            PasswordHasher::new("some other context", None).hash(b"123456")
+        );
+        assert_ne!(
+            hash,
+            PasswordHasher::new(DEFAULT_CONTEXT, Some([1; 32])).hash(b"123456")
+        );
+    }
+
+    #[test]
+    fn test_verify_keyed() {
+        let hasher = PasswordHasher::new(DEFAULT_CONTEXT, Some([1; 32]));
+        let hash = hasher.hash(b"123456");
+
+        assert!(hasher.verify(b"123456", &hash));
+        assert!(!hasher.verify(b"1234567", &hash));
+        assert!(!PasswordHasher::new(DEFAULT_CONTEXT, Some([2; 32])).verify(b"123456", &hash));
+    }
+
+    #[test]
+    fn test_verify_legacy() {
+        let hasher = PasswordHasher::default();
+        let legacy_hash: PasswordHash = blake3::hash(b"123456").into();
+
+        assert!(!hasher.verify(b"123456", &legacy_hash));
+        assert!(hasher.verify_with_legacy(b"123456", &legacy_hash));
+        assert!(hasher.verify_with_legacy(b"123456", &hasher.hash(b"123456")));
+        assert!(!hasher.verify_with_legacy(b"1234567", &legacy_hash));
+    }
+
+    #[test]
+    fn test_hash_password_salted() {
+        let (salt, other_salt) = (generate_salt(), generate_salt());
+        assert_ne!(salt, other_salt);
+
+        let hash = hash_password("123456", &salt);
+
+        assert_ne!(hash, hash_password("123456", &other_salt));
+        assert!(verify_password("123456", &salt, &hash));
+        assert!(!verify_password("123456", &other_salt, &hash));
+        assert!(!verify_password("1234567", &salt, &hash));
+    }
+
+    #[test]
+    fn test_password_hash_constant_time_eq() {
+        let hash = PasswordHash::new([7; HASH_LENGTH]);
+
+        assert_eq!(hash, PasswordHash::new([7; HASH_LENGTH]));
+        assert_eq!(hash, hash.clone());
+
+        // Hashes differing in only their first or last byte are still distinguished
+        for &i in &[0, HASH_LENGTH - 1] {
+            let mut bytes = [7; HASH_LENGTH];
+            bytes[i] = 8;
+
+            assert_ne!(hash, PasswordHash::new(bytes));
+        }
+    }
+
+    #[test]
+    fn test_password_hash_debug_redacted() {
+        let hash = hash_password("123456", &generate_salt());
+        let debugged = format!("{:?}", hash);
+
+        assert_eq!(debugged, "PasswordHash(<redacted>)");
+        assert!(!debugged.contains(&hash.to_base58()));
+
+        let verification = PasswordVerification::Upgraded {
+            hash,
+            salt: generate_salt(),
+        };
+        assert!(format!("{:?}", verification).contains("PasswordHash(<redacted>)"));
+    }
+
+    #[test]
+    fn test_password_hash_from_slice() {
+        let hash = PasswordHash::new([7; HASH_LENGTH]);
+
+        assert_eq!(PasswordHash::try_from(&hash.as_bytes()[..]).unwrap(), hash);
+        assert!(PasswordHash::try_from(&hash.as_bytes()[1..]).is_err());
+    }
+
+    #[test]
+    fn test_policy_rejects_weak_password() {
+        let policy = PasswordPolicy::default();
+
+        assert_eq!(policy.check("123456"), Err(PasswordError::TooShort(10)));
+        assert_eq!(
+            policy.check("tr0ub4dour&3"),
+            Err(PasswordError::MissingUppercase)
+        );
+        assert_eq!(
+            policy.check("Troubadour&"),
+            Err(PasswordError::MissingDigit)
+        );
+        assert_eq!(policy.check("Password123"), Err(PasswordError::Common));
+        assert_eq!(
+            PasswordPolicy {
+                require_symbol: true,
+                ..PasswordPolicy::default()
+            }
+            .check("Tr0ub4dour3"),
+            Err(PasswordError::MissingSymbol)
+        );
+    }
+
+    #[test]
+    fn test_policy_accepts_strong_password() {
+        assert_eq!(PasswordPolicy::default().check("Tr0ub4dour&3"), Ok(()));
+        assert_eq!(PasswordPolicy::permissive().check("123456"), Ok(()));
+    }
+}
@@ -0,0 +1,214 @@
+use aes_gcm::{
+    aead::{generic_array::GenericArray, Aead, NewAead},
+    Aes256Gcm,
+};
+use bs58::decode::Error as Bs58DecodingError;
+use uuid::Uuid;
+
+use super::super::{error::IdentityError, schema::user::IdentityProvider};
+
+use std::{error::Error, fmt, string::FromUtf8Error};
+
+/// The number of bytes in each AES-GCM nonce. Nonces are prepended to the ciphertext they were
+/// used to produce.
+const NONCE_LENGTH: usize = 12;
+
+/// TokenKey represents a 256-bit AES-GCM key used to encrypt provider tokens before they are
+/// written to the database.
+#[derive(Clone)]
+pub struct TokenKey([u8; 32]);
+
+impl TokenKey {
+    /// Creates a new token key from the given raw key material.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The 32 bytes that should be used as the AES-256 key
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Generates a random token key.
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    /// Encrypts the provided token, returning a base58 string containing the nonce and resulting
+    /// ciphertext.
+    ///
+    /// # Arguments
+    ///
+    /// * `plaintext` - The token that should be encrypted
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::crypto::token::TokenKey;
+    ///
+    /// let key = TokenKey::generate();
+    ///
+    /// let ciphertext = key.encrypt("access_token").unwrap();
+    /// assert_eq!(key.decrypt(&ciphertext).unwrap(), "access_token");
+    /// ```
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, EncryptionError> {
+        let nonce: [u8; NONCE_LENGTH] = rand::random();
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher()
+                .encrypt(GenericArray::from_slice(&nonce), plaintext.as_bytes())
+                .map_err(|_| EncryptionError::EncryptionFailed)?,
+        );
+
+        Ok(bs58::encode(sealed).into_string())
+    }
+
+    /// Decrypts a token previously encrypted with this key.
+    ///
+    /// # Arguments
+    ///
+    /// * `ciphertext` - The base58-encoded nonce and ciphertext produced by encrypt
+    pub fn decrypt(&self, ciphertext: &str) -> Result<String, EncryptionError> {
+        let sealed = bs58::decode(ciphertext).into_vec()?;
+
+        // Each ciphertext must at least contain the nonce used to produce it
+        if sealed.len() < NONCE_LENGTH {
+            return Err(EncryptionError::MalformedCiphertext);
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+
+        self.cipher()
+            .decrypt(GenericArray::from_slice(nonce), ciphertext)
+            // AES-GCM doesn't tell us whether the key or the ciphertext was wrong
+            .map_err(|_| EncryptionError::DecryptionFailed)
+            .and_then(|plaintext| String::from_utf8(plaintext).map_err(|e| e.into()))
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(GenericArray::from_slice(&self.0))
+    }
+}
+
+// Keys should never end up in logs
+impl fmt::Debug for TokenKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TokenKey(<redacted>)")
+    }
+}
+
+/// EncryptionError represents an error that may be encountered while encrypting or decrypting a
+/// provider token.
+#[derive(Debug)]
+pub enum EncryptionError {
+    MissingKey,
+    EncryptionFailed,
+    DecryptionFailed,
+    MalformedCiphertext,
+    DecodingError(Bs58DecodingError),
+    Utf8Error(FromUtf8Error),
+
+    /// Some stored tokens couldn't be decrypted under the old key while rotating the token key,
+    /// were replaced while being rotated, or couldn't be read at all (e.g., as their provider
+    /// isn't enabled), and were left as they were. Unreadable tokens are keyed by the provider
+    /// name that they were stored under
+    RotationFailures {
+        rotated: u64,
+        tokens: Vec<(Uuid, IdentityProvider)>,
+        unreadable: Vec<(Uuid, String)>,
+    },
+}
+
+impl From<Bs58DecodingError> for EncryptionError {
+    fn from(e: Bs58DecodingError) -> Self {
+        Self::DecodingError(e)
+    }
+}
+
+impl From<FromUtf8Error> for EncryptionError {
+    fn from(e: FromUtf8Error) -> Self {
+        Self::Utf8Error(e)
+    }
+}
+
+impl From<EncryptionError> for IdentityError {
+    fn from(e: EncryptionError) -> Self {
+        IdentityError::EncryptionError(e)
+    }
+}
+
+impl fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "encountered an error while handling an encrypted token: {}",
+            match self {
+                Self::MissingKey => "no token key was configured".to_owned(),
+                Self::EncryptionFailed => "the token could not be encrypted".to_owned(),
+                Self::DecryptionFailed =>
+                    "the token could not be decrypted (wrong key or tampered ciphertext)".to_owned(),
+                Self::MalformedCiphertext => "the ciphertext is too short".to_owned(),
+                Self::RotationFailures {
+                    rotated,
+                    tokens,
+                    unreadable,
+                } => format!(
+                    "{} stored tokens could not be rotated ({} tokens were rotated)",
+                    tokens.len() + unreadable.len(),
+                    rotated
+                ),
+                _ => format!("{:?}", self.source()),
+            }
+        )
+    }
+}
+
+impl Error for EncryptionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::DecodingError(e) => Some(e),
+            Self::Utf8Error(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let key = TokenKey::generate();
+
+        let ciphertext = key.encrypt("ya29.a0AfH6SMB").unwrap();
+        assert_ne!(ciphertext, "ya29.a0AfH6SMB");
+        assert_eq!(key.decrypt(&ciphertext).unwrap(), "ya29.a0AfH6SMB");
+    }
+
+    #[test]
+    fn test_decrypt_wrong_key() {
+        let ciphertext = TokenKey::generate().encrypt("ya29.a0AfH6SMB").unwrap();
+
+        match TokenKey::generate().decrypt(&ciphertext) {
+            Err(EncryptionError::DecryptionFailed) => (),
+            res => panic!("expected a decryption failure, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_decrypt_tampered() {
+        let key = TokenKey::generate();
+
+        let mut sealed = bs58::decode(key.encrypt("ya29.a0AfH6SMB").unwrap())
+            .into_vec()
+            .unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+
+        match key.decrypt(&bs58::encode(sealed).into_string()) {
+            Err(EncryptionError::DecryptionFailed) => (),
+            res => panic!("expected a decryption failure, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use cdrs::query::QueryValues;
+use uuid::Uuid;
+
+use super::{
+    super::{
+        result::IdentityResult,
+        schema::user::{OwnedUser, UserQuery},
+        DbSession,
+    },
+    scylla::Scylla,
+    Deserializable, Insertable, Provider, Queryable, Serializable,
+};
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// CacheKey represents an allocated UserQuery, by which loaded users are memoized.
+#[derive(Hash, PartialEq, Eq, Debug)]
+enum CacheKey {
+    Id(Uuid),
+    Nickname(String),
+}
+
+impl CacheKey {
+    /// Gets the key under which the result of a query should be memoized. Queries that may match
+    /// many users aren't memoized.
+    ///
+    /// # Arguments
+    ///
+    /// * `q` - The query whose result should be memoized
+    fn of(q: &UserQuery) -> Option<Self> {
+        match q {
+            UserQuery::Id(id) => Some(Self::Id(**id)),
+            UserQuery::Nickname(nick) => Some(Self::Nickname((*nick).to_owned())),
+            UserQuery::RegisteredBetween(..) => None,
+        }
+    }
+}
+
+/// Cached represents a provider whose user lookups are memoized for a short period of time. Any
+/// records inserted through the cache invalidate it entirely; updates made directly through the
+/// wrapped provider must be followed by a call to invalidate.
+#[derive(Debug)]
+pub struct Cached<P> {
+    inner: P,
+
+    /// The amount of time for which a loaded user is considered fresh
+    ttl: Duration,
+
+    /// Each memoized user, alongside the time at which it was loaded
+    users: Mutex<HashMap<CacheKey, (OwnedUser, Instant)>>,
+}
+
+impl<P> Cached<P> {
+    /// Wraps a provider in a cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The provider whose lookups should be cached
+    /// * `ttl` - The amount of time for which a loaded user should be reused
+    pub fn new(inner: P, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Gets the wrapped provider.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Evicts every cached lookup of a user, such that the user is reloaded upon their next
+    /// lookup. This should be called after the user is updated or deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user that should be evicted
+    pub fn invalidate(&self, id: &Uuid) {
+        self.users().retain(|_, (u, _)| u.id() != id);
+    }
+
+    /// Evicts every cached lookup.
+    pub fn clear(&self) {
+        self.users().clear();
+    }
+
+    /// Locks the memoized users. A panic while the lock was held can't leave the map in an
+    /// inconsistent state, so poisoning is ignored.
+    fn users(&self) -> MutexGuard<'_, HashMap<CacheKey, (OwnedUser, Instant)>> {
+        self.users.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<P: Provider<Scylla, DbSession> + Sync> Cached<P>
+where
+    OwnedUser: Deserializable<OwnedUser, P::ResponseIntermediary>,
+{
+    /// Loads a user, reusing the result of an identical lookup made within the cache's TTL.
+    ///
+    /// # Arguments
+    ///
+    /// * `q` - The query that the user should match
+    pub async fn load_user(&self, q: &UserQuery<'_>) -> IdentityResult<OwnedUser> {
+        let key = match CacheKey::of(q) {
+            Some(key) => key,
+            None => return self.inner.load_record(q).await,
+        };
+
+        {
+            let mut users = self.users();
+
+            match users.get(&key) {
+                Some((u, loaded_at)) if loaded_at.elapsed() < self.ttl => return Ok(u.clone()),
+                Some(_) => {
+                    users.remove(&key);
+                }
+                None => (),
+            }
+        }
+
+        let u: OwnedUser = self.inner.load_record(q).await?;
+        self.users().insert(key, (u.clone(), Instant::now()));
+
+        Ok(u)
+    }
+}
+
+// Loads of arbitrary records aren't cached, since the cache only holds users
+#[async_trait]
+impl<P: Provider<Scylla, DbSession> + Send + Sync> Provider<Scylla, DbSession> for Cached<P> {
+    type ResponseIntermediary = P::ResponseIntermediary;
+    type RequestIntermediary = P::RequestIntermediary;
+
+    async fn load_record<
+        K: Queryable<Scylla, DbSession> + Send + Sync,
+        V: Deserializable<V, Self::ResponseIntermediary> + Send,
+    >(
+        &self,
+        q: &K,
+    ) -> IdentityResult<V> {
+        self.inner.load_record(q).await
+    }
+
+    async fn load_records<
+        K: Queryable<Scylla, DbSession> + Send + Sync,
+        V: Deserializable<V, Self::ResponseIntermediary> + Send,
+    >(
+        &self,
+        q: &K,
+    ) -> IdentityResult<Vec<V>> {
+        self.inner.load_records(q).await
+    }
+
+    async fn insert_record<
+        V: Serializable<Self::RequestIntermediary> + Insertable<Scylla, DbSession> + Send + Sync,
+    >(
+        &self,
+        r: &V,
+    ) -> IdentityResult<()> {
+        self.inner.insert_record(r).await?;
+
+        // The inserted record may replace a cached user. Clearing the cache before the write
+        // completes would let a concurrent lookup cache the user as it was before
+        self.clear();
+
+        Ok(())
+    }
+
+    async fn insert_if_absent<
+        V: Serializable<Self::RequestIntermediary> + Insertable<Scylla, DbSession> + Send + Sync,
+    >(
+        &self,
+        r: &V,
+    ) -> IdentityResult<bool> {
+        let inserted = self.inner.insert_if_absent(r).await?;
+
+        // Nothing was written if the record already existed, so no cached user was replaced
+        if inserted {
+            self.clear();
+        }
+
+        Ok(inserted)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::{error::IdentityError, testing};
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// MockProvider represents a provider holding a single user, which counts how many times it
+    /// has been queried.
+    #[derive(Debug)]
+    struct MockProvider {
+        user: OwnedUser,
+        loads: AtomicUsize,
+    }
+
+    impl Deserializable<OwnedUser, OwnedUser> for OwnedUser {
+        type Error = IdentityError;
+
+        fn try_from(value: OwnedUser) -> Result<OwnedUser, Self::Error> {
+            Ok(value)
+        }
+    }
+
+    #[async_trait]
+    impl Provider<Scylla, DbSession> for MockProvider {
+        type ResponseIntermediary = OwnedUser;
+        type RequestIntermediary = QueryValues;
+
+        async fn load_record<
+            K: Queryable<Scylla, DbSession> + Send + Sync,
+            V: Deserializable<V, Self::ResponseIntermediary> + Send,
+        >(
+            &self,
+            _q: &K,
+        ) -> IdentityResult<V> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+
+            V::try_from(self.user.clone()).map_err(|e| e.into())
+        }
+
+        async fn load_records<
+            K: Queryable<Scylla, DbSession> + Send + Sync,
+            V: Deserializable<V, Self::ResponseIntermediary> + Send,
+        >(
+            &self,
+            q: &K,
+        ) -> IdentityResult<Vec<V>> {
+            self.load_record(q).await.map(|v| vec![v])
+        }
+
+        async fn insert_record<
+            V: Serializable<Self::RequestIntermediary> + Insertable<Scylla, DbSession> + Send + Sync,
+        >(
+            &self,
+            _r: &V,
+        ) -> IdentityResult<()> {
+            Ok(())
+        }
+
+        async fn insert_if_absent<
+            V: Serializable<Self::RequestIntermediary> + Insertable<Scylla, DbSession> + Send + Sync,
+        >(
+            &self,
+            _r: &V,
+        ) -> IdentityResult<bool> {
+            Ok(true)
+        }
+    }
+
+    /// Wraps a mock provider holding a freshly generated user in a cache.
+    fn cached_mock(ttl: Duration) -> Cached<MockProvider> {
+        Cached::new(
+            MockProvider {
+                user: OwnedUser::from(testing::generate_user()),
+                loads: AtomicUsize::new(0),
+            },
+            ttl,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_load_user_cached() -> IdentityResult<()> {
+        let cache = cached_mock(Duration::from_secs(60));
+        let id = *cache.inner().user.id();
+
+        let u = cache.load_user(&UserQuery::Id(&id)).await?;
+        assert_eq!(cache.load_user(&UserQuery::Id(&id)).await?.id(), u.id());
+        assert_eq!(cache.inner().loads.load(Ordering::SeqCst), 1);
+
+        // Distinct queries are cached separately
+        cache.load_user(&UserQuery::Nickname(u.username())).await?;
+        assert_eq!(cache.inner().loads.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_user_invalidated() -> IdentityResult<()> {
+        let cache = cached_mock(Duration::from_secs(60));
+        let id = *cache.inner().user.id();
+
+        cache.load_user(&UserQuery::Id(&id)).await?;
+        cache.load_user(&UserQuery::Nickname("test")).await?;
+        cache.invalidate(&id);
+
+        cache.load_user(&UserQuery::Id(&id)).await?;
+        cache.load_user(&UserQuery::Nickname("test")).await?;
+        assert_eq!(cache.inner().loads.load(Ordering::SeqCst), 4);
+
+        // Inserting a record through the cache should also invalidate it
+        cache.insert_record(&testing::generate_user()).await?;
+        cache.load_user(&UserQuery::Id(&id)).await?;
+        assert_eq!(cache.inner().loads.load(Ordering::SeqCst), 5);
+
+        // As should inserting a record that didn't already exist
+        assert!(cache.insert_if_absent(&testing::generate_user()).await?);
+        cache.load_user(&UserQuery::Id(&id)).await?;
+        assert_eq!(cache.inner().loads.load(Ordering::SeqCst), 6);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_user_expired() -> IdentityResult<()> {
+        let cache = cached_mock(Duration::from_secs(0));
+        let id = *cache.inner().user.id();
+
+        cache.load_user(&UserQuery::Id(&id)).await?;
+        cache.load_user(&UserQuery::Id(&id)).await?;
+        assert_eq!(cache.inner().loads.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,583 @@
+use cdrs::{
+    authenticators::StaticPasswordAuthenticator,
+    cluster::{session, ClusterTcpConfig, NodeTcpConfigBuilder},
+    error::Error as CDRSError,
+    load_balancing::RoundRobin,
+};
+
+use super::{super::result::IdentityResult, scylla::Scylla};
+
+use std::{
+    env,
+    error::Error,
+    fmt,
+    net::{SocketAddr, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+/// The environment variable holding the comma-separated addresses of the scylla nodes that should
+/// be contacted.
+pub const NODE_URL_VAR: &str = "SCYLLA_NODE_URL";
+
+/// The environment variable holding the username used to authenticate with scylla.
+pub const USERNAME_VAR: &str = "SCYLLA_USERNAME";
+
+/// The environment variable holding the password used to authenticate with scylla.
+pub const PASSWORD_VAR: &str = "SCYLLA_PASSWORD";
+
+/// The environment variable holding the maximum duration of a database operation, in
+/// milliseconds, if any.
+pub const TIMEOUT_VAR: &str = "SCYLLA_TIMEOUT_MS";
+
+/// The environment variable holding the number of rows fetched per page, if not the default.
+pub const PAGE_SIZE_VAR: &str = "SCYLLA_PAGE_SIZE";
+
+/// The environment variable holding the keyspace in which identity tables are stored. Only the
+/// default keyspace is currently supported, so any other keyspace is rejected by validation.
+pub const KEYSPACE_VAR: &str = "SCYLLA_KEYSPACE";
+
+/// The environment variable holding the algorithm used to compress traffic with scylla ("lz4" or
+/// "snappy"), if any.
+pub const COMPRESSION_VAR: &str = "SCYLLA_COMPRESSION";
+
+/// The keyspace in which identity tables are stored by default.
+pub const DEFAULT_KEYSPACE: &str = "identity";
+
+/// The address of the scylla node contacted by default.
+const DEFAULT_CONTACT_POINT: &str = "127.0.0.1:9042";
+
+/// Compression represents an algorithm used to compress the frames exchanged with scylla.
+/// Compression trades CPU time for bandwidth, which pays off for large batches and wide reads.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    Lz4,
+    Snappy,
+}
+
+impl Default for Compression {
+    /// LZ4 is used by default, being the cheaper of the two to compress with.
+    fn default() -> Self {
+        Self::Lz4
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "lz4" => Ok(Self::Lz4),
+            "snappy" => Ok(Self::Snappy),
+            _ => Err(()),
+        }
+    }
+}
+
+/// ScyllaConfig represents the details required to connect to a scylla cluster, and to configure
+/// the resulting connector.
+#[derive(Clone)]
+pub struct ScyllaConfig {
+    /// The addresses of the scylla nodes through which the cluster is discovered
+    contact_points: Vec<SocketAddr>,
+
+    username: String,
+    password: String,
+
+    /// The keyspace in which identity tables are stored
+    keyspace: String,
+
+    timeout: Option<Duration>,
+    page_size: Option<i32>,
+
+    /// The algorithm used to compress traffic with scylla, if any
+    compression: Option<Compression>,
+}
+
+impl ScyllaConfig {
+    /// Creates a new scylla configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `contact_points` - The addresses of the scylla nodes that should be contacted
+    /// * `username` - The username used to authenticate with scylla
+    /// * `password` - The password used to authenticate with scylla
+    pub fn new(contact_points: Vec<SocketAddr>, username: &str, password: &str) -> Self {
+        Self {
+            contact_points,
+            username: username.to_owned(),
+            password: password.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    /// Loads a scylla configuration from the SCYLLA_NODE_URL, SCYLLA_USERNAME, and SCYLLA_PASSWORD
+    /// environment variables, and the optional SCYLLA_KEYSPACE, SCYLLA_TIMEOUT_MS,
+    /// SCYLLA_PAGE_SIZE, and SCYLLA_COMPRESSION environment variables.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::db::config::ScyllaConfig;
+    /// use std::error::Error;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn Error>> {
+    /// # if std::path::Path::new(".env").exists() {
+    /// #     dotenv::dotenv().ok();
+    /// # }
+    ///
+    /// let db = ScyllaConfig::from_env()?.connect().await?;
+    ///
+    /// Ok(())
+    /// # }
+    /// ```
+    pub fn from_env() -> IdentityResult<Self> {
+        Self::from_vars(|var| env::var(var).ok())
+    }
+
+    /// Loads a scylla configuration from the variables provided by a lookup function, under the
+    /// same names as from_env.
+    ///
+    /// # Arguments
+    ///
+    /// * `lookup` - A function returning the value of a variable, if it is set
+    fn from_vars<F: Fn(&str) -> Option<String>>(lookup: F) -> IdentityResult<Self> {
+        let required = |var: &'static str| lookup(var).ok_or(ConfigError::MissingVar(var));
+
+        let mut config = Self::new(
+            parse_contact_points(required(NODE_URL_VAR)?)?,
+            &required(USERNAME_VAR)?,
+            &required(PASSWORD_VAR)?,
+        );
+        if let Some(keyspace) = lookup(KEYSPACE_VAR) {
+            config.keyspace = keyspace;
+        }
+        config.timeout = parse_var(TIMEOUT_VAR, lookup(TIMEOUT_VAR))?.map(Duration::from_millis);
+        config.page_size = parse_var(PAGE_SIZE_VAR, lookup(PAGE_SIZE_VAR))?;
+        config.compression = parse_var(COMPRESSION_VAR, lookup(COMPRESSION_VAR))?;
+
+        Ok(config)
+    }
+
+    /// Configures the keyspace in which identity tables are stored. Every query made by the
+    /// connector names the default keyspace, so any other keyspace is rejected by validation with
+    /// a ConfigError::UnsupportedKeyspace, rather than being silently ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `keyspace` - The name of the keyspace
+    pub fn with_keyspace(mut self, keyspace: &str) -> Self {
+        self.keyspace = keyspace.to_owned();
+
+        self
+    }
+
+    /// Gets the keyspace in which identity tables are stored.
+    pub fn keyspace(&self) -> &str {
+        &self.keyspace
+    }
+
+    /// Gets the addresses of the scylla nodes that are contacted.
+    pub fn contact_points(&self) -> &[SocketAddr] {
+        &self.contact_points
+    }
+
+    /// Ensures that the configuration could be used to connect to a cluster, without connecting.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.contact_points.is_empty() {
+            return Err(ConfigError::NoContactPoints);
+        }
+
+        if self.keyspace.trim().is_empty() {
+            return Err(ConfigError::BlankKeyspace);
+        }
+
+        if self.keyspace != DEFAULT_KEYSPACE {
+            return Err(ConfigError::UnsupportedKeyspace(self.keyspace.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Configures the maximum amount of time that any one operation performed by the connector
+    /// may take before being aborted.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The maximum duration of a database operation
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+
+        self
+    }
+
+    /// Configures the number of rows fetched per page by the connector.
+    ///
+    /// # Arguments
+    ///
+    /// * `page_size` - The number of rows that should be fetched per page
+    pub fn with_page_size(mut self, page_size: i32) -> Self {
+        self.page_size = Some(page_size);
+
+        self
+    }
+
+    /// Configures the algorithm used to compress traffic with scylla. Compression is disabled
+    /// unless configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `compression` - The compression algorithm that should be used (e.g.,
+    /// Compression::default() for LZ4)
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+
+        self
+    }
+
+    /// Gets the algorithm used to compress traffic with scylla, if any.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Opens a session with the configured scylla nodes, producing a configured connector. Fails
+    /// without connecting if the configuration is invalid.
+    pub async fn connect(&self) -> IdentityResult<Scylla> {
+        self.validate()?;
+
+        let auth = StaticPasswordAuthenticator::new(&self.username, &self.password);
+        let addrs = self
+            .contact_points
+            .iter()
+            .map(SocketAddr::to_string)
+            .collect::<Vec<String>>();
+        let nodes = addrs
+            .iter()
+            .map(|addr| NodeTcpConfigBuilder::new(addr, auth.clone()).build())
+            .collect();
+
+        let cluster = ClusterTcpConfig(nodes);
+        let session = match self.compression {
+            None => session::new(&cluster, RoundRobin::new()).await,
+            Some(Compression::Lz4) => session::new_lz4(&cluster, RoundRobin::new()).await,
+            Some(Compression::Snappy) => session::new_snappy(&cluster, RoundRobin::new()).await,
+        }
+        .map_err(ConfigError::Connection)?;
+
+        let db = Scylla::new(session);
+        let db = match self.timeout {
+            Some(timeout) => db.with_timeout(timeout),
+            None => db,
+        };
+
+        Ok(match self.page_size {
+            Some(page_size) => db.with_page_size(page_size),
+            None => db,
+        })
+    }
+}
+
+impl Default for ScyllaConfig {
+    /// Creates a configuration for a local, unauthenticated scylla node.
+    fn default() -> Self {
+        Self {
+            contact_points: vec![DEFAULT_CONTACT_POINT
+                .parse()
+                .expect("the default contact point should be a valid address")],
+            username: String::new(),
+            password: String::new(),
+            keyspace: DEFAULT_KEYSPACE.to_owned(),
+            timeout: None,
+            page_size: None,
+            compression: None,
+        }
+    }
+}
+
+// Passwords should never end up in logs
+impl fmt::Debug for ScyllaConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScyllaConfig")
+            .field("contact_points", &self.contact_points)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("keyspace", &self.keyspace)
+            .field("timeout", &self.timeout)
+            .field("page_size", &self.page_size)
+            .field("compression", &self.compression)
+            .finish()
+    }
+}
+
+/// Parses the value of an optional variable, if it is set.
+///
+/// # Arguments
+///
+/// * `var` - The name of the variable
+/// * `value` - The value of the variable, if it is set
+fn parse_var<T: FromStr>(
+    var: &'static str,
+    value: Option<String>,
+) -> Result<Option<T>, ConfigError> {
+    value
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| ConfigError::InvalidVar { var, value })
+        })
+        .transpose()
+}
+
+/// Parses a comma-separated list of contact points, resolving any hostnames.
+///
+/// # Arguments
+///
+/// * `value` - The value of the variable holding the contact points
+fn parse_contact_points(value: String) -> Result<Vec<SocketAddr>, ConfigError> {
+    let mut contact_points = Vec::new();
+
+    for addr in value
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+    {
+        match addr.to_socket_addrs() {
+            Ok(addrs) => contact_points.extend(addrs),
+            Err(_) => {
+                return Err(ConfigError::InvalidVar {
+                    var: NODE_URL_VAR,
+                    value,
+                })
+            }
+        }
+    }
+
+    Ok(contact_points)
+}
+
+/// ConfigError represents any error that may be encountered while configuring or connecting to
+/// the database, before any query is made.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A required variable isn't set
+    MissingVar(&'static str),
+
+    /// A variable is set to a value that can't be parsed
+    InvalidVar { var: &'static str, value: String },
+
+    /// No scylla nodes are configured to be contacted
+    NoContactPoints,
+
+    /// The configured keyspace is blank
+    BlankKeyspace,
+
+    /// The configured keyspace isn't the default keyspace, which is the only keyspace supported
+    UnsupportedKeyspace(String),
+
+    /// A session couldn't be opened with the configured nodes
+    Connection(CDRSError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "encountered an error while configuring the database connection: {}",
+            match self {
+                Self::MissingVar(var) => format!("the {} variable isn't set", var),
+                Self::InvalidVar { var, value } => {
+                    format!("the {} variable holds an invalid value: {:?}", var, value)
+                }
+                Self::NoContactPoints => "no nodes are configured to be contacted".to_owned(),
+                Self::BlankKeyspace => "the configured keyspace is blank".to_owned(),
+                Self::UnsupportedKeyspace(keyspace) => format!(
+                    "the {} keyspace isn't supported; only the {} keyspace is",
+                    keyspace, DEFAULT_KEYSPACE
+                ),
+                Self::Connection(e) => format!("couldn't connect to the database: {}", e),
+            }
+        )
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Connection(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::error::IdentityError;
+
+    use std::collections::HashMap;
+
+    /// Builds a lookup function over a fixed set of variables.
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = pairs
+            .iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect::<HashMap<String, String>>();
+
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let config = ScyllaConfig::from_vars(vars(&[
+            (NODE_URL_VAR, "127.0.0.1:9042, 127.0.0.2:9042"),
+            (USERNAME_VAR, "scylla"),
+            (PASSWORD_VAR, "scylla"),
+            (TIMEOUT_VAR, "1500"),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            config.contact_points(),
+            &[
+                "127.0.0.1:9042".parse().unwrap(),
+                "127.0.0.2:9042".parse().unwrap()
+            ][..]
+        );
+        assert_eq!(config.keyspace(), DEFAULT_KEYSPACE);
+        assert!(config.validate().is_ok());
+        assert_eq!(config.timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(config.page_size, None);
+        assert_eq!(config.compression(), None);
+        assert!(!format!("{:?}", config).contains("password: \"scylla\""));
+    }
+
+    #[test]
+    fn test_from_vars_missing() {
+        match ScyllaConfig::from_vars(vars(&[(NODE_URL_VAR, "127.0.0.1:9042")])) {
+            Err(IdentityError::Config(ConfigError::MissingVar(var))) => {
+                assert_eq!(var, USERNAME_VAR)
+            }
+            res => panic!("expected a missing variable, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_from_vars_malformed() {
+        match ScyllaConfig::from_vars(vars(&[
+            (NODE_URL_VAR, "127.0.0.1:9042"),
+            (USERNAME_VAR, "scylla"),
+            (PASSWORD_VAR, "scylla"),
+            (PAGE_SIZE_VAR, "lots"),
+        ])) {
+            Err(IdentityError::Config(ConfigError::InvalidVar { var, value })) => {
+                assert_eq!(var, PAGE_SIZE_VAR);
+                assert_eq!(value, "lots");
+            }
+            res => panic!("expected an invalid variable, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_from_vars_compression() {
+        let lookup = |compression| {
+            ScyllaConfig::from_vars(vars(&[
+                (NODE_URL_VAR, "127.0.0.1:9042"),
+                (USERNAME_VAR, "scylla"),
+                (PASSWORD_VAR, "scylla"),
+                (COMPRESSION_VAR, compression),
+            ]))
+        };
+
+        assert_eq!(lookup("lz4").unwrap().compression(), Some(Compression::Lz4));
+        assert_eq!(
+            lookup("Snappy").unwrap().compression(),
+            Some(Compression::Snappy)
+        );
+
+        match lookup("zstd") {
+            Err(IdentityError::Config(ConfigError::InvalidVar { var, .. })) => {
+                assert_eq!(var, COMPRESSION_VAR)
+            }
+            res => panic!("expected an invalid variable, got {:?}", res),
+        }
+
+        assert_eq!(
+            ScyllaConfig::default()
+                .with_compression(Compression::default())
+                .compression(),
+            Some(Compression::Lz4)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_compressed() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::{
+            db::{InTable, Provider},
+            schema::user::{OwnedUser, User, UserQuery},
+            testing,
+        };
+
+        crate::load_env!();
+
+        for compression in &[Compression::Lz4, Compression::Snappy] {
+            let db = ScyllaConfig::from_env()?
+                .with_compression(*compression)
+                .connect()
+                .await?;
+
+            crate::create_keyspace(db.session()).await?;
+            User::create_prerequisite_objects(db.session()).await?;
+
+            let u = testing::generate_user();
+            testing::insert_user(&db, &u).await?;
+
+            let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+            assert_eq!(loaded_u.id(), u.id());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ScyllaConfig::default().validate().is_ok());
+
+        match ScyllaConfig::default().with_keyspace("  ").validate() {
+            Err(ConfigError::BlankKeyspace) => (),
+            res => panic!("expected a blank keyspace, got {:?}", res),
+        }
+
+        // Every query names the default keyspace, so no other keyspace may be configured
+        assert!(ScyllaConfig::default()
+            .with_keyspace(DEFAULT_KEYSPACE)
+            .validate()
+            .is_ok());
+        match ScyllaConfig::default()
+            .with_keyspace("identity_staging")
+            .validate()
+        {
+            Err(ConfigError::UnsupportedKeyspace(keyspace)) => {
+                assert_eq!(keyspace, "identity_staging")
+            }
+            res => panic!("expected an unsupported keyspace, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_no_contact_points() {
+        let config = ScyllaConfig::new(Vec::new(), "scylla", "scylla");
+
+        match config.validate() {
+            Err(ConfigError::NoContactPoints) => (),
+            res => panic!("expected no contact points, got {:?}", res),
+        }
+
+        // The configuration is rejected before any connection is attempted
+        match config.connect().await {
+            Err(IdentityError::Config(ConfigError::NoContactPoints)) => (),
+            Err(e) => panic!("expected no contact points, got {:?}", e),
+            Ok(_) => panic!("expected no contact points, got a connector"),
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use cdrs::{error::Error as CDRSError, query::QueryExecutor, types::IntoRustByName};
+use futures::future::BoxFuture;
+
+use super::{
+    super::{error::IdentityError, result::IdentityResult, schema::user::UserColumn, DbSession},
+    scylla::{table_columns, Scylla},
+    ColumnSchema,
+};
+
+use std::fmt;
+
+/// Every migration evolving the identity keyspace's tables from the schema that they were first
+/// deployed with, in the order that they're applied.
+pub const MIGRATIONS: &[Migration] = &[
+    add_user_column(1, UserColumn::EmailVerified),
+    add_user_column(2, UserColumn::RotateHashOnNextLogin),
+    add_user_column(3, UserColumn::FailedLoginAttempts),
+    add_user_column(4, UserColumn::LockedUntil),
+    add_user_column(5, UserColumn::DisplayName),
+    add_user_column(6, UserColumn::Locale),
+    add_user_column(7, UserColumn::Timezone),
+    add_user_column(8, UserColumn::Suspended),
+    add_user_column(9, UserColumn::PasswordSalt),
+    Migration {
+        version: 10,
+        name: "index_existing_usernames",
+        step: MigrationStep::Backfill(Scylla::backfill_username_prefixes),
+    },
+];
+
+/// Describes a migration adding a column of the users table.
+///
+/// # Arguments
+///
+/// * `version` - The version of the migration
+/// * `column` - The column that the migration adds
+const fn add_user_column(version: u32, column: UserColumn) -> Migration {
+    Migration {
+        version,
+        name: column.as_str(),
+        step: MigrationStep::AddColumn {
+            keyspace: "identity",
+            table: "users",
+            column: ColumnSchema::new(column.as_str(), column.cql_type()),
+        },
+    }
+}
+
+/// Migration represents a single, idempotent change to the schema or data of an existing
+/// keyspace. Each migration is recorded in the identity.schema_migrations table once applied, such
+/// that it is only ever applied once.
+#[derive(Clone, Copy, Debug)]
+pub struct Migration {
+    /// The version of the migration. Migrations are applied in ascending order of version, and no
+    /// two migrations may share a version
+    pub version: u32,
+
+    /// A short, human-readable description of the migration
+    pub name: &'static str,
+
+    /// The change made by the migration
+    pub step: MigrationStep,
+}
+
+/// MigrationStep represents the change made by a migration. Every step must be idempotent, as a
+/// migration interrupted before being recorded is applied again.
+#[derive(Clone, Copy)]
+pub enum MigrationStep {
+    /// Adds a column to an existing table. Tables that don't exist yet are left alone, as they're
+    /// created with the column
+    AddColumn {
+        keyspace: &'static str,
+        table: &'static str,
+        column: ColumnSchema,
+    },
+
+    /// Executes an arbitrary CQL statement
+    Statement(&'static str),
+
+    /// Backfills existing records (e.g., populating a newly added column)
+    Backfill(for<'a> fn(&'a Scylla) -> BoxFuture<'a, IdentityResult<()>>),
+}
+
+impl fmt::Debug for MigrationStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddColumn {
+                keyspace,
+                table,
+                column,
+            } => f
+                .debug_struct("AddColumn")
+                .field("keyspace", keyspace)
+                .field("table", table)
+                .field("column", column)
+                .finish(),
+            Self::Statement(statement) => f.debug_tuple("Statement").field(statement).finish(),
+            Self::Backfill(_) => f.debug_tuple("Backfill").finish(),
+        }
+    }
+}
+
+/// Creates the table recording which migrations have been applied, unless it already exists.
+///
+/// # Arguments
+///
+/// * `session` - The session that the table should be created via
+pub(crate) async fn create_migrations_table(session: &DbSession) -> IdentityResult<()> {
+    session
+        .query(
+            "
+                CREATE TABLE IF NOT EXISTS identity.schema_migrations (
+                    version INT,
+                    name TEXT,
+                    applied_at TIMESTAMP,
+                    PRIMARY KEY (version)
+                );
+            ",
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))
+}
+
+impl Scylla {
+    /// Applies every migration that has yet to be applied, in ascending order of version,
+    /// returning the versions of the migrations that were applied. Migrations that have already
+    /// been applied are skipped, such that running the same migrations again does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `migrations` - The migrations that should be applied
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async fn example(db: swaply_identity::db::scylla::Scylla) -> swaply_identity::result::IdentityResult<()> {
+    /// use swaply_identity::db::migration::MIGRATIONS;
+    ///
+    /// let applied = db.migrate(MIGRATIONS).await?;
+    /// println!("applied {} migrations", applied.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn migrate(&self, migrations: &[Migration]) -> IdentityResult<Vec<u32>> {
+        let applied = self
+            .run(
+                self.session()
+                    .query("SELECT version FROM identity.schema_migrations;"),
+            )
+            .await?
+            .get_body()?
+            .into_rows()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|row| {
+                let version: i32 = row.get_r_by_name("version")?;
+
+                Ok(version as u32)
+            })
+            .collect::<IdentityResult<Vec<u32>>>()?;
+
+        let mut pending = migrations
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|migration| migration.version);
+
+        let mut newly_applied = Vec::with_capacity(pending.len());
+
+        for migration in pending {
+            self.apply_migration(&migration.step).await?;
+
+            // Another instance may have applied the migration concurrently, in which case it has
+            // already been recorded
+            if self
+                .execute_lwt(
+                    "INSERT INTO identity.schema_migrations (version, name, applied_at) VALUES (?, ?, ?) IF NOT EXISTS;",
+                    cdrs::query_values!(migration.version as i32, migration.name, time::get_time()),
+                )
+                .await?
+            {
+                newly_applied.push(migration.version);
+            }
+        }
+
+        Ok(newly_applied)
+    }
+
+    /// Makes the change described by a step of a migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `step` - The change that should be made
+    async fn apply_migration(&self, step: &MigrationStep) -> IdentityResult<()> {
+        match step {
+            MigrationStep::AddColumn {
+                keyspace,
+                table,
+                column,
+            } => {
+                let existing = table_columns(self.session(), keyspace, table).await?;
+
+                // Tables that have yet to be created are created with the column
+                if existing.is_empty() || existing.iter().any(|(name, _)| name == column.name) {
+                    return Ok(());
+                }
+
+                let res = self
+                    .run(self.session().query(format!(
+                        "ALTER TABLE {}.{} ADD {} {};",
+                        keyspace, table, column.name, column.cql_type
+                    )))
+                    .await;
+
+                // The column may have been added concurrently, which the database reports as an
+                // error
+                match res {
+                    Ok(_) => Ok(()),
+                    Err(e) => {
+                        if table_columns(self.session(), keyspace, table)
+                            .await?
+                            .iter()
+                            .any(|(name, _)| name == column.name)
+                        {
+                            Ok(())
+                        } else {
+                            Err(e)
+                        }
+                    }
+                }
+            }
+            MigrationStep::Statement(statement) => {
+                self.run(self.session().query(*statement)).await.map(|_| ())
+            }
+            MigrationStep::Backfill(backfill) => backfill(self).await,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::{
+        super::{scylla::verify_table_schema, TableSchema},
+        *,
+    };
+    use crate::testing;
+
+    /// A version reserved for migrations applied in tests, far beyond that of any real migration.
+    const TEST_VERSION: u32 = i32::MAX as u32;
+
+    const TEST_MIGRATIONS: &[Migration] = &[Migration {
+        version: TEST_VERSION,
+        name: "add_widgets_colour",
+        step: MigrationStep::AddColumn {
+            keyspace: "identity_migration",
+            table: "widgets",
+            column: ColumnSchema::new("colour", "TEXT"),
+        },
+    }];
+
+    #[tokio::test]
+    async fn test_migrate_add_column() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        db.ensure_schema().await?;
+
+        // The identity keyspace is shared by every test, so the altered table is created in a
+        // keyspace of its own
+        db.session()
+            .query(
+                "
+                    CREATE KEYSPACE IF NOT EXISTS identity_migration
+                        WITH REPLICATION = {
+                            'class': 'SimpleStrategy',
+                            'replication_factor': 1
+                    };
+                ",
+            )
+            .await?;
+        db.session()
+            .query("DROP TABLE IF EXISTS identity_migration.widgets;")
+            .await?;
+        db.session()
+            .query("CREATE TABLE identity_migration.widgets (id UUID, PRIMARY KEY (id));")
+            .await?;
+        db.session()
+            .query_with_values(
+                "DELETE FROM identity.schema_migrations WHERE version = ?;",
+                cdrs::query_values!(TEST_VERSION as i32),
+            )
+            .await?;
+
+        assert_eq!(db.migrate(TEST_MIGRATIONS).await?, vec![TEST_VERSION]);
+
+        // The migration has been recorded, so running it again does nothing
+        assert_eq!(db.migrate(TEST_MIGRATIONS).await?, Vec::<u32>::new());
+
+        verify_table_schema(
+            db.session(),
+            &TableSchema {
+                keyspace: "identity_migration",
+                name: "widgets",
+                columns: vec![
+                    ColumnSchema::new("id", "UUID"),
+                    ColumnSchema::new("colour", "TEXT"),
+                ],
+                partition_key: vec!["id"],
+                clustering_key: Vec::new(),
+            },
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrate_baseline_users() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        db.ensure_schema().await?;
+
+        // The users table as it was first deployed, in a keyspace of its own
+        db.session()
+            .query(
+                "
+                    CREATE KEYSPACE IF NOT EXISTS identity_baseline
+                        WITH REPLICATION = {
+                            'class': 'SimpleStrategy',
+                            'replication_factor': 1
+                    };
+                ",
+            )
+            .await?;
+        db.session()
+            .query("DROP TABLE IF EXISTS identity_baseline.users;")
+            .await?;
+        db.session()
+            .query(
+                "
+                    CREATE TABLE identity_baseline.users (
+                        id UUID,
+                        username TEXT,
+                        email TEXT,
+                        password_hash TEXT,
+                        registered_at TIMESTAMP,
+                        PRIMARY KEY (id)
+                    );
+                ",
+            )
+            .await?;
+
+        // Every column added to the users table is added to the baseline table instead
+        for migration in MIGRATIONS {
+            if let MigrationStep::AddColumn {
+                table: "users",
+                column,
+                ..
+            } = migration.step
+            {
+                db.apply_migration(&MigrationStep::AddColumn {
+                    keyspace: "identity_baseline",
+                    table: "users",
+                    column,
+                })
+                .await?;
+            }
+        }
+
+        let columns = table_columns(db.session(), "identity_baseline", "users").await?;
+        for column in UserColumn::ALL.iter() {
+            assert!(
+                columns.iter().any(|(name, _)| name == column.as_str()),
+                "no migration adds the {} column",
+                column.as_str()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrations_ordered() {
+        assert!(MIGRATIONS
+            .windows(2)
+            .all(|pair| pair[0].version < pair[1].version));
+    }
+}
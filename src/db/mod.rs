@@ -1,5 +1,15 @@
-use super::{error::IdentityError, result::IdentityResult};
+use super::{
+    error::{IdentityError, QueryError},
+    events::EventSink,
+    result::IdentityResult,
+};
 
+use std::fmt;
+
+pub mod cache;
+pub mod config;
+pub mod migration;
+pub mod read_only;
 pub mod scylla;
 
 /// Provider represents any provider of long-term user information (e.g., redis, scylla).
@@ -26,6 +36,19 @@ pub trait Provider<Db, Session> {
         q: &K,
     ) -> IdentityResult<V>;
 
+    /// Loads every database record matching a query into a set of struct instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `q` - The query that should be executed on the provider.
+    async fn load_records<
+        K: Queryable<Db, Session> + Send + Sync,
+        V: Deserializable<V, Self::ResponseIntermediary> + Send,
+    >(
+        &self,
+        q: &K,
+    ) -> IdentityResult<Vec<V>>;
+
     /// Inserts a new record into the database.
     ///
     /// # Arguments
@@ -37,6 +60,47 @@ pub trait Provider<Db, Session> {
         &self,
         r: &V,
     ) -> IdentityResult<()>;
+
+    /// Inserts a new record into the database, unless a record with the same primary key already
+    /// exists. Returns whether or not the record was inserted.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - The record that should be inserted into the database
+    async fn insert_if_absent<
+        V: Serializable<Self::RequestIntermediary> + Insertable<Db, Session> + Send + Sync,
+    >(
+        &self,
+        r: &V,
+    ) -> IdentityResult<bool>;
+}
+
+/// LwtOutcome represents the result of a lightweight transaction (i.e., a conditional query):
+/// either its condition held and the query was applied, or it didn't, in which case the existing
+/// data that the query conflicted with is returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LwtOutcome<T> {
+    /// The condition held, and the query was applied
+    Applied,
+
+    /// The condition didn't hold, as a result of the given existing data
+    NotApplied(T),
+}
+
+impl<T> LwtOutcome<T> {
+    /// Determines whether or not the query was applied.
+    pub fn is_applied(&self) -> bool {
+        matches!(self, Self::Applied)
+    }
+
+    /// Consumes the outcome, producing the existing data that the query conflicted with, if it
+    /// wasn't applied.
+    pub fn conflict(self) -> Option<T> {
+        match self {
+            Self::Applied => None,
+            Self::NotApplied(existing) => Some(existing),
+        }
+    }
 }
 
 /// Queryable represents a type that implements a query generator for the respective database
@@ -47,11 +111,56 @@ pub trait Queryable<Db, Session> {
     async fn to_query(&self, session: &Session) -> IdentityResult<String>;
 }
 
+/// Idempotent represents a write operation that declares whether or not applying it several times
+/// has the same effect as applying it once. A write that timed out may or may not have been
+/// applied, so it may only be retried if it is idempotent.
+pub trait Idempotent {
+    /// Determines whether or not the write may safely be applied more than once.
+    fn idempotent(&self) -> bool;
+}
+
 /// Insertable represents a type that implements an insertion query generator for the respective
 /// database provider.
-pub trait Insertable<Db, Session> {
+pub trait Insertable<Db, Session>: Idempotent {
     //// The query that will be used to insert the record struct into the database.
     const INSERTION_QUERY: &'static str;
+
+    /// Notifies a sink that the record was inserted. Sinks only receive the events of users, so
+    /// nothing is reported by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `sink` - The sink that should be notified
+    fn notify_inserted(&self, _sink: &dyn EventSink) {}
+}
+
+/// Updatable represents a type that implements an update query generator for the respective
+/// database provider. The query updates the record identified by the struct's key to match the
+/// struct.
+pub trait Updatable<Db, Session>: Idempotent {
+    /// The query that will be used to update the record in the database.
+    const UPDATE_QUERY: &'static str;
+
+    /// Notifies a sink that the record was updated. Nothing is reported by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `sink` - The sink that should be notified
+    fn notify_updated(&self, _sink: &dyn EventSink) {}
+}
+
+/// Deletable represents a type that implements a deletion query generator for the respective
+/// database provider. The query deletes the record identified by the struct's key.
+pub trait Deletable<Db, Session>: Idempotent {
+    /// The query that will be used to delete the record from the database.
+    const DELETION_QUERY: &'static str;
+
+    /// Notifies a sink that the record was deleted. Nothing is reported by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `sink` - The sink that should be notified
+    fn notify_deleted(&self, _sink: &dyn EventSink) {}
 }
 
 /// Deserializable represents a type that may be converted to from a NativeType defined by a
@@ -80,6 +189,275 @@ pub trait Serializable<DbType> {
 #[async_trait]
 pub trait InTable<Db, Session> {
     /// Creates any keyspaces or tables necessary for the proper usage of the struct that may be
-    /// rperesented in a database.
+    /// rperesented in a database. The struct's own table is created from its table_schema; if it
+    /// already exists, but doesn't match the schema, a QueryError::SchemaMismatch is returned.
     async fn create_prerequisite_objects(session: &Session) -> IdentityResult<()>;
+
+    /// Describes the table that the struct is stored in.
+    fn table_schema() -> TableSchema;
+}
+
+/// ClusteringOrder represents the order in which the rows of a partition are stored by one of
+/// their clustering columns.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ClusteringOrder {
+    Asc,
+    Desc,
+}
+
+impl ClusteringOrder {
+    /// Gets the keyword denoting the order.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Asc => "ASC",
+            Self::Desc => "DESC",
+        }
+    }
+}
+
+/// ColumnSchema represents a single column of a table.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ColumnSchema {
+    /// The name of the column
+    pub name: &'static str,
+
+    /// The CQL type of the column (e.g., "TEXT")
+    pub cql_type: &'static str,
+}
+
+impl ColumnSchema {
+    /// Creates a new description of a column.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the column
+    /// * `cql_type` - The CQL type of the column
+    pub const fn new(name: &'static str, cql_type: &'static str) -> Self {
+        Self { name, cql_type }
+    }
+}
+
+/// SchemaDifference represents a way in which an existing table differs from the schema that it is
+/// expected to have.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SchemaDifference {
+    /// The table lacks a column
+    MissingColumn {
+        column: &'static str,
+        expected: &'static str,
+    },
+
+    /// A column of the table holds a different type than expected
+    WrongType {
+        column: &'static str,
+        expected: &'static str,
+        found: String,
+    },
+}
+
+impl fmt::Display for SchemaDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingColumn { column, expected } => {
+                write!(f, "the {} column ({}) is missing", column, expected)
+            }
+            Self::WrongType {
+                column,
+                expected,
+                found,
+            } => write!(
+                f,
+                "the {} column is {}, rather than {}",
+                column,
+                found.to_uppercase(),
+                expected
+            ),
+        }
+    }
+}
+
+/// TableSchema represents the structure of a table, as expected by the type stored in it: its
+/// columns, and the columns making up its primary key.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TableSchema {
+    /// The keyspace that the table belongs to
+    pub keyspace: &'static str,
+
+    /// The name of the table, excluding its keyspace
+    pub name: &'static str,
+
+    /// Every column of the table, in the order that they're declared
+    pub columns: Vec<ColumnSchema>,
+
+    /// The columns that rows are partitioned by
+    pub partition_key: Vec<&'static str>,
+
+    /// The columns that rows are ordered by within their partition, and the order of each
+    pub clustering_key: Vec<(&'static str, ClusteringOrder)>,
+}
+
+impl TableSchema {
+    /// Gets the description of a column of the table, if the table has such a column.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the column
+    pub fn column(&self, name: &str) -> Option<&ColumnSchema> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    /// Compares the columns of an existing table against the schema, reporting every expected
+    /// column that the table lacks or that holds a different type. Columns that aren't part of
+    /// the schema are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `existing` - The name and CQL type of each column of the existing table
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::db::{ColumnSchema, SchemaDifference, TableSchema};
+    ///
+    /// let schema = TableSchema {
+    ///     keyspace: "identity",
+    ///     name: "emails",
+    ///     columns: vec![ColumnSchema::new("email", "TEXT"), ColumnSchema::new("user_id", "UUID")],
+    ///     partition_key: vec!["email"],
+    ///     clustering_key: vec![],
+    /// };
+    /// assert_eq!(
+    ///     schema.differences(&[("email".to_owned(), "text".to_owned())]),
+    ///     vec![SchemaDifference::MissingColumn { column: "user_id", expected: "UUID" }]
+    /// );
+    /// ```
+    pub fn differences(&self, existing: &[(String, String)]) -> Vec<SchemaDifference> {
+        self.columns
+            .iter()
+            .filter_map(|column| {
+                match existing.iter().find(|(name, _)| name == column.name) {
+                    None => Some(SchemaDifference::MissingColumn {
+                        column: column.name,
+                        expected: column.cql_type,
+                    }),
+                    // Types are reported in lowercase by the database
+                    Some((_, found)) if !found.eq_ignore_ascii_case(column.cql_type) => {
+                        Some(SchemaDifference::WrongType {
+                            column: column.name,
+                            expected: column.cql_type,
+                            found: found.to_owned(),
+                        })
+                    }
+                    Some(_) => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Generates the clause ordering the rows of a partition of the table by a column. Rows may
+    /// only be ordered by the first column of the table's clustering key, in either direction;
+    /// any other ordering is reported as a QueryError::UnsupportedOrdering.
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - The column that rows should be ordered by
+    /// * `order` - The direction in which rows should be ordered
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::db::{ClusteringOrder, ColumnSchema, TableSchema};
+    ///
+    /// let schema = TableSchema {
+    ///     keyspace: "identity",
+    ///     name: "username_history",
+    ///     columns: vec![
+    ///         ColumnSchema::new("user_id", "UUID"),
+    ///         ColumnSchema::new("changed_at", "TIMESTAMP"),
+    ///     ],
+    ///     partition_key: vec!["user_id"],
+    ///     clustering_key: vec![("changed_at", ClusteringOrder::Asc)],
+    /// };
+    /// assert_eq!(
+    ///     schema.order_by("changed_at", ClusteringOrder::Desc).unwrap(),
+    ///     " ORDER BY changed_at DESC"
+    /// );
+    /// assert!(schema.order_by("user_id", ClusteringOrder::Asc).is_err());
+    /// ```
+    pub fn order_by(
+        &self,
+        column: &'static str,
+        order: ClusteringOrder,
+    ) -> Result<String, QueryError> {
+        match self.clustering_key.first() {
+            Some((first, _)) if *first == column => {
+                Ok(format!(" ORDER BY {} {}", column, order.as_str()))
+            }
+            _ => Err(QueryError::UnsupportedOrdering {
+                table: format!("{}.{}", self.keyspace, self.name),
+                column,
+            }),
+        }
+    }
+
+    /// Generates the statement creating the table, unless it already exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::db::{ColumnSchema, TableSchema};
+    ///
+    /// let schema = TableSchema {
+    ///     keyspace: "identity",
+    ///     name: "emails",
+    ///     columns: vec![ColumnSchema::new("email", "TEXT"), ColumnSchema::new("user_id", "UUID")],
+    ///     partition_key: vec!["email"],
+    ///     clustering_key: vec![],
+    /// };
+    /// assert_eq!(
+    ///     schema.create_statement(),
+    ///     "CREATE TABLE IF NOT EXISTS identity.emails (email TEXT, user_id UUID, PRIMARY KEY (email));"
+    /// );
+    /// ```
+    pub fn create_statement(&self) -> String {
+        let partition_key = match self.partition_key.as_slice() {
+            [column] => (*column).to_owned(),
+            columns => format!("({})", columns.join(", ")),
+        };
+        let primary_key = std::iter::once(partition_key)
+            .chain(
+                self.clustering_key
+                    .iter()
+                    .map(|(column, _)| (*column).to_owned()),
+            )
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let mut statement = format!(
+            "CREATE TABLE IF NOT EXISTS {}.{} ({}, PRIMARY KEY ({}))",
+            self.keyspace,
+            self.name,
+            self.columns
+                .iter()
+                .map(|column| format!("{} {}", column.name, column.cql_type))
+                .collect::<Vec<String>>()
+                .join(", "),
+            primary_key
+        );
+
+        if !self.clustering_key.is_empty() {
+            statement.push_str(&format!(
+                " WITH CLUSTERING ORDER BY ({})",
+                self.clustering_key
+                    .iter()
+                    .map(|(column, order)| format!("{} {}", column, order.as_str()))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+
+        statement.push(';');
+
+        statement
+    }
 }
@@ -0,0 +1,178 @@
+use futures::stream::Stream;
+use uuid::Uuid;
+
+use super::{
+    super::{
+        result::IdentityResult,
+        schema::user::{OwnedUser, UserOrder},
+        DbSession,
+    },
+    scylla::Scylla,
+    Deserializable, Provider, Queryable,
+};
+
+/// ReadOnly represents a view of a provider through which records may only be read, for use by
+/// components that must never write (e.g., reporting services reading from a replica). Writes
+/// aren't refused at runtime; the view simply doesn't expose them, and neither does it expose the
+/// wrapped provider.
+///
+/// # Examples
+///
+/// Records may be loaded through the view:
+///
+/// ```no_run
+/// # async fn example(db: swaply_identity::db::scylla::Scylla) -> swaply_identity::result::IdentityResult<()> {
+/// use swaply_identity::{
+///     db::read_only::ReadOnly,
+///     schema::user::{OwnedUser, UserQuery},
+/// };
+/// use uuid::Uuid;
+///
+/// let read_only = ReadOnly::new(db);
+/// let u: OwnedUser = read_only.load_record(&UserQuery::Id(&Uuid::new_v4())).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// But inserting a record through the view doesn't compile:
+///
+/// ```compile_fail
+/// # async fn example(db: swaply_identity::db::scylla::Scylla) -> swaply_identity::result::IdentityResult<()> {
+/// use swaply_identity::{
+///     db::{read_only::ReadOnly, Provider},
+///     schema::user::User,
+/// };
+///
+/// let read_only = ReadOnly::new(db);
+/// let u = User::new(None, "alice", "alice@example.com", None, None);
+/// read_only.insert_record(&u).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ReadOnly<P> {
+    inner: P,
+}
+
+impl<P> ReadOnly<P> {
+    /// Wraps a provider in a view that may only read from it.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The provider that should be read from
+    pub fn new(inner: P) -> Self {
+        Self { inner }
+    }
+}
+
+impl<P: Provider<Scylla, DbSession> + Send + Sync> ReadOnly<P> {
+    /// Loads a database record into a struct instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `q` - The query that should be executed on the provider
+    pub async fn load_record<
+        K: Queryable<Scylla, DbSession> + Send + Sync,
+        V: Deserializable<V, P::ResponseIntermediary> + Send,
+    >(
+        &self,
+        q: &K,
+    ) -> IdentityResult<V> {
+        self.inner.load_record(q).await
+    }
+
+    /// Loads every database record matching a query into a set of struct instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `q` - The query that should be executed on the provider
+    pub async fn load_records<
+        K: Queryable<Scylla, DbSession> + Send + Sync,
+        V: Deserializable<V, P::ResponseIntermediary> + Send,
+    >(
+        &self,
+        q: &K,
+    ) -> IdentityResult<Vec<V>> {
+        self.inner.load_records(q).await
+    }
+}
+
+impl ReadOnly<Scylla> {
+    /// Streams every user, fetching users from the database one page at a time as the stream is
+    /// consumed.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order in which users should be streamed, if any
+    pub fn stream_users(
+        &self,
+        order: Option<UserOrder>,
+    ) -> impl Stream<Item = IdentityResult<OwnedUser>> + '_ {
+        self.inner.stream_users(order)
+    }
+
+    /// Loads every user, fetching users from the database one page at a time. Prefer
+    /// stream_users for large tables.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order in which users should be loaded, if any
+    pub async fn list_users(&self, order: Option<UserOrder>) -> IdentityResult<Vec<OwnedUser>> {
+        self.inner.list_users(order).await
+    }
+
+    /// Finds the users whose usernames start with a prefix, ordered by their username.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The prefix that matching usernames start with
+    /// * `limit` - The maximum number of users that should be found
+    /// * `order` - The order in which users should be returned
+    pub async fn search_usernames(
+        &self,
+        prefix: &str,
+        limit: usize,
+        order: UserOrder,
+    ) -> IdentityResult<Vec<OwnedUser>> {
+        self.inner.search_usernames(prefix, limit, order).await
+    }
+
+    /// Gets the ID of the user holding a username, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The normalized username whose holder should be found
+    pub async fn username_owner(&self, username: &str) -> IdentityResult<Option<Uuid>> {
+        self.inner.username_owner(username).await
+    }
+
+    /// Gets the ID of the user holding an email address, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The normalized email address whose holder should be found
+    pub async fn email_owner(&self, email: &str) -> IdentityResult<Option<Uuid>> {
+        self.inner.email_owner(email).await
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::{schema::user::UserQuery, testing};
+
+    #[tokio::test]
+    async fn test_read_only_load_record() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        db.ensure_schema().await?;
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+
+        let read_only = ReadOnly::new(db);
+        let loaded_u: OwnedUser = read_only.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u, u);
+
+        Ok(())
+    }
+}
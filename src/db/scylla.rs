@@ -1,22 +1,136 @@
 use cdrs::{
+    consistency::Consistency,
     error::Error as CDRSError,
-    query::{QueryExecutor, QueryValues},
-    types::rows::Row,
+    frame::{frame_batch::BatchType, frame_error::CDRSError as ServerError},
+    query::{BatchExecutor, BatchQueryBuilder, QueryExecutor, QueryParamsBuilder, QueryValues},
+    types::{rows::Row, CBytes, IntoRustByName},
 };
+use futures::{
+    future,
+    stream::{self, Stream, StreamExt, TryStreamExt},
+};
+use tokio::time;
+use tracing::Instrument;
+use uuid::Uuid;
 
 use super::{
     super::{
-        error::{IdentityError, QueryError},
+        crypto::{
+            password::PasswordHasher,
+            token::{EncryptionError, TokenKey},
+        },
+        error::{self, IdentityError, QueryError},
+        events::{EventSink, NoopSink},
         result::IdentityResult,
+        schema::{
+            audit::AuditEvent,
+            provider_identity::ProviderIdentity,
+            session::LoginSession,
+            token::OwnedProviderToken,
+            user::{LockoutPolicy, User},
+            user_email::UserEmail,
+            username_history::UsernameChange,
+        },
         DbSession,
     },
-    Deserializable, Insertable, Provider, Queryable, Serializable,
+    config::DEFAULT_KEYSPACE,
+    migration::{create_migrations_table, MIGRATIONS},
+    Deletable, Deserializable, Idempotent, InTable, Insertable, LwtOutcome, Provider, Queryable,
+    Serializable, TableSchema, Updatable,
+};
+
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
+/// The number of rows fetched per page by default when paging through a table.
+pub const DEFAULT_PAGE_SIZE: i32 = 500;
+
+/// How often a connector that is shutting down checks whether its operations have completed.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+tokio::task_local! {
+    /// The ID of the request being served by the current task, if it is running within
+    /// Scylla::in_request
+    static REQUEST_ID: Uuid;
+
+    /// Set while the current task is running an operation admitted by Scylla::operation
+    static IN_OPERATION: ();
+}
+
+/// Gets the ID of the request being served by the current task, if it is running within
+/// Scylla::in_request.
+pub fn current_request_id() -> Option<Uuid> {
+    REQUEST_ID.try_with(|request_id| *request_id).ok()
+}
+
+/// RetryPolicy represents the rules by which writes that fail transiently are retried. The
+/// default policy retries a write twice, waiting 50 milliseconds before each retry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of times that a failed write is retried
+    pub max_retries: u32,
+
+    /// The amount of time waited before each retry
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            backoff: Duration::from_millis(50),
+        }
+    }
+}
+
 /// Scylla represents a connector capable of loading and inserting struct data via scylladb.
 #[derive(Debug)]
 pub struct Scylla {
     session: DbSession,
+
+    /// The key used to encrypt and decrypt provider tokens, if any
+    token_key: Option<TokenKey>,
+
+    /// The maximum amount of time that any one operation may take before being aborted, if any
+    timeout: Option<Duration>,
+
+    /// The scheme used to hash and verify user passwords
+    password_hasher: PasswordHasher,
+
+    /// The number of rows fetched per page when paging through a table
+    page_size: i32,
+
+    /// Whether or not writes that are immediately read back are made at QUORUM consistency
+    read_your_writes: bool,
+
+    /// The rules by which users that repeatedly fail to authenticate are locked out
+    lockout_policy: LockoutPolicy,
+
+    /// Whether or not users must verify their email address before they may authenticate
+    require_verified_email: bool,
+
+    /// The rules by which writes that fail transiently are retried
+    retry_policy: RetryPolicy,
+
+    /// The maximum number of rows that a query loading or streaming many records may match, if
+    /// any
+    max_rows: Option<usize>,
+
+    /// The sink notified of changes made to users
+    event_sink: Arc<dyn EventSink>,
+
+    /// The number of operations currently being run by the connector
+    in_flight: AtomicUsize,
+
+    /// Whether or not the connector has been shut down, and no longer accepts new operations
+    shut_down: AtomicBool,
 }
 
 impl Scylla {
@@ -26,7 +140,670 @@ impl Scylla {
     ///
     /// * `session` - The database session that should be used for database operation
     pub fn new(session: DbSession) -> Self {
-        Self { session }
+        Self {
+            session,
+            token_key: None,
+            timeout: None,
+            password_hasher: PasswordHasher::default(),
+            page_size: DEFAULT_PAGE_SIZE,
+            read_your_writes: false,
+            lockout_policy: LockoutPolicy::default(),
+            require_verified_email: false,
+            retry_policy: RetryPolicy::default(),
+            max_rows: None,
+            event_sink: Arc::new(NoopSink),
+            in_flight: AtomicUsize::new(0),
+            shut_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Configures the key used to encrypt provider tokens before they are written to the
+    /// database, and to decrypt them once they are read.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The AES-256-GCM key that should be used for token encryption
+    pub fn with_token_key(mut self, key: TokenKey) -> Self {
+        self.token_key = Some(key);
+
+        self
+    }
+
+    /// Configures the maximum amount of time that any one database operation may take before it
+    /// is aborted with an IdentityError::Timeout. By default, operations may take indefinitely.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The maximum duration of a database operation
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+
+        self
+    }
+
+    /// Configures the scheme used to hash and verify user passwords. By default, passwords are
+    /// hashed with an unpeppered PasswordHasher using the crate's default context string.
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - The password hasher that should be used
+    pub fn with_password_hasher(mut self, hasher: PasswordHasher) -> Self {
+        self.password_hasher = hasher;
+
+        self
+    }
+
+    /// Configures the number of rows fetched per page when paging through a table (e.g., when
+    /// listing users). By default, DEFAULT_PAGE_SIZE rows are fetched per page.
+    ///
+    /// # Arguments
+    ///
+    /// * `page_size` - The number of rows that should be fetched per page
+    pub fn with_page_size(mut self, page_size: i32) -> Self {
+        self.page_size = page_size;
+
+        self
+    }
+
+    /// Configures whether or not a registration is guaranteed to be visible to the read that
+    /// immediately follows it. If enabled, both the write and the read are made at QUORUM
+    /// consistency, rather than ONE, at the expense of latency and availability.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether or not registrations should be read-your-writes consistent
+    pub fn with_read_your_writes(mut self, enabled: bool) -> Self {
+        self.read_your_writes = enabled;
+
+        self
+    }
+
+    /// Configures the rules by which users that repeatedly fail to authenticate via
+    /// Scylla::authenticate or authenticate_by_username are locked out. By default, the default
+    /// LockoutPolicy is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The lockout policy that should be used
+    pub fn with_lockout_policy(mut self, policy: LockoutPolicy) -> Self {
+        self.lockout_policy = policy;
+
+        self
+    }
+
+    /// Gets the rules by which users that repeatedly fail to authenticate are locked out.
+    pub fn lockout_policy(&self) -> &LockoutPolicy {
+        &self.lockout_policy
+    }
+
+    /// Configures whether or not users must verify their email address before they may
+    /// authenticate via Scylla::authenticate or authenticate_by_username. By default, unverified
+    /// users may authenticate.
+    ///
+    /// # Arguments
+    ///
+    /// * `required` - Whether or not a verified email address should be required
+    pub fn with_verified_email_required(mut self, required: bool) -> Self {
+        self.require_verified_email = required;
+
+        self
+    }
+
+    /// Determines whether or not users must verify their email address before they may
+    /// authenticate.
+    pub fn verified_email_required(&self) -> bool {
+        self.require_verified_email
+    }
+
+    /// Configures the rules by which writes that fail transiently (e.g., because too few replicas
+    /// were available) are retried. Writes that time out are only retried if they're idempotent.
+    /// By default, the default RetryPolicy is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The retry policy that should be used
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+
+        self
+    }
+
+    /// Configures the sink notified once users are created, updated, or deleted through the
+    /// connector. By default, no sink is notified.
+    ///
+    /// # Arguments
+    ///
+    /// * `sink` - The sink that should be notified of changes made to users
+    pub fn with_event_sink(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.event_sink = sink;
+
+        self
+    }
+
+    /// Gets the sink notified of changes made to users.
+    pub fn event_sink(&self) -> &dyn EventSink {
+        self.event_sink.as_ref()
+    }
+
+    /// Configures the maximum number of rows that a query loading or streaming many records
+    /// (e.g., listing or filtering users) may match. Queries matching more rows fail with a
+    /// QueryError::ResultTooLarge, rather than buffering every row. By default, queries may match
+    /// any number of rows.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_rows` - The maximum number of rows that a query may match
+    pub fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = Some(max_rows);
+
+        self
+    }
+
+    /// Gets the maximum number of rows that a query loading or streaming many records may match,
+    /// if any.
+    pub fn max_rows(&self) -> Option<usize> {
+        self.max_rows
+    }
+
+    /// Fails with a QueryError::ResultTooLarge if the given number of rows exceeds the
+    /// connector's maximum result size.
+    ///
+    /// # Arguments
+    ///
+    /// * `rows` - The number of rows matched by a query so far
+    pub(crate) fn check_result_size(&self, rows: usize) -> IdentityResult<()> {
+        match self.max_rows {
+            Some(limit) if rows > limit => Err(QueryError::ResultTooLarge { limit }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets the consistency level at which writes that are immediately read back, and those
+    /// reads, are made.
+    pub(crate) fn read_your_writes_consistency(&self) -> Consistency {
+        if self.read_your_writes {
+            Consistency::Quorum
+        } else {
+            Consistency::One
+        }
+    }
+
+    /// Gets the scheme used by the connector to hash and verify user passwords.
+    pub fn password_hasher(&self) -> &PasswordHasher {
+        &self.password_hasher
+    }
+
+    /// Starts a batch of statements that are applied atomically once executed.
+    pub fn batch(&self) -> Batch<'_> {
+        Batch {
+            db: self,
+            statements: Ok(Vec::new()),
+            len: 0,
+            idempotent: true,
+            notifications: Vec::new(),
+        }
+    }
+
+    /// Creates the identity keyspace, and every table and index used by the connector, if they
+    /// don't already exist. Existing tables are migrated to the schema expected of them first.
+    pub async fn ensure_schema(&self) -> IdentityResult<()> {
+        crate::create_keyspace(&self.session).await?;
+
+        create_migrations_table(&self.session).await?;
+        self.migrate(MIGRATIONS).await?;
+
+        User::create_prerequisite_objects(&self.session).await?;
+        UsernameChange::create_prerequisite_objects(&self.session).await?;
+        OwnedProviderToken::create_prerequisite_objects(&self.session).await?;
+        ProviderIdentity::create_prerequisite_objects(&self.session).await?;
+        AuditEvent::create_prerequisite_objects(&self.session).await?;
+        LoginSession::create_prerequisite_objects(&self.session).await?;
+        UserEmail::create_prerequisite_objects(&self.session).await
+    }
+
+    /// Runs a database operation, aborting it if it doesn't complete within the connector's
+    /// configured timeout. Fails with an IdentityError::ShutDown once the connector has been shut
+    /// down, unless the database operation is a step of an operation admitted by
+    /// Scylla::operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The operation that should be run
+    pub(crate) async fn run<T, E: Into<IdentityError>>(
+        &self,
+        op: impl Future<Output = Result<T, E>>,
+    ) -> IdentityResult<T> {
+        // The operation is counted before the connector is checked, such that shutdown never
+        // misses an operation that it didn't refuse
+        let _in_flight = InFlight::enter(&self.in_flight);
+
+        // The remaining steps of an admitted operation are never refused
+        if self.is_shut_down() && IN_OPERATION.try_with(|_| ()).is_err() {
+            return Err(IdentityError::ShutDown);
+        }
+
+        let span = match current_request_id() {
+            Some(request_id) => tracing::debug_span!("identity_query", %request_id),
+            None => tracing::debug_span!("identity_query"),
+        };
+
+        match deadline(self.timeout, op).instrument(span).await {
+            Err(IdentityError::CDRSError(CDRSError::Server(e))) if error::is_invalid(&e) => {
+                Err(self.diagnose(e, DEFAULT_KEYSPACE).await)
+            }
+            res => res,
+        }
+    }
+
+    /// Determines whether a query that the server rejected as invalid failed because of the state
+    /// of the schema, rather than the query itself, by consulting the schema tables instead of the
+    /// server's message. Returns a QueryError::KeyspaceMissing if the keyspace doesn't exist, a
+    /// QueryError::IndexUnavailable if one of its secondary indexes has yet to be built, or the
+    /// server's error otherwise, including if the schema tables can't be read.
+    ///
+    /// # Arguments
+    ///
+    /// * `e` - The error returned by the server
+    /// * `keyspace` - The keyspace that the query referred to
+    pub(crate) async fn diagnose(&self, e: ServerError, keyspace: &str) -> IdentityError {
+        match keyspace_exists(&self.session, keyspace).await {
+            Ok(false) => return QueryError::KeyspaceMissing.into(),
+            Ok(true) => (),
+            Err(_) => return IdentityError::CDRSError(CDRSError::Server(e)),
+        }
+
+        match unbuilt_indexes(&self.session, keyspace).await {
+            Ok(unbuilt) if !unbuilt.is_empty() => QueryError::IndexUnavailable.into(),
+            _ => IdentityError::CDRSError(CDRSError::Server(e)),
+        }
+    }
+
+    /// Runs one or more operations of the connector as part of a single request, such that they
+    /// may be correlated with one another. The request's ID is attached to the tracing span of
+    /// every query made, and to any error returned, as an IdentityError::InRequest whose Display
+    /// includes the ID. Errors returned by operations run outside of any request carry no ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_id` - The ID of the request, as supplied by the caller, or None if one should
+    /// be generated
+    /// * `op` - The operations that should be run as part of the request
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async fn example(db: swaply_identity::db::scylla::Scylla) {
+    /// use swaply_identity::{
+    ///     db::Provider,
+    ///     schema::user::{OwnedUser, UserQuery},
+    /// };
+    /// use uuid::Uuid;
+    ///
+    /// let (request_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+    ///
+    /// let res: Result<OwnedUser, _> = db
+    ///     .in_request(Some(request_id), async {
+    ///         db.load_record(&UserQuery::Id(&user_id)).await
+    ///     })
+    ///     .await;
+    /// if let Err(e) = res {
+    ///     assert_eq!(e.request_id(), Some(&request_id));
+    /// }
+    /// # }
+    /// ```
+    pub async fn in_request<T>(
+        &self,
+        request_id: Option<Uuid>,
+        op: impl Future<Output = IdentityResult<T>>,
+    ) -> IdentityResult<T> {
+        let request_id = request_id.unwrap_or_else(Uuid::new_v4);
+        let span = tracing::debug_span!("identity_request", %request_id);
+
+        REQUEST_ID
+            .scope(request_id, op)
+            .instrument(span)
+            .await
+            .map_err(|e| {
+                tracing::debug!(%request_id, error = %e, "identity request failed");
+
+                e.in_request(request_id)
+            })
+    }
+
+    /// Runs an operation made up of several queries as a whole, counting it as in flight until
+    /// every one of its queries has completed. Once the operation is admitted, its queries are run
+    /// even if the connector is shut down partway, such that shutdown never abandons it between
+    /// steps (e.g., after reserving a username, but before inserting the user or releasing the
+    /// username). Fails with an IdentityError::ShutDown if the connector has been shut down
+    /// before the operation is admitted.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The operation that should be run
+    pub(crate) async fn operation<T>(
+        &self,
+        op: impl Future<Output = IdentityResult<T>>,
+    ) -> IdentityResult<T> {
+        let _in_flight = InFlight::enter(&self.in_flight);
+
+        if IN_OPERATION.try_with(|_| ()).is_ok() {
+            return op.await;
+        }
+
+        if self.is_shut_down() {
+            return Err(IdentityError::ShutDown);
+        }
+
+        IN_OPERATION.scope((), op).await
+    }
+
+    /// Shuts the connector down: new operations are refused with an IdentityError::ShutDown, and
+    /// operations already in flight are awaited, such that no write is abandoned partway. Fails
+    /// with an IdentityError::Timeout if operations are still in flight once the timeout elapses,
+    /// in which case they continue to run. The session is closed once the connector is dropped.
+    ///
+    /// Since operations borrow the connector, shutdown is typically requested by a task sharing
+    /// the connector with those serving requests (e.g., via an Arc), upon receiving a signal.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The maximum amount of time to wait for operations in flight to complete
+    pub async fn shutdown(&self, timeout: Duration) -> IdentityResult<()> {
+        self.shut_down.store(true, Ordering::SeqCst);
+
+        time::timeout(timeout, async {
+            while self.in_flight.load(Ordering::SeqCst) > 0 {
+                time::delay_for(DRAIN_POLL_INTERVAL).await;
+            }
+        })
+        .await
+        .map_err(|_| IdentityError::Timeout)
+    }
+
+    /// Determines whether or not the connector has been shut down.
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down.load(Ordering::SeqCst)
+    }
+
+    /// Runs a write, retrying it according to the connector's retry policy for as long as it fails
+    /// transiently. Each attempt is aborted if it doesn't complete within the connector's
+    /// configured timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `idempotent` - Whether or not the write may safely be applied more than once, as declared
+    /// by Idempotent::idempotent, and thus retried after timing out
+    /// * `op` - Produces an attempt at the write
+    pub(crate) async fn run_write<T, E, F, Fut>(
+        &self,
+        idempotent: bool,
+        mut op: F,
+    ) -> IdentityResult<T>
+    where
+        E: Into<IdentityError>,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        retry(&self.retry_policy, idempotent, move || self.run(op())).await
+    }
+
+    /// Gets the database session used by the connector.
+    pub(crate) fn session(&self) -> &DbSession {
+        &self.session
+    }
+
+    /// Executes a query with the given values, disregarding its results.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query that should be executed
+    /// * `values` - The values that should be bound to the query
+    pub(crate) async fn execute(&self, query: &str, values: QueryValues) -> IdentityResult<()> {
+        self.run(self.session.query_with_values(query, values))
+            .await
+            .map(|_| ())
+    }
+
+    /// Executes a lightweight transaction (i.e., a conditional query), returning whether or not
+    /// its condition held and the query was applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The conditional query that should be executed
+    /// * `values` - The values that should be bound to the query
+    pub(crate) async fn execute_lwt(
+        &self,
+        query: &str,
+        values: QueryValues,
+    ) -> IdentityResult<bool> {
+        self.execute_lwt_outcome(query, values)
+            .await
+            .map(|outcome| outcome.is_applied())
+    }
+
+    /// Executes a lightweight transaction (i.e., a conditional query), returning the existing row
+    /// that the query conflicted with if its condition didn't hold.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The conditional query that should be executed
+    /// * `values` - The values that should be bound to the query
+    pub(crate) async fn execute_lwt_outcome(
+        &self,
+        query: &str,
+        values: QueryValues,
+    ) -> IdentityResult<LwtOutcome<Row>> {
+        // A conditional write that timed out may have been applied, in which case its condition
+        // no longer holds, so it's never idempotent
+        let row = self
+            .run_write(false, move || {
+                self.session.query_with_values(query, values.clone())
+            })
+            .await
+            .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+            .and_then(|resp| resp.into_rows().ok_or(QueryError::NoResults.into()))
+            .and_then(|rows| rows.into_iter().next().ok_or(QueryError::NoResults.into()))?;
+
+        // Scylla reports whether the transaction's condition held in an [applied] column, followed
+        // by the existing values of the row if it didn't
+        if row.get_r_by_name("[applied]")? {
+            Ok(LwtOutcome::Applied)
+        } else {
+            Ok(LwtOutcome::NotApplied(row))
+        }
+    }
+
+    /// Executes a batch of conditional statements, all of which must concern the same partition,
+    /// returning whether or not every condition held and the batch was applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch` - The conditional statements that should be executed
+    pub(crate) async fn execute_lwt_batch(&self, batch: BatchQueryBuilder) -> IdentityResult<bool> {
+        self.run(self.session.batch_with_params(batch.finalize()?))
+            .await
+            .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+            .and_then(|resp| resp.into_rows().ok_or(QueryError::NoResults.into()))
+            .and_then(|rows| rows.into_iter().next().ok_or(QueryError::NoResults.into()))
+            .and_then(|row| row.get_r_by_name("[applied]").map_err(|e| e.into()))
+    }
+
+    /// Loads the first record matching a query, reading at the given consistency level rather
+    /// than the session's default.
+    ///
+    /// # Arguments
+    ///
+    /// * `q` - The query that should be executed
+    /// * `consistency` - The consistency level at which the record should be read
+    pub(crate) async fn load_record_at<
+        K: Queryable<Self, DbSession> + Send + Sync,
+        V: Deserializable<V, Row> + Send,
+    >(
+        &self,
+        q: &K,
+        consistency: Consistency,
+    ) -> IdentityResult<V> {
+        let params = QueryParamsBuilder::new()
+            .consistency(consistency)
+            .finalize();
+
+        self.run(
+            self.session
+                .query_with_params(q.to_query(&self.session).await?, params),
+        )
+        .await
+        .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+        .and_then(|resp| resp.into_rows().ok_or(QueryError::NoResults.into()))
+        .and_then(|rows| rows.into_iter().next().ok_or(QueryError::NoResults.into()))
+        .and_then(|row| V::try_from(row).map_err(|e| e.into()))
+    }
+
+    /// Inserts a record, writing at the given consistency level rather than the session's
+    /// default.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - The record that should be inserted
+    /// * `consistency` - The consistency level at which the record should be written
+    pub(crate) async fn insert_record_at<
+        V: Serializable<QueryValues> + Insertable<Self, DbSession> + Send + Sync,
+    >(
+        &self,
+        r: &V,
+        consistency: Consistency,
+    ) -> IdentityResult<()> {
+        let values = <V as Serializable<QueryValues>>::try_into(r).map_err(|e| e.into())?;
+
+        self.run_write(r.idempotent(), move || {
+            let params = QueryParamsBuilder::new()
+                .values(values.clone())
+                .consistency(consistency)
+                .finalize();
+
+            self.session.query_with_params(V::INSERTION_QUERY, params)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Fetches a single page of the results of a query, returning the page's rows and the state
+    /// from which the next page may be fetched, if there are any more pages.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query whose results should be fetched
+    /// * `paging_state` - The state returned alongside the previous page, if any
+    pub(crate) async fn query_page(
+        &self,
+        query: &str,
+        paging_state: Option<CBytes>,
+    ) -> IdentityResult<(Vec<Row>, Option<CBytes>)> {
+        let params = match paging_state {
+            Some(state) => QueryParamsBuilder::new()
+                .page_size(self.page_size)
+                .paging_state(state),
+            None => QueryParamsBuilder::new().page_size(self.page_size),
+        };
+
+        let body = self
+            .run(self.session.query_with_params(query, params.finalize()))
+            .await
+            .and_then(|frame| frame.get_body().map_err(|e| e.into()))?;
+        let next_state = body
+            .as_rows_metadata()
+            .and_then(|metadata| metadata.paging_state);
+
+        Ok((body.into_rows().unwrap_or_default(), next_state))
+    }
+
+    /// Streams every row matching a query, fetching rows from the database one page at a time as
+    /// the stream is consumed. If the query matches more rows than the connector's maximum result
+    /// size, the stream ends with a QueryError::ResultTooLarge.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query whose rows should be streamed
+    pub(crate) fn stream_rows(
+        &self,
+        query: impl Into<String>,
+    ) -> impl Stream<Item = IdentityResult<Row>> + '_ {
+        self.limit_rows(self.stream_pages(query.into()))
+    }
+
+    /// Streams every row matching a query, fetching rows from the database one page at a time as
+    /// the stream is consumed, regardless of the connector's maximum result size.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query whose rows should be streamed
+    pub(crate) fn stream_pages(
+        &self,
+        query: String,
+    ) -> impl Stream<Item = IdentityResult<Row>> + '_ {
+        // The paging state of the next page to fetch: None once every page has been fetched, and
+        // Some(None) before the first page has been fetched
+        stream::try_unfold(Some(None), move |paging_state| {
+            let query = query.clone();
+
+            async move {
+                match paging_state {
+                    Some(paging_state) => self
+                        .query_page(&query, paging_state)
+                        .await
+                        .map(|(rows, next_state)| Some((rows, next_state.map(Some)))),
+                    None => Ok(None),
+                }
+            }
+        })
+        .map_ok(|rows| stream::iter(rows.into_iter().map(Ok)))
+        .try_flatten()
+    }
+
+    /// Ends a stream of rows with a QueryError::ResultTooLarge once it yields more rows than the
+    /// connector's maximum result size.
+    ///
+    /// # Arguments
+    ///
+    /// * `rows` - The stream of rows that should be limited
+    pub(crate) fn limit_rows<'a>(
+        &'a self,
+        rows: impl Stream<Item = IdentityResult<Row>> + 'a,
+    ) -> impl Stream<Item = IdentityResult<Row>> + 'a {
+        rows.scan(0, move |streamed, row| {
+            *streamed += 1;
+
+            // Only the first row beyond the limit is reported, after which the stream ends
+            future::ready(match self.check_result_size(*streamed) {
+                Err(_) if self.check_result_size(*streamed - 1).is_err() => None,
+                res => Some(res.and(row)),
+            })
+        })
+    }
+
+    /// Encrypts a provider token with the connector's token key.
+    ///
+    /// # Arguments
+    ///
+    /// * `plaintext` - The token that should be encrypted
+    pub(crate) fn encrypt_token(&self, plaintext: &str) -> IdentityResult<String> {
+        self.token_key
+            .as_ref()
+            .ok_or(EncryptionError::MissingKey)
+            .and_then(|key| key.encrypt(plaintext))
+            .map_err(|e| e.into())
+    }
+
+    /// Decrypts a provider token with the connector's token key.
+    ///
+    /// # Arguments
+    ///
+    /// * `ciphertext` - The encrypted token, as stored in the database
+    pub(crate) fn decrypt_token(&self, ciphertext: &str) -> IdentityResult<String> {
+        self.token_key
+            .as_ref()
+            .ok_or(EncryptionError::MissingKey)
+            .and_then(|key| key.decrypt(ciphertext))
+            .map_err(|e| e.into())
     }
 }
 
@@ -53,13 +830,11 @@ impl Provider<Self, DbSession> for Scylla {
         &self,
         q: &K,
     ) -> IdentityResult<V> {
-        self.session
-            // Allow the struct impelemting conversion to construct a query
-            .query(q.to_query(&self.session).await?)
+        // Allow the struct impelemting conversion to construct a query
+        self.run(self.session.query(q.to_query(&self.session).await?))
             .await
             // Convert generalized results into a set of rows
-            .and_then(|frame| frame.get_body())
-            .map_err(|e| e.into())
+            .and_then(|frame| frame.get_body().map_err(|e| e.into()))
             // Ensure that some rows have been returned
             .and_then(|resp| resp.into_rows().ok_or(QueryError::NoResults.into()))
             .and_then(|mut rows| {
@@ -75,6 +850,28 @@ impl Provider<Self, DbSession> for Scylla {
             .map_err(|e| e.into())
     }
 
+    async fn load_records<
+        K: Queryable<Self, DbSession> + Send + Sync,
+        V: Deserializable<V, Self::ResponseIntermediary> + Send,
+    >(
+        &self,
+        q: &K,
+    ) -> IdentityResult<Vec<V>> {
+        self.run(self.session.query(q.to_query(&self.session).await?))
+            .await
+            .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+            // A query matching no records simply yields no rows
+            .map(|resp| resp.into_rows().unwrap_or_default())
+            // Convert each of the rows to the struct in question, failing if any one can't be
+            .and_then(|rows| {
+                self.check_result_size(rows.len())?;
+
+                rows.into_iter()
+                    .map(|row| V::try_from(row).map_err(|e| e.into()))
+                    .collect()
+            })
+    }
+
     /// Inserts a struct into the scylla database via the working session. Insertion is
     /// automatically supported for structs that:
     /// - Implement conversion into a CDRS QueryValues instance
@@ -86,19 +883,1033 @@ impl Provider<Self, DbSession> for Scylla {
         &self,
         r: &V,
     ) -> IdentityResult<()> {
-        self.session
-            .query_with_values(
-                V::INSERTION_QUERY,
-
-                // The struct being inserted must return a type that can be converted to an
-                // IdentityError when the struct is converted to a QueryValues instance. As such,
-                // we can convert the error that the struct returns upon conversion to the desired
-                // generalized IdentityError type. Furthermore, we can use ? to simply pass the
-                // result up
+        // The struct being inserted must return a type that can be converted to an IdentityError
+        // when the struct is converted to a QueryValues instance. As such, we can convert the
+        // error that the struct returns upon conversion to the desired generalized IdentityError
+        // type. Furthermore, we can use ? to simply pass the result up
+        let values = <V as Serializable<QueryValues>>::try_into(&r).map_err(|e| e.into())?;
+
+        // Writes that time out are only retried if the record declares itself idempotent
+        self.run_write(r.idempotent(), move || {
+            self.session
+                .query_with_values(V::INSERTION_QUERY, values.clone())
+        })
+        .await?;
+        r.notify_inserted(self.event_sink());
+
+        Ok(())
+    }
+
+    /// Inserts a struct into the scylla database via a lightweight transaction, such that an
+    /// existing row with the same primary key is never overwritten.
+    async fn insert_if_absent<
+        V: Serializable<Self::RequestIntermediary> + Insertable<Self, DbSession> + Send + Sync,
+    >(
+        &self,
+        r: &V,
+    ) -> IdentityResult<bool> {
+        let inserted = self
+            .execute_lwt(
+                &format!(
+                    "{} IF NOT EXISTS;",
+                    V::INSERTION_QUERY.trim_end().trim_end_matches(';')
+                ),
                 <V as Serializable<QueryValues>>::try_into(&r).map_err(|e| e.into())?,
             )
+            .await?;
+        if inserted {
+            r.notify_inserted(self.event_sink());
+        }
+
+        Ok(inserted)
+    }
+}
+
+/// Batch represents a set of insertions, updates, and deletions that are applied atomically via a
+/// logged batch: either every statement is applied, or none are.
+///
+/// # Examples
+///
+/// ```
+/// use swaply_identity::{db::scylla::Scylla, schema::user::User};
+/// use std::error::Error;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn Error>> {
+/// # if std::path::Path::new(".env").exists() {
+/// #     dotenv::dotenv().ok();
+/// # }
+/// # let db = swaply_identity::db::config::ScyllaConfig::from_env()?.connect().await?;
+/// # db.ensure_schema().await?;
+/// let (a, b) = (
+///     User::new(None, "a", "a@test.com", None, None),
+///     User::new(None, "b", "b@test.com", None, None),
+/// );
+///
+/// db.batch().insert(&a).insert(&b).execute().await?;
+///
+/// Ok(())
+/// # }
+/// ```
+pub struct Batch<'a> {
+    db: &'a Scylla,
+
+    /// The statements added to the batch so far, alongside their values, or the first error
+    /// encountered while adding a statement
+    statements: IdentityResult<Vec<(String, QueryValues)>>,
+
+    /// The number of statements added to the batch so far
+    len: usize,
+
+    /// Whether or not every statement added to the batch so far is idempotent
+    idempotent: bool,
+
+    /// Notifies the connector's event sink of the write made by each statement, once the batch
+    /// has been applied
+    notifications: Vec<Box<dyn Fn(&dyn EventSink) + Send + Sync + 'a>>,
+}
+
+impl<'a> Batch<'a> {
+    /// Adds a statement inserting a record to the batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - The record that should be inserted
+    pub fn insert<V: Serializable<QueryValues> + Insertable<Scylla, DbSession> + Sync>(
+        mut self,
+        r: &'a V,
+    ) -> Self {
+        self.notifications
+            .push(Box::new(move |sink| r.notify_inserted(sink)));
+
+        self.add(V::INSERTION_QUERY, r)
+    }
+
+    /// Adds a statement updating a record to the batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - The record that should be updated
+    pub fn update<V: Serializable<QueryValues> + Updatable<Scylla, DbSession> + Sync>(
+        mut self,
+        r: &'a V,
+    ) -> Self {
+        self.notifications
+            .push(Box::new(move |sink| r.notify_updated(sink)));
+
+        self.add(V::UPDATE_QUERY, r)
+    }
+
+    /// Adds a statement deleting a record to the batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - The record that should be deleted
+    pub fn delete<V: Serializable<QueryValues> + Deletable<Scylla, DbSession> + Sync>(
+        mut self,
+        r: &'a V,
+    ) -> Self {
+        self.notifications
+            .push(Box::new(move |sink| r.notify_deleted(sink)));
+
+        self.add(V::DELETION_QUERY, r)
+    }
+
+    /// Applies every statement in the batch atomically. If any statement couldn't be added to the
+    /// batch, nothing is applied. The batch is only retried after timing out if every statement
+    /// in it is idempotent. The connector's event sink is notified of each write once every
+    /// statement has been applied.
+    pub async fn execute(self) -> IdentityResult<()> {
+        let statements = self.statements?;
+        let (db, statements) = (self.db, &statements);
+
+        db.run_write(self.idempotent, move || async move {
+            let query = statements
+                .iter()
+                .fold(
+                    BatchQueryBuilder::new().batch_type(BatchType::Logged),
+                    |batch, (query, values)| batch.add_query(query.as_str(), values.clone()),
+                )
+                .finalize()?;
+
+            db.session().batch_with_params(query).await
+        })
+        .await?;
+
+        for notify in &self.notifications {
+            notify(db.event_sink());
+        }
+
+        Ok(())
+    }
+
+    /// Adds a statement to the batch, binding the values of a record to it. Batches don't accept
+    /// named values, so only the values of the columns named by the statement are bound, in the
+    /// order that the statement names them (e.g., only a deleted record's key).
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The statement that should be added
+    /// * `r` - The record whose values should be bound to the statement
+    fn add<V: Serializable<QueryValues> + Idempotent>(mut self, query: &str, r: &V) -> Self {
+        self.statements = self.statements.and_then(|mut statements| {
+            let values = Serializable::try_into(r).map_err(|e| e.into())?;
+            statements.push((query.to_owned(), bind_positionally(query, values)?));
+
+            Ok(statements)
+        });
+        self.idempotent &= r.idempotent();
+        self.len += 1;
+
+        self
+    }
+}
+
+impl fmt::Debug for Batch<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Batch")
+            .field("db", &self.db)
+            .field("statements", &self.len)
+            .field("failed", &self.statements.is_err())
+            .finish()
+    }
+}
+
+/// Gets the columns whose values are bound to a statement, in the order of its bind markers. The
+/// columns of an insertion are those in its column list, and those of any other statement are the
+/// columns compared against or assigned a marker (e.g., "id = ?").
+///
+/// # Arguments
+///
+/// * `query` - The statement whose bound columns should be found
+fn bound_columns(query: &str) -> Vec<&str> {
+    if query.trim_start().to_uppercase().starts_with("INSERT") {
+        return query
+            .find('(')
+            .and_then(|start| {
+                query[start + 1..]
+                    .find(')')
+                    .map(|end| &query[start + 1..start + 1 + end])
+            })
+            .map(|columns| columns.split(',').map(str::trim).collect())
+            .unwrap_or_default();
+    }
+
+    let markers = query.matches('?').count();
+
+    query
+        .split('?')
+        .take(markers)
+        .map(|preceding| {
+            let preceding = preceding.trim_end().trim_end_matches('=').trim_end();
+            let start = preceding
+                .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map_or(0, |i| i + 1);
+
+            &preceding[start..]
+        })
+        .collect()
+}
+
+/// Converts a record's named values into positional values, ordered by the bind markers of a
+/// statement. Values not bound by the statement are dropped. Positional values are returned as-is.
+///
+/// # Arguments
+///
+/// * `query` - The statement that the values should be bound to
+/// * `values` - The values that should be bound
+fn bind_positionally(query: &str, values: QueryValues) -> IdentityResult<QueryValues> {
+    let named = match values {
+        QueryValues::NamedValues(named) => named,
+        values => return Ok(values),
+    };
+
+    bound_columns(query)
+        .into_iter()
+        .map(|column| {
+            named.get(column).cloned().ok_or_else(|| {
+                QueryError::UnboundColumn {
+                    column: column.to_owned(),
+                }
+                .into()
+            })
+        })
+        .collect::<IdentityResult<Vec<_>>>()
+        .map(QueryValues::SimpleValues)
+}
+
+/// InFlight represents an operation being run by a connector, which is counted as in flight until
+/// it is dropped.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    /// Counts a new operation as in flight.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - The connector's count of operations in flight
+    fn enter(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+
+        Self(count)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Awaits an operation, aborting it with an IdentityError::Timeout if it doesn't complete within
+/// the given timeout.
+///
+/// # Arguments
+///
+/// * `timeout` - The maximum duration of the operation, if any
+/// * `op` - The operation that should be awaited
+async fn deadline<T, E: Into<IdentityError>>(
+    timeout: Option<Duration>,
+    op: impl Future<Output = Result<T, E>>,
+) -> IdentityResult<T> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, op)
             .await
-            .map(|_| ())
-            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e).into())
+            .map_err(|_| IdentityError::Timeout)?
+            .map_err(|e| e.into()),
+        None => op.await.map_err(|e| e.into()),
+    }
+}
+
+/// Determines whether or not a write that failed with the given error may be retried. Writes that
+/// were never attempted may always be retried, whereas writes that timed out may or may not have
+/// been applied, and may only be retried if they're idempotent.
+///
+/// # Arguments
+///
+/// * `e` - The error with which the write failed
+/// * `idempotent` - Whether or not the write may safely be applied more than once
+fn is_retryable_write(e: &IdentityError, idempotent: bool) -> bool {
+    match e {
+        IdentityError::Timeout | IdentityError::QueryError(QueryError::WriteTimeout { .. }) => {
+            idempotent
+        }
+        IdentityError::QueryError(e) => e.is_retryable(),
+        _ => false,
+    }
+}
+
+/// Makes attempts at a write until one succeeds, fails permanently, or the retry policy's
+/// retries are exhausted, returning the result of the final attempt.
+///
+/// # Arguments
+///
+/// * `policy` - The rules by which the write should be retried
+/// * `idempotent` - Whether or not the write may safely be applied more than once
+/// * `op` - Produces an attempt at the write
+async fn retry<T, F, Fut>(policy: &RetryPolicy, idempotent: bool, mut op: F) -> IdentityResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = IdentityResult<T>>,
+{
+    let mut retries = 0;
+
+    loop {
+        match op().await {
+            Err(e) if retries < policy.max_retries && is_retryable_write(&e, idempotent) => {
+                retries += 1;
+                time::delay_for(policy.backoff).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Determines whether or not a keyspace exists.
+///
+/// # Arguments
+///
+/// * `session` - The session that the schema should be inspected via
+/// * `keyspace` - The name of the keyspace
+pub(crate) async fn keyspace_exists(session: &DbSession, keyspace: &str) -> IdentityResult<bool> {
+    Ok(session
+        .query_with_values(
+            "SELECT keyspace_name FROM system_schema.keyspaces WHERE keyspace_name = ?;",
+            cdrs::query_values!(keyspace),
+        )
+        .await?
+        .get_body()?
+        .into_rows()
+        .map_or(false, |rows| !rows.is_empty()))
+}
+
+/// Gets the names of the secondary indexes of a keyspace that have yet to be built.
+///
+/// # Arguments
+///
+/// * `session` - The session that the schema should be inspected via
+/// * `keyspace` - The name of the keyspace
+pub(crate) async fn unbuilt_indexes(
+    session: &DbSession,
+    keyspace: &str,
+) -> IdentityResult<Vec<String>> {
+    let indexes = schema_names(
+        session,
+        "SELECT index_name FROM system_schema.indexes WHERE keyspace_name = ?;",
+        keyspace,
+        "index_name",
+    )
+    .await?;
+    let built_views = schema_names(
+        session,
+        "SELECT view_name FROM system.built_views WHERE keyspace_name = ?;",
+        keyspace,
+        "view_name",
+    )
+    .await?;
+
+    Ok(filter_unbuilt(indexes, &built_views))
+}
+
+/// Filters the secondary indexes of a keyspace down to those that have yet to be built. Each
+/// secondary index is backed by a materialized view named after the index, which is recorded
+/// among the keyspace's built views once the index has been built.
+///
+/// # Arguments
+///
+/// * `indexes` - The names of the keyspace's secondary indexes
+/// * `built_views` - The names of the keyspace's materialized views that have been built
+fn filter_unbuilt(indexes: Vec<String>, built_views: &[String]) -> Vec<String> {
+    indexes
+        .into_iter()
+        .filter(|index| !built_views.contains(&format!("{}_index", index)))
+        .collect()
+}
+
+/// Gets a name from each row of a schema table belonging to a keyspace.
+///
+/// # Arguments
+///
+/// * `session` - The session that the schema should be inspected via
+/// * `query` - The query selecting the rows of the keyspace, with the keyspace as its only marker
+/// * `keyspace` - The name of the keyspace
+/// * `column` - The column holding the name
+async fn schema_names(
+    session: &DbSession,
+    query: &str,
+    keyspace: &str,
+    column: &str,
+) -> IdentityResult<Vec<String>> {
+    session
+        .query_with_values(query, cdrs::query_values!(keyspace))
+        .await?
+        .get_body()?
+        .into_rows()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|row| Ok(row.get_r_by_name(column)?))
+        .collect()
+}
+
+/// Gets the name and CQL type of each column of a table. A table that doesn't exist has no
+/// columns.
+///
+/// # Arguments
+///
+/// * `session` - The session that the table should be inspected via
+/// * `keyspace` - The keyspace that the table belongs to
+/// * `table` - The name of the table, excluding its keyspace
+pub(crate) async fn table_columns(
+    session: &DbSession,
+    keyspace: &str,
+    table: &str,
+) -> IdentityResult<Vec<(String, String)>> {
+    session
+        .query_with_values(
+            "SELECT column_name, type FROM system_schema.columns WHERE keyspace_name = ? AND table_name = ?;",
+            cdrs::query_values!(keyspace, table),
+        )
+        .await?
+        .get_body()?
+        .into_rows()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|row| {
+            let name: String = row.get_r_by_name("column_name")?;
+            let cql_type: String = row.get_r_by_name("type")?;
+
+            Ok((name, cql_type))
+        })
+        .collect()
+}
+
+/// Compares an existing table against the schema expected of it, returning a
+/// QueryError::SchemaMismatch if the two differ.
+///
+/// # Arguments
+///
+/// * `session` - The session that the table should be inspected via
+/// * `schema` - The schema that the table is expected to have
+pub(crate) async fn verify_table_schema(
+    session: &DbSession,
+    schema: &TableSchema,
+) -> IdentityResult<()> {
+    let existing = table_columns(session, schema.keyspace, schema.name).await?;
+
+    let differences = schema.differences(&existing);
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(QueryError::SchemaMismatch {
+            table: format!("{}.{}", schema.keyspace, schema.name),
+            differences,
+        }
+        .into())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::{super::SchemaDifference, *};
+    use crate::{
+        schema::user::{DisplayNameUpdate, OwnedUser, UserQuery},
+        testing,
+    };
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn test_deadline_elapsed() {
+        let op = async {
+            time::delay_for(Duration::from_secs(5)).await;
+
+            Ok::<(), IdentityError>(())
+        };
+
+        match deadline(Some(Duration::from_millis(10)), op).await {
+            Err(IdentityError::Timeout) => (),
+            res => panic!("expected the operation to time out, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deadline_met() {
+        let op = async { Ok::<u8, IdentityError>(1) };
+
+        assert_eq!(deadline(Some(Duration::from_secs(5)), op).await.unwrap(), 1);
+    }
+
+    /// Produces an operation that takes the given amount of time to complete.
+    async fn slow_op(duration: Duration) -> IdentityResult<()> {
+        time::delay_for(duration).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+
+        let shutdown = async {
+            time::delay_for(Duration::from_millis(50)).await;
+
+            db.shutdown(Duration::from_secs(5)).await
+        };
+        let (op_res, shutdown_res) =
+            futures::join!(db.run(slow_op(Duration::from_millis(250))), shutdown);
+
+        // The operation in flight completes before shutdown does
+        op_res?;
+        shutdown_res?;
+        assert_eq!(db.in_flight.load(Ordering::SeqCst), 0);
+
+        assert!(db.is_shut_down());
+        match db.run(slow_op(Duration::from_millis(0))).await {
+            Err(IdentityError::ShutDown) => (),
+            res => panic!("expected the connector to be shut down, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_mid_operation() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+
+        // Shutdown is requested once the operation's first step is in flight
+        let op = db.operation(async {
+            db.run(slow_op(Duration::from_millis(100))).await?;
+            db.run(slow_op(Duration::from_millis(100))).await
+        });
+        let shutdown = async {
+            time::delay_for(Duration::from_millis(50)).await;
+
+            db.shutdown(Duration::from_secs(5)).await
+        };
+        let (op_res, shutdown_res) = futures::join!(op, shutdown);
+
+        // The operation's second step is run, and shutdown awaits it
+        op_res?;
+        shutdown_res?;
+        assert_eq!(db.in_flight.load(Ordering::SeqCst), 0);
+
+        match db
+            .operation(db.run(slow_op(Duration::from_millis(0))))
+            .await
+        {
+            Err(IdentityError::ShutDown) => (),
+            res => panic!("expected the connector to be shut down, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+
+        let shutdown = async {
+            time::delay_for(Duration::from_millis(50)).await;
+
+            db.shutdown(Duration::from_millis(50)).await
+        };
+        let (op_res, shutdown_res) =
+            futures::join!(db.run(slow_op(Duration::from_millis(500))), shutdown);
+
+        // Shutdown gives up waiting, but the operation in flight still runs to completion
+        match shutdown_res {
+            Err(IdentityError::Timeout) => (),
+            res => panic!("expected shutdown to time out, got {:?}", res),
+        }
+        op_res?;
+
+        Ok(())
+    }
+
+    /// Makes a write that always fails with the same error, returning the number of attempts
+    /// that were made at it.
+    ///
+    /// # Arguments
+    ///
+    /// * `idempotent` - Whether or not the write may safely be applied more than once
+    /// * `error` - Produces the error with which each attempt fails
+    async fn count_attempts(idempotent: bool, error: fn() -> QueryError) -> u32 {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let attempts = Cell::new(0);
+
+        let res: IdentityResult<()> = retry(&policy, idempotent, || {
+            attempts.set(attempts.get() + 1);
+
+            async move { Err(error().into()) }
+        })
+        .await;
+        assert!(res.is_err());
+
+        attempts.get()
+    }
+
+    #[tokio::test]
+    async fn test_retry_write_timeout() {
+        let timeout = || QueryError::WriteTimeout {
+            acknowledged: 0,
+            required: 1,
+        };
+
+        // A timed out write may have been applied, so it may only be repeated if it's idempotent
+        assert_eq!(count_attempts(true, timeout).await, 3);
+        assert_eq!(count_attempts(false, timeout).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_unattempted_write() {
+        let unavailable = || QueryError::Unavailable {
+            alive: 0,
+            required: 1,
+        };
+
+        // A write that was never attempted may always be retried, but permanent failures never
+        // are
+        assert_eq!(count_attempts(false, unavailable).await, 3);
+        assert_eq!(count_attempts(true, || QueryError::EmailTaken).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_table_schema_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+        let session = testing::open_session().await?;
+
+        // Tables are always created in the identity keyspace, which every test shares, so an
+        // incompatible users table is created in a keyspace of its own
+        session
+            .query(
+                "
+                    CREATE KEYSPACE IF NOT EXISTS identity_mismatch
+                        WITH REPLICATION = {
+                            'class': 'SimpleStrategy',
+                            'replication_factor': 1
+                    };
+                ",
+            )
+            .await?;
+        let schema = TableSchema {
+            keyspace: "identity_mismatch",
+            ..<User as InTable<Scylla, DbSession>>::table_schema()
+        };
+        session
+            .query(
+                schema
+                    .create_statement()
+                    .replace("password_hash BLOB", "password_hash TEXT"),
+            )
+            .await?;
+
+        match verify_table_schema(&session, &schema).await {
+            Err(IdentityError::QueryError(QueryError::SchemaMismatch { table, differences })) => {
+                assert_eq!(table, "identity_mismatch.users");
+                assert_eq!(
+                    differences,
+                    vec![SchemaDifference::WrongType {
+                        column: "password_hash",
+                        expected: "BLOB",
+                        found: "text".to_owned(),
+                    }]
+                );
+            }
+            res => panic!("expected a schema mismatch, got {:?}", res),
+        }
+
+        // The identity keyspace's tables are created as expected
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        verify_table_schema(&session, &User::table_schema()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_missing() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+
+        // The identity keyspace is shared by every test, so a keyspace that is never created
+        // stands in for it
+        let e = match db
+            .session()
+            .query("SELECT * FROM swaply_missing.users;")
+            .await
+        {
+            Err(CDRSError::Server(e)) => e,
+            res => panic!("expected a server error, got {:?}", res.map(|_| ())),
+        };
+
+        match db.diagnose(e, "swaply_missing").await {
+            IdentityError::QueryError(QueryError::KeyspaceMissing) => (),
+            e => panic!("expected the keyspace to be missing, got {:?}", e),
+        }
+
+        // An invalid query against a keyspace that exists isn't reported as a missing keyspace
+        crate::create_keyspace(db.session()).await?;
+
+        match db
+            .run(db.session().query("SELECT * FROM identity.swaply_missing;"))
+            .await
+        {
+            Err(IdentityError::CDRSError(CDRSError::Server(ref e))) if error::is_invalid(e) => (),
+            res => panic!("expected an invalid query, got {:?}", res.map(|_| ())),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_unbuilt() {
+        let indexes = vec![
+            "users_username_idx".to_owned(),
+            "users_email_idx".to_owned(),
+        ];
+        let built_views = vec![
+            "users_email_idx_index".to_owned(),
+            "users_username_idx".to_owned(),
+        ];
+
+        // Only a view named after the index, rather than the index itself, marks it as built
+        assert_eq!(
+            filter_unbuilt(indexes.clone(), &built_views),
+            vec!["users_username_idx".to_owned()]
+        );
+        assert_eq!(filter_unbuilt(indexes.clone(), &[]), indexes);
+        assert!(QueryError::IndexUnavailable.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_indexes_built() -> Result<(), Box<dyn std::error::Error>> {
+        let session = testing::open_session().await?;
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        // Building an index can take a moment after it has been created
+        for _ in 0..50 {
+            if unbuilt_indexes(&session, DEFAULT_KEYSPACE)
+                .await?
+                .is_empty()
+            {
+                return Ok(());
+            }
+
+            time::delay_for(Duration::from_millis(100)).await;
+        }
+
+        panic!("expected the indexes of the users table to be built");
+    }
+
+    /// A record whose insertion always fails, since its table never exists.
+    struct Unwritable;
+
+    impl Serializable<QueryValues> for Unwritable {
+        type Error = IdentityError;
+
+        fn try_into(&self) -> Result<QueryValues, Self::Error> {
+            Ok(cdrs::query_values!("id" => uuid::Uuid::new_v4()))
+        }
+    }
+
+    impl Idempotent for Unwritable {
+        fn idempotent(&self) -> bool {
+            true
+        }
+    }
+
+    impl Insertable<Scylla, DbSession> for Unwritable {
+        const INSERTION_QUERY: &'static str = "INSERT INTO identity.missing_table (id) VALUES (?);";
+    }
+
+    #[test]
+    fn test_insertion_column_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+        testing::assert_insertion_columns_match(&Unwritable)?;
+
+        // A column added to the values but not the query, and one renamed in the query alone
+        let values = cdrs::query_values!("id" => 1, "username" => "test", "email" => "a@b.c");
+        assert_eq!(
+            testing::insertion_column_mismatch(
+                "INSERT INTO identity.users (id, user_name) VALUES (?, ?);",
+                &values
+            ),
+            Some((
+                vec!["email".to_owned(), "username".to_owned()],
+                vec!["user_name".to_owned()]
+            ))
+        );
+        assert_eq!(
+            testing::insertion_column_mismatch(
+                "INSERT INTO identity.users (email, id, username) VALUES (?, ?, ?);",
+                &values
+            ),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't match its values")]
+    fn test_assert_insertion_columns_mismatch() {
+        struct Drifted;
+
+        impl Serializable<QueryValues> for Drifted {
+            type Error = IdentityError;
+
+            fn try_into(&self) -> Result<QueryValues, Self::Error> {
+                Ok(cdrs::query_values!("id" => uuid::Uuid::new_v4(), "added" => 1))
+            }
+        }
+
+        impl Idempotent for Drifted {
+            fn idempotent(&self) -> bool {
+                true
+            }
+        }
+
+        impl Insertable<Scylla, DbSession> for Drifted {
+            const INSERTION_QUERY: &'static str = "INSERT INTO identity.drifted (id) VALUES (?);";
+        }
+
+        testing::assert_insertion_columns_match(&Drifted).unwrap();
+    }
+
+    #[test]
+    fn test_bind_positionally() -> Result<(), Box<dyn std::error::Error>> {
+        let u = testing::generate_user();
+        let values = <User as Serializable<QueryValues>>::try_into(&u)?;
+
+        // Every column of an insertion is bound, in the order of its column list
+        let insertion = <User as Insertable<Scylla, DbSession>>::INSERTION_QUERY;
+        match bind_positionally(insertion, values.clone())? {
+            QueryValues::SimpleValues(bound) => {
+                assert_eq!(bound.len(), bound_columns(insertion).len())
+            }
+            values => panic!("expected positional values, got {:?}", values),
+        }
+
+        // The key of an update is bound last, after the columns that it sets
+        let update = <User as Updatable<Scylla, DbSession>>::UPDATE_QUERY;
+        assert_eq!(bound_columns(update).first(), Some(&"username"));
+        assert_eq!(bound_columns(update).last(), Some(&"id"));
+
+        // Only the key of a deletion is bound
+        let deletion = <User as Deletable<Scylla, DbSession>>::DELETION_QUERY;
+        assert_eq!(bound_columns(deletion), vec!["id"]);
+        match bind_positionally(deletion, values)? {
+            QueryValues::SimpleValues(bound) => assert_eq!(bound.len(), 1),
+            values => panic!("expected positional values, got {:?}", values),
+        }
+
+        let unbound = cdrs::query_values!("id" => uuid::Uuid::new_v4());
+        match bind_positionally(update, unbound) {
+            Err(IdentityError::QueryError(QueryError::UnboundColumn { column })) => {
+                assert_eq!(column, "username")
+            }
+            res => panic!("expected the username to be unbound, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        db.ensure_schema().await?;
+
+        let (doomed, survivor, renamed) = (
+            testing::generate_user(),
+            testing::generate_user(),
+            testing::generate_user(),
+        );
+        testing::insert_user(&db, &doomed).await?;
+        testing::insert_user(&db, &renamed).await?;
+
+        db.batch()
+            .insert(&survivor)
+            .update(&DisplayNameUpdate::new(*renamed.id(), Some("Renamed")))
+            .delete(&doomed)
+            .execute()
+            .await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(survivor.id())).await?;
+        assert_eq!(loaded_u, survivor);
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(renamed.id())).await?;
+        assert_eq!(loaded_u.display_name(), Some("Renamed"));
+
+        let loaded_u: IdentityResult<OwnedUser> = db.load_record(&UserQuery::Id(doomed.id())).await;
+        match loaded_u {
+            Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+            res => panic!("expected the user to be deleted, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_atomic() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        db.ensure_schema().await?;
+
+        let (a, b) = (testing::generate_user(), testing::generate_user());
+        let res = db
+            .batch()
+            .insert(&a)
+            .insert(&b)
+            .insert(&Unwritable)
+            .execute()
+            .await;
+        assert!(res.is_err());
+
+        // Neither user may have been written alongside the failing statement
+        for u in &[a, b] {
+            let loaded_u: IdentityResult<OwnedUser> = db.load_record(&UserQuery::Id(u.id())).await;
+
+            match loaded_u {
+                Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+                res => panic!("expected no user to be written, got {:?}", res),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ensure_schema() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        db.ensure_schema().await?;
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u, u);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_in_request_supplied_id() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        db.ensure_schema().await?;
+
+        let (request_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let res: IdentityResult<OwnedUser> = db
+            .in_request(Some(request_id), async {
+                db.load_record(&UserQuery::Id(&user_id)).await
+            })
+            .await;
+
+        let e = res.expect_err("expected the user to be missing");
+        assert_eq!(e.request_id(), Some(&request_id));
+        assert!(e.to_string().contains(&request_id.to_string()));
+
+        match e.into_inner() {
+            IdentityError::QueryError(QueryError::NoResults) => (),
+            e => panic!("expected the user to be missing, got {:?}", e),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_in_request_generated_id() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        db.ensure_schema().await?;
+
+        let user_id = Uuid::new_v4();
+        let res: IdentityResult<OwnedUser> = db
+            .in_request(None, async {
+                assert!(current_request_id().is_some());
+
+                db.load_record(&UserQuery::Id(&user_id)).await
+            })
+            .await;
+
+        let e = res.expect_err("expected the user to be missing");
+        let request_id = e.request_id().expect("expected a generated request ID");
+        assert!(e.to_string().contains(&request_id.to_string()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_current_request_id() -> Result<(), Box<dyn std::error::Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        let request_id = Uuid::new_v4();
+
+        assert_eq!(current_request_id(), None);
+        db.in_request(Some(request_id), async {
+            assert_eq!(current_request_id(), Some(request_id));
+
+            Ok(())
+        })
+        .await?;
+        assert_eq!(current_request_id(), None);
+
+        Ok(())
     }
 }
@@ -0,0 +1,149 @@
+use uuid::Uuid;
+
+use super::schema::user::User;
+
+use std::fmt;
+
+/// EventSink represents a receiver of the lifecycle events of users (e.g., a publisher to a
+/// message queue feeding search indexers and email senders). A connector notifies its sink only
+/// once a write has succeeded, and never for a write that failed. Sinks are notified before the
+/// write returns, so they should hand events off rather than block. Users written through the
+/// Provider methods (including via a Cached connector) and through a Batch are reported too; a
+/// batch reports its writes once every statement in it has been applied. Every event is ignored
+/// by default.
+pub trait EventSink: fmt::Debug + Send + Sync {
+    /// Notifies the sink that a user was registered.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user, as inserted
+    fn on_user_created(&self, _user: &User<'_>) {}
+
+    /// Notifies the sink that a user's profile, username, email address, or password changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that was updated
+    fn on_user_updated(&self, _user_id: &Uuid) {}
+
+    /// Notifies the sink that a user was deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that was deleted
+    fn on_user_deleted(&self, _user_id: &Uuid) {}
+}
+
+/// NoopSink represents a sink that ignores every event. This is the sink used by default.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct NoopSink;
+
+impl EventSink for NoopSink {}
+
+#[cfg(test)]
+pub mod test {
+    use std::{
+        error::Error,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::{
+        db::{scylla::Scylla, Provider},
+        error::{IdentityError, QueryError},
+        testing,
+    };
+
+    /// UserEvent represents an event received by a RecordingSink.
+    #[derive(Debug, PartialEq, Eq)]
+    enum UserEvent {
+        Created(Uuid),
+        Updated(Uuid),
+        Deleted(Uuid),
+    }
+
+    /// RecordingSink represents a sink that holds every event that it receives, in order.
+    #[derive(Default, Debug)]
+    struct RecordingSink {
+        events: Mutex<Vec<UserEvent>>,
+    }
+
+    impl RecordingSink {
+        fn record(&self, event: UserEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+
+        /// Gets the number of received events matching the given event.
+        fn count(&self, event: &UserEvent) -> usize {
+            self.events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|received| *received == event)
+                .count()
+        }
+    }
+
+    impl EventSink for RecordingSink {
+        fn on_user_created(&self, user: &User<'_>) {
+            self.record(UserEvent::Created(*user.id()));
+        }
+
+        fn on_user_updated(&self, user_id: &Uuid) {
+            self.record(UserEvent::Updated(*user_id));
+        }
+
+        fn on_user_deleted(&self, user_id: &Uuid) {
+            self.record(UserEvent::Deleted(*user_id));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_user_created_event() -> Result<(), Box<dyn Error>> {
+        let sink = Arc::new(RecordingSink::default());
+        let db = Scylla::new(testing::open_session().await?).with_event_sink(sink.clone());
+        db.ensure_schema().await?;
+
+        let username = format!("events_{}", &Uuid::new_v4().to_simple().to_string()[..16]);
+        let email = format!("{}@test.com", username);
+        let u = User::new(None, &username, &email, None, None);
+        db.insert_unique(&u).await?;
+        assert_eq!(sink.count(&UserEvent::Created(*u.id())), 1);
+
+        // The username is taken, so nothing is written, and no event fires
+        let duplicate_email = format!("duplicate_{}", email);
+        let duplicate = User::new(None, &username, &duplicate_email, None, None);
+        match db.insert_unique(&duplicate).await {
+            Err(IdentityError::QueryError(QueryError::UsernameTaken)) => (),
+            res => panic!("expected the username to be taken, got {:?}", res),
+        }
+        assert_eq!(sink.count(&UserEvent::Created(*duplicate.id())), 0);
+        assert_eq!(sink.count(&UserEvent::Created(*u.id())), 1);
+
+        db.set_display_name(u.id(), Some("Events")).await?;
+        assert_eq!(sink.count(&UserEvent::Updated(*u.id())), 1);
+
+        db.delete_user_cascade(u.id()).await?;
+        assert_eq!(sink.count(&UserEvent::Deleted(*u.id())), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_provider_events() -> Result<(), Box<dyn Error>> {
+        let sink = Arc::new(RecordingSink::default());
+        let db = Scylla::new(testing::open_session().await?).with_event_sink(sink.clone());
+        db.ensure_schema().await?;
+
+        let username = format!("provider_{}", &Uuid::new_v4().to_simple().to_string()[..16]);
+        let email = format!("{}@test.com", username);
+        let u = User::new(None, &username, &email, None, None);
+        db.insert_record(&u).await?;
+        assert_eq!(sink.count(&UserEvent::Created(*u.id())), 1);
+
+        db.batch().delete(&u).execute().await?;
+        assert_eq!(sink.count(&UserEvent::Deleted(*u.id())), 1);
+
+        Ok(())
+    }
+}
@@ -23,6 +23,15 @@ pub mod server;
 /// Db defines various database helper methods and types.
 pub mod db;
 
+/// Crypto implements cryptographic helpers used to protect sensitive user data at rest.
+pub mod crypto;
+
+/// Clock implements injectable sources of the current time.
+pub mod clock;
+
+/// Events implements hooks notifying embedders of changes made to users.
+pub mod events;
+
 /// DbSession represents a Scylla database session.
 pub type DbSession = Session<RoundRobin<TcpConnectionPool<StaticPasswordAuthenticator>>>;
 
@@ -32,14 +41,16 @@ pub(crate) mod testing {
     use cdrs::{
         cluster::{ClusterTcpConfig, NodeTcpConfigBuilder},
         load_balancing::RoundRobin,
+        query::QueryValues,
     };
-    use std::{env, error::Error};
+    use std::{collections::BTreeSet, env, error::Error};
 
     use super::{
-        db::{Insertable, Provider, Serializable},
-        schema::user::User,
+        db::{scylla::Scylla, Insertable, Provider, Serializable},
+        schema::user::{OwnedUser, User},
         *,
     };
+    use uuid::Uuid;
 
     /// Represents a default password utilized by the generate_user method.
     const TEST_PASSWORD_HASH: &'static [u8] = b"123456";
@@ -88,15 +99,99 @@ pub(crate) mod testing {
         session.insert_record(u).await
     }
 
-    /// Generates an instance of the User struct.
+    /// Compares the columns named by an insertion query against the names of the values bound
+    /// to it. Returns the columns bound but not named by the query, followed by the columns named
+    /// by the query but not bound, or None if the two agree.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The insertion query, of the form INSERT INTO table (columns) VALUES (...)
+    /// * `values` - The named values bound to the query
+    pub fn insertion_column_mismatch(
+        query: &str,
+        values: &QueryValues,
+    ) -> Option<(Vec<String>, Vec<String>)> {
+        let columns: BTreeSet<String> = query
+            .find('(')
+            .and_then(|start| {
+                query[start + 1..]
+                    .find(')')
+                    .map(|end| &query[start + 1..start + 1 + end])
+            })
+            .unwrap_or_else(|| panic!("no column list in insertion query {:?}", query))
+            .split(',')
+            .map(|column| column.trim().to_owned())
+            .collect();
+
+        let bound: BTreeSet<String> = match values {
+            QueryValues::NamedValues(values) => values.keys().cloned().collect(),
+            values => panic!("expected named values, got {:?}", values),
+        };
+
+        if columns == bound {
+            None
+        } else {
+            Some((
+                bound.difference(&columns).cloned().collect(),
+                columns.difference(&bound).cloned().collect(),
+            ))
+        }
+    }
+
+    /// Asserts that the columns named by a record's insertion query exactly match the columns
+    /// bound by its serialized values, such that neither may drift from the other.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - An instance of the record type that should be checked
+    pub fn assert_insertion_columns_match<V>(record: &V) -> Result<(), Box<dyn Error>>
+    where
+        V: Insertable<Scylla, DbSession> + Serializable<QueryValues>,
+    {
+        let values = record
+            .try_into()
+            .map_err(<V::Error as Into<error::IdentityError>>::into)?;
+
+        if let Some((missing_from_query, missing_from_values)) =
+            insertion_column_mismatch(V::INSERTION_QUERY, &values)
+        {
+            panic!(
+                "insertion query {:?} doesn't match its values: {:?} aren't named by the query, \
+                 and {:?} aren't bound",
+                V::INSERTION_QUERY,
+                missing_from_query,
+                missing_from_values
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Generates an instance of the User struct. Every call produces a user with the same
+    /// username and email address, so tests asserting on either should use generate_random_user.
     pub fn generate_user<'a>() -> User<'a> {
         User::new(
             None,
             "test",
             "test@test.com",
-            blake3::hash(TEST_PASSWORD_HASH).into(),
+            Some(blake3::hash(TEST_PASSWORD_HASH).into()),
+            None,
+        )
+    }
+
+    /// Generates an instance of the OwnedUser struct with a random ID, username, and email
+    /// address, such that tests using it don't collide when run concurrently.
+    pub fn generate_random_user() -> OwnedUser {
+        let username = format!("u{}", &Uuid::new_v4().to_simple().to_string()[..16]);
+
+        User::new(
+            None,
+            &username,
+            &format!("{}@test.com", username),
+            Some(blake3::hash(TEST_PASSWORD_HASH).into()),
             None,
         )
+        .into()
     }
 }
 
@@ -111,16 +206,94 @@ pub mod result {
 
 /// Error implements helpful error types.
 pub mod error {
-    use cdrs::error::Error as CDRSError;
+    use cdrs::{
+        error::Error as CDRSError,
+        frame::frame_error::{AdditionalErrorInfo, CDRSError as ServerError},
+    };
     use std::{error::Error, fmt};
+    use uuid::Uuid;
 
-    use super::schema::user::{ConvertRowToUserError, ConvertUserToQueryValuesError};
+    use super::{
+        crypto::{password::PasswordError, token::EncryptionError},
+        db::{config::ConfigError, SchemaDifference},
+        schema::user::{
+            ConvertRowToUserError, ConvertUserToQueryValuesError, IntoIdentityProviderError,
+        },
+    };
 
     /// Error represents any error emitted by the swaply identity service.
     #[derive(Debug)]
     pub enum IdentityError {
         QueryError(QueryError),
         CDRSError(CDRSError),
+        EncryptionError(EncryptionError),
+        ValidationError(ValidationError),
+        Config(ConfigError),
+        Auth(AuthError),
+        Timeout,
+
+        /// The connector has been shut down, and no longer accepts new operations
+        ShutDown,
+
+        /// An error encountered while serving the request with the given ID, as run via
+        /// Scylla::in_request
+        InRequest {
+            request_id: Uuid,
+            error: Box<IdentityError>,
+        },
+    }
+
+    impl IdentityError {
+        /// Gets the ID of the request that the error was encountered while serving, if it was
+        /// encountered within Scylla::in_request.
+        pub fn request_id(&self) -> Option<&Uuid> {
+            match self {
+                Self::InRequest { request_id, .. } => Some(request_id),
+                _ => None,
+            }
+        }
+
+        /// Discards the ID of any request that the error was encountered while serving, such that
+        /// the underlying error may be matched.
+        pub fn into_inner(self) -> Self {
+            match self {
+                Self::InRequest { error, .. } => *error,
+                e => e,
+            }
+        }
+
+        /// Attributes the error to a request, unless it is already attributed to one.
+        ///
+        /// # Arguments
+        ///
+        /// * `request_id` - The ID of the request that the error was encountered while serving
+        pub fn in_request(self, request_id: Uuid) -> Self {
+            match self {
+                e @ Self::InRequest { .. } => e,
+                e => Self::InRequest {
+                    request_id,
+                    error: Box::new(e),
+                },
+            }
+        }
+    }
+
+    impl From<ConfigError> for IdentityError {
+        fn from(e: ConfigError) -> Self {
+            Self::Config(e)
+        }
+    }
+
+    impl From<AuthError> for IdentityError {
+        fn from(e: AuthError) -> Self {
+            Self::Auth(e)
+        }
+    }
+
+    impl From<ValidationError> for IdentityError {
+        fn from(e: ValidationError) -> Self {
+            Self::ValidationError(e)
+        }
     }
 
     impl From<QueryError> for IdentityError {
@@ -131,13 +304,71 @@ pub mod error {
 
     impl From<CDRSError> for IdentityError {
         fn from(e: CDRSError) -> Self {
-            Self::CDRSError(e)
+            let classified = match e {
+                CDRSError::Server(ref server_err) => classify(server_err),
+                _ => None,
+            };
+
+            match classified {
+                Some(e) => Self::QueryError(e),
+                None => Self::CDRSError(e),
+            }
+        }
+    }
+
+    /// Classifies an error returned by the database as a QueryError, if it is one of the failures
+    /// that callers are expected to handle distinctly (e.g., by retrying the query).
+    ///
+    /// # Arguments
+    ///
+    /// * `e` - The error returned by the database
+    pub fn classify(e: &ServerError) -> Option<QueryError> {
+        match e.additional_info {
+            AdditionalErrorInfo::WriteTimeout(ref info) => Some(QueryError::WriteTimeout {
+                acknowledged: info.received,
+                required: info.blockfor,
+            }),
+            AdditionalErrorInfo::ReadTimeout(ref info) => Some(QueryError::ReadTimeout {
+                received: info.received,
+                required: info.blockfor,
+                data_present: info.replica_has_responded(),
+            }),
+            AdditionalErrorInfo::Unavailable(ref info) => Some(QueryError::Unavailable {
+                alive: info.alive,
+                required: info.required,
+            }),
+            AdditionalErrorInfo::Overloaded(_) => Some(QueryError::Overloaded),
+            _ => None,
+        }
+    }
+
+    /// Determines whether or not an error returned by the database rejected a query as invalid.
+    /// The server reports a query referring to a keyspace that doesn't exist, or relying on a
+    /// secondary index that has yet to be built, as it would any other invalid query, so such
+    /// errors can only be told apart by consulting the schema (see Scylla::diagnose).
+    ///
+    /// # Arguments
+    ///
+    /// * `e` - The error returned by the database
+    pub(crate) fn is_invalid(e: &ServerError) -> bool {
+        match e.additional_info {
+            AdditionalErrorInfo::Invalid(_) => true,
+            _ => false,
         }
     }
 
     impl fmt::Display for IdentityError {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "encountered an error: {:?}", self.source())
+            match self {
+                Self::Timeout => write!(f, "encountered an error: the operation timed out"),
+                Self::ShutDown => write!(f, "encountered an error: the connector has shut down"),
+                Self::Config(e) => write!(f, "{}", e),
+                Self::Auth(e) => write!(f, "{}", e),
+                Self::InRequest { request_id, error } => {
+                    write!(f, "{} (in request {})", error, request_id)
+                }
+                _ => write!(f, "encountered an error: {:?}", self.source()),
+            }
         }
     }
 
@@ -146,6 +377,12 @@ pub mod error {
             match self {
                 Self::QueryError(e) => Some(e),
                 Self::CDRSError(e) => Some(e),
+                Self::EncryptionError(e) => Some(e),
+                Self::ValidationError(e) => Some(e),
+                Self::Config(e) => Some(e),
+                Self::Auth(e) => Some(e),
+                Self::InRequest { error, .. } => Some(error.as_ref()),
+                Self::Timeout | Self::ShutDown => None,
             }
         }
     }
@@ -154,8 +391,100 @@ pub mod error {
     #[derive(Debug)]
     pub enum QueryError {
         NoResults,
+        UsernameTaken,
+        EmailTaken,
+        LastAuthMethod,
+        Contended,
+        KeyspaceMissing,
+
+        /// A user has linked several accounts held with the same provider, so the account that an
+        /// operation refers to is ambiguous
+        AmbiguousLink,
+
+        /// A user's primary email address can't be removed until another address is promoted
+        PrimaryEmail,
+
+        /// The normalized email addresses of some users are held by other users
+        EmailConflicts {
+            updated: u64,
+            users: Vec<Uuid>,
+        },
+
+        /// Fewer replicas than required acknowledged a write in time. The write may or may not
+        /// have been applied
+        WriteTimeout {
+            acknowledged: i32,
+            required: i32,
+        },
+
+        /// Fewer replicas than required responded to a read in time
+        ReadTimeout {
+            received: i32,
+            required: i32,
+            data_present: bool,
+        },
+
+        /// Fewer replicas than required were alive when the query was received, so it wasn't
+        /// attempted
+        Unavailable {
+            alive: i32,
+            required: i32,
+        },
+
+        /// The coordinator was too busy to handle the query, so it wasn't attempted
+        Overloaded,
+
+        /// A secondary index that the query relies on has yet to be built, or is otherwise
+        /// unavailable. This doesn't mean that no matching records exist
+        IndexUnavailable,
+
+        /// An existing table doesn't match the schema expected of it, and must be migrated before
+        /// it can be used
+        SchemaMismatch {
+            table: String,
+            differences: Vec<SchemaDifference>,
+        },
+
+        /// A query matched more rows than the connector's configured maximum result size
+        ResultTooLarge {
+            limit: usize,
+        },
+
+        /// Results were requested in an order that the clustering key of the queried table doesn't
+        /// support
+        UnsupportedOrdering {
+            table: String,
+            column: &'static str,
+        },
+
+        /// A statement names a column to which no value is bound
+        UnboundColumn {
+            column: String,
+        },
         SerializationError(ConvertUserToQueryValuesError),
         DeserializationError(ConvertRowToUserError),
+        InvalidProvider(IntoIdentityProviderError),
+    }
+
+    impl QueryError {
+        /// Determines whether or not the failed query may safely be retried as-is. Queries that
+        /// were never attempted, and reads, may be retried; writes that timed out may have been
+        /// applied, and are only safe to retry if they are idempotent.
+        pub fn is_retryable(&self) -> bool {
+            match self {
+                Self::ReadTimeout { .. }
+                | Self::Unavailable { .. }
+                | Self::Overloaded
+                | Self::IndexUnavailable => true,
+                _ => false,
+            }
+        }
+    }
+
+    impl From<IntoIdentityProviderError> for IdentityError {
+        fn from(e: IntoIdentityProviderError) -> Self {
+            Self::QueryError(QueryError::InvalidProvider(e))
+        }
     }
 
     impl fmt::Display for QueryError {
@@ -165,6 +494,71 @@ pub mod error {
                 "encountered an error while querying the database: {}",
                 match self {
                     Self::NoResults => "no results found".to_owned(),
+                    Self::UsernameTaken => "the username is already taken".to_owned(),
+                    Self::EmailTaken => "the email address is already taken".to_owned(),
+                    Self::LastAuthMethod => {
+                        "the user's last authentication method can't be removed".to_owned()
+                    }
+                    Self::PrimaryEmail => {
+                        "the user's primary email address can't be removed".to_owned()
+                    }
+                    Self::Contended => {
+                        "the operation repeatedly conflicted with concurrent operations".to_owned()
+                    }
+                    Self::KeyspaceMissing => {
+                        "the keyspace doesn't exist; has the schema been created?".to_owned()
+                    }
+                    Self::AmbiguousLink => {
+                        "the user has linked several accounts held with the provider".to_owned()
+                    }
+                    Self::EmailConflicts { updated, users } => format!(
+                        "the normalized email addresses of {} users are held by other users \
+                         ({} users were normalized)",
+                        users.len(),
+                        updated
+                    ),
+                    Self::WriteTimeout {
+                        acknowledged,
+                        required,
+                    } => format!(
+                        "the write timed out after {} of {} required replicas acknowledged it",
+                        acknowledged, required
+                    ),
+                    Self::ReadTimeout {
+                        received, required, ..
+                    } => format!(
+                        "the read timed out after {} of {} required replicas responded",
+                        received, required
+                    ),
+                    Self::Unavailable { alive, required } => format!(
+                        "only {} of {} required replicas are available",
+                        alive, required
+                    ),
+                    Self::Overloaded => "the database is overloaded".to_owned(),
+                    Self::IndexUnavailable => {
+                        "a secondary index used by the query is unavailable; retry once it has \
+                         been built"
+                            .to_owned()
+                    }
+                    Self::SchemaMismatch { table, differences } => format!(
+                        "the existing {} table doesn't match its expected schema: {}",
+                        table,
+                        differences
+                            .iter()
+                            .map(SchemaDifference::to_string)
+                            .collect::<Vec<String>>()
+                            .join("; ")
+                    ),
+                    Self::ResultTooLarge { limit } => {
+                        format!("the query matched more than the maximum of {} rows", limit)
+                    }
+                    Self::UnsupportedOrdering { table, column } => format!(
+                        "the {} table can't be ordered by its {} column",
+                        table, column
+                    ),
+                    Self::UnboundColumn { column } => {
+                        format!("no value is bound to the {} column", column)
+                    }
                     _ => format!("{:?}", self.source()),
                 }
             )
@@ -174,10 +568,218 @@ pub mod error {
     impl Error for QueryError {
         fn source(&self) -> Option<&(dyn Error + 'static)> {
             match self {
-                Self::NoResults => None,
+                Self::NoResults
+                | Self::UsernameTaken
+                | Self::EmailTaken
+                | Self::LastAuthMethod
+                | Self::PrimaryEmail
+                | Self::Contended
+                | Self::KeyspaceMissing
+                | Self::AmbiguousLink
+                | Self::EmailConflicts { .. }
+                | Self::WriteTimeout { .. }
+                | Self::ReadTimeout { .. }
+                | Self::Unavailable { .. }
+                | Self::Overloaded
+                | Self::IndexUnavailable
+                | Self::SchemaMismatch { .. }
+                | Self::ResultTooLarge { .. }
+                | Self::UnsupportedOrdering { .. }
+                | Self::UnboundColumn { .. } => None,
                 Self::SerializationError(e) => Some(e),
                 Self::DeserializationError(e) => Some(e),
+                Self::InvalidProvider(e) => Some(e),
+            }
+        }
+    }
+
+    /// AuthError represents the reason that a user couldn't be authenticated. Unlike a QueryError,
+    /// an AuthError is the fault of the presented credentials or the account that they belong to,
+    /// rather than of the database.
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum AuthError {
+        /// The email address, username, or password presented by a user is incorrect. Which of
+        /// them is deliberately left unspecified
+        InvalidCredentials,
+
+        /// The user has failed to authenticate too many times, and may not authenticate until
+        /// their lock expires
+        AccountLocked,
+
+        /// The user has been suspended, and may not authenticate until they're reinstated
+        AccountSuspended,
+
+        /// The user has yet to verify their email address, which the connector requires
+        EmailUnverified,
+    }
+
+    impl fmt::Display for AuthError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "encountered an error while authenticating: {}",
+                match self {
+                    Self::InvalidCredentials => "the credentials are incorrect",
+                    Self::AccountLocked => "the account is temporarily locked",
+                    Self::AccountSuspended => "the account has been suspended",
+                    Self::EmailUnverified => "the email address has yet to be verified",
+                }
+            )
+        }
+    }
+
+    impl Error for AuthError {}
+
+    /// ValidationError represents any error that may be encountered while validating user-supplied
+    /// details.
+    #[derive(Debug)]
+    pub enum ValidationError {
+        InvalidUsername,
+        InvalidEmail,
+        InvalidDisplayName,
+        InvalidLocale,
+        InvalidTimezone,
+        InvalidRange,
+
+        /// An update of a user doesn't change any columns
+        EmptyUpdate,
+
+        /// A page is empty, or lies too far into the results to be loaded
+        InvalidPage,
+        WeakPassword(PasswordError),
+        MissingField(&'static str),
+    }
+
+    impl From<PasswordError> for ValidationError {
+        fn from(e: PasswordError) -> Self {
+            Self::WeakPassword(e)
+        }
+    }
+
+    impl fmt::Display for ValidationError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "encountered an error while validating user details: {}",
+                match self {
+                    Self::InvalidUsername => "the username is invalid".to_owned(),
+                    Self::InvalidEmail => "the email address is invalid".to_owned(),
+                    Self::InvalidDisplayName => "the display name is invalid".to_owned(),
+                    Self::InvalidLocale => {
+                        "the locale is not a well-formed BCP-47 language tag".to_owned()
+                    }
+                    Self::InvalidTimezone => {
+                        "the timezone is not in the IANA timezone database".to_owned()
+                    }
+                    Self::InvalidRange => "the range starts after it ends".to_owned(),
+                    Self::EmptyUpdate => "the update doesn't change anything".to_owned(),
+                    Self::InvalidPage => "the page is empty or out of range".to_owned(),
+                    Self::MissingField(field) => format!("the {} field is missing", field),
+                    Self::WeakPassword(e) => e.to_string(),
+                }
+            )
+        }
+    }
+
+    impl Error for ValidationError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            match self {
+                Self::WeakPassword(e) => Some(e),
+                _ => None,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub mod test {
+        use cdrs::types::from_cursor::FromCursor;
+        use std::io::Cursor;
+
+        use super::*;
+
+        /// The QUORUM consistency level, as encoded in error bodies.
+        const QUORUM: [u8; 2] = [0, 4];
+
+        /// Parses an error as it would be sent by the database.
+        ///
+        /// # Arguments
+        ///
+        /// * `code` - The error code
+        /// * `info` - The encoded additional information specific to the error code
+        fn server_error(code: i32, info: &[u8]) -> ServerError {
+            let message = b"test error";
+
+            let mut body = code.to_be_bytes().to_vec();
+            body.extend_from_slice(&(message.len() as u16).to_be_bytes());
+            body.extend_from_slice(message);
+            body.extend_from_slice(info);
+
+            ServerError::from_cursor(&mut Cursor::new(&body[..])).unwrap()
+        }
+
+        #[test]
+        fn test_classify_write_timeout() {
+            let info = [
+                &QUORUM[..],
+                &1i32.to_be_bytes(),
+                &2i32.to_be_bytes(),
+                &6u16.to_be_bytes(),
+                b"SIMPLE",
+            ]
+            .concat();
+
+            match classify(&server_error(0x1100, &info)) {
+                Some(QueryError::WriteTimeout {
+                    acknowledged: 1,
+                    required: 2,
+                }) => (),
+                e => panic!("expected a write timeout, got {:?}", e),
+            }
+        }
+
+        #[test]
+        fn test_classify_read_timeout() {
+            let info = [&QUORUM[..], &1i32.to_be_bytes(), &2i32.to_be_bytes(), &[1]].concat();
+
+            match classify(&server_error(0x1200, &info)) {
+                Some(QueryError::ReadTimeout {
+                    received: 1,
+                    required: 2,
+                    data_present: true,
+                }) => (),
+                e => panic!("expected a read timeout, got {:?}", e),
+            }
+        }
+
+        #[test]
+        fn test_classify_unavailable() {
+            let info = [&QUORUM[..], &3i32.to_be_bytes(), &1i32.to_be_bytes()].concat();
+
+            match classify(&server_error(0x1000, &info)) {
+                Some(QueryError::Unavailable {
+                    alive: 1,
+                    required: 3,
+                }) => (),
+                e => panic!("expected an unavailable error, got {:?}", e),
+            }
+        }
+
+        #[test]
+        fn test_classify_overloaded() {
+            match classify(&server_error(0x1001, &[])) {
+                Some(e @ QueryError::Overloaded) => assert!(e.is_retryable()),
+                e => panic!("expected an overloaded error, got {:?}", e),
+            }
+        }
+
+        #[test]
+        fn test_classify_other() {
+            assert!(classify(&server_error(0x0000, &[])).is_none());
+            assert!(!QueryError::WriteTimeout {
+                acknowledged: 0,
+                required: 1
             }
+            .is_retryable());
         }
     }
 }
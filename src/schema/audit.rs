@@ -0,0 +1,689 @@
+use cdrs::{
+    error::Error as CDRSError,
+    query::{BatchExecutor, BatchQueryBuilder, QueryExecutor, QueryValues},
+    query_values,
+    types::{prelude::Row, IntoRustByName},
+};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use time::Timespec;
+use uuid::Uuid;
+
+use super::{
+    super::{
+        clock::{Clock, SystemClock},
+        db::{
+            scylla::{verify_table_schema, Scylla},
+            ClusteringOrder, ColumnSchema, Deserializable, Idempotent, InTable, Insertable,
+            Provider, Queryable, Serializable, TableSchema,
+        },
+        error::{IdentityError, QueryError, ValidationError},
+        result::IdentityResult,
+        DbSession,
+    },
+    user::RegistrationTimestamp,
+};
+
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt,
+};
+
+/// The number of seconds spanned by each partition of the audit timeline. Every user's events are
+/// recorded twice: once in their own audit log, and once in the timeline, in which events are
+/// partitioned by the day (since January 1, 1970, UTC) on which they occurred, and ordered by time
+/// within each day. Events in a time range can thus be read one day at a time, without scanning
+/// every user's log, and no one partition grows without bound.
+pub const AUDIT_BUCKET_SECS: i64 = 24 * 60 * 60;
+
+/// Gets the partition of the audit timeline in which events occurring at a time are recorded.
+///
+/// # Arguments
+///
+/// * `time` - The time at which an event occurred
+fn audit_bucket(time: DateTime<Utc>) -> i64 {
+    time.timestamp().div_euclid(AUDIT_BUCKET_SECS)
+}
+
+/// AuditKind represents any kind of security-relevant event that may be recorded in a user's
+/// audit log. Kinds are serialized as they are stored (e.g., "failed_login").
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    Login,
+    FailedLogin,
+    PasswordChanged,
+    EmailChanged,
+    UsernameChanged,
+    ProviderLinked,
+    ProviderUnlinked,
+}
+
+impl AuditKind {
+    /// Gets the name of the kind of event, as it is stored in the audit log.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::FailedLogin => "failed_login",
+            Self::PasswordChanged => "password_changed",
+            Self::EmailChanged => "email_changed",
+            Self::UsernameChanged => "username_changed",
+            Self::ProviderLinked => "provider_linked",
+            Self::ProviderUnlinked => "provider_unlinked",
+        }
+    }
+}
+
+impl fmt::Display for AuditKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl TryFrom<&str> for AuditKind {
+    type Error = CDRSError;
+
+    fn try_from(kind: &str) -> Result<Self, Self::Error> {
+        match kind {
+            "login" => Ok(Self::Login),
+            "failed_login" => Ok(Self::FailedLogin),
+            "password_changed" => Ok(Self::PasswordChanged),
+            "email_changed" => Ok(Self::EmailChanged),
+            "username_changed" => Ok(Self::UsernameChanged),
+            "provider_linked" => Ok(Self::ProviderLinked),
+            "provider_unlinked" => Ok(Self::ProviderUnlinked),
+            _ => Err(CDRSError::General(format!(
+                "unknown kind of audit event: {}",
+                kind
+            ))),
+        }
+    }
+}
+
+/// AuditEvent represents a security-relevant event concerning a user, as recorded in their audit
+/// log.
+#[derive(Debug)]
+pub struct AuditEvent<'a> {
+    /// The ID of the user that the event concerns
+    user_id: Uuid,
+
+    /// The time at which the event occurred
+    occurred_at: RegistrationTimestamp,
+
+    /// A unique ID distinguishing events that occurred in the same millisecond
+    event_id: Uuid,
+
+    /// The kind of event
+    kind: AuditKind,
+
+    /// Any further details of the event (e.g., the IP address that a login originated from)
+    detail: &'a str,
+}
+
+impl<'a> AuditEvent<'a> {
+    /// Creates a new event, occurring at the current UTC time.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that the event concerns
+    /// * `kind` - The kind of event
+    /// * `detail` - Any further details of the event
+    pub fn new(user_id: Uuid, kind: AuditKind, detail: &'a str) -> Self {
+        Self::at(user_id, kind, detail, SystemClock.now())
+    }
+
+    /// Creates a new event, occurring at the provided time.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that the event concerns
+    /// * `kind` - The kind of event
+    /// * `detail` - Any further details of the event
+    /// * `occurred_at` - The time at which the event occurred
+    pub fn at(user_id: Uuid, kind: AuditKind, detail: &'a str, occurred_at: DateTime<Utc>) -> Self {
+        Self {
+            user_id,
+            occurred_at: occurred_at.try_into().unwrap_or_default(),
+            event_id: Uuid::new_v4(),
+            kind,
+            detail,
+        }
+    }
+}
+
+/// OwnedAuditEvent represents an allocated event from a user's audit log.
+#[derive(Serialize, Debug)]
+pub struct OwnedAuditEvent {
+    user_id: Uuid,
+    occurred_at: RegistrationTimestamp,
+    event_id: Uuid,
+    kind: AuditKind,
+    detail: String,
+}
+
+impl OwnedAuditEvent {
+    /// Gets the ID of the user that the event concerns.
+    pub fn user_id(&self) -> &Uuid {
+        &self.user_id
+    }
+
+    /// Gets the time at which the event occurred.
+    pub fn occurred_at(&self) -> DateTime<Utc> {
+        self.occurred_at.into()
+    }
+
+    /// Gets the unique ID of the event.
+    pub fn event_id(&self) -> &Uuid {
+        &self.event_id
+    }
+
+    /// Gets the kind of event.
+    pub fn kind(&self) -> AuditKind {
+        self.kind
+    }
+
+    /// Gets any further details of the event.
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+
+    /// Gets a statement deleting the event's entry in the audit timeline, alongside its values.
+    pub(crate) fn timeline_deletion(&self) -> (&'static str, QueryValues) {
+        (
+            "DELETE FROM identity.audit_events_by_time WHERE bucket = ? AND occurred_at = ? AND event_id = ?;",
+            query_values!(
+                audit_bucket(self.occurred_at()),
+                <&RegistrationTimestamp as Into<Timespec>>::into(&self.occurred_at),
+                self.event_id
+            ),
+        )
+    }
+}
+
+/// AuditTimelineEntry represents an event's entry in the audit timeline, in which every user's
+/// events are partitioned by the day on which they occurred.
+#[derive(Debug)]
+struct AuditTimelineEntry<'e, 'a>(&'e AuditEvent<'a>);
+
+#[async_trait]
+impl InTable<Scylla, DbSession> for AuditEvent<'_> {
+    async fn create_prerequisite_objects(session: &DbSession) -> IdentityResult<()> {
+        session
+            .query(Self::table_schema().create_statement())
+            .await
+            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))?;
+
+        verify_table_schema(session, &Self::table_schema()).await?;
+
+        AuditTimelineEntry::create_prerequisite_objects(session).await
+    }
+
+    fn table_schema() -> TableSchema {
+        // Each user's security-relevant events, most recent first
+        TableSchema {
+            keyspace: "identity",
+            name: "audit_events",
+            columns: vec![
+                ColumnSchema::new("user_id", "UUID"),
+                ColumnSchema::new("occurred_at", "TIMESTAMP"),
+                ColumnSchema::new("event_id", "UUID"),
+                ColumnSchema::new("kind", "TEXT"),
+                ColumnSchema::new("detail", "TEXT"),
+            ],
+            partition_key: vec!["user_id"],
+            clustering_key: vec![
+                ("occurred_at", ClusteringOrder::Desc),
+                ("event_id", ClusteringOrder::Asc),
+            ],
+        }
+    }
+}
+
+impl Serializable<QueryValues> for AuditEvent<'_> {
+    type Error = IdentityError;
+
+    fn try_into(&self) -> Result<QueryValues, Self::Error> {
+        Ok(query_values!(
+            "user_id" => self.user_id,
+            "occurred_at" => <&RegistrationTimestamp as Into<Timespec>>::into(&self.occurred_at),
+            "event_id" => self.event_id,
+            "kind" => self.kind.as_str(),
+            "detail" => self.detail
+        ))
+    }
+}
+
+impl Idempotent for AuditEvent<'_> {
+    /// The event's ID is generated before it is inserted, so reinserting it overwrites the same
+    /// row.
+    fn idempotent(&self) -> bool {
+        true
+    }
+}
+
+impl Insertable<Scylla, DbSession> for AuditEvent<'_> {
+    const INSERTION_QUERY: &'static str = r#"INSERT INTO identity.audit_events (user_id, occurred_at, event_id, kind, detail) VALUES (?, ?, ?, ?, ?);"#;
+}
+
+#[async_trait]
+impl InTable<Scylla, DbSession> for AuditTimelineEntry<'_, '_> {
+    async fn create_prerequisite_objects(session: &DbSession) -> IdentityResult<()> {
+        session
+            .query(Self::table_schema().create_statement())
+            .await
+            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))?;
+
+        verify_table_schema(session, &Self::table_schema()).await
+    }
+
+    fn table_schema() -> TableSchema {
+        // Every user's security-relevant events, partitioned by day, oldest first
+        TableSchema {
+            keyspace: "identity",
+            name: "audit_events_by_time",
+            columns: vec![
+                ColumnSchema::new("bucket", "BIGINT"),
+                ColumnSchema::new("occurred_at", "TIMESTAMP"),
+                ColumnSchema::new("event_id", "UUID"),
+                ColumnSchema::new("user_id", "UUID"),
+                ColumnSchema::new("kind", "TEXT"),
+                ColumnSchema::new("detail", "TEXT"),
+            ],
+            partition_key: vec!["bucket"],
+            clustering_key: vec![
+                ("occurred_at", ClusteringOrder::Asc),
+                ("event_id", ClusteringOrder::Asc),
+            ],
+        }
+    }
+}
+
+impl Serializable<QueryValues> for AuditTimelineEntry<'_, '_> {
+    type Error = IdentityError;
+
+    fn try_into(&self) -> Result<QueryValues, Self::Error> {
+        let event = self.0;
+
+        Ok(query_values!(
+            "bucket" => audit_bucket(event.occurred_at.into()),
+            "occurred_at" => <&RegistrationTimestamp as Into<Timespec>>::into(&event.occurred_at),
+            "event_id" => event.event_id,
+            "user_id" => event.user_id,
+            "kind" => event.kind.as_str(),
+            "detail" => event.detail
+        ))
+    }
+}
+
+impl Idempotent for AuditTimelineEntry<'_, '_> {
+    /// The entry is keyed by its event, so reinserting it overwrites the same row.
+    fn idempotent(&self) -> bool {
+        true
+    }
+}
+
+impl Insertable<Scylla, DbSession> for AuditTimelineEntry<'_, '_> {
+    const INSERTION_QUERY: &'static str = r#"INSERT INTO identity.audit_events_by_time (bucket, occurred_at, event_id, user_id, kind, detail) VALUES (?, ?, ?, ?, ?, ?);"#;
+}
+
+impl Deserializable<OwnedAuditEvent, Row> for OwnedAuditEvent {
+    type Error = IdentityError;
+
+    fn try_from(value: Row) -> Result<OwnedAuditEvent, Self::Error> {
+        Ok(OwnedAuditEvent {
+            user_id: value.get_r_by_name("user_id")?,
+            occurred_at: <Row as IntoRustByName<Timespec>>::get_r_by_name(&value, "occurred_at")?
+                .into(),
+            event_id: value.get_r_by_name("event_id")?,
+            kind: AuditKind::try_from(
+                <Row as IntoRustByName<String>>::get_r_by_name(&value, "kind")?.as_str(),
+            )?,
+            detail: value.get_r_by_name("detail")?,
+        })
+    }
+}
+
+/// AuditLogQuery represents a query for every event in a user's audit log.
+#[derive(Debug)]
+pub struct AuditLogQuery<'a>(pub &'a Uuid);
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for AuditLogQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.audit_events WHERE user_id = {};",
+            self.0
+        ))
+    }
+}
+
+impl Scylla {
+    /// Records a security-relevant event in a user's audit log, occurring at the current UTC time.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that the event concerns
+    /// * `kind` - The kind of event
+    /// * `detail` - Any further details of the event
+    pub async fn record_audit_event(
+        &self,
+        user_id: &Uuid,
+        kind: AuditKind,
+        detail: &str,
+    ) -> IdentityResult<()> {
+        self.insert_audit_event(&AuditEvent::new(*user_id, kind, detail))
+            .await
+    }
+
+    /// Records an event in both the audit log of the user that it concerns and the audit
+    /// timeline, atomically. Batches only accept positional values, so each row's values are bound
+    /// in the order of its columns.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event that should be recorded
+    async fn insert_audit_event(&self, event: &AuditEvent<'_>) -> IdentityResult<()> {
+        let occurred_at = <&RegistrationTimestamp as Into<Timespec>>::into(&event.occurred_at);
+
+        let query = BatchQueryBuilder::new()
+            .add_query(
+                <AuditEvent as Insertable<Scylla, DbSession>>::INSERTION_QUERY,
+                query_values!(
+                    event.user_id,
+                    occurred_at,
+                    event.event_id,
+                    event.kind.as_str(),
+                    event.detail
+                ),
+            )
+            .add_query(
+                <AuditTimelineEntry as Insertable<Scylla, DbSession>>::INSERTION_QUERY,
+                query_values!(
+                    audit_bucket(event.occurred_at.into()),
+                    occurred_at,
+                    event.event_id,
+                    event.user_id,
+                    event.kind.as_str(),
+                    event.detail
+                ),
+            )
+            .finalize()?;
+
+        self.run(self.session().batch_with_params(query))
+            .await
+            .map(|_| ())
+    }
+
+    /// Streams every user's events that occurred at or after the start of a time range, but
+    /// before its end, ordered from oldest to most recent. Events are read from the audit
+    /// timeline one day (see AUDIT_BUCKET_SECS) at a time, fetching one page at a time as the
+    /// stream is consumed. If the range holds more events than the connector's maximum result
+    /// size, the stream ends with a QueryError::ResultTooLarge.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The start of the time range, inclusive
+    /// * `to` - The end of the time range, exclusive
+    pub fn stream_audit_events(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> impl Stream<Item = IdentityResult<OwnedAuditEvent>> + '_ {
+        let queries = (audit_bucket(from)..=audit_bucket(to)).map(move |bucket| {
+            format!(
+                "SELECT * FROM identity.audit_events_by_time WHERE bucket = {} AND occurred_at >= {} AND occurred_at < {};",
+                bucket,
+                from.timestamp_millis(),
+                to.timestamp_millis()
+            )
+        });
+
+        self.limit_rows(
+            stream::iter(queries)
+                .map(move |query| self.stream_pages(query))
+                .flatten(),
+        )
+        .and_then(|row| async move {
+            <OwnedAuditEvent as Deserializable<OwnedAuditEvent, Row>>::try_from(row)
+        })
+    }
+
+    /// Loads every event in a user's audit log, ordered from most recent to oldest. Prefer
+    /// load_audit_events for anything but exhaustive exports.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose events should be loaded
+    pub async fn load_audit_log(&self, user_id: &Uuid) -> IdentityResult<Vec<OwnedAuditEvent>> {
+        self.load_records(&AuditLogQuery(user_id)).await
+    }
+
+    /// Loads a page of the events in a user's audit log, ordered from most recent to oldest.
+    /// Since the events preceding the page must still be read, later pages are more expensive to
+    /// load than earlier ones. Fails with a ValidationError::InvalidPage if the limit is zero, or
+    /// if more events than can be requested at once precede the end of the page.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose events should be loaded
+    /// * `page` - The index of the page that should be loaded, starting at zero
+    /// * `limit` - The maximum number of events on each page
+    /// * `kind_filter` - The kind of event that should be loaded, if not every kind
+    pub async fn load_audit_events(
+        &self,
+        user_id: &Uuid,
+        page: usize,
+        limit: usize,
+        kind_filter: Option<AuditKind>,
+    ) -> IdentityResult<Vec<OwnedAuditEvent>> {
+        // Every event up to the end of the page is requested, so their number must fit a CQL int
+        let skipped = page
+            .checked_mul(limit)
+            .ok_or(ValidationError::InvalidPage)?;
+        let fetched = skipped
+            .checked_add(limit)
+            .filter(|_| limit > 0)
+            .and_then(|fetched| i32::try_from(fetched).ok())
+            .ok_or(ValidationError::InvalidPage)?;
+
+        // Filtering is confined to the user's partition
+        let (query, values) = match kind_filter {
+            Some(kind) => (
+                "SELECT * FROM identity.audit_events WHERE user_id = ? AND kind = ? LIMIT ? ALLOW FILTERING;",
+                query_values!(*user_id, kind.as_str(), fetched),
+            ),
+            None => (
+                "SELECT * FROM identity.audit_events WHERE user_id = ? LIMIT ?;",
+                query_values!(*user_id, fetched),
+            ),
+        };
+
+        self.run(self.session().query_with_values(query, values))
+            .await
+            .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+            .and_then(|resp| resp.into_rows().ok_or(QueryError::NoResults.into()))?
+            .into_iter()
+            .skip(skipped)
+            .map(<OwnedAuditEvent as Deserializable<OwnedAuditEvent, Row>>::try_from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::error::Error;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_insertion_columns_match() -> Result<(), Box<dyn Error>> {
+        let event = AuditEvent::new(Uuid::new_v4(), AuditKind::Login, "127.0.0.1");
+
+        testing::assert_insertion_columns_match(&event)?;
+        testing::assert_insertion_columns_match(&AuditTimelineEntry(&event))
+    }
+
+    #[tokio::test]
+    async fn test_load_audit_events() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        AuditEvent::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        // Logins and password changes alternate, a minute apart
+        let (user_id, start) = (Uuid::new_v4(), Utc::now());
+        let kinds = [
+            AuditKind::Login,
+            AuditKind::PasswordChanged,
+            AuditKind::Login,
+            AuditKind::PasswordChanged,
+            AuditKind::Login,
+        ];
+        for (i, kind) in kinds.iter().enumerate() {
+            let detail = i.to_string();
+
+            db.insert_record(&AuditEvent::at(
+                user_id,
+                *kind,
+                &detail,
+                start + chrono::Duration::minutes(i as i64),
+            ))
+            .await?;
+        }
+
+        let details = |events: Vec<OwnedAuditEvent>| {
+            events
+                .iter()
+                .map(|event| event.detail().to_owned())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            details(db.load_audit_events(&user_id, 0, 2, None).await?),
+            vec!["4", "3"]
+        );
+        assert_eq!(
+            details(
+                db.load_audit_events(&user_id, 0, 2, Some(AuditKind::Login))
+                    .await?
+            ),
+            vec!["4", "2"]
+        );
+        assert_eq!(
+            details(
+                db.load_audit_events(&user_id, 1, 2, Some(AuditKind::Login))
+                    .await?
+            ),
+            vec!["0"]
+        );
+        assert!(db
+            .load_audit_events(&user_id, 1, 2, Some(AuditKind::PasswordChanged))
+            .await?
+            .is_empty());
+
+        // Pages that are empty, or that can't be requested, are rejected before querying
+        for (page, limit) in &[(0, 0), (usize::MAX, 2), (1, i32::MAX as usize)] {
+            match db.load_audit_events(&user_id, *page, *limit, None).await {
+                Err(IdentityError::ValidationError(ValidationError::InvalidPage)) => (),
+                res => panic!("expected the page to be invalid, got {:?}", res),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_record_audit_event() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        AuditEvent::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let user_id = Uuid::new_v4();
+        db.record_audit_event(&user_id, AuditKind::PasswordChanged, "127.0.0.1")
+            .await?;
+
+        // The event is recorded in the user's own log
+        let logged = db.load_audit_log(&user_id).await?;
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].kind(), AuditKind::PasswordChanged);
+        assert_eq!(logged[0].detail(), "127.0.0.1");
+
+        // And in the timeline, under the same ID
+        let occurred_at = logged[0].occurred_at();
+        let timeline = db
+            .stream_audit_events(occurred_at, occurred_at + chrono::Duration::milliseconds(1))
+            .try_filter(|event| futures::future::ready(event.user_id() == &user_id))
+            .try_collect::<Vec<OwnedAuditEvent>>()
+            .await?;
+        assert_eq!(timeline.len(), 1);
+        assert_eq!(timeline[0].event_id(), logged[0].event_id());
+        assert_eq!(timeline[0].kind(), AuditKind::PasswordChanged);
+        assert_eq!(timeline[0].detail(), "127.0.0.1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_audit_events() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        AuditEvent::create_prerequisite_objects(&session).await?;
+
+        // Single-row pages, such that events are paged through within each bucket
+        let db = Scylla::new(session).with_page_size(1);
+
+        // Events are recorded across five days, of which only the middle three are streamed
+        let (user_id, start) = (Uuid::new_v4(), Utc::now() - chrono::Duration::days(30));
+        let (from, to) = (
+            start + chrono::Duration::days(1),
+            start + chrono::Duration::days(4),
+        );
+        let offsets = [
+            chrono::Duration::zero(),
+            chrono::Duration::days(1),
+            chrono::Duration::days(2),
+            chrono::Duration::days(2) + chrono::Duration::minutes(1),
+            chrono::Duration::days(3),
+            chrono::Duration::days(4),
+        ];
+        for (i, offset) in offsets.iter().enumerate() {
+            let detail = i.to_string();
+
+            db.insert_audit_event(&AuditEvent::at(
+                user_id,
+                AuditKind::Login,
+                &detail,
+                start + *offset,
+            ))
+            .await?;
+        }
+
+        // Other tests' events may lie within the range
+        let details = db
+            .stream_audit_events(from, to)
+            .try_filter(|event| futures::future::ready(event.user_id() == &user_id))
+            .map_ok(|event| event.detail().to_owned())
+            .try_collect::<Vec<String>>()
+            .await?;
+        assert_eq!(details, vec!["1", "2", "3", "4"]);
+
+        // Events are also recorded in the user's own log
+        assert_eq!(db.load_audit_log(&user_id).await?.len(), offsets.len());
+
+        assert!(db
+            .stream_audit_events(to, from)
+            .try_collect::<Vec<OwnedAuditEvent>>()
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{
+    super::{
+        db::{scylla::Scylla, Provider},
+        result::IdentityResult,
+    },
+    audit::OwnedAuditEvent,
+    provider_identity::OwnedProviderIdentity,
+    session::LoginSession,
+    user::{OwnedUser, UserQuery},
+    user_email::UserEmail,
+    username_history::OwnedUsernameChange,
+};
+
+/// UserDataExport represents everything stored about a user, as is disclosed upon a data subject
+/// access request. The user's password hash and salt are never serialized, the IDs of the user's
+/// sessions are omitted, as are the tokens issued to the user by identity providers.
+#[derive(Serialize, Debug)]
+pub struct UserDataExport {
+    /// The user's profile
+    user: OwnedUser,
+
+    /// Every email address added by the user, including their primary address
+    emails: Vec<UserEmail>,
+
+    /// Every external account linked to the user
+    provider_identities: Vec<OwnedProviderIdentity>,
+
+    /// Every username that the user held before their current username
+    username_history: Vec<OwnedUsernameChange>,
+
+    /// Every event in the user's audit log, most recent first
+    audit_events: Vec<OwnedAuditEvent>,
+
+    /// Every session established by the user that has yet to be revoked
+    sessions: Vec<LoginSession>,
+}
+
+impl UserDataExport {
+    /// Gets the user's profile.
+    pub fn user(&self) -> &OwnedUser {
+        &self.user
+    }
+
+    /// Gets every email address added by the user, including their primary address. Users that
+    /// have never added an email address have none.
+    pub fn emails(&self) -> &[UserEmail] {
+        &self.emails
+    }
+
+    /// Gets every external account linked to the user.
+    pub fn provider_identities(&self) -> &[OwnedProviderIdentity] {
+        &self.provider_identities
+    }
+
+    /// Gets every username that the user held before their current username.
+    pub fn username_history(&self) -> &[OwnedUsernameChange] {
+        &self.username_history
+    }
+
+    /// Gets every event in the user's audit log, most recent first.
+    pub fn audit_events(&self) -> &[OwnedAuditEvent] {
+        &self.audit_events
+    }
+
+    /// Gets every session established by the user that has yet to be revoked, including those
+    /// that have expired.
+    pub fn sessions(&self) -> &[LoginSession] {
+        &self.sessions
+    }
+}
+
+impl Scylla {
+    /// Gathers everything stored about a user into a single, serializable export.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose data should be exported
+    pub async fn export_user_data(&self, user_id: &Uuid) -> IdentityResult<UserDataExport> {
+        Ok(UserDataExport {
+            user: self.load_record(&UserQuery::Id(user_id)).await?,
+            emails: self.load_user_emails(user_id).await?,
+            provider_identities: self.load_linked_identities(user_id).await?,
+            username_history: self.load_username_history(user_id).await?,
+            audit_events: self.load_audit_log(user_id).await?,
+            sessions: self.load_user_sessions(user_id).await?,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::error::Error;
+
+    use super::{
+        super::{
+            audit::{AuditEvent, AuditKind},
+            provider_identity::ProviderIdentity,
+            session::LoginSession,
+            user::{IdentityProvider, User},
+            user_email::UserEmail,
+            username_history::UsernameChange,
+        },
+        *,
+    };
+    use crate::{db::InTable, testing};
+
+    #[cfg(all(feature = "provider-google", feature = "provider-discord"))]
+    #[tokio::test]
+    async fn test_export_user_data() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        UsernameChange::create_prerequisite_objects(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+        AuditEvent::create_prerequisite_objects(&session).await?;
+        UserEmail::create_prerequisite_objects(&session).await?;
+        LoginSession::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_random_user();
+        testing::insert_user(&db, &User::from(&u)).await?;
+
+        for provider in &[IdentityProvider::Google, IdentityProvider::Discord] {
+            db.link_provider(u.id(), *provider, &Uuid::new_v4().to_string())
+                .await?;
+        }
+        db.record_username_change(u.id(), "previous").await?;
+        db.add_email(u.id(), &format!("secondary.{}", u.email()))
+            .await?;
+        db.record_audit_event(u.id(), AuditKind::Login, "127.0.0.1")
+            .await?;
+        db.record_audit_event(u.id(), AuditKind::ProviderLinked, "google")
+            .await?;
+        let login = db
+            .create_session(u.id(), std::time::Duration::from_secs(60 * 60))
+            .await?;
+
+        let export = db.export_user_data(u.id()).await?;
+        assert_eq!(export.user().id(), u.id());
+        assert_eq!(export.emails().len(), 2);
+        assert_eq!(export.provider_identities().len(), 2);
+        assert_eq!(
+            export
+                .username_history()
+                .iter()
+                .map(|change| change.username())
+                .collect::<Vec<&str>>(),
+            vec!["previous"]
+        );
+        assert_eq!(export.audit_events().len(), 2);
+        assert_eq!(export.sessions(), &[login][..]);
+
+        // Password material and session IDs must never leave the database
+        let json = serde_json::to_string(&export)?;
+        assert!(json.contains(u.username()));
+        assert!(!json.contains("password_hash"));
+        assert!(!json.contains(&export.sessions()[0].session_id().to_string()));
+
+        Ok(())
+    }
+}
@@ -1 +1,9 @@
+pub mod audit;
+pub mod export;
+pub mod provider_identity;
+pub mod provider_profile;
+pub mod session;
+pub mod token;
 pub mod user;
+pub mod user_email;
+pub mod username_history;
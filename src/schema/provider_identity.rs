@@ -0,0 +1,973 @@
+use cdrs::{
+    error::Error as CDRSError,
+    query::{BatchQueryBuilder, QueryExecutor, QueryValues},
+    query_values,
+    types::{prelude::Row, IntoRustByName},
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{
+    super::{
+        db::{
+            scylla::{verify_table_schema, Scylla},
+            ClusteringOrder, ColumnSchema, Deserializable, Idempotent, InTable, Insertable,
+            Provider, Queryable, Serializable, TableSchema,
+        },
+        error::{IdentityError, QueryError},
+        result::IdentityResult,
+        DbSession,
+    },
+    user::{
+        normalize_email, normalize_username, validate_email, IdentityProvider, OwnedUser, User,
+        UserQuery, MAX_USERNAME_LENGTH, MIN_USERNAME_LENGTH,
+    },
+};
+
+use std::{collections::HashMap, convert::TryInto};
+
+/// The maximum number of times that resolving an external account may conflict with concurrent
+/// resolutions before giving up.
+const MAX_RESOLUTION_ATTEMPTS: usize = 3;
+
+/// The maximum number of usernames tried when creating a user for an external account.
+const MAX_USERNAME_ATTEMPTS: usize = 5;
+
+/// The number of random characters appended to a username hint that has already been taken.
+const USERNAME_SUFFIX_LENGTH: usize = 6;
+
+/// Derives a valid username from a username suggested by an identity provider, leaving room for
+/// a random suffix.
+///
+/// # Arguments
+///
+/// * `hint` - The username that the user holds with the identity provider
+fn username_from_hint(hint: &str) -> String {
+    let username = normalize_username(hint)
+        .chars()
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '_' || *c == '.')
+        .take(MAX_USERNAME_LENGTH - USERNAME_SUFFIX_LENGTH - 1)
+        .collect::<String>();
+
+    if username.len() < MIN_USERNAME_LENGTH {
+        "user".to_owned()
+    } else {
+        username
+    }
+}
+
+/// ProviderIdentity represents a link between an account held with an identity provider and the
+/// swaply user that it authenticates.
+#[derive(Debug)]
+pub struct ProviderIdentity<'a> {
+    /// The provider that the external account is held with
+    provider: IdentityProvider,
+
+    /// The ID of the external account, as issued by the provider
+    provider_user_id: &'a str,
+
+    /// The ID of the swaply user that the external account is linked to
+    user_id: Uuid,
+}
+
+impl<'a> ProviderIdentity<'a> {
+    /// Creates a new link between an external account and a swaply user.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - The provider that the external account is held with
+    /// * `provider_user_id` - The ID of the external account, as issued by the provider
+    /// * `user_id` - The ID of the swaply user that the external account should be linked to
+    pub fn new(provider: IdentityProvider, provider_user_id: &'a str, user_id: Uuid) -> Self {
+        Self {
+            provider,
+            provider_user_id,
+            user_id,
+        }
+    }
+}
+
+/// OwnedProviderIdentity represents an allocated link between an external account and a swaply
+/// user.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct OwnedProviderIdentity {
+    provider: IdentityProvider,
+    provider_user_id: String,
+    user_id: Uuid,
+}
+
+impl OwnedProviderIdentity {
+    /// Gets the provider that the external account is held with.
+    pub fn provider(&self) -> IdentityProvider {
+        self.provider
+    }
+
+    /// Gets the ID of the external account, as issued by the provider.
+    pub fn provider_user_id(&self) -> &str {
+        &self.provider_user_id
+    }
+
+    /// Gets the ID of the swaply user that the external account is linked to.
+    pub fn user_id(&self) -> &Uuid {
+        &self.user_id
+    }
+}
+
+#[async_trait]
+impl InTable<Scylla, DbSession> for ProviderIdentity<'_> {
+    async fn create_prerequisite_objects(session: &DbSession) -> IdentityResult<()> {
+        session
+            .query(Self::table_schema().create_statement())
+            .await
+            .and(
+                session
+                    .query(
+                        // Mappings from swaply users to their linked external accounts
+                        "CREATE INDEX IF NOT EXISTS ON identity.provider_identities (user_id);",
+                    )
+                    .await,
+            )
+            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))?;
+
+        verify_table_schema(session, &Self::table_schema()).await
+    }
+
+    fn table_schema() -> TableSchema {
+        // Mappings from each external account to the swaply user it authenticates, partitioned
+        // by provider
+        TableSchema {
+            keyspace: "identity",
+            name: "provider_identities",
+            columns: vec![
+                ColumnSchema::new("provider", "TEXT"),
+                ColumnSchema::new("provider_user_id", "TEXT"),
+                ColumnSchema::new("user_id", "UUID"),
+            ],
+            partition_key: vec!["provider"],
+            clustering_key: vec![("provider_user_id", ClusteringOrder::Asc)],
+        }
+    }
+}
+
+impl Serializable<QueryValues> for ProviderIdentity<'_> {
+    type Error = IdentityError;
+
+    fn try_into(&self) -> Result<QueryValues, Self::Error> {
+        Ok(query_values!(
+            "provider" => <&str as From<IdentityProvider>>::from(self.provider),
+            "provider_user_id" => self.provider_user_id,
+            "user_id" => self.user_id
+        ))
+    }
+}
+
+impl Idempotent for ProviderIdentity<'_> {
+    /// Identities are keyed by the provider's ID for the account, so relinking it overwrites the
+    /// same row.
+    fn idempotent(&self) -> bool {
+        true
+    }
+}
+
+impl Insertable<Scylla, DbSession> for ProviderIdentity<'_> {
+    const INSERTION_QUERY: &'static str = r#"INSERT INTO identity.provider_identities (provider, provider_user_id, user_id) VALUES (?, ?, ?);"#;
+}
+
+impl Deserializable<OwnedProviderIdentity, Row> for OwnedProviderIdentity {
+    type Error = IdentityError;
+
+    fn try_from(value: Row) -> Result<OwnedProviderIdentity, Self::Error> {
+        Ok(OwnedProviderIdentity {
+            provider: <Row as IntoRustByName<String>>::get_r_by_name(&value, "provider")?
+                .try_into()?,
+            provider_user_id: value.get_r_by_name("provider_user_id")?,
+            user_id: value.get_r_by_name("user_id")?,
+        })
+    }
+}
+
+/// ProviderIdentityQuery represents a query for the swaply user linked to an external account.
+#[derive(Debug)]
+pub struct ProviderIdentityQuery<'a> {
+    pub provider: IdentityProvider,
+    pub provider_user_id: &'a str,
+}
+
+/// LinkedIdentitiesQuery represents a query for every external account linked to a swaply user.
+#[derive(Debug)]
+pub struct LinkedIdentitiesQuery<'a>(pub &'a Uuid);
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for LinkedIdentitiesQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.provider_identities WHERE user_id = {};",
+            self.0
+        ))
+    }
+}
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for ProviderIdentityQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.provider_identities WHERE provider = '{}' AND provider_user_id = '{}';",
+            <&str as From<IdentityProvider>>::from(self.provider),
+            self.provider_user_id.replace('\'', "''")
+        ))
+    }
+}
+
+impl Scylla {
+    /// Links an external account to a swaply user, unless the account is already linked to some
+    /// user. Returns whether or not the account was linked.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the swaply user that the account should be linked to
+    /// * `provider` - The provider that the external account is held with
+    /// * `provider_user_id` - The ID of the external account, as issued by the provider
+    pub async fn link_provider(
+        &self,
+        user_id: &Uuid,
+        provider: IdentityProvider,
+        provider_user_id: &str,
+    ) -> IdentityResult<bool> {
+        self.insert_if_absent(&ProviderIdentity::new(provider, provider_user_id, *user_id))
+            .await
+    }
+
+    /// Replaces the ID of the external account held with a provider that is linked to a swaply
+    /// user, as is necessary when the provider migrates its account IDs. The old link is removed
+    /// and the new link is added atomically, unless the new ID is already linked to some user.
+    /// Returns whether or not the account was relinked.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the swaply user whose linked account should be relinked
+    /// * `provider` - The provider that the external account is held with
+    /// * `new_provider_user_id` - The new ID of the external account, as issued by the provider
+    pub async fn relink_provider(
+        &self,
+        user_id: &Uuid,
+        provider: IdentityProvider,
+        new_provider_user_id: &str,
+    ) -> IdentityResult<bool> {
+        let mut linked = self
+            .load_linked_identities(user_id)
+            .await?
+            .into_iter()
+            .filter(|identity| identity.provider == provider);
+
+        let old = match (linked.next(), linked.next()) {
+            (Some(old), None) => old,
+            (None, _) => return Err(QueryError::NoResults.into()),
+            (Some(_), Some(_)) => return Err(QueryError::AmbiguousLink.into()),
+        };
+
+        if old.provider_user_id == new_provider_user_id {
+            return Ok(true);
+        }
+
+        // Both links lie in the provider's partition, so they may be conditionally updated by a
+        // single batch
+        let provider = <&str as From<IdentityProvider>>::from(provider);
+        self.execute_lwt_batch(
+            BatchQueryBuilder::new()
+                .add_query(
+                    "DELETE FROM identity.provider_identities WHERE provider = ? AND provider_user_id = ? IF user_id = ?;",
+                    query_values!(provider, old.provider_user_id, *user_id),
+                )
+                .add_query(
+                    "INSERT INTO identity.provider_identities (provider, provider_user_id, user_id) VALUES (?, ?, ?) IF NOT EXISTS;",
+                    query_values!(provider, new_provider_user_id, *user_id),
+                ),
+        )
+        .await
+    }
+
+    /// Loads the link between an external account and the swaply user it authenticates.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - The provider that the external account is held with
+    /// * `provider_user_id` - The ID of the external account, as issued by the provider
+    pub async fn load_provider_identity(
+        &self,
+        provider: IdentityProvider,
+        provider_user_id: &str,
+    ) -> IdentityResult<OwnedProviderIdentity> {
+        self.load_record(&ProviderIdentityQuery {
+            provider,
+            provider_user_id,
+        })
+        .await
+    }
+
+    /// Loads every external account linked to a swaply user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the swaply user whose linked accounts should be loaded
+    pub async fn load_linked_identities(
+        &self,
+        user_id: &Uuid,
+    ) -> IdentityResult<Vec<OwnedProviderIdentity>> {
+        self.load_records(&LinkedIdentitiesQuery(user_id)).await
+    }
+
+    /// Finds every external account linked to the swaply user holding an email address, across
+    /// all providers, as is needed when investigating account takeovers. Finds nothing if no user
+    /// holds the address.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The email address of the swaply user whose linked accounts should be found
+    pub async fn find_identities_by_email(
+        &self,
+        email: &str,
+    ) -> IdentityResult<Vec<OwnedProviderIdentity>> {
+        // Email addresses are reserved separately from the provider identities table, so the two
+        // are joined here
+        match self.email_owner(&normalize_email(email)).await? {
+            Some(user_id) => self.load_linked_identities(&user_id).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Lists each provider with which a swaply user has linked an external account. A user
+    /// without any linked accounts has no linked providers.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the swaply user whose linked providers should be listed
+    pub async fn list_linked_providers(
+        &self,
+        user_id: &Uuid,
+    ) -> IdentityResult<Vec<IdentityProvider>> {
+        let mut providers = self
+            .load_linked_identities(user_id)
+            .await?
+            .into_iter()
+            .map(|identity| identity.provider)
+            .collect::<Vec<IdentityProvider>>();
+
+        // A user may have linked several accounts held with the same provider, which are
+        // returned adjacently since they share a partition
+        providers.dedup();
+
+        Ok(providers)
+    }
+
+    /// Unlinks every external account held with a provider from a swaply user, returning whether
+    /// or not any accounts were unlinked. Unlinking fails with a QueryError::LastAuthMethod if
+    /// the user has no password and no other linked accounts, as they would be locked out.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the swaply user whose accounts should be unlinked
+    /// * `provider` - The provider that the unlinked accounts are held with
+    pub async fn unlink_provider(
+        &self,
+        user_id: &Uuid,
+        provider: IdentityProvider,
+    ) -> IdentityResult<bool> {
+        let linked = self.load_linked_identities(user_id).await?;
+        let (unlinked, remaining): (Vec<OwnedProviderIdentity>, Vec<OwnedProviderIdentity>) =
+            linked
+                .into_iter()
+                .partition(|identity| identity.provider == provider);
+
+        if unlinked.is_empty() {
+            return Ok(false);
+        }
+
+        if remaining.is_empty() {
+            let u: OwnedUser = self.load_record(&UserQuery::Id(user_id)).await?;
+
+            if !u.has_password() {
+                return Err(QueryError::LastAuthMethod.into());
+            }
+        }
+
+        for identity in unlinked {
+            self.execute(
+                "DELETE FROM identity.provider_identities WHERE provider = ? AND provider_user_id = ?;",
+                query_values!(
+                    <&str as From<IdentityProvider>>::from(identity.provider),
+                    identity.provider_user_id
+                ),
+            )
+            .await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Resolves the swaply user authenticated by an external account, as is done upon an OAuth
+    /// callback. If the account isn't linked to any user, it is linked to the user holding the
+    /// same email address, or to a new, password-less user if no user holds the address. Returns
+    /// the user, and whether or not they were created.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - The provider that the external account is held with
+    /// * `provider_user_id` - The ID of the external account, as issued by the provider
+    /// * `email` - The email address of the external account, as verified by the provider
+    /// * `username_hint` - The username of the external account, from which the username of a
+    /// new user is derived
+    pub async fn resolve_or_create_from_provider(
+        &self,
+        provider: IdentityProvider,
+        provider_user_id: &str,
+        email: &str,
+        username_hint: &str,
+    ) -> IdentityResult<(OwnedUser, bool)> {
+        let email = normalize_email(email);
+        validate_email(&email)?;
+
+        // Each attempt may be undone by a concurrent callback for the same account or address,
+        // in which case the callback's work is resolved by the next attempt
+        for _ in 0..MAX_RESOLUTION_ATTEMPTS {
+            match self
+                .load_provider_identity(provider, provider_user_id)
+                .await
+            {
+                Ok(identity) => {
+                    return Ok((
+                        self.load_record(&UserQuery::Id(&identity.user_id)).await?,
+                        false,
+                    ))
+                }
+                Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+                Err(e) => return Err(e),
+            }
+
+            if let Some(user_id) = self.email_owner(&email).await? {
+                if self
+                    .link_provider(&user_id, provider, provider_user_id)
+                    .await?
+                {
+                    return Ok((self.load_record(&UserQuery::Id(&user_id)).await?, false));
+                }
+
+                continue;
+            }
+
+            let u = match self.insert_from_hint(username_hint, &email).await {
+                Ok(u) => u,
+                Err(IdentityError::QueryError(QueryError::EmailTaken)) => continue,
+                Err(e) => return Err(e),
+            };
+
+            // A concurrent callback may have found the new user by their email address and linked
+            // them first
+            if self
+                .link_provider(u.id(), provider, provider_user_id)
+                .await?
+                || self
+                    .load_provider_identity(provider, provider_user_id)
+                    .await?
+                    .user_id
+                    == *u.id()
+            {
+                return Ok((u, true));
+            }
+        }
+
+        Err(QueryError::Contended.into())
+    }
+
+    /// Inserts a new, password-less user with a username derived from a hint, appending a random
+    /// suffix to the username for as long as it is taken.
+    ///
+    /// # Arguments
+    ///
+    /// * `username_hint` - The username from which the user's username should be derived
+    /// * `email` - The normalized email address of the user
+    async fn insert_from_hint(
+        &self,
+        username_hint: &str,
+        email: &str,
+    ) -> IdentityResult<OwnedUser> {
+        let base = username_from_hint(username_hint);
+
+        for attempt in 0..MAX_USERNAME_ATTEMPTS {
+            let username = if attempt == 0 {
+                base.clone()
+            } else {
+                format!(
+                    "{}_{}",
+                    base,
+                    &Uuid::new_v4().to_simple().to_string()[..USERNAME_SUFFIX_LENGTH]
+                )
+            };
+
+            let u = User::new(None, &username, email, None, None);
+
+            match self.insert_unique(&u).await {
+                Ok(()) => return Ok(u.into()),
+                Err(IdentityError::QueryError(QueryError::UsernameTaken)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(QueryError::UsernameTaken.into())
+    }
+
+    /// Counts the number of external accounts linked with each provider. Each provider's links
+    /// are counted by scanning its partition, so this should be reserved for administrative use.
+    pub async fn count_by_provider(&self) -> IdentityResult<HashMap<IdentityProvider, u64>> {
+        let mut counts = HashMap::new();
+
+        for provider in IdentityProvider::all() {
+            let count: i64 = self
+                .run(self.session().query_with_values(
+                    "SELECT COUNT(*) FROM identity.provider_identities WHERE provider = ?;",
+                    query_values!(<&str as From<IdentityProvider>>::from(provider)),
+                ))
+                .await
+                .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+                .and_then(|resp| resp.into_rows().ok_or(QueryError::NoResults.into()))
+                .and_then(|rows| rows.into_iter().next().ok_or(QueryError::NoResults.into()))
+                .and_then(|row| row.get_r_by_name("count").map_err(|e| e.into()))?;
+
+            counts.insert(provider, count as u64);
+        }
+
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::error::Error;
+
+    use super::{super::user::User, *};
+    use crate::testing;
+
+    #[cfg(feature = "provider-google")]
+    #[test]
+    fn test_insertion_columns_match() -> Result<(), Box<dyn Error>> {
+        testing::assert_insertion_columns_match(&ProviderIdentity::new(
+            IdentityProvider::Google,
+            "1234",
+            Uuid::new_v4(),
+        ))
+    }
+
+    #[cfg(all(feature = "provider-google", feature = "provider-discord"))]
+    #[tokio::test]
+    async fn test_count_by_provider() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let before = db.count_by_provider().await?;
+
+        for provider in &[
+            IdentityProvider::Google,
+            IdentityProvider::Google,
+            IdentityProvider::Discord,
+        ] {
+            let external_id = Uuid::new_v4().to_string();
+            assert!(
+                db.link_provider(&Uuid::new_v4(), *provider, &external_id)
+                    .await?
+            );
+        }
+
+        let after = db.count_by_provider().await?;
+        assert_eq!(
+            after[&IdentityProvider::Google] - before[&IdentityProvider::Google],
+            2
+        );
+        assert_eq!(
+            after[&IdentityProvider::Discord] - before[&IdentityProvider::Discord],
+            1
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_link_provider_once() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (user_id, external_id) = (Uuid::new_v4(), Uuid::new_v4().to_string());
+        assert!(
+            db.link_provider(&user_id, IdentityProvider::Google, &external_id)
+                .await?
+        );
+
+        // An external account may only ever authenticate a single user
+        assert!(
+            !db.link_provider(&Uuid::new_v4(), IdentityProvider::Google, &external_id)
+                .await?
+        );
+        assert_eq!(
+            db.load_provider_identity(IdentityProvider::Google, &external_id)
+                .await?
+                .user_id(),
+            &user_id
+        );
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "provider-google", feature = "provider-discord"))]
+    #[tokio::test]
+    async fn test_list_linked_providers() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let user_id = Uuid::new_v4();
+        assert!(db.list_linked_providers(&user_id).await?.is_empty());
+
+        for provider in &[IdentityProvider::Google, IdentityProvider::Discord] {
+            db.link_provider(&user_id, *provider, &Uuid::new_v4().to_string())
+                .await?;
+        }
+
+        let mut providers = db.list_linked_providers(&user_id).await?;
+        providers.sort_by_key(|provider| <&str as From<IdentityProvider>>::from(*provider));
+        assert_eq!(
+            providers,
+            vec![IdentityProvider::Discord, IdentityProvider::Google]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "provider-google", feature = "provider-discord"))]
+    #[tokio::test]
+    async fn test_unlink_provider() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+
+        for provider in &[IdentityProvider::Google, IdentityProvider::Discord] {
+            db.link_provider(u.id(), *provider, &Uuid::new_v4().to_string())
+                .await?;
+        }
+
+        assert!(db.unlink_provider(u.id(), IdentityProvider::Google).await?);
+        assert!(!db.unlink_provider(u.id(), IdentityProvider::Google).await?);
+        assert_eq!(
+            db.list_linked_providers(u.id()).await?,
+            vec![IdentityProvider::Discord]
+        );
+
+        // The user still has a password to fall back on
+        assert!(
+            db.unlink_provider(u.id(), IdentityProvider::Discord)
+                .await?
+        );
+        assert!(db.list_linked_providers(u.id()).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_unlink_last_auth_method() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        // The user has only ever authenticated via google
+        let u = User::new(None, "test", "test@test.com", None, None);
+        testing::insert_user(&db, &u).await?;
+
+        db.link_provider(
+            u.id(),
+            IdentityProvider::Google,
+            &Uuid::new_v4().to_string(),
+        )
+        .await?;
+
+        match db.unlink_provider(u.id(), IdentityProvider::Google).await {
+            Err(IdentityError::QueryError(QueryError::LastAuthMethod)) => (),
+            res => panic!("expected the last auth method to be kept, got {:?}", res),
+        }
+        assert_eq!(
+            db.list_linked_providers(u.id()).await?,
+            vec![IdentityProvider::Google]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "provider-google", feature = "provider-discord"))]
+    #[tokio::test]
+    async fn test_find_identities_by_email() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let email = unique_email();
+        let u = User::new(
+            None,
+            &Uuid::new_v4().to_simple().to_string()[..16],
+            &email,
+            None,
+            None,
+        );
+        db.insert_unique(&u).await?;
+
+        let (google_id, discord_id) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        db.link_provider(u.id(), IdentityProvider::Google, &google_id)
+            .await?;
+        db.link_provider(u.id(), IdentityProvider::Discord, &discord_id)
+            .await?;
+
+        let mut identities = db
+            .find_identities_by_email(&email.to_uppercase())
+            .await?
+            .into_iter()
+            .map(|identity| {
+                (
+                    identity.provider(),
+                    identity.provider_user_id().to_owned(),
+                    *identity.user_id(),
+                )
+            })
+            .collect::<Vec<(IdentityProvider, String, Uuid)>>();
+        identities.sort_by_key(|(provider, ..)| <&str as From<IdentityProvider>>::from(*provider));
+
+        assert_eq!(
+            identities,
+            vec![
+                (IdentityProvider::Discord, discord_id, *u.id()),
+                (IdentityProvider::Google, google_id, *u.id()),
+            ]
+        );
+        assert!(db
+            .find_identities_by_email(&unique_email())
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_relink_provider() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (user_id, old_id, new_id) = (
+            Uuid::new_v4(),
+            Uuid::new_v4().to_string(),
+            Uuid::new_v4().to_string(),
+        );
+        db.link_provider(&user_id, IdentityProvider::Google, &old_id)
+            .await?;
+
+        assert!(
+            db.relink_provider(&user_id, IdentityProvider::Google, &new_id)
+                .await?
+        );
+        assert_eq!(
+            db.load_provider_identity(IdentityProvider::Google, &new_id)
+                .await?
+                .user_id(),
+            &user_id
+        );
+
+        match db
+            .load_provider_identity(IdentityProvider::Google, &old_id)
+            .await
+        {
+            Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+            res => panic!("expected the old link to be removed, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_relink_provider_taken() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (user_id, other_user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let (old_id, taken_id) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        db.link_provider(&user_id, IdentityProvider::Google, &old_id)
+            .await?;
+        db.link_provider(&other_user_id, IdentityProvider::Google, &taken_id)
+            .await?;
+
+        assert!(
+            !db.relink_provider(&user_id, IdentityProvider::Google, &taken_id)
+                .await?
+        );
+
+        // Neither link should have changed
+        for (external_id, owner) in &[(&old_id, &user_id), (&taken_id, &other_user_id)] {
+            assert_eq!(
+                db.load_provider_identity(IdentityProvider::Google, external_id)
+                    .await?
+                    .user_id(),
+                *owner
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Generates an email address that is unlikely to have been used by any previous test.
+    fn unique_email() -> String {
+        format!("{}@test.com", Uuid::new_v4().to_simple())
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_resolve_new_user() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (external_id, email) = (Uuid::new_v4().to_string(), unique_email());
+        let (u, created) = db
+            .resolve_or_create_from_provider(IdentityProvider::Google, &external_id, &email, "Test")
+            .await?;
+
+        assert!(created);
+        assert!(u.username().starts_with("test"));
+        assert_eq!(u.email(), email);
+        assert!(!u.has_password());
+        assert_eq!(
+            db.list_linked_providers(u.id()).await?,
+            vec![IdentityProvider::Google]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_resolve_linked_user() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (external_id, email) = (Uuid::new_v4().to_string(), unique_email());
+        let (u, _) = db
+            .resolve_or_create_from_provider(IdentityProvider::Google, &external_id, &email, "test")
+            .await?;
+
+        // The external account's email address may since have changed
+        let (resolved, created) = db
+            .resolve_or_create_from_provider(
+                IdentityProvider::Google,
+                &external_id,
+                &unique_email(),
+                "test",
+            )
+            .await?;
+
+        assert!(!created);
+        assert_eq!(resolved.id(), u.id());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_resolve_user_by_email() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (username, email) = (
+            format!("u{}", &Uuid::new_v4().to_simple().to_string()[..16]),
+            unique_email(),
+        );
+        let u = User::new(
+            None,
+            &username,
+            &email,
+            Some(blake3::hash(b"123456").into()),
+            None,
+        );
+        db.insert_unique(&u).await?;
+
+        let (resolved, created) = db
+            .resolve_or_create_from_provider(
+                IdentityProvider::Google,
+                &Uuid::new_v4().to_string(),
+                &email.to_uppercase(),
+                "test",
+            )
+            .await?;
+
+        assert!(!created);
+        assert_eq!(resolved, u);
+        assert_eq!(
+            db.list_linked_providers(u.id()).await?,
+            vec![IdentityProvider::Google]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_username_from_hint() {
+        assert_eq!(username_from_hint(" Some User! "), "someuser");
+        assert_eq!(username_from_hint("!!"), "user");
+        assert!(
+            username_from_hint(&"a".repeat(64)).len() + USERNAME_SUFFIX_LENGTH
+                < MAX_USERNAME_LENGTH
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use serde_json::Value;
+
+use super::user::IdentityProvider;
+
+use std::{error::Error, fmt};
+
+/// ProviderProfile represents the identity of an account held with an identity provider, as
+/// extracted from the user info returned by the provider.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProviderProfile {
+    /// The ID of the external account, as issued by the provider
+    external_id: String,
+
+    /// The email address of the external account, if the provider disclosed one
+    email: Option<String>,
+
+    /// The name that the user holds with the provider, if any, from which a username may be
+    /// derived
+    username: Option<String>,
+}
+
+impl ProviderProfile {
+    /// Gets the ID of the external account, as issued by the provider.
+    pub fn external_id(&self) -> &str {
+        &self.external_id
+    }
+
+    /// Gets the email address of the external account, if the provider disclosed one.
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
+    /// Gets the name that the user holds with the provider, if any.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+}
+
+/// MapError represents an error that may be encountered while mapping the user info returned by
+/// an identity provider to a ProviderProfile.
+#[derive(Debug, PartialEq)]
+pub enum MapError {
+    /// The user info lacks the claim holding the ID of the external account
+    MissingClaim { claim: &'static str },
+
+    /// A claim in the user info isn't of the expected type
+    InvalidClaim { claim: &'static str },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "encountered an error while mapping a provider's user info: {}",
+            match self {
+                Self::MissingClaim { claim } => format!("the {} claim is missing", claim),
+                Self::InvalidClaim { claim } => format!("the {} claim is malformed", claim),
+            }
+        )
+    }
+}
+
+impl Error for MapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::MissingClaim { .. } | Self::InvalidClaim { .. } => None,
+        }
+    }
+}
+
+/// Gets the value of a claim, treating null claims as absent.
+///
+/// # Arguments
+///
+/// * `raw` - The user info returned by the provider
+/// * `claim` - The name of the claim
+fn claim<'a>(raw: &'a Value, claim: &str) -> Option<&'a Value> {
+    raw.get(claim).filter(|value| !value.is_null())
+}
+
+/// Gets the ID of an external account, which providers issue as either strings or integers.
+///
+/// # Arguments
+///
+/// * `raw` - The user info returned by the provider
+/// * `name` - The name of the claim holding the ID
+fn id_claim(raw: &Value, name: &'static str) -> Result<String, MapError> {
+    match claim(raw, name) {
+        None => Err(MapError::MissingClaim { claim: name }),
+        Some(Value::String(id)) if !id.is_empty() => Ok(id.clone()),
+        Some(Value::Number(id)) if id.is_u64() => Ok(id.to_string()),
+        Some(_) => Err(MapError::InvalidClaim { claim: name }),
+    }
+}
+
+/// Gets the value of an optional string claim. Empty strings are treated as absent.
+///
+/// # Arguments
+///
+/// * `raw` - The user info returned by the provider
+/// * `name` - The name of the claim
+fn string_claim(raw: &Value, name: &'static str) -> Result<Option<String>, MapError> {
+    match claim(raw, name) {
+        None => Ok(None),
+        Some(Value::String(s)) if s.is_empty() => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(MapError::InvalidClaim { claim: name }),
+    }
+}
+
+impl IdentityProvider {
+    /// Extracts the identity of an external account from the user info returned by the provider,
+    /// accounting for the differing names of each provider's claims. Email addresses are returned
+    /// as disclosed by the provider, and should be normalized before use.
+    ///
+    /// # Arguments
+    ///
+    /// * `raw` - The user info returned by the provider (e.g., the body of a response from
+    /// Google's OpenID Connect userinfo endpoint, or GitHub's /user endpoint)
+    ///
+    /// # Examples
+    ///
+    #[cfg_attr(feature = "provider-github", doc = "```")]
+    #[cfg_attr(not(feature = "provider-github"), doc = "```ignore")]
+    /// use swaply_identity::schema::user::IdentityProvider;
+    ///
+    /// let profile = IdentityProvider::GitHub
+    ///     .map_profile(serde_json::json!({ "id": 1, "login": "octocat", "email": null }))
+    ///     .unwrap();
+    /// assert_eq!(profile.external_id(), "1");
+    /// assert_eq!(profile.username(), Some("octocat"));
+    /// assert_eq!(profile.email(), None);
+    /// ```
+    pub fn map_profile(&self, raw: Value) -> Result<ProviderProfile, MapError> {
+        let (id, email, username) = match *self {
+            // OpenID Connect claims
+            #[cfg(feature = "provider-google")]
+            Self::Google => ("sub", Some("email"), "name"),
+            #[cfg(feature = "provider-github")]
+            Self::GitHub => ("id", Some("email"), "login"),
+            #[cfg(feature = "provider-twitch")]
+            Self::Twitch => ("sub", Some("email"), "preferred_username"),
+            // Reddit never discloses email addresses
+            #[cfg(feature = "provider-reddit")]
+            Self::Reddit => ("id", None, "name"),
+            // Twitter's integer IDs are too large to be represented exactly by some JSON parsers,
+            // so the string form is used instead
+            #[cfg(feature = "provider-twitter")]
+            Self::Twitter => ("id_str", Some("email"), "screen_name"),
+            #[cfg(feature = "provider-discord")]
+            Self::Discord => ("id", Some("email"), "username"),
+            #[cfg(feature = "provider-facebook")]
+            Self::Facebook => ("id", Some("email"), "name"),
+            // No provider can be constructed while every provider's feature is disabled
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("every identity provider is disabled"),
+        };
+
+        Ok(ProviderProfile {
+            external_id: id_claim(&raw, id)?,
+            email: email
+                .map(|email| string_claim(&raw, email))
+                .transpose()?
+                .flatten(),
+            username: string_claim(&raw, username)?,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[cfg(feature = "provider-google")]
+    #[test]
+    fn test_map_google_profile() {
+        let profile = IdentityProvider::Google
+            .map_profile(json!({
+                "sub": "110169484474386276334",
+                "name": "Test User",
+                "given_name": "Test",
+                "email": "test@gmail.com",
+                "email_verified": true,
+            }))
+            .unwrap();
+
+        assert_eq!(profile.external_id(), "110169484474386276334");
+        assert_eq!(profile.email(), Some("test@gmail.com"));
+        assert_eq!(profile.username(), Some("Test User"));
+    }
+
+    #[cfg(feature = "provider-github")]
+    #[test]
+    fn test_map_github_profile() {
+        let profile = IdentityProvider::GitHub
+            .map_profile(json!({
+                "login": "octocat",
+                "id": 583231,
+                "node_id": "MDQ6VXNlcjU4MzIzMQ==",
+                "name": "The Octocat",
+                "email": null,
+            }))
+            .unwrap();
+
+        assert_eq!(profile.external_id(), "583231");
+        assert_eq!(profile.email(), None);
+        assert_eq!(profile.username(), Some("octocat"));
+    }
+
+    #[cfg(feature = "provider-discord")]
+    #[test]
+    fn test_map_discord_profile() {
+        let profile = IdentityProvider::Discord
+            .map_profile(json!({
+                "id": "80351110224678912",
+                "username": "Nelly",
+                "discriminator": "1337",
+                "email": "nelly@discord.com",
+                "verified": true,
+            }))
+            .unwrap();
+
+        assert_eq!(profile.external_id(), "80351110224678912");
+        assert_eq!(profile.email(), Some("nelly@discord.com"));
+        assert_eq!(profile.username(), Some("Nelly"));
+    }
+
+    #[cfg(all(feature = "provider-google", feature = "provider-discord"))]
+    #[test]
+    fn test_map_malformed_profile() {
+        assert_eq!(
+            IdentityProvider::Google.map_profile(json!({ "email": "test@gmail.com" })),
+            Err(MapError::MissingClaim { claim: "sub" })
+        );
+        assert_eq!(
+            IdentityProvider::Discord.map_profile(json!({ "id": "1", "email": 1 })),
+            Err(MapError::InvalidClaim { claim: "email" })
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use cdrs::{
+    error::Error as CDRSError,
+    query::{BatchExecutor, BatchQueryBuilder, QueryExecutor, QueryValues},
+    query_values,
+    types::{prelude::Row, IntoRustByName},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use time::Timespec;
+use uuid::Uuid;
+
+use super::{
+    super::{
+        clock::{Clock, SystemClock},
+        db::{
+            scylla::{verify_table_schema, Scylla},
+            ColumnSchema, Deserializable, Idempotent, InTable, Insertable, Provider, Queryable,
+            Serializable, TableSchema,
+        },
+        error::{IdentityError, QueryError},
+        result::IdentityResult,
+        DbSession,
+    },
+    user::RegistrationTimestamp,
+};
+
+use std::{convert::TryInto, time::Duration};
+
+/// LoginSession represents a session established by a user upon logging in, which authenticates
+/// them until it expires or is revoked.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LoginSession {
+    /// The ID of the session, as presented by the user. The ID authenticates whoever holds it,
+    /// so it is never serialized
+    #[serde(skip_serializing)]
+    session_id: Uuid,
+
+    /// The ID of the user that the session authenticates
+    user_id: Uuid,
+
+    /// The time at which the session was established
+    created_at: RegistrationTimestamp,
+
+    /// The time at which the session expires
+    expires_at: RegistrationTimestamp,
+}
+
+impl LoginSession {
+    /// Creates a new session with a random ID, established at the current UTC time.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that the session authenticates
+    /// * `lifetime` - How long the session lasts before it expires
+    pub fn new(user_id: Uuid, lifetime: Duration) -> Self {
+        Self::at(user_id, lifetime, SystemClock.now())
+    }
+
+    /// Creates a new session with a random ID, established at the provided time.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that the session authenticates
+    /// * `lifetime` - How long the session lasts before it expires
+    /// * `created_at` - The time at which the session was established
+    pub fn at(user_id: Uuid, lifetime: Duration, created_at: DateTime<Utc>) -> Self {
+        let expires_at = chrono::Duration::from_std(lifetime)
+            .ok()
+            .and_then(|lifetime| created_at.checked_add_signed(lifetime))
+            .unwrap_or(created_at);
+
+        Self {
+            session_id: Uuid::new_v4(),
+            user_id,
+            created_at: created_at.try_into().unwrap_or_default(),
+            expires_at: expires_at.try_into().unwrap_or_default(),
+        }
+    }
+
+    /// Gets the ID of the session.
+    pub fn session_id(&self) -> &Uuid {
+        &self.session_id
+    }
+
+    /// Gets the ID of the user that the session authenticates.
+    pub fn user_id(&self) -> &Uuid {
+        &self.user_id
+    }
+
+    /// Gets the time at which the session was established.
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at.into()
+    }
+
+    /// Gets the time at which the session expires.
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at.into()
+    }
+
+    /// Determines whether or not the session has expired at the provided time.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time at which the session's expiry should be checked
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        now >= self.expires_at()
+    }
+}
+
+#[async_trait]
+impl InTable<Scylla, DbSession> for LoginSession {
+    async fn create_prerequisite_objects(session: &DbSession) -> IdentityResult<()> {
+        session
+            .query(Self::table_schema().create_statement())
+            .await
+            .and(
+                session
+                    .query(
+                        // Mappings from users to their sessions, such that every session held by
+                        // a user may be revoked at once
+                        "CREATE INDEX IF NOT EXISTS ON identity.sessions (user_id);",
+                    )
+                    .await,
+            )
+            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))?;
+
+        verify_table_schema(session, &Self::table_schema()).await
+    }
+
+    fn table_schema() -> TableSchema {
+        // The sessions established by each user
+        TableSchema {
+            keyspace: "identity",
+            name: "sessions",
+            columns: vec![
+                ColumnSchema::new("session_id", "UUID"),
+                ColumnSchema::new("user_id", "UUID"),
+                ColumnSchema::new("created_at", "TIMESTAMP"),
+                ColumnSchema::new("expires_at", "TIMESTAMP"),
+            ],
+            partition_key: vec!["session_id"],
+            clustering_key: Vec::new(),
+        }
+    }
+}
+
+impl Serializable<QueryValues> for LoginSession {
+    type Error = IdentityError;
+
+    fn try_into(&self) -> Result<QueryValues, Self::Error> {
+        Ok(query_values!(
+            "session_id" => self.session_id,
+            "user_id" => self.user_id,
+            "created_at" => <&RegistrationTimestamp as Into<Timespec>>::into(&self.created_at),
+            "expires_at" => <&RegistrationTimestamp as Into<Timespec>>::into(&self.expires_at)
+        ))
+    }
+}
+
+impl Idempotent for LoginSession {
+    /// The session's ID is generated before it is inserted, so reinserting it overwrites the same
+    /// row.
+    fn idempotent(&self) -> bool {
+        true
+    }
+}
+
+impl Insertable<Scylla, DbSession> for LoginSession {
+    const INSERTION_QUERY: &'static str = r#"INSERT INTO identity.sessions (session_id, user_id, created_at, expires_at) VALUES (?, ?, ?, ?);"#;
+}
+
+impl Deserializable<LoginSession, Row> for LoginSession {
+    type Error = IdentityError;
+
+    fn try_from(value: Row) -> Result<LoginSession, Self::Error> {
+        Ok(LoginSession {
+            session_id: value.get_r_by_name("session_id")?,
+            user_id: value.get_r_by_name("user_id")?,
+            created_at: <Row as IntoRustByName<Timespec>>::get_r_by_name(&value, "created_at")?
+                .into(),
+            expires_at: <Row as IntoRustByName<Timespec>>::get_r_by_name(&value, "expires_at")?
+                .into(),
+        })
+    }
+}
+
+/// SessionQuery represents a query for a session by its ID.
+#[derive(Debug)]
+pub struct SessionQuery<'a>(pub &'a Uuid);
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for SessionQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.sessions WHERE session_id = {};",
+            self.0
+        ))
+    }
+}
+
+/// UserSessionsQuery represents a query for every session established by a user.
+#[derive(Debug)]
+pub struct UserSessionsQuery<'a>(pub &'a Uuid);
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for UserSessionsQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.sessions WHERE user_id = {};",
+            self.0
+        ))
+    }
+}
+
+impl Scylla {
+    /// Establishes a new session for a user, lasting for the provided lifetime.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that the session authenticates
+    /// * `lifetime` - How long the session lasts before it expires
+    pub async fn create_session(
+        &self,
+        user_id: &Uuid,
+        lifetime: Duration,
+    ) -> IdentityResult<LoginSession> {
+        let session = LoginSession::new(*user_id, lifetime);
+        self.insert_record(&session).await?;
+
+        Ok(session)
+    }
+
+    /// Loads the session with the provided ID, as is done whenever a user presents it. Fails
+    /// with a QueryError::NoResults if the session doesn't exist, has been revoked, or has
+    /// expired.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id` - The ID of the session presented by the user
+    pub async fn resolve_session(&self, session_id: &Uuid) -> IdentityResult<LoginSession> {
+        let session: LoginSession = self.load_record(&SessionQuery(session_id)).await?;
+
+        if session.is_expired_at(SystemClock.now()) {
+            return Err(QueryError::NoResults.into());
+        }
+
+        Ok(session)
+    }
+
+    /// Loads every session established by a user that has yet to be revoked, including those
+    /// that have expired.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose sessions should be loaded
+    pub async fn load_user_sessions(&self, user_id: &Uuid) -> IdentityResult<Vec<LoginSession>> {
+        self.load_records(&UserSessionsQuery(user_id)).await
+    }
+
+    /// Revokes a single session, such that it can no longer be resolved.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id` - The ID of the session that should be revoked
+    pub async fn revoke_session(&self, session_id: &Uuid) -> IdentityResult<()> {
+        self.execute(
+            "DELETE FROM identity.sessions WHERE session_id = ?;",
+            query_values!(*session_id),
+        )
+        .await
+    }
+
+    /// Revokes every session established by a user, forcing them to log in again, as should be
+    /// done once their password changes or their account is suspected of being compromised.
+    /// Returns the number of sessions that were revoked.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose sessions should be revoked
+    pub async fn revoke_all_sessions(&self, user_id: &Uuid) -> IdentityResult<u64> {
+        let sessions = self.load_user_sessions(user_id).await?;
+
+        if sessions.is_empty() {
+            return Ok(0);
+        }
+
+        let query = sessions
+            .iter()
+            .fold(BatchQueryBuilder::new(), |query, session| {
+                query.add_query(
+                    "DELETE FROM identity.sessions WHERE session_id = ?;",
+                    query_values!(session.session_id),
+                )
+            })
+            .finalize()?;
+        self.run(self.session().batch_with_params(query)).await?;
+
+        Ok(sessions.len() as u64)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::error::Error;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_insertion_columns_match() -> Result<(), Box<dyn Error>> {
+        testing::assert_insertion_columns_match(&LoginSession::new(
+            Uuid::new_v4(),
+            Duration::from_secs(60),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_revoke_all_sessions() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        LoginSession::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (user_id, other_user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let hour = Duration::from_secs(60 * 60);
+
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            sessions.push(db.create_session(&user_id, hour).await?);
+        }
+        let other_session = db.create_session(&other_user_id, hour).await?;
+
+        for session in &sessions {
+            assert_eq!(&db.resolve_session(session.session_id()).await?, session);
+        }
+
+        assert_eq!(db.revoke_all_sessions(&user_id).await?, 3);
+
+        for session in &sessions {
+            match db.resolve_session(session.session_id()).await {
+                Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+                res => panic!("expected the session to be revoked, got {:?}", res),
+            }
+        }
+        assert!(db.load_user_sessions(&user_id).await?.is_empty());
+        assert_eq!(db.revoke_all_sessions(&user_id).await?, 0);
+
+        // Other users' sessions are untouched
+        db.resolve_session(other_session.session_id()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_expired_session() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        LoginSession::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let expired = LoginSession::at(
+            Uuid::new_v4(),
+            Duration::from_secs(60),
+            Utc::now() - chrono::Duration::hours(1),
+        );
+        db.insert_record(&expired).await?;
+
+        match db.resolve_session(expired.session_id()).await {
+            Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+            res => panic!("expected the session to have expired, got {:?}", res),
+        }
+
+        Ok(())
+    }
+}
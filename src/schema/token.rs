@@ -0,0 +1,541 @@
+use cdrs::{
+    error::Error as CDRSError,
+    query::{QueryExecutor, QueryValues},
+    query_values,
+    types::{prelude::Row, IntoRustByName},
+};
+use futures::stream::TryStreamExt;
+use uuid::Uuid;
+
+use super::{
+    super::{
+        crypto::token::{EncryptionError, TokenKey},
+        db::{
+            scylla::{verify_table_schema, Scylla},
+            ClusteringOrder, ColumnSchema, Deserializable, Idempotent, InTable, Insertable,
+            Provider, Queryable, Serializable, TableSchema,
+        },
+        error::IdentityError,
+        result::IdentityResult,
+        DbSession,
+    },
+    user::IdentityProvider,
+};
+
+use std::convert::TryInto;
+
+/// ProviderToken represents a set of OAuth tokens issued by an identity provider, allowing swaply
+/// to act on a user's behalf.
+#[derive(Debug)]
+pub struct ProviderToken<'a> {
+    /// The ID of the swaply user that the tokens were issued for
+    user_id: Uuid,
+
+    /// The provider that issued the tokens
+    provider: IdentityProvider,
+
+    /// The access token issued by the provider
+    access_token: &'a str,
+
+    /// The refresh token issued by the provider, if any
+    refresh_token: Option<&'a str>,
+}
+
+impl<'a> ProviderToken<'a> {
+    /// Creates a new instance of the provider token struct.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the swaply user that the tokens were issued for
+    /// * `provider` - The provider that issued the tokens
+    /// * `access_token` - The access token issued by the provider
+    /// * `refresh_token` - The refresh token issued by the provider, if any
+    pub fn new(
+        user_id: Uuid,
+        provider: IdentityProvider,
+        access_token: &'a str,
+        refresh_token: Option<&'a str>,
+    ) -> Self {
+        Self {
+            user_id,
+            provider,
+            access_token,
+            refresh_token,
+        }
+    }
+}
+
+/// OwnedProviderToken represents an allocated set of provider tokens. Tokens held in an
+/// OwnedProviderToken returned by the Scylla connector have already been decrypted.
+#[derive(Debug, PartialEq)]
+pub struct OwnedProviderToken {
+    user_id: Uuid,
+    provider: IdentityProvider,
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+impl OwnedProviderToken {
+    /// Gets the ID of the swaply user that the tokens were issued for.
+    pub fn user_id(&self) -> &Uuid {
+        &self.user_id
+    }
+
+    /// Gets the provider that issued the tokens.
+    pub fn provider(&self) -> IdentityProvider {
+        self.provider
+    }
+
+    /// Gets the access token issued by the provider.
+    pub fn access_token(&self) -> &str {
+        &self.access_token
+    }
+
+    /// Gets the refresh token issued by the provider, if any.
+    pub fn refresh_token(&self) -> Option<&str> {
+        self.refresh_token.as_deref()
+    }
+}
+
+#[async_trait]
+impl InTable<Scylla, DbSession> for OwnedProviderToken {
+    async fn create_prerequisite_objects(session: &DbSession) -> IdentityResult<()> {
+        session
+            .query(Self::table_schema().create_statement())
+            .await
+            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))?;
+
+        verify_table_schema(session, &Self::table_schema()).await
+    }
+
+    fn table_schema() -> TableSchema {
+        // A table storing the encrypted tokens issued to each user by each provider
+        TableSchema {
+            keyspace: "identity",
+            name: "provider_tokens",
+            columns: vec![
+                ColumnSchema::new("user_id", "UUID"),
+                ColumnSchema::new("provider", "TEXT"),
+                ColumnSchema::new("access_token", "TEXT"),
+                ColumnSchema::new("refresh_token", "TEXT"),
+            ],
+            partition_key: vec!["user_id"],
+            clustering_key: vec![("provider", ClusteringOrder::Asc)],
+        }
+    }
+}
+
+impl Serializable<QueryValues> for OwnedProviderToken {
+    type Error = IdentityError;
+
+    fn try_into(&self) -> Result<QueryValues, Self::Error> {
+        Ok(query_values!(
+            "user_id" => self.user_id,
+            "provider" => <&str as From<IdentityProvider>>::from(self.provider),
+            "access_token" => self.access_token.clone(),
+            "refresh_token" => self.refresh_token.clone()
+        ))
+    }
+}
+
+impl Idempotent for OwnedProviderToken {
+    /// Tokens are keyed by their user and provider, so restoring them overwrites the same row.
+    fn idempotent(&self) -> bool {
+        true
+    }
+}
+
+impl Insertable<Scylla, DbSession> for OwnedProviderToken {
+    const INSERTION_QUERY: &'static str = r#"INSERT INTO identity.provider_tokens (user_id, provider, access_token, refresh_token) VALUES (?, ?, ?, ?);"#;
+}
+
+impl Deserializable<OwnedProviderToken, Row> for OwnedProviderToken {
+    type Error = IdentityError;
+
+    fn try_from(value: Row) -> Result<OwnedProviderToken, Self::Error> {
+        Ok(OwnedProviderToken {
+            user_id: value.get_r_by_name("user_id")?,
+            provider: <Row as IntoRustByName<String>>::get_r_by_name(&value, "provider")?
+                .try_into()?,
+            access_token: value.get_r_by_name("access_token")?,
+            refresh_token: value.get_by_name("refresh_token")?,
+        })
+    }
+}
+
+/// ProviderTokenQuery represents a query for the tokens issued to a user by a provider.
+#[derive(Debug)]
+pub struct ProviderTokenQuery<'a> {
+    pub user_id: &'a Uuid,
+    pub provider: IdentityProvider,
+}
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for ProviderTokenQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.provider_tokens WHERE user_id = {} AND provider = '{}';",
+            self.user_id,
+            <&str as From<IdentityProvider>>::from(self.provider)
+        ))
+    }
+}
+
+/// The query used to page through every stored set of provider tokens.
+const LIST_TOKENS_QUERY: &str = "SELECT * FROM identity.provider_tokens;";
+
+/// Decrypts a stored set of provider tokens under an old key, and encrypts them under a new key.
+/// Returns None if the tokens are already encrypted under the new key.
+///
+/// # Arguments
+///
+/// * `token` - The tokens, as stored in the database
+/// * `old` - The key that the tokens should currently be encrypted under
+/// * `new` - The key that the tokens should be encrypted under instead
+fn reencrypt_token(
+    token: &OwnedProviderToken,
+    old: &TokenKey,
+    new: &TokenKey,
+) -> Result<Option<OwnedProviderToken>, EncryptionError> {
+    let decrypt = |key: &TokenKey| -> Result<(String, Option<String>), EncryptionError> {
+        Ok((
+            key.decrypt(&token.access_token)?,
+            token
+                .refresh_token
+                .as_deref()
+                .map(|t| key.decrypt(t))
+                .transpose()?,
+        ))
+    };
+
+    let (access_token, refresh_token) = match decrypt(old) {
+        Ok(plaintext) => plaintext,
+        // A previous rotation may have rewritten the tokens before being interrupted
+        Err(e) => return decrypt(new).map(|_| None).map_err(|_| e),
+    };
+
+    Ok(Some(OwnedProviderToken {
+        user_id: token.user_id,
+        provider: token.provider,
+        access_token: new.encrypt(&access_token)?,
+        refresh_token: refresh_token.map(|t| new.encrypt(&t)).transpose()?,
+    }))
+}
+
+impl Scylla {
+    /// Re-encrypts every stored set of provider tokens under a new token key, as is done when the
+    /// key is rotated. Returns the number of sets of tokens that were rotated. Tokens already
+    /// encrypted under the new key are skipped, such that an interrupted rotation may be resumed.
+    /// Tokens that can't be decrypted under either key, that are replaced while being rotated, or
+    /// whose rows can't be read, are left untouched, and reported in an
+    /// EncryptionError::RotationFailures once every other set of tokens has been rotated.
+    ///
+    /// Connectors that store tokens should only be configured with the new key once the rotation
+    /// has completed.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The key that tokens are currently encrypted under
+    /// * `new` - The key that tokens should be encrypted under instead
+    pub async fn rotate_encryption_key(
+        &self,
+        old: &TokenKey,
+        new: &TokenKey,
+    ) -> IdentityResult<u64> {
+        let (rotated, failed, unreadable) = self
+            .stream_pages(LIST_TOKENS_QUERY.to_owned())
+            .try_fold(
+                (0, Vec::new(), Vec::new()),
+                move |(mut rotated, mut failed, mut unreadable), row| async move {
+                    // The key columns of a row are always present, even if the rest of it can't
+                    // be read
+                    let key = (
+                        <Row as IntoRustByName<Uuid>>::get_r_by_name(&row, "user_id")?,
+                        <Row as IntoRustByName<String>>::get_r_by_name(&row, "provider")?,
+                    );
+                    let token = match <OwnedProviderToken as Deserializable<
+                        OwnedProviderToken,
+                        Row,
+                    >>::try_from(row)
+                    {
+                        Ok(token) => token,
+                        Err(_) => {
+                            unreadable.push(key);
+
+                            return Ok((rotated, failed, unreadable));
+                        }
+                    };
+
+                    let reencrypted = match reencrypt_token(&token, old, new) {
+                        Ok(Some(reencrypted)) => reencrypted,
+                        Ok(None) => return Ok((rotated, failed, unreadable)),
+                        Err(_) => {
+                            failed.push((token.user_id, token.provider));
+
+                            return Ok((rotated, failed, unreadable));
+                        }
+                    };
+
+                    // Tokens stored since they were read mustn't be overwritten
+                    if self
+                        .execute_lwt(
+                            "UPDATE identity.provider_tokens SET access_token = ?, \
+                             refresh_token = ? WHERE user_id = ? AND provider = ? \
+                             IF access_token = ?;",
+                            query_values!(
+                                reencrypted.access_token,
+                                reencrypted.refresh_token,
+                                token.user_id,
+                                <&str as From<IdentityProvider>>::from(token.provider),
+                                token.access_token
+                            ),
+                        )
+                        .await?
+                    {
+                        rotated += 1;
+                    } else {
+                        failed.push((token.user_id, token.provider));
+                    }
+
+                    Ok((rotated, failed, unreadable))
+                },
+            )
+            .await?;
+
+        if failed.is_empty() && unreadable.is_empty() {
+            Ok(rotated)
+        } else {
+            Err(EncryptionError::RotationFailures {
+                rotated,
+                tokens: failed,
+                unreadable,
+            }
+            .into())
+        }
+    }
+
+    /// Encrypts and stores a set of provider tokens, replacing any tokens previously issued to
+    /// the user by the same provider. Requires a token key to have been configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The tokens that should be stored
+    pub async fn store_provider_token(&self, token: &ProviderToken<'_>) -> IdentityResult<()> {
+        let encrypted = OwnedProviderToken {
+            user_id: token.user_id,
+            provider: token.provider,
+            access_token: self.encrypt_token(token.access_token)?,
+            refresh_token: token
+                .refresh_token
+                .map(|t| self.encrypt_token(t))
+                .transpose()?,
+        };
+
+        self.insert_record(&encrypted).await
+    }
+
+    /// Loads and decrypts the tokens issued to a user by a provider. Requires a token key to have
+    /// been configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that the tokens were issued for
+    /// * `provider` - The provider that issued the tokens
+    pub async fn load_provider_token(
+        &self,
+        user_id: &Uuid,
+        provider: IdentityProvider,
+    ) -> IdentityResult<OwnedProviderToken> {
+        let encrypted: OwnedProviderToken = self
+            .load_record(&ProviderTokenQuery { user_id, provider })
+            .await?;
+
+        Ok(OwnedProviderToken {
+            access_token: self.decrypt_token(&encrypted.access_token)?,
+            refresh_token: encrypted
+                .refresh_token
+                .as_deref()
+                .map(|t| self.decrypt_token(t))
+                .transpose()?,
+            ..encrypted
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::error::Error;
+
+    use super::*;
+    use crate::{crypto::token::TokenKey, error::IdentityError, testing};
+
+    #[cfg(feature = "provider-google")]
+    #[test]
+    fn test_insertion_columns_match() -> Result<(), Box<dyn Error>> {
+        testing::assert_insertion_columns_match(&OwnedProviderToken {
+            user_id: Uuid::new_v4(),
+            provider: IdentityProvider::Google,
+            access_token: "access".to_owned(),
+            refresh_token: Some("refresh".to_owned()),
+        })
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_store_provider_token() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        OwnedProviderToken::create_prerequisite_objects(&session).await?;
+
+        let key = TokenKey::generate();
+        let db = Scylla::new(session).with_token_key(key.clone());
+
+        let id = Uuid::new_v4();
+        db.store_provider_token(&ProviderToken::new(
+            id,
+            IdentityProvider::Google,
+            "access",
+            Some("refresh"),
+        ))
+        .await?;
+
+        let loaded = db
+            .load_provider_token(&id, IdentityProvider::Google)
+            .await?;
+        assert_eq!(loaded.access_token(), "access");
+        assert_eq!(loaded.refresh_token(), Some("refresh"));
+
+        // The stored token must not be readable without the key
+        let encrypted: OwnedProviderToken = db
+            .load_record(&ProviderTokenQuery {
+                user_id: &id,
+                provider: IdentityProvider::Google,
+            })
+            .await?;
+        assert_ne!(encrypted.access_token(), "access");
+        assert_eq!(key.decrypt(encrypted.access_token())?, "access");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_rotate_encryption_key() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        OwnedProviderToken::create_prerequisite_objects(&session).await?;
+
+        let (old, new) = (TokenKey::generate(), TokenKey::generate());
+        let db = Scylla::new(session).with_token_key(old.clone());
+
+        let (id, other_id, malformed_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        db.store_provider_token(&ProviderToken::new(
+            id,
+            IdentityProvider::Google,
+            "access",
+            Some("refresh"),
+        ))
+        .await?;
+        db.store_provider_token(&ProviderToken::new(
+            other_id,
+            IdentityProvider::Google,
+            "other_access",
+            None,
+        ))
+        .await?;
+
+        // A token that was never encrypted must be reported, rather than dropped
+        db.insert_record(&OwnedProviderToken {
+            user_id: malformed_id,
+            provider: IdentityProvider::Google,
+            access_token: "plaintext".to_owned(),
+            refresh_token: None,
+        })
+        .await?;
+
+        // As must a token stored under a provider that isn't enabled
+        let unknown_id = Uuid::new_v4();
+        db.execute(
+            "INSERT INTO identity.provider_tokens (user_id, provider, access_token) \
+             VALUES (?, ?, ?);",
+            query_values!(unknown_id, "unknown", "access"),
+        )
+        .await?;
+
+        // Tokens stored by other tests under other keys are reported too
+        match db.rotate_encryption_key(&old, &new).await {
+            Err(IdentityError::EncryptionError(EncryptionError::RotationFailures {
+                rotated,
+                tokens,
+                unreadable,
+            })) => {
+                assert!(rotated >= 2);
+                assert!(tokens.contains(&(malformed_id, IdentityProvider::Google)));
+                assert!(unreadable.contains(&(unknown_id, "unknown".to_owned())));
+                assert!(!tokens.contains(&(id, IdentityProvider::Google)));
+                assert!(!tokens.contains(&(other_id, IdentityProvider::Google)));
+            }
+            res => panic!("expected the malformed token to be reported, got {:?}", res),
+        }
+
+        let rotated_db = Scylla::new(testing::open_session().await?).with_token_key(new.clone());
+        let loaded = rotated_db
+            .load_provider_token(&id, IdentityProvider::Google)
+            .await?;
+        assert_eq!(loaded.access_token(), "access");
+        assert_eq!(loaded.refresh_token(), Some("refresh"));
+        assert_eq!(
+            rotated_db
+                .load_provider_token(&other_id, IdentityProvider::Google)
+                .await?
+                .access_token(),
+            "other_access"
+        );
+
+        // The old key no longer decrypts the rotated tokens
+        for id in &[id, other_id] {
+            match db.load_provider_token(id, IdentityProvider::Google).await {
+                Err(IdentityError::EncryptionError(EncryptionError::DecryptionFailed)) => (),
+                res => panic!("expected a decryption failure, got {:?}", res),
+            }
+        }
+
+        let malformed: OwnedProviderToken = db
+            .load_record(&ProviderTokenQuery {
+                user_id: &malformed_id,
+                provider: IdentityProvider::Google,
+            })
+            .await?;
+        assert_eq!(malformed.access_token(), "plaintext");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "provider-discord")]
+    #[tokio::test]
+    async fn test_load_provider_token_wrong_key() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        OwnedProviderToken::create_prerequisite_objects(&session).await?;
+
+        let id = Uuid::new_v4();
+        let db = Scylla::new(session).with_token_key(TokenKey::generate());
+        db.store_provider_token(&ProviderToken::new(
+            id,
+            IdentityProvider::Discord,
+            "access",
+            None,
+        ))
+        .await?;
+
+        let db = Scylla::new(testing::open_session().await?).with_token_key(TokenKey::generate());
+
+        match db.load_provider_token(&id, IdentityProvider::Discord).await {
+            Err(IdentityError::EncryptionError(_)) => Ok(()),
+            res => panic!("expected a decryption failure, got {:?}", res),
+        }
+    }
+}
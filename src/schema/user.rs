@@ -1,57 +1,90 @@
 use bincode::Error as BincodeError;
 use bs58::{decode::Error as Bs58DecodingError, encode::Error as Bs58EncodingError};
 use cdrs::{
-    error::Error as CDRSError,
-    query::{QueryExecutor, QueryValues},
+    error::{column_is_empty_err, Error as CDRSError},
+    query::{BatchExecutor, BatchQueryBuilder, QueryExecutor, QueryValues},
     query_values,
-    types::{prelude::Row, value::Bytes, IntoRustByName},
+    types::{
+        blob::Blob,
+        prelude::Row,
+        value::{Bytes, Value},
+        IntoRustByName,
+    },
 };
 use chrono::{naive::NaiveDateTime, DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono_tz::Tz;
+use futures::{
+    future::{self, BoxFuture},
+    stream::{self, Stream, TryStreamExt},
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use time::Timespec;
 use uuid::Uuid;
 
 use super::super::{
-    db::{scylla::Scylla, Deserializable, InTable, Insertable, Queryable, Serializable},
-    error::{IdentityError, QueryError},
+    clock::{Clock, SystemClock},
+    crypto::password::{
+        self, PasswordHash, PasswordHasher, PasswordPolicy, PasswordVerification, HASH_LENGTH,
+        SALT_LENGTH,
+    },
+    db::{
+        scylla::{table_columns, verify_table_schema, Scylla},
+        ClusteringOrder, ColumnSchema, Deletable, Deserializable, Idempotent, InTable, Insertable,
+        LwtOutcome, Provider, Queryable, Serializable, TableSchema, Updatable,
+    },
+    error::{AuthError, IdentityError, QueryError, ValidationError},
+    events::EventSink,
     result::IdentityResult,
     DbSession,
 };
 
 use std::{
+    collections::{HashMap, HashSet},
     convert::{TryFrom, TryInto},
     error::Error,
     fmt,
+    hash::{Hash, Hasher},
     num::TryFromIntError,
+    str::FromStr,
+    time::Duration,
 };
 
 /// IdentityProvider represents any arbitrary provider of an authorization or
 /// authentication service (i.e., a provider of an OpenID Connection-capable
-/// identity API).
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+/// identity API). Each provider is only available when its respective
+/// `provider-*` feature is enabled (all are enabled by default). Providers are serialized as their
+/// lowercase string form (e.g., "google"), matching how they are stored.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum IdentityProvider {
     /// Google provides an OpenID connect OAuth 2.0 API: https://developers.google.com/identity/protocols/oauth2/openid-connect.
     /// As does twitch, Google returns IDs as "sub" claims--strings.
+    #[cfg(feature = "provider-google")]
     Google,
 
     /// GitHub also provides an OAuth 2.0 API, but uses non-standard endpoints: https://fusionauth.io/docs/v1/tech/identity-providers/openid-connect/github
     /// Also, their docs are pretty unclear, which doesn't help. IDs are
     /// returned as integers in the GitHub oauth API.
+    #[cfg(feature = "provider-github")]
     GitHub,
 
     /// Twitch has excellent OpenID connect integration: https://dev.twitch.tv/docs/authentication/getting-tokens-oidc.
     /// User IDs are returned in the "sub" OpenID connect claim, and are
     /// returned as strings.
+    #[cfg(feature = "provider-twitch")]
     Twitch,
 
     /// Reddit doesn't have support for OpenID connect, but does have a
     /// /api/v1/me route that we can use to get the ID of a user. In the
     /// Reddit user API, IDs are stored as SERIAL strings.
+    #[cfg(feature = "provider-reddit")]
     Reddit,
 
     /// Twitter's docs are pretty god-awful. Here's a route we can use to get
     /// a user ID from an access token: https://developer.twitter.com/en/docs/accounts-and-users/manage-account-settings/api-reference/get-account-verify_credentials.
     /// In the Twitter user API, IDs are stored as large unsigned integers.
+    #[cfg(feature = "provider-twitter")]
     Twitter,
 
     /// In contrast to Twitter, Discord's docs are pretty top-tier. Here's how
@@ -59,13 +92,39 @@ pub enum IdentityProvider {
     /// https://discord.com/developers/docs/resources/user#get-current-user.
     /// For the discord identity provider, we'll want to use a string to store
     /// IDs.
+    #[cfg(feature = "provider-discord")]
     Discord,
 
     /// We can use a Facebook access token to obtain some data regarding a user
     /// by sending a GET to this URL: graph.facebook.com/debug_token?input_token={token-to-inspect}
+    #[cfg(feature = "provider-facebook")]
     Facebook,
 }
 
+impl IdentityProvider {
+    /// Gets every provider enabled by the crate's features.
+    pub fn all() -> Vec<Self> {
+        let mut all = Vec::new();
+
+        #[cfg(feature = "provider-google")]
+        all.push(Self::Google);
+        #[cfg(feature = "provider-github")]
+        all.push(Self::GitHub);
+        #[cfg(feature = "provider-twitch")]
+        all.push(Self::Twitch);
+        #[cfg(feature = "provider-reddit")]
+        all.push(Self::Reddit);
+        #[cfg(feature = "provider-twitter")]
+        all.push(Self::Twitter);
+        #[cfg(feature = "provider-discord")]
+        all.push(Self::Discord);
+        #[cfg(feature = "provider-facebook")]
+        all.push(Self::Facebook);
+
+        all
+    }
+}
+
 /// IntoIdentityProviderError represents an error that may be encountered while parsing a type into
 /// an IdentityProvider.
 #[derive(Debug)]
@@ -74,6 +133,28 @@ pub enum IntoIdentityProviderError {
     InvalidProvider,
 }
 
+impl fmt::Display for IntoIdentityProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "encountered an error while parsing an identity provider: {}",
+            match self {
+                Self::InvalidProvider => "unknown provider".to_owned(),
+                _ => format!("{:?}", self.source()),
+            }
+        )
+    }
+}
+
+impl Error for IntoIdentityProviderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Utf8Error(e) => Some(e),
+            Self::InvalidProvider => None,
+        }
+    }
+}
+
 impl TryFrom<&[u8]> for IdentityProvider {
     type Error = IntoIdentityProviderError;
 
@@ -99,12 +180,19 @@ impl From<IdentityProvider> for &[u8] {
 impl From<IdentityProvider> for &str {
     fn from(id: IdentityProvider) -> Self {
         match id {
+            #[cfg(feature = "provider-google")]
             IdentityProvider::Google => "google",
+            #[cfg(feature = "provider-github")]
             IdentityProvider::GitHub => "github",
+            #[cfg(feature = "provider-twitch")]
             IdentityProvider::Twitch => "twitch",
+            #[cfg(feature = "provider-reddit")]
             IdentityProvider::Reddit => "reddit",
+            #[cfg(feature = "provider-twitter")]
             IdentityProvider::Twitter => "twitter",
+            #[cfg(feature = "provider-discord")]
             IdentityProvider::Discord => "discord",
+            #[cfg(feature = "provider-facebook")]
             IdentityProvider::Facebook => "facebook",
         }
     }
@@ -123,22 +211,158 @@ impl TryFrom<&str> for IdentityProvider {
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
         match s {
+            #[cfg(feature = "provider-google")]
             "google" => Ok(Self::Google),
+            #[cfg(feature = "provider-github")]
             "github" => Ok(Self::GitHub),
+            #[cfg(feature = "provider-twitch")]
             "twitch" => Ok(Self::Twitch),
+            #[cfg(feature = "provider-reddit")]
+            "reddit" => Ok(Self::Reddit),
+            #[cfg(feature = "provider-twitter")]
             "twitter" => Ok(Self::Twitter),
+            #[cfg(feature = "provider-discord")]
             "discord" => Ok(Self::Discord),
+            #[cfg(feature = "provider-facebook")]
             "facebook" => Ok(Self::Facebook),
             _ => Err(Self::Error::InvalidProvider),
         }
     }
 }
 
+impl FromStr for IdentityProvider {
+    type Err = IntoIdentityProviderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as TryFrom<&str>>::try_from(s)
+    }
+}
+
+impl Serialize for IdentityProvider {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str((*self).into())
+    }
+}
+
+impl<'de> Deserialize<'de> for IdentityProvider {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)
+            .and_then(|s| <Self as TryFrom<String>>::try_from(s).map_err(de::Error::custom))
+    }
+}
+
+/// ProviderKind represents a provider named by an integration, whether or not the crate
+/// recognizes it. Unlike an IdentityProvider, a ProviderKind may be parsed from any string, such
+/// that the names of unrecognized providers are preserved rather than rejected. Strict parsing
+/// should still be preferred for validated inputs.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum ProviderKind {
+    /// A provider supported by the crate's enabled features
+    Known(IdentityProvider),
+
+    /// Any other provider, by its name exactly as it was received
+    Other(String),
+}
+
+impl ProviderKind {
+    /// Parses a provider name, falling back to ProviderKind::Other if the provider isn't
+    /// recognized.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - The name of the provider
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::schema::user::ProviderKind;
+    ///
+    /// let provider = ProviderKind::parse_lenient("myspace");
+    /// assert_eq!(provider, ProviderKind::Other("myspace".to_owned()));
+    /// assert_eq!(provider.as_str(), "myspace");
+    /// ```
+    pub fn parse_lenient(s: &str) -> Self {
+        <IdentityProvider as TryFrom<&str>>::try_from(s)
+            .map(Self::Known)
+            .unwrap_or_else(|_| Self::Other(s.to_owned()))
+    }
+
+    /// Gets the name of the provider.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Known(provider) => (*provider).into(),
+            Self::Other(name) => name,
+        }
+    }
+
+    /// Gets the provider, if it is recognized by the crate.
+    pub fn known(&self) -> Option<IdentityProvider> {
+        match self {
+            Self::Known(provider) => Some(*provider),
+            Self::Other(_) => None,
+        }
+    }
+}
+
+impl From<IdentityProvider> for ProviderKind {
+    fn from(provider: IdentityProvider) -> Self {
+        Self::Known(provider)
+    }
+}
+
+impl From<&str> for ProviderKind {
+    fn from(s: &str) -> Self {
+        Self::parse_lenient(s)
+    }
+}
+
+impl From<String> for ProviderKind {
+    fn from(s: String) -> Self {
+        Self::parse_lenient(&s)
+    }
+}
+
+impl TryFrom<ProviderKind> for IdentityProvider {
+    type Error = IntoIdentityProviderError;
+
+    fn try_from(kind: ProviderKind) -> Result<Self, Self::Error> {
+        kind.known()
+            .ok_or(IntoIdentityProviderError::InvalidProvider)
+    }
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for ProviderKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ProviderKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
 /* Timespecs themselves don't implement conversions to and from cdrs types (i.e., Bytes), so we
  * need to do it ourselves by rolling a custom RegistrationTimestamp struct. */
 
-/// RegistrationTimestamp represents a timestamp for a user registration (UTC).
-#[derive(Copy, Clone, Default, Serialize, Deserialize, Debug)]
+/// The number of nanoseconds in a millisecond.
+const NANOS_PER_MILLI: i32 = 1_000_000;
+
+/// The number of nanoseconds in a second.
+const NANOS_PER_SEC: i32 = 1_000_000_000;
+
+/// RegistrationTimestamp represents a timestamp for a user registration (UTC). Since Scylla only
+/// stores timestamps with millisecond precision, a RegistrationTimestamp is always truncated to
+/// the millisecond upon construction, such that a timestamp is unchanged by a trip through the
+/// database.
+#[derive(Copy, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub struct RegistrationTimestamp {
     sec: i64,
     nsec: i32,
@@ -151,25 +375,79 @@ impl RegistrationTimestamp {
     }
 
     /// Gets the number of remaining nanoseconds since January 1, 1970 represented by this
-    /// timestamp.
+    /// timestamp. This is always a whole number of milliseconds.
     pub fn nanoseconds(&self) -> i32 {
         self.nsec
     }
+
+    /// Creates a timestamp, truncating the nanoseconds to the millisecond boundary.
+    ///
+    /// # Arguments
+    ///
+    /// * `sec` - The number of whole seconds since January 1, 1970
+    /// * `nsec` - The number of remaining nanoseconds
+    fn truncated(sec: i64, nsec: i32) -> Self {
+        Self {
+            sec,
+            nsec: nsec - nsec % NANOS_PER_MILLI,
+        }
+    }
+
+    /// Converts the timestamp into a DateTime, failing if its nanoseconds don't lie within a
+    /// single second, or if it lies outside of the range of times that a DateTime can represent.
+    /// Unlike the infallible conversion, this never panics, nor interprets an extra second of
+    /// nanoseconds as a leap second.
+    pub fn to_datetime(&self) -> Result<DateTime<Utc>, TimestampError> {
+        if self.nsec < 0 || self.nsec >= NANOS_PER_SEC {
+            return Err(TimestampError::InvalidNanoseconds(self.nsec));
+        }
+
+        NaiveDateTime::from_timestamp_opt(self.sec, self.nsec as u32)
+            .map(|timestamp| DateTime::<Utc>::from_utc(timestamp, Utc))
+            .ok_or(TimestampError::OutOfRange(self.sec))
+    }
 }
 
-impl PartialEq for RegistrationTimestamp {
-    fn eq(&self, other: &Self) -> bool {
-        self.sec == other.sec && self.nsec / 1_000_000 == other.nsec / 1_000_000
+/// TimestampError represents an error that may be encountered while converting a stored
+/// timestamp into a DateTime.
+#[derive(Debug, PartialEq)]
+pub enum TimestampError {
+    /// The timestamp's nanoseconds are negative, or amount to a whole second or more
+    InvalidNanoseconds(i32),
+
+    /// The timestamp's seconds lie outside of the range of representable times
+    OutOfRange(i64),
+}
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "encountered an error while converting a timestamp: {}",
+            match self {
+                Self::InvalidNanoseconds(nsec) => {
+                    format!("{} nanoseconds don't lie within a second", nsec)
+                }
+                Self::OutOfRange(sec) => {
+                    format!("{} seconds since the epoch is out of range", sec)
+                }
+            }
+        )
+    }
+}
+
+impl Error for TimestampError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidNanoseconds(_) | Self::OutOfRange(_) => None,
+        }
     }
 }
 
-// Conversion from a Timespec to a RegistrationTimestamp
+// Conversion from a Timespec to a RegistrationTimestamp, truncated to the millisecond
 impl From<Timespec> for RegistrationTimestamp {
     fn from(timestamp: Timespec) -> Self {
-        Self {
-            sec: timestamp.sec,
-            nsec: timestamp.nsec,
-        }
+        Self::truncated(timestamp.sec, timestamp.nsec)
     }
 }
 
@@ -208,19 +486,214 @@ impl From<RegistrationTimestamp> for DateTime<Utc> {
 }
 
 // However, DateTime uses differently sized sec and nsec nums, so we need to do very careful
-// conversion between the two
+// conversion between the two. Any sub-millisecond precision is truncated, as Scylla wouldn't
+// store it anyway.
 impl TryFrom<DateTime<Utc>> for RegistrationTimestamp {
     type Error = TryFromIntError;
 
     fn try_from(timestamp: DateTime<Utc>) -> Result<Self, Self::Error> {
-        println!("{}", timestamp.timestamp_millis());
-        Ok(Self {
-            sec: timestamp.timestamp(),
-            nsec: timestamp.timestamp_subsec_nanos().try_into()?,
-        })
+        Ok(Self::truncated(
+            timestamp.timestamp(),
+            timestamp.timestamp_subsec_nanos().try_into()?,
+        ))
+    }
+}
+
+/// The minimum number of characters permitted in a username.
+pub const MIN_USERNAME_LENGTH: usize = 3;
+
+/// The maximum number of characters permitted in a username.
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+/// The maximum number of leading characters by which usernames are bucketed for prefix search.
+/// Each reserved username is indexed under every prefix up to this length, such that a search
+/// for any prefix reads a single partition.
+const USERNAME_PREFIX_LENGTH: usize = 3;
+
+/// Gets every prefix that a normalized username is indexed under for prefix search: its first
+/// character, its first two characters, and so on, up to USERNAME_PREFIX_LENGTH characters.
+///
+/// # Arguments
+///
+/// * `username` - The normalized username whose prefixes should be found
+fn username_prefixes(username: &str) -> Vec<String> {
+    (1..=USERNAME_PREFIX_LENGTH.min(username.chars().count()))
+        .map(|length| username.chars().take(length).collect())
+        .collect()
+}
+
+/// Converts a username into the canonical form in which it is stored (i.e., trimmed and
+/// lowercase), such that two usernames differing only in case are considered identical.
+///
+/// # Arguments
+///
+/// * `username` - The username that should be normalized
+///
+/// # Examples
+///
+/// ```
+/// use swaply_identity::schema::user::normalize_username;
+///
+/// assert_eq!(normalize_username(" Test_User "), "test_user");
+/// ```
+pub fn normalize_username(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+/// Ensures that a normalized username is between MIN_USERNAME_LENGTH and MAX_USERNAME_LENGTH
+/// characters long, and consists only of lowercase alphanumeric characters, underscores, and
+/// periods.
+///
+/// # Arguments
+///
+/// * `username` - The normalized username that should be validated
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let len = username.chars().count();
+
+    if len < MIN_USERNAME_LENGTH
+        || len > MAX_USERNAME_LENGTH
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '.')
+    {
+        return Err(ValidationError::InvalidUsername);
+    }
+
+    Ok(())
+}
+
+/// The maximum number of characters permitted in an email address.
+pub const MAX_EMAIL_LENGTH: usize = 254;
+
+/// The maximum number of characters permitted in a display name.
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+
+/// Ensures that a display name is no longer than MAX_DISPLAY_NAME_LENGTH characters, isn't blank,
+/// and contains no control characters. Unlike usernames, display names needn't be unique.
+///
+/// # Arguments
+///
+/// * `display_name` - The display name that should be validated
+///
+/// # Examples
+///
+/// ```
+/// use swaply_identity::schema::user::validate_display_name;
+///
+/// assert!(validate_display_name("Jane Doe").is_ok());
+/// assert!(validate_display_name("  ").is_err());
+/// ```
+pub fn validate_display_name(display_name: &str) -> Result<(), ValidationError> {
+    if display_name.trim().is_empty()
+        || display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH
+        || display_name.chars().any(char::is_control)
+    {
+        return Err(ValidationError::InvalidDisplayName);
+    }
+
+    Ok(())
+}
+
+/// Converts an email address into the canonical form in which it is stored (i.e., trimmed and
+/// lowercase).
+///
+/// # Arguments
+///
+/// * `email` - The email address that should be normalized
+///
+/// # Examples
+///
+/// ```
+/// use swaply_identity::schema::user::normalize_email;
+///
+/// assert_eq!(normalize_email(" Test@Test.com "), "test@test.com");
+/// ```
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Ensures that a normalized email address is no longer than MAX_EMAIL_LENGTH characters, and
+/// consists of a non-empty local part and a dotted domain separated by a single '@'.
+///
+/// # Arguments
+///
+/// * `email` - The normalized email address that should be validated
+pub fn validate_email(email: &str) -> Result<(), ValidationError> {
+    let valid = email.chars().count() <= MAX_EMAIL_LENGTH
+        && !email.chars().any(char::is_whitespace)
+        && match email.split('@').collect::<Vec<&str>>().as_slice() {
+            [local, domain] => {
+                !local.is_empty()
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+            }
+            _ => false,
+        };
+
+    if !valid {
+        return Err(ValidationError::InvalidEmail);
+    }
+
+    Ok(())
+}
+
+/// Matches a well-formed BCP-47 language tag (e.g., "en", "en-US", or "zh-Hant-TW"), as
+/// described by RFC 5646. Grandfathered tags are not accepted.
+const LOCALE_PATTERN: &str = r"^(?i:([a-z]{2,3}(-[a-z]{3}){0,3}|[a-z]{5,8})(-[a-z]{4})?(-([a-z]{2}|[0-9]{3}))?(-([a-z0-9]{5,8}|[0-9][a-z0-9]{3}))*(-[0-9a-wy-z](-[a-z0-9]{2,8})+)*(-x(-[a-z0-9]{1,8})+)?)$";
+
+/// LOCALE_PATTERN, compiled once upon first validating a locale.
+static LOCALE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(LOCALE_PATTERN).expect("LOCALE_PATTERN is a valid regex"));
+
+/// Ensures that a locale is a well-formed BCP-47 language tag.
+///
+/// # Arguments
+///
+/// * `locale` - The locale that should be validated
+///
+/// # Examples
+///
+/// ```
+/// use swaply_identity::schema::user::validate_locale;
+///
+/// assert!(validate_locale("en-US").is_ok());
+/// assert!(validate_locale("en_US").is_err());
+/// ```
+pub fn validate_locale(locale: &str) -> Result<(), ValidationError> {
+    if !LOCALE_REGEX.is_match(locale) {
+        return Err(ValidationError::InvalidLocale);
     }
+
+    Ok(())
+}
+
+/// Ensures that a timezone is named in the IANA timezone database (e.g., "Europe/London").
+///
+/// # Arguments
+///
+/// * `timezone` - The timezone that should be validated
+///
+/// # Examples
+///
+/// ```
+/// use swaply_identity::schema::user::validate_timezone;
+///
+/// assert!(validate_timezone("America/New_York").is_ok());
+/// assert!(validate_timezone("Mars/Phobos").is_err());
+/// ```
+pub fn validate_timezone(timezone: &str) -> Result<(), ValidationError> {
+    timezone
+        .parse::<Tz>()
+        .map(|_| ())
+        .map_err(|_| ValidationError::InvalidTimezone)
 }
 
+/// The namespace that User::new_deterministic derives user IDs in.
+pub const DETERMINISTIC_ID_NAMESPACE: Uuid = Uuid::from_bytes([
+    0x6f, 0x1c, 0x2a, 0x4e, 0x9b, 0x1d, 0x4c, 0x8e, 0xa3, 0xf2, 0x7d, 0x4b, 0x0e, 0x9c, 0x1a, 0x65,
+]);
+
 /// User represents a user of any one of the swaply products. A user may be
 /// authenticated with swaply itself, or with one of the supported
 /// authentication providers.
@@ -241,11 +714,49 @@ pub struct User<'a> {
     /// A hash of this user's password, if they are registered through the
     /// traditional password-based registration service. Typically, such hashes
     /// are generated by passing a password with a prepended salt to the blake3
-    /// hashing function.
-    password_hash: [u8; 32],
+    /// hashing function. Users that only authenticate via an identity provider
+    /// have no password hash.
+    password_hash: Option<PasswordHash>,
+
+    /// The salt prepended to the user's password before it was hashed. Users registered before
+    /// passwords were salted have no salt.
+    password_salt: Option<[u8; SALT_LENGTH]>,
 
     /// The time at which this user was registered.
     registered_at: RegistrationTimestamp,
+
+    /// Whether or not the user has proven that they own their email address.
+    email_verified: bool,
+
+    /// Whether or not the user's password should be hashed with a fresh salt the next time that
+    /// they successfully authenticate with it.
+    rotate_hash_on_next_login: bool,
+
+    /// The number of times that the user has failed to authenticate with their password since
+    /// they last succeeded.
+    failed_login_attempts: u32,
+
+    /// The time until which the user may not authenticate, having failed too many times, if
+    /// they have ever been locked out.
+    locked_until: Option<RegistrationTimestamp>,
+
+    /// The name that the user is presented by, if they've chosen one. Unlike the username, the
+    /// display name needn't be unique.
+    #[serde(borrow)]
+    display_name: Option<&'a str>,
+
+    /// The BCP-47 language tag of the locale that content should be presented to the user in, if
+    /// they've chosen one.
+    #[serde(borrow)]
+    locale: Option<&'a str>,
+
+    /// The IANA timezone that times should be presented to the user in, if they've chosen one.
+    #[serde(borrow)]
+    timezone: Option<&'a str>,
+
+    /// Whether or not the user has been suspended, such that they may not authenticate until
+    /// they're reinstated.
+    suspended: bool,
 }
 
 impl PartialEq<OwnedUser> for User<'_> {
@@ -253,8 +764,17 @@ impl PartialEq<OwnedUser> for User<'_> {
         self.id == other.id
             && self.username == other.username
             && self.email == other.email
-            && self.password_hash == other.password_hash.as_slice()
+            && self.password_hash == other.password_hash
+            && self.password_salt.as_ref().map(|salt| &salt[..]) == other.password_salt.as_deref()
             && self.registered_at == other.registered_at
+            && self.email_verified == other.email_verified
+            && self.rotate_hash_on_next_login == other.rotate_hash_on_next_login
+            && self.failed_login_attempts == other.failed_login_attempts
+            && self.locked_until == other.locked_until
+            && self.display_name == other.display_name.as_deref()
+            && self.locale == other.locale.as_deref()
+            && self.timezone == other.timezone.as_deref()
+            && self.suspended == other.suspended
     }
 }
 
@@ -266,7 +786,8 @@ impl<'a> User<'a> {
     /// * `id` - The ID of the user: if unassigned, a random UUID will be generated
     /// * `username` - The username associated with the user
     /// * `email` - The email associated with the user
-    /// * `password_hash` - The hash of the user's password
+    /// * `password_hash` - The hash of the user's password: if unassigned, the user may only
+    /// authenticate via an identity provider
     /// * `registered_at` - The time that the user registered with swaply: if left unassigned, the
     /// current UTC time will be used
     ///
@@ -278,13 +799,13 @@ impl<'a> User<'a> {
     ///
     /// let password_hash = blake3::hash(b"123456");
     ///
-    /// let u = User::new(None, "test", "test@test.com", *password_hash.as_bytes(), None);
+    /// let u = User::new(None, "test", "test@test.com", Some(password_hash.into()), None);
     /// ```
     pub fn new(
         id: Option<Uuid>,
         username: &'a str,
         email: &'a str,
-        password_hash: [u8; 32],
+        password_hash: Option<PasswordHash>,
         registered_at: Option<DateTime<Utc>>,
     ) -> Self {
         Self {
@@ -292,16 +813,194 @@ impl<'a> User<'a> {
             username,
             email,
             password_hash,
+            password_salt: None,
             registered_at: registered_at
                 .map(|timestamp| timestamp.try_into().unwrap_or_default())
                 .unwrap_or_else(|| {
-                    Utc::now()
+                    SystemClock
+                        .now()
                         .try_into()
                         .unwrap_or(RegistrationTimestamp::default())
                 }),
+            email_verified: false,
+            rotate_hash_on_next_login: false,
+            failed_login_attempts: 0,
+            locked_until: None,
+            display_name: None,
+            locale: None,
+            timezone: None,
+            suspended: false,
         }
     }
 
+    /// Creates a new instance of the user details struct, hashing the user's password with a
+    /// freshly generated salt via password::hash_password. Unlike the UserBuilder, no details are
+    /// validated.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user: if unassigned, a random UUID will be generated
+    /// * `username` - The username associated with the user
+    /// * `email` - The email associated with the user
+    /// * `password` - The plaintext password that the user should authenticate with
+    /// * `registered_at` - The time that the user registered with swaply: if left unassigned, the
+    /// current UTC time will be used
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::schema::user::User;
+    ///
+    /// let u = User::with_password(None, "test", "test@test.com", "correct horse", None);
+    /// assert!(u.verify_password("correct horse"));
+    /// assert!(!u.verify_password("123456"));
+    /// ```
+    pub fn with_password(
+        id: Option<Uuid>,
+        username: &'a str,
+        email: &'a str,
+        password: &str,
+        registered_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        let salt = password::generate_salt();
+
+        Self::new(
+            id,
+            username,
+            email,
+            Some(password::hash_password(password, &salt)),
+            registered_at,
+        )
+        .with_password_salt(salt)
+    }
+
+    /// Creates a new instance of the user details struct, whose ID is derived from a seed, such
+    /// that the same seed always yields the same ID. Intended for reproducible tests and
+    /// fixtures: users registered in production should be assigned random IDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed that the user's ID should be derived from
+    /// * `username` - The username associated with the user
+    /// * `email` - The email associated with the user
+    /// * `password_hash` - The hash of the user's password: if unassigned, the user may only
+    /// authenticate via an identity provider
+    /// * `registered_at` - The time that the user registered with swaply: if left unassigned, the
+    /// current UTC time will be used
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::schema::user::User;
+    ///
+    /// let u = User::new_deterministic(b"alice", "alice", "alice@test.com", None, None);
+    /// let again = User::new_deterministic(b"alice", "alice", "alice@test.com", None, None);
+    /// assert_eq!(u.id(), again.id());
+    /// ```
+    pub fn new_deterministic(
+        seed: &[u8],
+        username: &'a str,
+        email: &'a str,
+        password_hash: Option<PasswordHash>,
+        registered_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self::new_deterministic_in(
+            &DETERMINISTIC_ID_NAMESPACE,
+            seed,
+            username,
+            email,
+            password_hash,
+            registered_at,
+        )
+    }
+
+    /// Creates a new instance of the user details struct, whose ID is derived from a seed in the
+    /// provided namespace. Fixtures that must not collide with one another may each use their
+    /// own namespace.
+    ///
+    /// # Arguments
+    ///
+    /// * `namespace` - The namespace that the user's ID should be derived in
+    /// * `seed` - The seed that the user's ID should be derived from
+    /// * `username` - The username associated with the user
+    /// * `email` - The email associated with the user
+    /// * `password_hash` - The hash of the user's password: if unassigned, the user may only
+    /// authenticate via an identity provider
+    /// * `registered_at` - The time that the user registered with swaply: if left unassigned, the
+    /// current UTC time will be used
+    pub fn new_deterministic_in(
+        namespace: &Uuid,
+        seed: &[u8],
+        username: &'a str,
+        email: &'a str,
+        password_hash: Option<PasswordHash>,
+        registered_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self::new(
+            Some(Uuid::new_v5(namespace, seed)),
+            username,
+            email,
+            password_hash,
+            registered_at,
+        )
+    }
+
+    /// Creates a builder for a new user, which validates the user's details and hashes their
+    /// password before producing the user.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::schema::user::User;
+    ///
+    /// let u = User::builder()
+    ///     .username("test")
+    ///     .email("test@test.com")
+    ///     .password("Tr0ub4dour&3")
+    ///     .build()
+    ///     .unwrap();
+    /// assert!(u.verify_password("Tr0ub4dour&3"));
+    /// ```
+    pub fn builder() -> UserBuilder<'a> {
+        UserBuilder::default()
+    }
+
+    /// Records the salt that was prepended to the user's password before it was hashed.
+    ///
+    /// # Arguments
+    ///
+    /// * `salt` - The salt passed to hash_password alongside the user's password
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::{crypto::password, schema::user::User};
+    ///
+    /// let salt = password::generate_salt();
+    /// let password_hash = password::hash_password("correct horse battery staple", &salt);
+    ///
+    /// let u = User::new(None, "test", "test@test.com", Some(password_hash), None)
+    ///     .with_password_salt(salt);
+    /// assert_eq!(u.password_salt(), Some(&salt));
+    /// ```
+    pub fn with_password_salt(mut self, salt: [u8; SALT_LENGTH]) -> Self {
+        self.password_salt = Some(salt);
+
+        self
+    }
+
+    /// Records whether or not the user's password should be hashed with a fresh salt the next
+    /// time that they successfully authenticate with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `rotate` - Whether or not the user's password hash should be rotated
+    pub fn with_rotate_hash_on_next_login(mut self, rotate: bool) -> Self {
+        self.rotate_hash_on_next_login = rotate;
+
+        self
+    }
+
     /// Gets the ID of the Swaply user.
     ///
     /// # Examples
@@ -314,7 +1013,7 @@ impl<'a> User<'a> {
     /// let password_hash = blake3::hash(b"123456");
     ///
     /// let id = Uuid::new_v4();
-    /// let u = User::new(Some(id), "test", "test@test.com", *password_hash.as_bytes(), None);
+    /// let u = User::new(Some(id), "test", "test@test.com", Some(password_hash.into()), None);
     /// assert_eq!(u.id(), &id);
     /// ```
     pub fn id(&self) -> &Uuid {
@@ -331,7 +1030,7 @@ impl<'a> User<'a> {
     ///
     /// let password_hash = blake3::hash(b"123456");
     ///
-    /// let u = User::new(None, "test", "test@test.com", *password_hash.as_bytes(), None);
+    /// let u = User::new(None, "test", "test@test.com", Some(password_hash.into()), None);
     /// assert_eq!(u.username(), "test");
     /// ```
     pub fn username(&self) -> &str {
@@ -348,17 +1047,56 @@ impl<'a> User<'a> {
     ///
     /// let password_hash = blake3::hash(b"123456");
     ///
-    /// let u = User::new(None, "test", "test@test.com", *password_hash.as_bytes(), None);
+    /// let u = User::new(None, "test", "test@test.com", Some(password_hash.into()), None);
     /// assert_eq!(u.email(), "test@test.com");
     /// ```
     pub fn email(&self) -> &str {
         self.email
     }
 
-    /// Obtains a hash of the user's password, if they have registered via the traditional password
-    /// authentication system.
-    ///
-    /// # Examples
+    /// Gets the name that the user is presented by, if they've chosen one. Callers should fall
+    /// back to the username otherwise.
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name
+    }
+
+    /// Gets the BCP-47 language tag of the user's preferred locale, if they've chosen one.
+    pub fn locale(&self) -> Option<&str> {
+        self.locale
+    }
+
+    /// Gets the IANA name of the user's preferred timezone, if they've chosen one.
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone
+    }
+
+    /// Determines whether or not the user has been suspended. Newly created users have not.
+    pub fn suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Determines whether or not the user has proven that they own their email address. Newly
+    /// created users have not.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::schema::user::User;
+    ///
+    /// let password_hash = blake3::hash(b"123456");
+    ///
+    /// let u = User::new(None, "test", "test@test.com", Some(password_hash.into()), None);
+    /// assert!(!u.email_verified());
+    /// ```
+    pub fn email_verified(&self) -> bool {
+        self.email_verified
+    }
+
+    /// Obtains a hash of the user's password, if they have registered via the traditional password
+    /// authentication system. Users that only authenticate via an identity provider have no
+    /// password hash.
+    ///
+    /// # Examples
     ///
     /// ```
     /// use swaply_identity::schema::user::{User, IdentityProvider};
@@ -366,15 +1104,117 @@ impl<'a> User<'a> {
     ///
     /// let password_hash = blake3::hash(b"123456");
     ///
-    /// let u = User::new(None, "test", "test@test.com", *password_hash.as_bytes(), None);
-    /// assert_eq!(u.password_hash(), password_hash.as_bytes());
+    /// let u = User::new(None, "test", "test@test.com", Some(password_hash.into()), None);
+    /// assert_eq!(u.password_hash(), Some(&password_hash.into()));
+    /// ```
+    pub fn password_hash(&self) -> Option<&PasswordHash> {
+        self.password_hash.as_ref()
+    }
+
+    /// Gets the salt prepended to the user's password before it was hashed, if any.
+    pub fn password_salt(&self) -> Option<&[u8; SALT_LENGTH]> {
+        self.password_salt.as_ref()
+    }
+
+    /// Determines whether or not the provided password is the user's password, assuming that it
+    /// was hashed with the default PasswordHasher (i.e., via hash_password).
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The plaintext password that should be verified
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::{crypto::password, schema::user::User};
+    ///
+    /// let salt = password::generate_salt();
+    /// let password_hash = password::hash_password("correct horse battery staple", &salt);
+    ///
+    /// let u = User::new(None, "test", "test@test.com", Some(password_hash), None)
+    ///     .with_password_salt(salt);
+    /// assert!(u.verify_password("correct horse battery staple"));
+    /// assert!(!u.verify_password("123456"));
+    /// ```
+    pub fn verify_password(&self, password: &str) -> bool {
+        self.verify_password_with(&PasswordHasher::default(), password)
+    }
+
+    /// Determines whether or not the provided password is the user's password, as hashed by the
+    /// provided hasher. Users without a password salt are assumed to hold a legacy, unsalted hash,
+    /// and users without a password hash never have their password verified.
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - The hasher that the user's password hash was produced by
+    /// * `password` - The plaintext password that should be verified
+    pub fn verify_password_with(&self, hasher: &PasswordHasher, password: &str) -> bool {
+        match (&self.password_hash, &self.password_salt) {
+            (Some(hash), Some(salt)) => hasher.verify_salted(password, salt, hash),
+            (Some(hash), None) => hasher.verify_with_legacy(password.as_bytes(), hash),
+            (None, _) => false,
+        }
+    }
+
+    /// Determines whether or not the provided password is the user's password, as hashed by the
+    /// provided hasher, and if so, whether or not the stored hash should be replaced. Legacy,
+    /// unsalted hashes and hashes flagged for rotation are replaced by a freshly salted hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - The hasher that the user's password hash was produced by
+    /// * `password` - The plaintext password that should be verified
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::crypto::password::{self, PasswordHasher, PasswordVerification};
+    /// use swaply_identity::schema::user::User;
+    ///
+    /// let salt = password::generate_salt();
+    /// let password_hash = password::hash_password("correct horse battery staple", &salt);
+    ///
+    /// let u = User::new(None, "test", "test@test.com", Some(password_hash), None)
+    ///     .with_password_salt(salt)
+    ///     .with_rotate_hash_on_next_login(true);
+    ///
+    /// let hasher = PasswordHasher::default();
+    /// match u.verify_and_maybe_upgrade(&hasher, "correct horse battery staple") {
+    ///     PasswordVerification::Upgraded { salt: new_salt, .. } => assert_ne!(new_salt, salt),
+    ///     v => panic!("expected an upgraded hash, got {:?}", v),
+    /// }
     /// ```
-    pub fn password_hash(&self) -> &[u8; 32] {
-        array_ref![self.password_hash, 0, 32]
+    pub fn verify_and_maybe_upgrade(
+        &self,
+        hasher: &PasswordHasher,
+        password: &str,
+    ) -> PasswordVerification {
+        if !self.verify_password_with(hasher, password) {
+            return PasswordVerification::Rejected;
+        }
+
+        if self.password_salt.is_some() && !self.rotate_hash_on_next_login {
+            return PasswordVerification::Accepted;
+        }
+
+        let salt = password::generate_salt();
+
+        PasswordVerification::Upgraded {
+            hash: hasher.hash_salted(password, &salt),
+            salt,
+        }
+    }
+
+    /// Determines whether or not the user may authenticate with a password.
+    pub fn has_password(&self) -> bool {
+        self.password_hash.is_some()
     }
 
     /// Gets a timestamp matching the time at which the user registered with the swaply identity
-    /// service.
+    /// service, truncated to the millisecond. This assumes that the stored timestamp is valid, as
+    /// it is for any user constructed from a DateTime: it panics if the timestamp lies outside of
+    /// the range of representable times, and reads an extra second of nanoseconds as a leap
+    /// second. try_registered_at should be preferred for timestamps of unknown origin.
     ///
     /// # Examples
     ///
@@ -387,9 +1227,9 @@ impl<'a> User<'a> {
     ///
     /// let now = Utc::now();
     ///
-    /// let u = User::new(None, "test", "test@test.com", *password_hash.as_bytes(), Some(now));
-    /// assert_eq!(u.registered_at(), now);
-
+    /// let u = User::new(None, "test", "test@test.com", Some(password_hash.into()), Some(now));
+    /// assert_eq!(u.registered_at().timestamp_millis(), now.timestamp_millis());
+    /// assert_eq!(u.registered_at().timestamp_subsec_nanos() % 1_000_000, 0);
     /// ```
     pub fn registered_at(&self) -> DateTime<Utc> {
         DateTime::<Utc>::from_utc(
@@ -397,6 +1237,248 @@ impl<'a> User<'a> {
             Utc,
         )
     }
+
+    /// Gets a timestamp matching the time at which the user registered with the swaply identity
+    /// service, failing rather than panicking if the stored timestamp is invalid.
+    pub fn try_registered_at(&self) -> Result<DateTime<Utc>, TimestampError> {
+        self.registered_at.to_datetime()
+    }
+}
+
+/// UserBuilder represents a set of user details that have yet to be validated. A user's username
+/// and email address are expected to have been normalized already.
+#[derive(Debug, Default)]
+pub struct UserBuilder<'a> {
+    id: Option<Uuid>,
+    username: Option<&'a str>,
+    email: Option<&'a str>,
+    password: Option<&'a str>,
+    registered_at: Option<DateTime<Utc>>,
+    password_policy: PasswordPolicy,
+    password_hasher: PasswordHasher,
+    clock: Option<&'a dyn Clock>,
+}
+
+impl<'a> UserBuilder<'a> {
+    /// Sets the ID of the user. If unassigned, a random UUID will be generated.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user
+    pub fn id(mut self, id: Uuid) -> Self {
+        self.id = Some(id);
+
+        self
+    }
+
+    /// Sets the username of the user.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The normalized username associated with the user
+    pub fn username(mut self, username: &'a str) -> Self {
+        self.username = Some(username);
+
+        self
+    }
+
+    /// Sets the email address of the user.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The normalized email address associated with the user
+    pub fn email(mut self, email: &'a str) -> Self {
+        self.email = Some(email);
+
+        self
+    }
+
+    /// Sets the plaintext password of the user, which will be hashed with a freshly generated
+    /// salt once the user is built. If unassigned, the user may only authenticate via an
+    /// identity provider.
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The plaintext password that the user should authenticate with
+    pub fn password(mut self, password: &'a str) -> Self {
+        self.password = Some(password);
+
+        self
+    }
+
+    /// Sets the time at which the user registered. If unassigned, the current UTC time will be
+    /// used.
+    ///
+    /// # Arguments
+    ///
+    /// * `registered_at` - The time that the user registered with swaply
+    pub fn registered_at(mut self, registered_at: DateTime<Utc>) -> Self {
+        self.registered_at = Some(registered_at);
+
+        self
+    }
+
+    /// Overrides the clock that the user's registration time is read from, if it isn't set
+    /// explicitly. By default, the SystemClock is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `clock` - The clock that the current time should be read from
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use chrono::{TimeZone, Utc};
+    /// use swaply_identity::{clock::FixedClock, schema::user::User};
+    ///
+    /// let clock = FixedClock(Utc.timestamp(1_592_000_000, 0));
+    ///
+    /// let u = User::builder()
+    ///     .username("test")
+    ///     .email("test@test.com")
+    ///     .clock(&clock)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(u.registered_at(), Utc.timestamp(1_592_000_000, 0));
+    /// ```
+    pub fn clock(mut self, clock: &'a dyn Clock) -> Self {
+        self.clock = Some(clock);
+
+        self
+    }
+
+    /// Overrides the policy that the user's password must satisfy. By default, the
+    /// PasswordPolicy::default() policy is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy that the user's password should be checked against
+    pub fn password_policy(mut self, policy: PasswordPolicy) -> Self {
+        self.password_policy = policy;
+
+        self
+    }
+
+    /// Overrides the scheme used to hash the user's password. This should match the hasher of
+    /// the connector that the user will be inserted with.
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - The hasher that the user's password should be hashed with
+    pub fn password_hasher(mut self, hasher: PasswordHasher) -> Self {
+        self.password_hasher = hasher;
+
+        self
+    }
+
+    /// Validates the user's details and hashes their password, if any, producing a user.
+    pub fn build(self) -> Result<User<'a>, ValidationError> {
+        let username = self
+            .username
+            .ok_or(ValidationError::MissingField("username"))?;
+        let email = self.email.ok_or(ValidationError::MissingField("email"))?;
+
+        validate_username(username)?;
+        validate_email(email)?;
+
+        let registered_at = Some(
+            self.registered_at
+                .unwrap_or_else(|| self.clock.unwrap_or(&SystemClock).now()),
+        );
+
+        let password = match self.password {
+            Some(password) => password,
+            None => return Ok(User::new(self.id, username, email, None, registered_at)),
+        };
+
+        self.password_policy.check(password)?;
+
+        let salt = password::generate_salt();
+
+        Ok(User::new(
+            self.id,
+            username,
+            email,
+            Some(self.password_hasher.hash_salted(password, &salt)),
+            registered_at,
+        )
+        .with_password_salt(salt))
+    }
+}
+
+/// RegistrationRequest represents the body of a request to register a new user with a password,
+/// as sent by a client.
+#[derive(Deserialize)]
+pub struct RegistrationRequest {
+    /// The username that the user would like to be known by
+    pub username: String,
+
+    /// The email address that the user would like to be reachable at
+    pub email: String,
+
+    /// The user's plaintext password
+    pub password: String,
+}
+
+impl RegistrationRequest {
+    /// Validates the request, producing a new user with a generated ID, the current time as its
+    /// registration time, and a freshly salted hash of the requested password.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::schema::user::RegistrationRequest;
+    ///
+    /// let req = RegistrationRequest {
+    ///     username: "Test".to_owned(),
+    ///     email: "test@test.com".to_owned(),
+    ///     password: "Tr0ub4dour&3".to_owned(),
+    /// };
+    ///
+    /// let u = req.into_user().unwrap();
+    /// assert_eq!(u.username(), "test");
+    /// ```
+    pub fn into_user(self) -> Result<OwnedUser, ValidationError> {
+        self.into_user_with(PasswordPolicy::default(), PasswordHasher::default())
+    }
+
+    /// Validates the request against the given password policy, producing a new user whose
+    /// password is hashed with the given hasher.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy that the requested password should be checked against
+    /// * `hasher` - The hasher that the requested password should be hashed with
+    pub fn into_user_with(
+        self,
+        policy: PasswordPolicy,
+        hasher: PasswordHasher,
+    ) -> Result<OwnedUser, ValidationError> {
+        let (username, email) = (
+            normalize_username(&self.username),
+            normalize_email(&self.email),
+        );
+
+        User::builder()
+            .username(&username)
+            .email(&email)
+            .password(&self.password)
+            .password_policy(policy)
+            .password_hasher(hasher)
+            .build()
+            .map(OwnedUser::from)
+    }
+}
+
+// Plaintext passwords should never end up in logs
+impl fmt::Debug for RegistrationRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistrationRequest")
+            .field("username", &self.username)
+            .field("email", &self.email)
+            .field("password", &"<redacted>")
+            .finish()
+    }
 }
 
 #[async_trait]
@@ -405,49 +1487,145 @@ impl<'a> InTable<Scylla, DbSession> for User<'a> {
         session
             .query(
                 // A table storing all users
-                "
-                    CREATE TABLE IF NOT EXISTS identity.users (
-                        id UUID,
-                        username TEXT,
-                        email TEXT,
-                        password_hash TEXT,
-                        registered_at TIMESTAMP,
-                        PRIMARY KEY (id)
-                    );
-                ",
+                Self::table_schema().create_statement(),
             )
             .await
             .and(
                 session
                     .query(
                         // Mappings from nicknames to user IDs
-                        "CREATE INDEX IF NOT EXISTS ON identity.users (username);",
+                        format!(
+                            "CREATE INDEX IF NOT EXISTS ON identity.users ({});",
+                            UserColumn::Username.as_str()
+                        ),
                     )
                     .await,
             )
-            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))
-            .map(|_| ())
+            .and(
+                session
+                    .query(
+                        // Reservations of each username, such that no two users may hold the
+                        // same username
+                        "
+                            CREATE TABLE IF NOT EXISTS identity.usernames (
+                                username TEXT,
+                                user_id UUID,
+                                PRIMARY KEY (username)
+                            );
+                        ",
+                    )
+                    .await,
+            )
+            .and(
+                session
+                    .query(username_prefixes_schema().create_statement())
+                    .await,
+            )
+            .and(
+                session
+                    .query(
+                        // Reservations of each email address, such that no two users may share
+                        // the same email address
+                        "
+                            CREATE TABLE IF NOT EXISTS identity.emails (
+                                email TEXT,
+                                user_id UUID,
+                                PRIMARY KEY (email)
+                            );
+                        ",
+                    )
+                    .await,
+            )
+            .and(
+                session
+                    .query(
+                        // Mappings from client-supplied idempotency keys to the users registered
+                        // under them, such that a retried registration request yields the same
+                        // user
+                        "
+                            CREATE TABLE IF NOT EXISTS identity.registration_idempotency (
+                                key TEXT,
+                                user_id UUID,
+                                PRIMARY KEY (key)
+                            );
+                        ",
+                    )
+                    .await,
+            )
+            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))?;
+
+        verify_table_schema(session, &Self::table_schema()).await
+    }
+
+    fn table_schema() -> TableSchema {
+        TableSchema {
+            keyspace: "identity",
+            name: "users",
+            columns: UserColumn::ALL
+                .iter()
+                .map(|column| ColumnSchema::new(column.as_str(), column.cql_type()))
+                .collect(),
+            partition_key: vec![UserColumn::Id.as_str()],
+            clustering_key: Vec::new(),
+        }
     }
 }
 
 impl Serializable<QueryValues> for User<'_> {
     type Error = ConvertUserToQueryValuesError;
 
-    /// Note: This implementation of try_into requires an allocation to convert the password hash
-    /// into a base58 string.
+    /// Note: This implementation of try_into requires an allocation to copy the password hash and
+    /// salt into BLOBs.
     fn try_into(&self) -> Result<QueryValues, Self::Error> {
         Ok(query_values!(
-            "id" => self.id,
-            "username" => self.username,
-            "email" => self.email,
-            "password_hash" => bs58::encode(self.password_hash.to_vec()).into_string(),
-            "registered_at" => <&RegistrationTimestamp as Into<Timespec>>::into(&self.registered_at)
+            UserColumn::Id.as_str() => self.id,
+            UserColumn::Username.as_str() => self.username,
+            UserColumn::Email.as_str() => self.email,
+            UserColumn::PasswordHash.as_str() => self.password_hash.as_ref().map(|hash| Blob::from(hash.as_bytes().to_vec())),
+            UserColumn::PasswordSalt.as_str() => self.password_salt.map(|salt| Blob::from(salt.to_vec())),
+            UserColumn::RegisteredAt.as_str() => <&RegistrationTimestamp as Into<Timespec>>::into(&self.registered_at),
+            UserColumn::EmailVerified.as_str() => self.email_verified,
+            UserColumn::RotateHashOnNextLogin.as_str() => self.rotate_hash_on_next_login,
+            UserColumn::FailedLoginAttempts.as_str() => self.failed_login_attempts as i32,
+            UserColumn::LockedUntil.as_str() => self.locked_until.map(<RegistrationTimestamp as Into<Timespec>>::into),
+            UserColumn::DisplayName.as_str() => self.display_name,
+            UserColumn::Locale.as_str() => self.locale,
+            UserColumn::Timezone.as_str() => self.timezone,
+            UserColumn::Suspended.as_str() => self.suspended
         ))
     }
 }
 
+impl Idempotent for User<'_> {
+    /// Users are inserted, updated, and deleted by their ID, and every column is set to an
+    /// absolute value, so each write may be repeated.
+    fn idempotent(&self) -> bool {
+        true
+    }
+}
+
 impl<'a> Insertable<Scylla, DbSession> for User<'a> {
-    const INSERTION_QUERY: &'static str = r#"INSERT INTO identity.users (id, username, email, password_hash, registered_at) VALUES (?, ?, ?, ?, ?);"#;
+    const INSERTION_QUERY: &'static str = r#"INSERT INTO identity.users (id, username, email, password_hash, password_salt, registered_at, email_verified, rotate_hash_on_next_login, failed_login_attempts, locked_until, display_name, locale, timezone, suspended) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);"#;
+
+    fn notify_inserted(&self, sink: &dyn EventSink) {
+        sink.on_user_created(self);
+    }
+}
+
+impl<'a> Updatable<Scylla, DbSession> for User<'a> {
+    const UPDATE_QUERY: &'static str = r#"UPDATE identity.users SET username = ?, email = ?, password_hash = ?, password_salt = ?, registered_at = ?, email_verified = ?, rotate_hash_on_next_login = ?, failed_login_attempts = ?, locked_until = ?, display_name = ?, locale = ?, timezone = ?, suspended = ? WHERE id = ?;"#;
+
+    fn notify_updated(&self, sink: &dyn EventSink) {
+        sink.on_user_updated(&self.id);
+    }
+}
+
+impl<'a> Deletable<Scylla, DbSession> for User<'a> {
+    const DELETION_QUERY: &'static str = r#"DELETE FROM identity.users WHERE id = ?;"#;
+
+    fn notify_deleted(&self, sink: &dyn EventSink) {
+        sink.on_user_deleted(&self.id);
+    }
 }
 
 #[derive(Debug)]
@@ -497,17 +1675,20 @@ impl Error for ConvertUserToQueryValuesError {
     }
 }
 
+// Both borrowed and allocated users share the column mapping of the Serializable implementation
 impl TryFrom<User<'_>> for QueryValues {
     type Error = ConvertUserToQueryValuesError;
 
     fn try_from(u: User) -> Result<Self, Self::Error> {
-        Ok(query_values!(
-            "id" => u.id,
-            "username" => u.username,
-            "email" => u.email,
-            "password_hash" => bs58::encode(u.password_hash.to_vec()).into_string(),
-            "registered_at" => <&RegistrationTimestamp as Into<Timespec>>::into(&u.registered_at)
-        ))
+        Serializable::try_into(&u)
+    }
+}
+
+impl TryFrom<&OwnedUser> for QueryValues {
+    type Error = ConvertUserToQueryValuesError;
+
+    fn try_from(u: &OwnedUser) -> Result<Self, Self::Error> {
+        Serializable::try_into(&User::from(u))
     }
 }
 
@@ -517,140 +1698,5168 @@ impl<'a> From<&'a OwnedUser> for User<'a> {
             id: u.id,
             username: u.username.as_ref(),
             email: u.email.as_ref(),
-            password_hash: *array_ref![u.password_hash.as_slice(), 0, 32],
+            password_hash: u.password_hash.clone(),
+            password_salt: u
+                .password_salt
+                .as_ref()
+                .map(|salt| *array_ref![salt.as_slice(), 0, SALT_LENGTH]),
             registered_at: u.registered_at,
+            email_verified: u.email_verified,
+            rotate_hash_on_next_login: u.rotate_hash_on_next_login,
+            failed_login_attempts: u.failed_login_attempts,
+            locked_until: u.locked_until,
+            display_name: u.display_name.as_deref(),
+            locale: u.locale.as_deref(),
+            timezone: u.timezone.as_deref(),
+            suspended: u.suspended,
         }
     }
 }
 
-/// UserQuery represents all non-filter queries for users.
+/// DisplayNameUpdate represents a change to the display name of a user, leaving every other
+/// column untouched.
 #[derive(Debug)]
-pub enum UserQuery<'a> {
-    Id(&'a Uuid),
-    Nickname(&'a str),
+pub struct DisplayNameUpdate<'a> {
+    /// The ID of the user whose display name should be changed
+    id: Uuid,
+
+    /// The user's new display name, or None if it should be cleared
+    display_name: Option<&'a str>,
 }
 
-#[async_trait]
-impl Queryable<Scylla, DbSession> for UserQuery<'_> {
-    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
-        Ok(match self {
-            Self::Id(id) => format!("SELECT * FROM identity.users WHERE id = {};", id),
-            Self::Nickname(nick) => {
-                format!("SELECT * FROM identity.users WHERE username = '{}';", nick)
-            }
-        })
+impl<'a> DisplayNameUpdate<'a> {
+    /// Creates a new change to the display name of a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose display name should be changed
+    /// * `display_name` - The user's new display name, or None if it should be cleared
+    pub fn new(id: Uuid, display_name: Option<&'a str>) -> Self {
+        Self { id, display_name }
     }
 }
 
-/// OwnedUser represents an allocated user.
-#[derive(Debug)]
-pub struct OwnedUser {
-    id: Uuid,
-    username: String,
-    email: String,
-    password_hash: Vec<u8>,
-    registered_at: RegistrationTimestamp,
+impl Serializable<QueryValues> for DisplayNameUpdate<'_> {
+    type Error = IdentityError;
+
+    fn try_into(&self) -> Result<QueryValues, Self::Error> {
+        Ok(query_values!(
+            UserColumn::DisplayName.as_str() => self.display_name,
+            UserColumn::Id.as_str() => self.id
+        ))
+    }
 }
 
-impl PartialEq<User<'_>> for OwnedUser {
-    fn eq(&self, other: &User) -> bool {
-        self.id == other.id
-            && self.username == other.username
-            && self.email == other.email
-            && self.password_hash == other.password_hash
-            && self.registered_at == other.registered_at
+impl Idempotent for DisplayNameUpdate<'_> {
+    fn idempotent(&self) -> bool {
+        true
     }
 }
 
-/// ConvertRowToUserError represents an error that may be encountered whilst converting a row to
-/// an owned user instance.
-#[derive(Debug)]
-pub enum ConvertRowToUserError {
-    CDRSError(CDRSError),
-    DecodingError(Bs58DecodingError),
+impl<'a> Updatable<Scylla, DbSession> for DisplayNameUpdate<'a> {
+    const UPDATE_QUERY: &'static str =
+        r#"UPDATE identity.users SET display_name = ? WHERE id = ?;"#;
+
+    fn notify_updated(&self, sink: &dyn EventSink) {
+        sink.on_user_updated(&self.id);
+    }
+}
+
+/// UserUpdate represents a change to some of the columns of a user, leaving every other column
+/// untouched. Usernames, email addresses, and passwords can't be changed by an update, since they
+/// must be reserved or hashed; see Scylla::change_username, change_email, and change_password.
+///
+/// # Examples
+///
+/// ```
+/// use swaply_identity::schema::user::{UserColumn, UserUpdate};
+///
+/// let update = UserUpdate::default()
+///     .display_name(Some("Jane Doe"))
+///     .email_verified(true);
+/// assert_eq!(
+///     update.changed_columns(),
+///     vec![UserColumn::EmailVerified, UserColumn::DisplayName]
+/// );
+/// ```
+#[derive(Default, Clone, Debug)]
+pub struct UserUpdate<'a> {
+    email_verified: Option<bool>,
+    rotate_hash_on_next_login: Option<bool>,
+    suspended: Option<bool>,
+    display_name: Option<Option<&'a str>>,
+    locale: Option<Option<&'a str>>,
+    timezone: Option<Option<&'a str>>,
+}
+
+impl<'a> UserUpdate<'a> {
+    /// Sets whether or not the user has proven that they own their email address.
+    ///
+    /// # Arguments
+    ///
+    /// * `verified` - Whether or not the user's email address is verified
+    pub fn email_verified(mut self, verified: bool) -> Self {
+        self.email_verified = Some(verified);
+
+        self
+    }
+
+    /// Sets whether or not the user's password should be hashed with a fresh salt the next time
+    /// that they successfully authenticate with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `rotate` - Whether or not the user's password hash should be rotated
+    pub fn rotate_hash_on_next_login(mut self, rotate: bool) -> Self {
+        self.rotate_hash_on_next_login = Some(rotate);
+
+        self
+    }
+
+    /// Sets whether or not the user is suspended, such that they may not authenticate.
+    ///
+    /// # Arguments
+    ///
+    /// * `suspended` - Whether or not the user is suspended
+    pub fn suspended(mut self, suspended: bool) -> Self {
+        self.suspended = Some(suspended);
+
+        self
+    }
+
+    /// Sets or clears the name that the user is presented by.
+    ///
+    /// # Arguments
+    ///
+    /// * `display_name` - The user's display name, or None if it should be cleared
+    pub fn display_name(mut self, display_name: Option<&'a str>) -> Self {
+        self.display_name = Some(display_name);
+
+        self
+    }
+
+    /// Sets or clears the user's preferred locale.
+    ///
+    /// # Arguments
+    ///
+    /// * `locale` - The BCP-47 language tag of the user's locale, or None if it should be cleared
+    pub fn locale(mut self, locale: Option<&'a str>) -> Self {
+        self.locale = Some(locale);
+
+        self
+    }
+
+    /// Sets or clears the user's preferred timezone.
+    ///
+    /// # Arguments
+    ///
+    /// * `timezone` - The IANA name of the user's timezone, or None if it should be cleared
+    pub fn timezone(mut self, timezone: Option<&'a str>) -> Self {
+        self.timezone = Some(timezone);
+
+        self
+    }
+
+    /// Gets the columns that the update changes, in the order that they're declared.
+    pub fn changed_columns(&self) -> Vec<UserColumn> {
+        self.changes()
+            .into_iter()
+            .map(|(column, _)| column)
+            .collect()
+    }
+
+    /// Determines whether or not the update changes any columns.
+    pub fn is_empty(&self) -> bool {
+        self.changes().is_empty()
+    }
+
+    /// Gets each column that the update changes, alongside its new value.
+    fn changes(&self) -> Vec<(UserColumn, Value)> {
+        let flags = vec![
+            (UserColumn::EmailVerified, self.email_verified),
+            (
+                UserColumn::RotateHashOnNextLogin,
+                self.rotate_hash_on_next_login,
+            ),
+            (UserColumn::Suspended, self.suspended),
+        ];
+        let preferences = vec![
+            (UserColumn::DisplayName, self.display_name),
+            (UserColumn::Locale, self.locale),
+            (UserColumn::Timezone, self.timezone),
+        ];
+
+        flags
+            .into_iter()
+            .filter_map(|(column, value)| value.map(|value| (column, value.into())))
+            .chain(
+                preferences
+                    .into_iter()
+                    .filter_map(|(column, value)| value.map(|value| (column, value.into()))),
+            )
+            .collect()
+    }
+
+    /// Ensures that every value set by the update is valid.
+    fn validate(&self) -> Result<(), ValidationError> {
+        if let Some(Some(display_name)) = self.display_name {
+            validate_display_name(display_name)?;
+        }
+
+        if let Some(Some(locale)) = self.locale {
+            validate_locale(locale)?;
+        }
+
+        if let Some(Some(timezone)) = self.timezone {
+            validate_timezone(timezone)?;
+        }
+
+        Ok(())
+    }
+
+    /// Validates the update, producing a parameterized statement that changes only the columns
+    /// set by the update, alongside the values that it should be bound to. The ID of the user
+    /// must be bound to the final parameter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::schema::user::UserUpdate;
+    ///
+    /// let (query, values) = UserUpdate::default().locale(Some("en-GB")).to_cql().unwrap();
+    /// assert_eq!(query, "UPDATE identity.users SET locale = ? WHERE id = ?;");
+    /// assert_eq!(values.len(), 1);
+    ///
+    /// assert!(UserUpdate::default().to_cql().is_err());
+    /// ```
+    pub fn to_cql(&self) -> Result<(String, Vec<Value>), ValidationError> {
+        self.validate()?;
+
+        let changes = self.changes();
+        if changes.is_empty() {
+            return Err(ValidationError::EmptyUpdate);
+        }
+
+        let assignments = changes
+            .iter()
+            .map(|(column, _)| format!("{} = ?", column.as_str()))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        Ok((
+            format!(
+                "UPDATE identity.users SET {} WHERE {} = ?;",
+                assignments,
+                UserColumn::Id.as_str()
+            ),
+            changes.into_iter().map(|(_, value)| value).collect(),
+        ))
+    }
+}
+
+impl Idempotent for UserUpdate<'_> {
+    /// Every column changed by the update is set to an absolute value, rather than one derived
+    /// from its current value.
+    fn idempotent(&self) -> bool {
+        true
+    }
+}
+
+/// UserQuery represents all non-filter queries for users.
+#[derive(Debug)]
+pub enum UserQuery<'a> {
+    Id(&'a Uuid),
+    Nickname(&'a str),
+
+    /// Every user holding one of the IDs, in no particular order
+    Ids(&'a [Uuid]),
+
+    /// Every user registered between the two instants, inclusive. Since registered_at isn't
+    /// indexed, this requires a scan of the users table, and should be reserved for reporting.
+    RegisteredBetween(DateTime<Utc>, DateTime<Utc>),
+}
+
+impl UserQuery<'_> {
+    /// Gets the condition that a user must meet in order to match the query.
+    fn condition(&self) -> Result<String, ValidationError> {
+        match self {
+            Self::Id(id) => Ok(format!("{} = {}", UserColumn::Id.as_str(), id)),
+            Self::Ids(ids) => Ok(format!(
+                "{} IN ({})",
+                UserColumn::Id.as_str(),
+                ids.iter()
+                    .map(Uuid::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )),
+            Self::Nickname(nick) => Ok(format!("{} = '{}'", UserColumn::Username.as_str(), nick)),
+            Self::RegisteredBetween(start, end) if start > end => {
+                Err(ValidationError::InvalidRange)
+            }
+            Self::RegisteredBetween(start, end) => Ok(format!(
+                "{col} >= {} AND {col} <= {}",
+                start.timestamp_millis(),
+                end.timestamp_millis(),
+                col = UserColumn::RegisteredAt.as_str()
+            )),
+        }
+    }
+
+    /// Gets any clause that must follow the query's condition in order for it to be accepted.
+    fn suffix(&self) -> &'static str {
+        match self {
+            Self::RegisteredBetween(..) => " ALLOW FILTERING",
+            _ => "",
+        }
+    }
+}
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for UserQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.users WHERE {}{};",
+            self.condition()?,
+            self.suffix()
+        ))
+    }
+}
+
+/// UserColumn represents any column of the users table that may be selected individually.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum UserColumn {
+    Id,
+    Username,
+    Email,
+    PasswordHash,
+    PasswordSalt,
+    RegisteredAt,
+    EmailVerified,
+    RotateHashOnNextLogin,
+    FailedLoginAttempts,
+    LockedUntil,
+    DisplayName,
+    Locale,
+    Timezone,
+    Suspended,
+}
+
+impl UserColumn {
+    /// Every column of the users table, in the order that they're declared and inserted.
+    pub const ALL: [UserColumn; 14] = [
+        Self::Id,
+        Self::Username,
+        Self::Email,
+        Self::PasswordHash,
+        Self::PasswordSalt,
+        Self::RegisteredAt,
+        Self::EmailVerified,
+        Self::RotateHashOnNextLogin,
+        Self::FailedLoginAttempts,
+        Self::LockedUntil,
+        Self::DisplayName,
+        Self::Locale,
+        Self::Timezone,
+        Self::Suspended,
+    ];
+
+    /// Gets the name of the column, as it appears in the users table.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Username => "username",
+            Self::Email => "email",
+            Self::PasswordHash => "password_hash",
+            Self::PasswordSalt => "password_salt",
+            Self::RegisteredAt => "registered_at",
+            Self::EmailVerified => "email_verified",
+            Self::RotateHashOnNextLogin => "rotate_hash_on_next_login",
+            Self::FailedLoginAttempts => "failed_login_attempts",
+            Self::LockedUntil => "locked_until",
+            Self::DisplayName => "display_name",
+            Self::Locale => "locale",
+            Self::Timezone => "timezone",
+            Self::Suspended => "suspended",
+        }
+    }
+
+    /// Gets the CQL type of the column.
+    pub const fn cql_type(&self) -> &'static str {
+        match self {
+            Self::Id => "UUID",
+            Self::Username | Self::Email | Self::DisplayName | Self::Locale | Self::Timezone => {
+                "TEXT"
+            }
+            Self::PasswordHash | Self::PasswordSalt => "BLOB",
+            Self::RegisteredAt | Self::LockedUntil => "TIMESTAMP",
+            Self::EmailVerified | Self::RotateHashOnNextLogin | Self::Suspended => "BOOLEAN",
+            Self::FailedLoginAttempts => "INT",
+        }
+    }
+}
+
+/// Generates the statement inserting a user from the UserColumn enum. The Insertable trait
+/// requires a constant query, so User::INSERTION_QUERY is checked against this in tests.
+#[cfg(test)]
+fn users_insertion_query() -> String {
+    format!(
+        "INSERT INTO identity.users ({}) VALUES ({});",
+        UserColumn::ALL
+            .iter()
+            .map(UserColumn::as_str)
+            .collect::<Vec<&str>>()
+            .join(", "),
+        vec!["?"; UserColumn::ALL.len()].join(", ")
+    )
+}
+
+/// UniqueConflict represents an existing reservation that prevented a user from being inserted
+/// via Scylla::try_insert_unique.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UniqueConflict {
+    /// The user's username is already held by another user
+    Username { username: String, user_id: Uuid },
+
+    /// The user's email address is already held by another user
+    Email { email: String, user_id: Uuid },
+}
+
+/// Inconsistency represents a disagreement between the users table and the username or email
+/// address reservations, as reported by Scylla::audit_lookup_consistency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// A username is reserved for a user that doesn't exist
+    OrphanedUsername { username: String, user_id: Uuid },
+
+    /// An email address is reserved for a user that doesn't exist
+    OrphanedEmail { email: String, user_id: Uuid },
+
+    /// A user's username isn't reserved for them
+    MissingUsernameReservation { username: String, user_id: Uuid },
+
+    /// A user's email address isn't reserved for them
+    MissingEmailReservation { email: String, user_id: Uuid },
+}
+
+/// Extracts the ID of the user holding a username or email address reservation from the outcome
+/// of an attempt to reserve it.
+///
+/// # Arguments
+///
+/// * `outcome` - The outcome of the reservation
+fn reservation_holder(outcome: LwtOutcome<Row>) -> IdentityResult<LwtOutcome<Uuid>> {
+    match outcome {
+        LwtOutcome::Applied => Ok(LwtOutcome::Applied),
+        LwtOutcome::NotApplied(row) => Ok(LwtOutcome::NotApplied(row.get_r_by_name("user_id")?)),
+    }
+}
+
+/// PartialUser represents a user of which only some columns have been loaded. Columns that
+/// weren't selected are None.
+#[derive(Default, Debug)]
+pub struct PartialUser {
+    id: Option<Uuid>,
+    username: Option<String>,
+    email: Option<String>,
+    password_hash: Option<PasswordHash>,
+    password_salt: Option<Vec<u8>>,
+    registered_at: Option<RegistrationTimestamp>,
+    email_verified: Option<bool>,
+    rotate_hash_on_next_login: Option<bool>,
+    failed_login_attempts: Option<u32>,
+    locked_until: Option<RegistrationTimestamp>,
+    display_name: Option<String>,
+    locale: Option<String>,
+    timezone: Option<String>,
+    suspended: Option<bool>,
+}
+
+impl PartialUser {
+    /// Gets the ID of the user, if it was selected.
+    pub fn id(&self) -> Option<&Uuid> {
+        self.id.as_ref()
+    }
+
+    /// Gets the username of the user, if it was selected.
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// Gets the email of the user, if it was selected.
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
+    /// Gets the hash of the user's password, if it was selected.
+    pub fn password_hash(&self) -> Option<&PasswordHash> {
+        self.password_hash.as_ref()
+    }
+
+    /// Gets the salt prepended to the user's password, if it was selected and the user has one.
+    pub fn password_salt(&self) -> Option<&[u8]> {
+        self.password_salt.as_deref()
+    }
+
+    /// Gets the time at which the user registered, if it was selected.
+    pub fn registered_at(&self) -> Option<DateTime<Utc>> {
+        self.registered_at.map(|timestamp| timestamp.into())
+    }
+
+    /// Determines whether or not the user has verified their email address, if it was selected.
+    pub fn email_verified(&self) -> Option<bool> {
+        self.email_verified
+    }
+
+    /// Determines whether or not the user's password hash is due to be rotated, if it was
+    /// selected.
+    pub fn rotate_hash_on_next_login(&self) -> Option<bool> {
+        self.rotate_hash_on_next_login
+    }
+
+    /// Gets the number of times that the user has failed to authenticate since they last
+    /// succeeded, if it was selected and the user has ever failed to authenticate.
+    pub fn failed_login_attempts(&self) -> Option<u32> {
+        self.failed_login_attempts
+    }
+
+    /// Gets the time until which the user is locked out, if it was selected and the user has ever
+    /// been locked out.
+    pub fn locked_until(&self) -> Option<DateTime<Utc>> {
+        self.locked_until.map(|timestamp| timestamp.into())
+    }
+
+    /// Gets the display name of the user, if it was selected and the user has one.
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    /// Gets the user's preferred locale, if it was selected and the user has one.
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
+    /// Gets the user's preferred timezone, if it was selected and the user has one.
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone.as_deref()
+    }
+
+    /// Determines whether or not the user has been suspended, if it was selected.
+    pub fn suspended(&self) -> Option<bool> {
+        self.suspended
+    }
+
+    /// Converts the selected columns of a row into a partial user.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - The row that should be converted
+    /// * `columns` - The columns that were selected in the row
+    fn from_row(row: &Row, columns: &[UserColumn]) -> Result<Self, ConvertRowToUserError> {
+        let mut u = Self::default();
+
+        for &column in columns {
+            match column {
+                UserColumn::Id => u.id = optional_column(row, column)?,
+                UserColumn::Username => u.username = optional_column(row, column)?,
+                UserColumn::Email => u.email = optional_column(row, column)?,
+                UserColumn::PasswordHash => u.password_hash = hash_column(row, column)?,
+                UserColumn::PasswordSalt => u.password_salt = salt_column(row, column)?,
+                UserColumn::RegisteredAt => {
+                    u.registered_at =
+                        optional_column::<Timespec>(row, column)?.map(|timespec| timespec.into())
+                }
+                UserColumn::EmailVerified => u.email_verified = optional_column(row, column)?,
+                UserColumn::RotateHashOnNextLogin => {
+                    u.rotate_hash_on_next_login = optional_column(row, column)?
+                }
+                UserColumn::FailedLoginAttempts => {
+                    u.failed_login_attempts =
+                        optional_column::<i32>(row, column)?.map(|attempts| attempts.max(0) as u32)
+                }
+                UserColumn::LockedUntil => {
+                    u.locked_until =
+                        optional_column::<Timespec>(row, column)?.map(|timespec| timespec.into())
+                }
+                UserColumn::DisplayName => u.display_name = optional_column(row, column)?,
+                UserColumn::Locale => u.locale = optional_column(row, column)?,
+                UserColumn::Timezone => u.timezone = optional_column(row, column)?,
+                UserColumn::Suspended => u.suspended = optional_column(row, column)?,
+            }
+        }
+
+        Ok(u)
+    }
+}
+
+/// The columns that a UserFilter may filter users by.
+const FILTER_COLUMNS: [UserColumn; 5] = [
+    UserColumn::Id,
+    UserColumn::Username,
+    UserColumn::Email,
+    UserColumn::RegisteredAt,
+    UserColumn::EmailVerified,
+];
+
+/// The salt that passwords are hashed with when authenticating a user that doesn't exist, or has
+/// no password.
+const DUMMY_PASSWORD_SALT: [u8; SALT_LENGTH] = [0; SALT_LENGTH];
+
+/// The hash that passwords are verified against when authenticating a user that doesn't exist, or
+/// has no password.
+const DUMMY_PASSWORD_HASH: PasswordHash = PasswordHash::new([0; HASH_LENGTH]);
+
+/// The maximum number of times that recording a failed login may conflict with concurrent failed
+/// logins before giving up.
+const MAX_FAILED_LOGIN_UPDATES: usize = 5;
+
+/// Generates the conditional update setting a user's failed login count and lock, which is only
+/// applied if their count still holds the expected value.
+///
+/// # Arguments
+///
+/// * `expected` - The CQL literal that the user's count must hold (e.g., "2" or "null")
+fn failed_login_update(expected: &str) -> String {
+    format!(
+        "UPDATE identity.users SET {col} = ?, {} = ? WHERE {} = ? IF {col} = {};",
+        UserColumn::LockedUntil.as_str(),
+        UserColumn::Id.as_str(),
+        expected,
+        col = UserColumn::FailedLoginAttempts.as_str()
+    )
+}
+
+/// LockoutPolicy represents the rules by which users that repeatedly fail to authenticate are
+/// locked out. The default policy locks users out for fifteen minutes after five failed attempts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// The number of consecutive failed attempts at which a user is locked out
+    pub threshold: u32,
+
+    /// The amount of time for which a user is locked out
+    pub window: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            window: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+/// The maximum number of users deleted by a single batch.
+const DELETE_BATCH_SIZE: usize = 100;
+
+/// The maximum number of users updated concurrently while backfilling a column.
+const BACKFILL_BATCH_SIZE: usize = 100;
+
+/// The amount of time for which an idempotency key is mapped to the user registered under it.
+/// Should a registration be abandoned before its user is inserted (e.g., by a crash), its key is
+/// released once this has elapsed.
+pub const REGISTRATION_KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// UserOrder represents the order in which a query's matching users are returned. Each query
+/// only supports the orders permitted by the clustering key of the table that it reads, and
+/// fails with a QueryError::UnsupportedOrdering otherwise.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UserOrder {
+    /// Alphabetically by username. This is the default
+    UsernameAsc,
+
+    /// Reverse-alphabetically by username
+    UsernameDesc,
+}
+
+impl UserOrder {
+    /// Gets the column that users are ordered by.
+    pub const fn column(&self) -> UserColumn {
+        match self {
+            Self::UsernameAsc | Self::UsernameDesc => UserColumn::Username,
+        }
+    }
+
+    /// Gets the direction in which users are ordered.
+    pub const fn order(&self) -> ClusteringOrder {
+        match self {
+            Self::UsernameAsc => ClusteringOrder::Asc,
+            Self::UsernameDesc => ClusteringOrder::Desc,
+        }
+    }
+
+    /// Generates the clause ordering the rows of a table by the order, if the table's clustering
+    /// key supports it.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - The table whose rows should be ordered
+    fn clause(&self, schema: &TableSchema) -> Result<String, QueryError> {
+        schema.order_by(self.column().as_str(), self.order())
+    }
+}
+
+impl Default for UserOrder {
+    fn default() -> Self {
+        Self::UsernameAsc
+    }
+}
+
+/// Describes the table of usernames bucketed by each of their leading characters up to
+/// USERNAME_PREFIX_LENGTH, such that usernames may be searched by prefix.
+fn username_prefixes_schema() -> TableSchema {
+    TableSchema {
+        keyspace: "identity",
+        name: "username_prefixes",
+        columns: vec![
+            ColumnSchema::new("prefix", "TEXT"),
+            ColumnSchema::new("username", "TEXT"),
+            ColumnSchema::new("user_id", "UUID"),
+        ],
+        partition_key: vec!["prefix"],
+        clustering_key: vec![("username", ClusteringOrder::Asc)],
+    }
+}
+
+/// UserFilter represents a set of conditions that a user must meet in order to match the filter.
+/// A filter without any conditions matches every user.
+#[derive(Default, Clone, Debug)]
+pub struct UserFilter {
+    registered_before: Option<DateTime<Utc>>,
+    username_prefix: Option<String>,
+    email_domain: Option<String>,
+    email_verified: Option<bool>,
+}
+
+impl UserFilter {
+    /// Only matches users that registered before the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - The time before which matching users registered
+    pub fn registered_before(mut self, time: DateTime<Utc>) -> Self {
+        self.registered_before = Some(time);
+
+        self
+    }
+
+    /// Only matches users whose username starts with the given prefix.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The normalized prefix of the usernames of matching users
+    pub fn username_prefix(mut self, prefix: &str) -> Self {
+        self.username_prefix = Some(prefix.to_owned());
+
+        self
+    }
+
+    /// Only matches users whose email address is at the given domain.
+    ///
+    /// # Arguments
+    ///
+    /// * `domain` - The normalized domain of the email addresses of matching users
+    pub fn email_domain(mut self, domain: &str) -> Self {
+        self.email_domain = Some(domain.to_owned());
+
+        self
+    }
+
+    /// Only matches users that have or haven't verified their email address.
+    ///
+    /// # Arguments
+    ///
+    /// * `verified` - Whether or not matching users have verified their email address
+    pub fn email_verified(mut self, verified: bool) -> Self {
+        self.email_verified = Some(verified);
+
+        self
+    }
+
+    /// Determines whether or not a user meets each of the filter's conditions.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - A user of which at least the FILTER_COLUMNS have been loaded
+    fn matches(&self, u: &PartialUser) -> bool {
+        self.registered_before
+            .map_or(true, |time| u.registered_at().map_or(false, |at| at < time))
+            && self.username_prefix.as_ref().map_or(true, |prefix| {
+                u.username()
+                    .map_or(false, |username| username.starts_with(prefix.as_str()))
+            })
+            && self.email_domain.as_ref().map_or(true, |domain| {
+                u.email().map_or(false, |email| {
+                    email.rsplit('@').next() == Some(domain.as_str())
+                })
+            })
+            && self.email_verified.map_or(true, |verified| {
+                u.email_verified().unwrap_or(false) == verified
+            })
+    }
+}
+
+/// OwnedUser represents an allocated user. The user's password hash and salt are never
+/// serialized, such that a serialized user may be cached or sent over the wire without exposing
+/// them; a deserialized user has no password.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OwnedUser {
+    id: Uuid,
+    username: String,
+    email: String,
+    #[serde(skip_serializing, default)]
+    password_hash: Option<PasswordHash>,
+    #[serde(skip_serializing, default)]
+    password_salt: Option<Vec<u8>>,
+    registered_at: RegistrationTimestamp,
+    email_verified: bool,
+    rotate_hash_on_next_login: bool,
+    failed_login_attempts: u32,
+    locked_until: Option<RegistrationTimestamp>,
+    display_name: Option<String>,
+    locale: Option<String>,
+    timezone: Option<String>,
+    #[serde(default)]
+    suspended: bool,
 }
 
-impl fmt::Display for ConvertRowToUserError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "encountered an error whilst deserializing a row: {:?}",
-            self.source()
-        )
+impl From<User<'_>> for OwnedUser {
+    fn from(u: User) -> Self {
+        Self {
+            id: u.id,
+            username: u.username.to_owned(),
+            email: u.email.to_owned(),
+            password_hash: u.password_hash,
+            password_salt: u.password_salt.map(|salt| salt.to_vec()),
+            registered_at: u.registered_at,
+            email_verified: u.email_verified,
+            rotate_hash_on_next_login: u.rotate_hash_on_next_login,
+            failed_login_attempts: u.failed_login_attempts,
+            locked_until: u.locked_until,
+            display_name: u.display_name.map(str::to_owned),
+            locale: u.locale.map(str::to_owned),
+            timezone: u.timezone.map(str::to_owned),
+            suspended: u.suspended,
+        }
+    }
+}
+
+impl OwnedUser {
+    /// Creates a new instance of the owned user struct, as is useful for fixtures and mocks that
+    /// don't load users from the database. Users are constructed as by User::new.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the Swaply user. If none is provided, one will be generated
+    /// * `username` - The username of the Swaply user
+    /// * `email` - The email of the Swaply user
+    /// * `password_hash` - The password hash of the Swaply user, if any
+    /// * `registered_at` - The time at which the user registered. If none is provided, the
+    /// current UTC time will be used
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use swaply_identity::schema::user::OwnedUser;
+    ///
+    /// let u = OwnedUser::new(None, "test", "test@test.com", None, None);
+    /// assert_eq!(u.username(), "test");
+    /// ```
+    pub fn new(
+        id: Option<Uuid>,
+        username: impl Into<String>,
+        email: impl Into<String>,
+        password_hash: Option<PasswordHash>,
+        registered_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self {
+            username: username.into(),
+            email: email.into(),
+            ..User::new(id, "", "", password_hash, registered_at).into()
+        }
+    }
+
+    /// Gets the ID of the Swaply user.
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    /// Gets the username of the Swaply user.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Gets the email of the Swaply user.
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// Gets the name that the user is presented by, if they've chosen one.
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    /// Gets the name that the user should be presented by: their display name, or their username
+    /// if they haven't chosen one.
+    pub fn display_name_or_username(&self) -> &str {
+        self.display_name().unwrap_or(&self.username)
+    }
+
+    /// Gets the BCP-47 language tag of the user's preferred locale, if they've chosen one.
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
+    /// Gets the IANA name of the user's preferred timezone, if they've chosen one.
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone.as_deref()
+    }
+
+    /// Gets the user's preferred timezone, if they've chosen one.
+    pub fn tz(&self) -> Option<Tz> {
+        self.timezone().and_then(|timezone| timezone.parse().ok())
+    }
+
+    /// Gets a timestamp matching the time at which the user registered with the swaply identity
+    /// service.
+    pub fn registered_at(&self) -> DateTime<Utc> {
+        self.registered_at.into()
+    }
+
+    /// Determines whether or not the user has proven that they own their email address.
+    pub fn email_verified(&self) -> bool {
+        self.email_verified
+    }
+
+    /// Determines whether or not the user's password is due to be hashed with a fresh salt the
+    /// next time that they successfully authenticate with it.
+    pub fn rotate_hash_on_next_login(&self) -> bool {
+        self.rotate_hash_on_next_login
+    }
+
+    /// Gets the number of times that the user has failed to authenticate with their password
+    /// since they last succeeded.
+    pub fn failed_login_attempts(&self) -> u32 {
+        self.failed_login_attempts
+    }
+
+    /// Gets the time until which the user may not authenticate, if they have ever been locked
+    /// out. The lock may since have expired.
+    pub fn locked_until(&self) -> Option<DateTime<Utc>> {
+        self.locked_until.map(|timestamp| timestamp.into())
+    }
+
+    /// Determines whether or not the user has failed to authenticate so many times that they are
+    /// currently locked out. Locks expire automatically once their window has passed.
+    pub fn is_locked_out(&self) -> bool {
+        self.is_locked_out_at(SystemClock.now())
+    }
+
+    /// Determines whether or not the user is locked out at the provided time.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time at which the lock should be checked
+    pub fn is_locked_out_at(&self, now: DateTime<Utc>) -> bool {
+        self.locked_until()
+            .map_or(false, |locked_until| now < locked_until)
+    }
+
+    /// Determines whether or not the user has been suspended, such that they may not
+    /// authenticate until they're reinstated.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Determines whether or not the user may authenticate with a password.
+    pub fn has_password(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    /// Determines whether or not the provided password is the user's password, assuming that it
+    /// was hashed with the default PasswordHasher (i.e., via hash_password).
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The plaintext password that should be verified
+    pub fn verify_password(&self, password: &str) -> bool {
+        User::from(self).verify_password(password)
+    }
+
+    /// Determines whether or not the provided password is the user's password, and if so, whether
+    /// or not the stored hash should be replaced. See User::verify_and_maybe_upgrade.
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - The hasher that the user's password hash was produced by
+    /// * `password` - The plaintext password that should be verified
+    pub fn verify_and_maybe_upgrade(
+        &self,
+        hasher: &PasswordHasher,
+        password: &str,
+    ) -> PasswordVerification {
+        User::from(self).verify_and_maybe_upgrade(hasher, password)
+    }
+
+    /// Determines whether or not the provided password is the user's password, as hashed by the
+    /// provided hasher.
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - The hasher that the user's password hash was produced by
+    /// * `password` - The plaintext password that should be verified
+    pub fn verify_password_with(&self, hasher: &PasswordHasher, password: &str) -> bool {
+        User::from(self).verify_password_with(hasher, password)
+    }
+}
+
+// Users are identified by their ID alone: two allocated users with the same ID are the same user,
+// even if one of them is stale. Note that this differs from the field-wise comparison of an
+// OwnedUser with a User.
+impl PartialEq for OwnedUser {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for OwnedUser {}
+
+impl Hash for OwnedUser {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl PartialEq<User<'_>> for OwnedUser {
+    fn eq(&self, other: &User) -> bool {
+        self.id == other.id
+            && self.username == other.username
+            && self.email == other.email
+            && self.password_hash == other.password_hash
+            && self.password_salt.as_deref() == other.password_salt.as_ref().map(|salt| &salt[..])
+            && self.registered_at == other.registered_at
+            && self.email_verified == other.email_verified
+            && self.rotate_hash_on_next_login == other.rotate_hash_on_next_login
+            && self.failed_login_attempts == other.failed_login_attempts
+            && self.locked_until == other.locked_until
+            && self.display_name.as_deref() == other.display_name
+            && self.locale.as_deref() == other.locale
+            && self.timezone.as_deref() == other.timezone
+            && self.suspended == other.suspended
+    }
+}
+
+/// ConvertRowToUserError represents an error that may be encountered whilst converting a row to
+/// an owned user instance.
+#[derive(Debug)]
+pub enum ConvertRowToUserError {
+    CDRSError(CDRSError),
+    DecodingError(Bs58DecodingError),
+
+    /// The named column is absent from the row, holds no value, or doesn't hold a value of the
+    /// expected type
+    MissingColumn(&'static str),
+
+    /// The named text column holds bytes that aren't valid UTF-8, as may be the case if a value
+    /// supplied by an identity provider was stored verbatim
+    InvalidUtf8 {
+        column: &'static str,
+    },
+
+    /// The named column holds a password hash or salt that doesn't decode to the expected number
+    /// of bytes, as may be the case if the column was corrupted
+    InvalidHashLength {
+        column: &'static str,
+        expected: usize,
+        got: usize,
+    },
+
+    /// The row of the user with the given ID couldn't be deserialized
+    InUser(Uuid, Box<ConvertRowToUserError>),
+}
+
+impl ConvertRowToUserError {
+    /// Gets the name of the column that couldn't be deserialized, if the error is specific to a
+    /// column.
+    pub fn column(&self) -> Option<&'static str> {
+        match self {
+            Self::MissingColumn(column)
+            | Self::InvalidUtf8 { column }
+            | Self::InvalidHashLength { column, .. } => Some(column),
+            Self::InUser(_, e) => e.column(),
+            _ => None,
+        }
+    }
+
+    /// Gets the ID of the user whose row couldn't be deserialized, if the ID itself could be.
+    pub fn id(&self) -> Option<&Uuid> {
+        match self {
+            Self::InUser(id, _) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConvertRowToUserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InUser(id, e) => write!(f, "{} (in the row of user {})", e, id),
+            Self::MissingColumn(column) => write!(
+                f,
+                "encountered an error whilst deserializing a row: missing column {}",
+                column
+            ),
+            Self::InvalidUtf8 { column } => write!(
+                f,
+                "encountered an error whilst deserializing a row: column {} holds invalid UTF-8",
+                column
+            ),
+            Self::InvalidHashLength {
+                column,
+                expected,
+                got,
+            } => write!(
+                f,
+                "encountered an error whilst deserializing a row: column {} holds {} bytes, rather than {}",
+                column, got, expected
+            ),
+            _ => write!(
+                f,
+                "encountered an error whilst deserializing a row: {:?}",
+                self.source()
+            ),
+        }
+    }
+}
+
+impl From<CDRSError> for ConvertRowToUserError {
+    fn from(e: CDRSError) -> Self {
+        Self::CDRSError(e)
+    }
+}
+
+impl From<Bs58DecodingError> for ConvertRowToUserError {
+    fn from(e: Bs58DecodingError) -> Self {
+        Self::DecodingError(e)
+    }
+}
+
+impl Error for ConvertRowToUserError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::CDRSError(ref e) => Some(e),
+            Self::DecodingError(ref e) => Some(e),
+            Self::MissingColumn(_) | Self::InvalidUtf8 { .. } | Self::InvalidHashLength { .. } => {
+                None
+            }
+            Self::InUser(_, e) => e.source(),
+        }
+    }
+}
+
+/// Reads the value of a column that may hold no value. Text that isn't valid UTF-8 is reported
+/// as such, naming the column, rather than as a missing column.
+///
+/// # Arguments
+///
+/// * `row` - The row that the column should be read from
+/// * `column` - The column that should be read
+fn optional_column<T>(row: &Row, column: UserColumn) -> Result<Option<T>, ConvertRowToUserError>
+where
+    Row: IntoRustByName<T>,
+{
+    row.get_by_name(column.as_str()).map_err(|e| match e {
+        CDRSError::FromUtf8(_) => ConvertRowToUserError::InvalidUtf8 {
+            column: column.as_str(),
+        },
+        _ => ConvertRowToUserError::MissingColumn(column.as_str()),
+    })
+}
+
+/// Reads the value of a column that was added to the table after it was first deployed, if it
+/// holds a value. Unlike optional_column, a column absent from the row altogether is read as
+/// holding no value, such that rows remain readable while the DDL adding the column has yet to be
+/// applied (e.g., during a rolling deploy). Columns holding values of an unexpected type are still
+/// reported as missing.
+///
+/// # Arguments
+///
+/// * `row` - The row that the column should be read from
+/// * `column` - The column that should be read
+fn tolerant_column<T>(row: &Row, column: UserColumn) -> Result<Option<T>, ConvertRowToUserError>
+where
+    Row: IntoRustByName<T>,
+{
+    match row.get_by_name(column.as_str()) {
+        Err(ref e) if is_column_absent(e, column.as_str()) => Ok(None),
+        _ => optional_column(row, column),
+    }
+}
+
+/// Determines whether or not an error encountered while reading a column indicates that the
+/// column is absent from the row's column specs altogether, rather than holding a value of an
+/// unexpected type. CDRS keeps a row's column specs private, and reports both as general errors,
+/// so the error is compared against the one that CDRS itself builds for a column missing from
+/// the specs, rather than against any particular wording.
+///
+/// # Arguments
+///
+/// * `e` - The error encountered while reading the column
+/// * `column` - The name of the column
+fn is_column_absent(e: &CDRSError, column: &str) -> bool {
+    match (e, column_is_empty_err(column)) {
+        (CDRSError::General(found), CDRSError::General(absent)) => *found == absent,
+        _ => false,
+    }
+}
+
+/// Reads the value of a column that must hold a value.
+///
+/// # Arguments
+///
+/// * `row` - The row that the column should be read from
+/// * `column` - The column that should be read
+fn required_column<T>(row: &Row, column: UserColumn) -> Result<T, ConvertRowToUserError>
+where
+    Row: IntoRustByName<T>,
+{
+    optional_column(row, column)?.ok_or(ConvertRowToUserError::MissingColumn(column.as_str()))
+}
+
+/// Reads the value of a column holding raw bytes, if it holds a value. Columns stored as BLOBs
+/// are read directly; while the legacy-base58-hashes feature is enabled, columns stored as base58
+/// TEXT are decoded as well, such that rows written before a column was migrated remain readable.
+///
+/// # Arguments
+///
+/// * `row` - The row that the column should be read from
+/// * `column` - The column that should be read
+fn bytes_column(row: &Row, column: UserColumn) -> Result<Option<Vec<u8>>, ConvertRowToUserError> {
+    if let Ok(bytes) = optional_column::<Blob>(row, column) {
+        return Ok(bytes.map(Blob::into_vec));
+    }
+
+    if cfg!(feature = "legacy-base58-hashes") {
+        optional_column::<String>(row, column)?
+            .map(|encoded| bs58::decode(encoded).into_vec())
+            .transpose()
+            .map_err(|e| e.into())
+    } else {
+        Err(ConvertRowToUserError::MissingColumn(column.as_str()))
+    }
+}
+
+/// Reads the value of a column holding a password hash, if it holds a value, as by bytes_column.
+/// A stored hash that isn't HASH_LENGTH bytes long is reported as such.
+///
+/// # Arguments
+///
+/// * `row` - The row that the column should be read from
+/// * `column` - The column that should be read
+fn hash_column(
+    row: &Row,
+    column: UserColumn,
+) -> Result<Option<PasswordHash>, ConvertRowToUserError> {
+    match bytes_column(row, column)? {
+        Some(bytes) => PasswordHash::try_from(bytes.as_slice())
+            .map(Some)
+            .map_err(|_| ConvertRowToUserError::InvalidHashLength {
+                column: column.as_str(),
+                expected: HASH_LENGTH,
+                got: bytes.len(),
+            }),
+        None => Ok(None),
+    }
+}
+
+/// Reads the value of a column holding a password salt, if it holds a value, as by bytes_column.
+/// A stored salt that isn't SALT_LENGTH bytes long is reported as such, rather than being
+/// returned only to panic once converted to an array.
+///
+/// # Arguments
+///
+/// * `row` - The row that the column should be read from
+/// * `column` - The column that should be read
+fn salt_column(row: &Row, column: UserColumn) -> Result<Option<Vec<u8>>, ConvertRowToUserError> {
+    match bytes_column(row, column)? {
+        Some(bytes) if bytes.len() != SALT_LENGTH => {
+            Err(ConvertRowToUserError::InvalidHashLength {
+                column: column.as_str(),
+                expected: SALT_LENGTH,
+                got: bytes.len(),
+            })
+        }
+        bytes => Ok(bytes),
+    }
+}
+
+impl From<ConvertRowToUserError> for IdentityError {
+    fn from(e: ConvertRowToUserError) -> Self {
+        IdentityError::QueryError(QueryError::DeserializationError(e))
+    }
+}
+
+impl Deserializable<OwnedUser, Row> for OwnedUser {
+    type Error = ConvertRowToUserError;
+
+    fn try_from(value: Row) -> Result<OwnedUser, Self::Error> {
+        // The ID is decoded first, such that a failure to decode any other column names the user
+        let id = required_column(&value, UserColumn::Id)?;
+
+        user_from_row(id, &value).map_err(|e| ConvertRowToUserError::InUser(id, Box::new(e)))
+    }
+}
+
+/// Converts the columns of a row other than the user's ID into a user. Columns unknown to the
+/// user are ignored. Columns added after the table was first deployed are read via
+/// tolerant_column, such that a row lacking them altogether is still converted.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the user, as already decoded from the row
+/// * `value` - The row that should be converted
+fn user_from_row(id: Uuid, value: &Row) -> Result<OwnedUser, ConvertRowToUserError> {
+    Ok(OwnedUser {
+        id,
+        username: required_column(value, UserColumn::Username)?,
+        email: required_column(value, UserColumn::Email)?,
+        // Users that only authenticate via an identity provider have no password
+        password_hash: hash_column(value, UserColumn::PasswordHash)?,
+        // Users registered before passwords were salted hold an unsalted hash
+        password_salt: salt_column(value, UserColumn::PasswordSalt)?,
+        registered_at: required_column::<Timespec>(value, UserColumn::RegisteredAt)
+            .map(|timespec| <Timespec as Into<RegistrationTimestamp>>::into(timespec))?,
+        // Users registered before email verification was tracked are unverified
+        email_verified: tolerant_column(value, UserColumn::EmailVerified)?.unwrap_or(false),
+        rotate_hash_on_next_login: tolerant_column(value, UserColumn::RotateHashOnNextLogin)?
+            .unwrap_or(false),
+        // Users that have never failed to authenticate hold no count
+        failed_login_attempts: tolerant_column::<i32>(value, UserColumn::FailedLoginAttempts)?
+            .map(|attempts| attempts.max(0) as u32)
+            .unwrap_or(0),
+        locked_until: tolerant_column::<Timespec>(value, UserColumn::LockedUntil)?
+            .map(|timespec| timespec.into()),
+        // Users that haven't chosen a display name are presented by their username
+        display_name: tolerant_column(value, UserColumn::DisplayName)?,
+        locale: tolerant_column(value, UserColumn::Locale)?,
+        timezone: tolerant_column(value, UserColumn::Timezone)?,
+        // Users registered before suspensions were tracked have never been suspended
+        suspended: tolerant_column(value, UserColumn::Suspended)?.unwrap_or(false),
+    })
+}
+
+/// The query used to page through every user.
+const LIST_USERS_QUERY: &str = "SELECT * FROM identity.users;";
+
+/// The query used to page through every username reservation.
+const LIST_USERNAMES_QUERY: &str = "SELECT username, user_id FROM identity.usernames;";
+
+/// The query used to page through every email address reservation.
+const LIST_EMAILS_QUERY: &str = "SELECT email, user_id FROM identity.emails;";
+
+impl Scylla {
+    /// Streams every user, fetching users from the database one page at a time as the stream is
+    /// consumed. The size of each page may be configured via Scylla::with_page_size. Users are
+    /// stored by their ID alone, so they're streamed in no particular order; requesting any order
+    /// yields a QueryError::UnsupportedOrdering, and nothing else.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order in which users should be streamed, if any
+    pub fn stream_users(
+        &self,
+        order: Option<UserOrder>,
+    ) -> impl Stream<Item = IdentityResult<OwnedUser>> + '_ {
+        let order_by = order
+            .map_or(Ok(String::new()), |order| {
+                order.clause(&<User as InTable<Scylla, DbSession>>::table_schema())
+            })
+            .map_err(IdentityError::from);
+
+        stream::once(future::ready(order_by))
+            .map_ok(move |order_by| {
+                self.stream_rows(format!(
+                    "{}{};",
+                    LIST_USERS_QUERY.trim_end_matches(';'),
+                    order_by
+                ))
+            })
+            .try_flatten()
+            .and_then(|row| async move {
+                <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(row).map_err(|e| e.into())
+            })
+    }
+
+    /// Loads every username or email address reservation, keyed by the reserved value.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query listing every reservation
+    /// * `column` - The name of the column holding the reserved value
+    async fn load_reservations(
+        &self,
+        query: &'static str,
+        column: &str,
+    ) -> IdentityResult<HashMap<String, Uuid>> {
+        self.stream_rows(query)
+            .and_then(|row| async move {
+                let reserved: String = row.get_r_by_name(column)?;
+                let user_id: Uuid = row.get_r_by_name("user_id")?;
+
+                Ok((reserved, user_id))
+            })
+            .try_collect()
+            .await
+    }
+
+    /// Scans the users table and the username and email address reservations, reporting every
+    /// reservation held by a user that doesn't exist, and every user whose username or email
+    /// address isn't reserved for them. Users inserted via insert_record rather than
+    /// insert_unique hold no reservations, and are reported as such. Since every table is read in
+    /// full, this should only be used for operational repair.
+    pub async fn audit_lookup_consistency(&self) -> IdentityResult<Vec<Inconsistency>> {
+        let users = self.list_users(None).await?;
+        let mut usernames = self
+            .load_reservations(LIST_USERNAMES_QUERY, "username")
+            .await?;
+        let mut emails = self.load_reservations(LIST_EMAILS_QUERY, "email").await?;
+        let mut inconsistencies = Vec::new();
+
+        // Every reservation held by an existing user is accounted for, so any that remain are
+        // orphaned
+        for u in &users {
+            if usernames.get(&u.username) == Some(&u.id) {
+                usernames.remove(&u.username);
+            } else {
+                inconsistencies.push(Inconsistency::MissingUsernameReservation {
+                    username: u.username.clone(),
+                    user_id: u.id,
+                });
+            }
+
+            if emails.get(&u.email) == Some(&u.id) {
+                emails.remove(&u.email);
+            } else {
+                inconsistencies.push(Inconsistency::MissingEmailReservation {
+                    email: u.email.clone(),
+                    user_id: u.id,
+                });
+            }
+        }
+
+        let user_ids = users.iter().map(|u| u.id).collect::<HashSet<Uuid>>();
+        inconsistencies.extend(
+            usernames
+                .into_iter()
+                .filter(|(_, user_id)| !user_ids.contains(user_id))
+                .map(|(username, user_id)| Inconsistency::OrphanedUsername { username, user_id }),
+        );
+        inconsistencies.extend(
+            emails
+                .into_iter()
+                .filter(|(_, user_id)| !user_ids.contains(user_id))
+                .map(|(email, user_id)| Inconsistency::OrphanedEmail { email, user_id }),
+        );
+
+        Ok(inconsistencies)
+    }
+
+    /// Loads the users that registered within the provided duration of the current UTC time,
+    /// newest first. See Scylla::recent_registrations_at.
+    ///
+    /// # Arguments
+    ///
+    /// * `within` - How long ago the earliest matching user may have registered
+    /// * `limit` - The maximum number of users that should be loaded
+    pub async fn recent_registrations(
+        &self,
+        within: Duration,
+        limit: usize,
+    ) -> IdentityResult<Vec<OwnedUser>> {
+        self.recent_registrations_at(SystemClock.now(), within, limit)
+            .await
+    }
+
+    /// Loads the users that registered within the provided duration of the provided time, newest
+    /// first. Since registered_at isn't indexed, this requires a scan of the entire users table,
+    /// which is paged through; every matching user is held until the scan completes, so if more
+    /// users than the connector's maximum result size match, a QueryError::ResultTooLarge is
+    /// returned instead. Configure Scylla::with_max_rows before exposing this to clients.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time that the window of registrations ends at
+    /// * `within` - How long before now the earliest matching user may have registered
+    /// * `limit` - The maximum number of users that should be loaded
+    pub async fn recent_registrations_at(
+        &self,
+        now: DateTime<Utc>,
+        within: Duration,
+        limit: usize,
+    ) -> IdentityResult<Vec<OwnedUser>> {
+        let cutoff = chrono::Duration::from_std(within)
+            .ok()
+            .and_then(|within| now.checked_sub_signed(within))
+            .ok_or(ValidationError::InvalidRange)?;
+
+        // Results can't be ordered by a column outside of the partition key, so they're sorted
+        // once loaded
+        let query = UserQuery::RegisteredBetween(cutoff, now)
+            .to_query(self.session())
+            .await?;
+        let mut users: Vec<OwnedUser> = self
+            .stream_rows(query)
+            .and_then(|row| async move {
+                <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(row).map_err(|e| e.into())
+            })
+            .try_collect()
+            .await?;
+        users.sort_by(|a, b| b.registered_at.cmp(&a.registered_at));
+        users.truncate(limit);
+
+        Ok(users)
+    }
+
+    /// Loads every user, fetching users from the database one page at a time. Prefer
+    /// stream_users for large tables. Users are loaded in no particular order, so requesting any
+    /// order fails with a QueryError::UnsupportedOrdering.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order in which users should be loaded, if any
+    pub async fn list_users(&self, order: Option<UserOrder>) -> IdentityResult<Vec<OwnedUser>> {
+        self.stream_users(order).try_collect().await
+    }
+
+    /// Gets the ID of the user holding a username, if any. Only users registered via
+    /// insert_unique, or that have changed their username, hold a reservation of their username.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The normalized username whose holder should be found
+    pub async fn username_owner(&self, username: &str) -> IdentityResult<Option<Uuid>> {
+        self.run(self.session().query_with_values(
+            "SELECT user_id FROM identity.usernames WHERE username = ?;",
+            query_values!(username),
+        ))
+        .await
+        .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+        .map(|resp| resp.into_rows().unwrap_or_default())
+        .and_then(|rows| {
+            rows.into_iter()
+                .next()
+                .map(|row| row.get_r_by_name("user_id").map_err(|e| e.into()))
+                .transpose()
+        })
+    }
+
+    /// Reserves a username for a user, unless it is already held by some other user, in which case
+    /// the ID of the user holding it is returned. A reserved username may be found by
+    /// search_usernames.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The normalized username that should be reserved
+    /// * `user_id` - The ID of the user that the username should be reserved for
+    pub async fn reserve_username(
+        &self,
+        username: &str,
+        user_id: &Uuid,
+    ) -> IdentityResult<LwtOutcome<Uuid>> {
+        let outcome = self
+            .execute_lwt_outcome(
+                "INSERT INTO identity.usernames (username, user_id) VALUES (?, ?) IF NOT EXISTS;",
+                query_values!(username, *user_id),
+            )
+            .await
+            .and_then(reservation_holder)?;
+
+        // Don't leave the username reserved if it can't be found by its prefixes
+        if outcome.is_applied() {
+            if let Err(e) = self.index_username(username, user_id).await {
+                self.release_username(username, user_id).await?;
+
+                return Err(e);
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    /// Releases a user's reservation of a username, if they hold it.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The normalized username that should be released
+    /// * `user_id` - The ID of the user that holds the reservation
+    pub async fn release_username(&self, username: &str, user_id: &Uuid) -> IdentityResult<bool> {
+        let released = self
+            .execute_lwt(
+                "DELETE FROM identity.usernames WHERE username = ? IF user_id = ?;",
+                query_values!(username, *user_id),
+            )
+            .await?;
+
+        if released {
+            self.unindex_username(username).await?;
+        }
+
+        Ok(released)
+    }
+
+    /// Indexes a reserved username under each of its prefixes, such that it may be found by
+    /// search_usernames.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The normalized username that should be indexed
+    /// * `user_id` - The ID of the user holding the username
+    async fn index_username(&self, username: &str, user_id: &Uuid) -> IdentityResult<()> {
+        let query = username_prefixes(username)
+            .into_iter()
+            .fold(BatchQueryBuilder::new(), |query, prefix| {
+                query.add_query(
+                    "INSERT INTO identity.username_prefixes (prefix, username, user_id) VALUES (?, ?, ?);",
+                    query_values!(prefix, username, *user_id),
+                )
+            })
+            .finalize()?;
+
+        self.run(self.session().batch_with_params(query))
+            .await
+            .map(|_| ())
+    }
+
+    /// Removes a released username from each of its prefixes.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The normalized username that should no longer be indexed
+    async fn unindex_username(&self, username: &str) -> IdentityResult<()> {
+        let query = username_prefixes(username)
+            .into_iter()
+            .fold(BatchQueryBuilder::new(), |query, prefix| {
+                query.add_query(
+                    "DELETE FROM identity.username_prefixes WHERE prefix = ? AND username = ?;",
+                    query_values!(prefix, username),
+                )
+            })
+            .finalize()?;
+
+        self.run(self.session().batch_with_params(query))
+            .await
+            .map(|_| ())
+    }
+
+    /// Searches for users whose username begins with a prefix, as is done to autocomplete
+    /// mentions. Matching is case-insensitive, as the prefix is normalized like a username.
+    /// Returns up to `limit` users, ordered by their username in either direction. Only users
+    /// holding a reservation of their username may be found; an empty prefix matches no users.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The leading characters of the usernames that should be found
+    /// * `limit` - The maximum number of users that should be returned
+    /// * `order` - The order in which users should be returned
+    pub async fn search_usernames(
+        &self,
+        prefix: &str,
+        limit: usize,
+        order: UserOrder,
+    ) -> IdentityResult<Vec<OwnedUser>> {
+        let order_by = order.clause(&username_prefixes_schema())?;
+        let prefix = normalize_username(prefix);
+
+        let bucket = match username_prefixes(&prefix).pop() {
+            Some(bucket) if limit > 0 => bucket,
+            _ => return Ok(Vec::new()),
+        };
+
+        // Usernames beginning with the prefix sort between it and the prefix followed by the
+        // greatest character
+        let rows = self
+            .run(self.session().query_with_values(
+                format!(
+                    "SELECT user_id FROM identity.username_prefixes WHERE prefix = ? AND \
+                     username >= ? AND username < ?{} LIMIT ?;",
+                    order_by
+                ),
+                query_values!(
+                    bucket,
+                    prefix.as_str(),
+                    format!("{}{}", prefix, char::MAX),
+                    limit.min(i32::MAX as usize) as i32
+                ),
+            ))
+            .await
+            .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+            .map(|resp| resp.into_rows().unwrap_or_default())?;
+
+        let ids = rows
+            .iter()
+            .map(|row| row.get_r_by_name("user_id"))
+            .collect::<Result<Vec<Uuid>, CDRSError>>()?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Every matching user is loaded at once, and returned in the order of their usernames.
+        // A username is indexed once it's reserved, which precedes the insertion of its user, so
+        // a user may be missing if they have yet to be inserted, or if their insertion failed
+        // before the reservation was released. Neither user is registered, so they're skipped.
+        let mut users: Vec<OwnedUser> = self.load_records(&UserQuery::Ids(&ids)).await?;
+        users.sort_by_key(|u| ids.iter().position(|id| id == u.id()));
+
+        Ok(users)
+    }
+
+    /// Indexes every existing username reservation for prefix search, as is needed once for
+    /// usernames reserved before they were indexed. Returns the number of usernames indexed.
+    /// Indexing a username more than once is harmless.
+    pub async fn index_existing_usernames(&self) -> IdentityResult<u64> {
+        let reservations = self
+            .load_reservations(LIST_USERNAMES_QUERY, "username")
+            .await?;
+
+        for (username, user_id) in &reservations {
+            self.index_username(username, user_id).await?;
+        }
+
+        Ok(reservations.len() as u64)
+    }
+
+    /// Indexes every existing username for prefix search, as a step of a migration. Keyspaces
+    /// without a usernames table have yet to reserve any usernames, and are left alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - The connector whose usernames should be indexed
+    pub(crate) fn backfill_username_prefixes(db: &Scylla) -> BoxFuture<'_, IdentityResult<()>> {
+        Box::pin(async move {
+            if table_columns(db.session(), "identity", "usernames")
+                .await?
+                .is_empty()
+            {
+                return Ok(());
+            }
+
+            // Migrations are applied before the keyspace's tables are created
+            db.run(
+                db.session()
+                    .query(username_prefixes_schema().create_statement()),
+            )
+            .await?;
+
+            db.index_existing_usernames().await.map(|_| ())
+        })
+    }
+
+    /// Changes the username of a user, claiming the new username before releasing the old one
+    /// such that no two users may ever hold the same username. The user's previous username is
+    /// recorded in their username history.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose username should be changed
+    /// * `new_username` - The username that the user should be known by
+    pub async fn change_username(&self, id: &Uuid, new_username: &str) -> IdentityResult<()> {
+        self.operation(self.rename_user(id, new_username)).await
+    }
+
+    /// Makes each of the steps of Scylla::change_username.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose username should be changed
+    /// * `new_username` - The username that the user should be known by
+    async fn rename_user(&self, id: &Uuid, new_username: &str) -> IdentityResult<()> {
+        let username = normalize_username(new_username);
+        validate_username(&username)?;
+
+        let current: OwnedUser = self.load_record(&UserQuery::Id(id)).await?;
+
+        if current.username == username {
+            return Ok(());
+        }
+
+        if !self.reserve_username(&username, id).await?.is_applied() {
+            return Err(QueryError::UsernameTaken.into());
+        }
+
+        // Don't leave the new username reserved if the user can't actually be renamed
+        if let Err(e) = self
+            .execute(
+                &format!(
+                    "UPDATE identity.users SET {} = ? WHERE {} = ?;",
+                    UserColumn::Username.as_str(),
+                    UserColumn::Id.as_str()
+                ),
+                query_values!(username.as_str(), *id),
+            )
+            .await
+        {
+            self.release_username(&username, id).await?;
+
+            return Err(e);
+        }
+
+        self.release_username(&current.username, id).await?;
+        self.record_username_change(id, &current.username).await?;
+        self.event_sink().on_user_updated(id);
+
+        Ok(())
+    }
+
+    /// Inserts a new user, reserving their username and email address such that no other user may
+    /// hold either. Fails with a QueryError::UsernameTaken or QueryError::EmailTaken if either is
+    /// already held, in which case nothing is inserted.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user that should be inserted, with a normalized username and email address
+    pub async fn insert_unique(&self, user: &User<'_>) -> IdentityResult<()> {
+        match self.try_insert_unique(user).await? {
+            LwtOutcome::Applied => Ok(()),
+            LwtOutcome::NotApplied(UniqueConflict::Username { .. }) => {
+                Err(QueryError::UsernameTaken.into())
+            }
+            LwtOutcome::NotApplied(UniqueConflict::Email { .. }) => {
+                Err(QueryError::EmailTaken.into())
+            }
+        }
+    }
+
+    /// Inserts a new user, reserving their username and email address such that no other user may
+    /// hold either. If either is already held, nothing is inserted, and the conflicting
+    /// reservation is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user that should be inserted, with a normalized username and email address
+    pub async fn try_insert_unique(
+        &self,
+        user: &User<'_>,
+    ) -> IdentityResult<LwtOutcome<UniqueConflict>> {
+        self.operation(self.reserve_and_insert(user)).await
+    }
+
+    /// Makes each of the steps of Scylla::try_insert_unique.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user that should be inserted, with a normalized username and email address
+    async fn reserve_and_insert(
+        &self,
+        user: &User<'_>,
+    ) -> IdentityResult<LwtOutcome<UniqueConflict>> {
+        if let LwtOutcome::NotApplied(user_id) =
+            self.reserve_username(user.username, &user.id).await?
+        {
+            return Ok(LwtOutcome::NotApplied(UniqueConflict::Username {
+                username: user.username.to_owned(),
+                user_id,
+            }));
+        }
+
+        if let LwtOutcome::NotApplied(user_id) = self.reserve_email(user.email, &user.id).await? {
+            self.release_username(user.username, &user.id).await?;
+
+            return Ok(LwtOutcome::NotApplied(UniqueConflict::Email {
+                email: user.email.to_owned(),
+                user_id,
+            }));
+        }
+
+        // Don't leave the username or email reserved if the user can't actually be inserted
+        if let Err(e) = self
+            .insert_record_at(user, self.read_your_writes_consistency())
+            .await
+        {
+            self.release_username(user.username, &user.id).await?;
+            self.release_email(user.email, &user.id).await?;
+
+            return Err(e);
+        }
+
+        self.event_sink().on_user_created(user);
+
+        Ok(LwtOutcome::Applied)
+    }
+
+    /// Registers a new user via insert_unique, returning the user as read back from the
+    /// database. If the connector was configured with read-your-writes consistency, the user is
+    /// guaranteed to be found immediately.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user that should be registered
+    pub async fn register(&self, user: &User<'_>) -> IdentityResult<OwnedUser> {
+        self.insert_unique(user).await?;
+
+        self.load_record_at(
+            &UserQuery::Id(&user.id),
+            self.read_your_writes_consistency(),
+        )
+        .await
+    }
+
+    /// Registers a new user from a client's request, such that retrying the request with the same
+    /// idempotency key yields the user registered by the first attempt, rather than registering
+    /// another user or failing because the username is taken. Concurrent requests with the same
+    /// key are resolved to a single user; should the user registered by a concurrent request not
+    /// yet be visible, a QueryError::Contended is returned, and the request may be retried. If
+    /// registration fails, the key is released, such that a corrected request may reuse it. Keys
+    /// are only held for REGISTRATION_KEY_TTL, such that a key whose registration was abandoned
+    /// without being released isn't contended forever.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The idempotency key supplied by the client alongside the request
+    /// * `request` - The request to register a new user
+    pub async fn register_idempotent(
+        &self,
+        key: &str,
+        request: RegistrationRequest,
+    ) -> IdentityResult<OwnedUser> {
+        let owned = request.into_user()?;
+
+        if let LwtOutcome::NotApplied(user_id) =
+            self.reserve_registration_key(key, &owned.id).await?
+        {
+            return match self
+                .load_record_at(
+                    &UserQuery::Id(&user_id),
+                    self.read_your_writes_consistency(),
+                )
+                .await
+            {
+                Err(IdentityError::QueryError(QueryError::NoResults)) => {
+                    Err(QueryError::Contended.into())
+                }
+                res => res,
+            };
+        }
+
+        match self.register(&User::from(&owned)).await {
+            Ok(user) => Ok(user),
+            Err(e) => {
+                self.release_registration_key(key, &owned.id).await?;
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Maps an idempotency key to the user being registered under it, unless it is already mapped
+    /// to some other user, in which case the ID of that user is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The idempotency key supplied by the client
+    /// * `user_id` - The ID of the user being registered under the key
+    async fn reserve_registration_key(
+        &self,
+        key: &str,
+        user_id: &Uuid,
+    ) -> IdentityResult<LwtOutcome<Uuid>> {
+        self.execute_lwt_outcome(
+            &format!(
+                "INSERT INTO identity.registration_idempotency (key, user_id) VALUES (?, ?) IF NOT EXISTS USING TTL {};",
+                REGISTRATION_KEY_TTL.as_secs()
+            ),
+            query_values!(key, *user_id),
+        )
+        .await
+        .and_then(reservation_holder)
+    }
+
+    /// Unmaps an idempotency key from the user that was to be registered under it.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The idempotency key supplied by the client
+    /// * `user_id` - The ID of the user that was to be registered under the key
+    async fn release_registration_key(&self, key: &str, user_id: &Uuid) -> IdentityResult<bool> {
+        self.execute_lwt(
+            "DELETE FROM identity.registration_idempotency WHERE key = ? IF user_id = ?;",
+            query_values!(key, *user_id),
+        )
+        .await
+    }
+
+    /// Inserts a new user that is deleted automatically once the given amount of time has
+    /// elapsed (e.g., a guest account). A TTL of zero inserts the user permanently. TTLs are
+    /// rounded down to the second.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user that should be inserted
+    /// * `ttl` - The amount of time for which the user should exist
+    pub async fn insert_with_ttl(&self, user: &User<'_>, ttl: Duration) -> IdentityResult<()> {
+        self.execute(
+            &format!(
+                "{} USING TTL {};",
+                <User as Insertable<Scylla, DbSession>>::INSERTION_QUERY
+                    .trim_end()
+                    .trim_end_matches(';'),
+                ttl.as_secs()
+            ),
+            Serializable::try_into(user)?,
+        )
+        .await?;
+        self.event_sink().on_user_created(user);
+
+        Ok(())
+    }
+
+    /// Inserts a new user, returning an allocated copy of the user as it was stored (i.e., with
+    /// any generated ID and registration timestamp), without reloading it.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user that should be inserted
+    pub async fn insert_and_return(&self, user: User<'_>) -> IdentityResult<OwnedUser> {
+        self.insert_record(&user).await?;
+
+        Ok(user.into())
+    }
+
+    /// Gets the ID of the user holding an email address, if any. Only users registered via
+    /// insert_unique, or that have changed their email address, hold a reservation of their
+    /// address.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The normalized email address whose holder should be found
+    pub async fn email_owner(&self, email: &str) -> IdentityResult<Option<Uuid>> {
+        self.run(self.session().query_with_values(
+            "SELECT user_id FROM identity.emails WHERE email = ?;",
+            query_values!(email),
+        ))
+        .await
+        .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+        .map(|resp| resp.into_rows().unwrap_or_default())
+        .and_then(|rows| {
+            rows.into_iter()
+                .next()
+                .map(|row| row.get_r_by_name("user_id").map_err(|e| e.into()))
+                .transpose()
+        })
+    }
+
+    /// Reserves an email address for a user, unless it is already held by some other user, in
+    /// which case the ID of the user holding it is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The normalized email address that should be reserved
+    /// * `user_id` - The ID of the user that the email address should be reserved for
+    pub async fn reserve_email(
+        &self,
+        email: &str,
+        user_id: &Uuid,
+    ) -> IdentityResult<LwtOutcome<Uuid>> {
+        self.execute_lwt_outcome(
+            "INSERT INTO identity.emails (email, user_id) VALUES (?, ?) IF NOT EXISTS;",
+            query_values!(email, *user_id),
+        )
+        .await
+        .and_then(reservation_holder)
+    }
+
+    /// Releases a user's reservation of an email address, if they hold it.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The normalized email address that should be released
+    /// * `user_id` - The ID of the user that holds the reservation
+    pub async fn release_email(&self, email: &str, user_id: &Uuid) -> IdentityResult<bool> {
+        self.execute_lwt(
+            "DELETE FROM identity.emails WHERE email = ? IF user_id = ?;",
+            query_values!(email, *user_id),
+        )
+        .await
+    }
+
+    /// Sets or clears the display name of a user. Since display names needn't be unique, no
+    /// reservation is made.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose display name should be changed
+    /// * `display_name` - The name that the user should be presented by, or None if they should be
+    /// presented by their username
+    pub async fn set_display_name(
+        &self,
+        id: &Uuid,
+        display_name: Option<&str>,
+    ) -> IdentityResult<()> {
+        self.apply_update(id, &UserUpdate::default().display_name(display_name))
+            .await
+    }
+
+    /// Sets or clears a user's preferred locale.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose locale should be changed
+    /// * `locale` - The BCP-47 language tag of the user's preferred locale, or None if they have
+    /// no preference
+    pub async fn set_locale(&self, id: &Uuid, locale: Option<&str>) -> IdentityResult<()> {
+        self.apply_update(id, &UserUpdate::default().locale(locale))
+            .await
+    }
+
+    /// Sets or clears a user's preferred timezone.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose timezone should be changed
+    /// * `timezone` - The IANA name of the user's preferred timezone, or None if they have no
+    /// preference
+    pub async fn set_timezone(&self, id: &Uuid, timezone: Option<&str>) -> IdentityResult<()> {
+        self.apply_update(id, &UserUpdate::default().timezone(timezone))
+            .await
+    }
+
+    /// Suspends or reinstates a user. A suspended user may not authenticate, though their
+    /// existing sessions are left for the caller to revoke.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user that should be suspended or reinstated
+    /// * `suspended` - Whether or not the user should be suspended
+    pub async fn set_suspended(&self, id: &Uuid, suspended: bool) -> IdentityResult<()> {
+        self.apply_update(id, &UserUpdate::default().suspended(suspended))
+            .await
+    }
+
+    /// Applies a validated update to an existing user, changing only the columns set by the
+    /// update. Empty updates are rejected with a ValidationError::EmptyUpdate.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user that should be updated
+    /// * `update` - The changes that should be made to the user
+    pub async fn apply_update(&self, id: &Uuid, update: &UserUpdate<'_>) -> IdentityResult<()> {
+        let (query, mut values) = update.to_cql()?;
+
+        // Don't create a partial row for a user that doesn't exist
+        let _: OwnedUser = self.load_record(&UserQuery::Id(id)).await?;
+
+        values.push((*id).into());
+        self.run_write(update.idempotent(), move || {
+            self.session()
+                .query_with_values(query.as_str(), QueryValues::SimpleValues(values.clone()))
+        })
+        .await?;
+        self.event_sink().on_user_updated(id);
+
+        Ok(())
+    }
+
+    /// Changes the email address of a user, claiming the new address before releasing the old
+    /// one such that no two users may ever share an email address. Since the user has yet to
+    /// prove that they own the new address, their email is marked as unverified.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose email address should be changed
+    /// * `new_email` - The email address that the user should be reachable at
+    pub async fn change_email(&self, id: &Uuid, new_email: &str) -> IdentityResult<()> {
+        self.operation(self.readdress_user(id, new_email)).await
+    }
+
+    /// Makes each of the steps of Scylla::change_email.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose email address should be changed
+    /// * `new_email` - The email address that the user should be reachable at
+    async fn readdress_user(&self, id: &Uuid, new_email: &str) -> IdentityResult<()> {
+        let email = normalize_email(new_email);
+        validate_email(&email)?;
+
+        let current: OwnedUser = self.load_record(&UserQuery::Id(id)).await?;
+
+        if current.email == email {
+            return Ok(());
+        }
+
+        // An address that the user already holds as a secondary address is simply promoted
+        if let Some(existing) = self
+            .load_user_emails(id)
+            .await?
+            .into_iter()
+            .find(|record| record.email() == email)
+        {
+            return self.promote_email(id, existing.email()).await;
+        }
+
+        if !self.reserve_email(&email, id).await?.is_applied() {
+            return Err(QueryError::EmailTaken.into());
+        }
+
+        // Don't leave the new address reserved if the user can't actually be updated
+        if let Err(e) = self
+            .execute(
+                &format!(
+                    "UPDATE identity.users SET {} = ?, {} = false WHERE {} = ?;",
+                    UserColumn::Email.as_str(),
+                    UserColumn::EmailVerified.as_str(),
+                    UserColumn::Id.as_str()
+                ),
+                query_values!(email.as_str(), *id),
+            )
+            .await
+        {
+            self.release_email(&email, id).await?;
+
+            return Err(e);
+        }
+
+        self.release_email(&current.email, id).await?;
+        self.replace_primary_email(id, &current.email, &email)
+            .await?;
+        self.event_sink().on_user_updated(id);
+
+        Ok(())
+    }
+
+    /// Changes the password of a user, hashing it with a freshly generated salt using the
+    /// connector's password hasher. Every session established by the user is revoked, such that
+    /// they must log in again with their new password.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose password should be changed
+    /// * `new_password` - The plaintext password that the user should authenticate with
+    pub async fn change_password(&self, id: &Uuid, new_password: &str) -> IdentityResult<()> {
+        let salt = password::generate_salt();
+        let hash = self.password_hasher().hash_salted(new_password, &salt);
+
+        self.store_password_hash(id, &hash, &salt).await?;
+        self.event_sink().on_user_updated(id);
+
+        self.revoke_all_sessions(id).await.map(|_| ())
+    }
+
+    /// Replaces the stored hash of a user's password, clearing any pending hash rotation.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose password hash should be replaced
+    /// * `hash` - The salted hash of the user's password
+    /// * `salt` - The salt prepended to the user's password before it was hashed
+    async fn store_password_hash(
+        &self,
+        id: &Uuid,
+        hash: &PasswordHash,
+        salt: &[u8; SALT_LENGTH],
+    ) -> IdentityResult<()> {
+        self.execute(
+            &format!(
+                "UPDATE identity.users SET {} = ?, {} = ?, {} = false WHERE {} = ?;",
+                UserColumn::PasswordHash.as_str(),
+                UserColumn::PasswordSalt.as_str(),
+                UserColumn::RotateHashOnNextLogin.as_str(),
+                UserColumn::Id.as_str()
+            ),
+            query_values!(
+                Blob::from(hash.as_bytes().to_vec()),
+                Blob::from(salt.to_vec()),
+                *id
+            ),
+        )
+        .await
+    }
+
+    /// Flags a user's password hash to be replaced by a freshly salted hash the next time that
+    /// the user successfully authenticates with their password (i.e., via
+    /// verify_and_maybe_upgrade). This allows salts suspected of compromise to be rotated
+    /// without knowledge of the user's password.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose password hash should be rotated
+    pub async fn rotate_hash_on_next_login(&self, id: &Uuid) -> IdentityResult<()> {
+        self.execute(
+            &format!(
+                "UPDATE identity.users SET {} = true WHERE {} = ?;",
+                UserColumn::RotateHashOnNextLogin.as_str(),
+                UserColumn::Id.as_str()
+            ),
+            query_values!(*id),
+        )
+        .await
+    }
+
+    /// Records that a user failed to authenticate with their password, returning the number of
+    /// times that they have failed to authenticate since they last succeeded or were last locked
+    /// out. Once the policy's threshold is reached, the user is locked out for the policy's
+    /// window.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user that failed to authenticate
+    /// * `policy` - The policy determining when, and for how long, the user is locked out
+    pub async fn record_failed_login(
+        &self,
+        id: &Uuid,
+        policy: &LockoutPolicy,
+    ) -> IdentityResult<u32> {
+        // The count is compared and set, such that concurrent failures are each counted
+        for _ in 0..MAX_FAILED_LOGIN_UPDATES {
+            let current = self
+                .load_projection(
+                    &UserQuery::Id(id),
+                    &[UserColumn::FailedLoginAttempts, UserColumn::LockedUntil],
+                )
+                .await?;
+            let now = SystemClock.now();
+
+            // Failures are counted afresh once a lock has expired
+            let previous = match current.locked_until() {
+                Some(locked_until) if locked_until <= now => 0,
+                _ => current.failed_login_attempts().unwrap_or(0),
+            };
+            let attempts = previous + 1;
+
+            let locked_until = if attempts >= policy.threshold {
+                chrono::Duration::from_std(policy.window)
+                    .ok()
+                    .and_then(|window| now.checked_add_signed(window))
+                    .and_then(|locked_until| RegistrationTimestamp::try_from(locked_until).ok())
+            } else {
+                None
+            };
+
+            let applied = self
+                .execute_lwt(
+                    &failed_login_update(
+                        &current
+                            .failed_login_attempts()
+                            .map_or("null".to_owned(), |current| current.to_string()),
+                    ),
+                    query_values!(
+                        attempts as i32,
+                        locked_until.map(<RegistrationTimestamp as Into<Timespec>>::into),
+                        *id
+                    ),
+                )
+                .await?;
+
+            if applied {
+                return Ok(attempts);
+            }
+        }
+
+        Err(QueryError::Contended.into())
+    }
+
+    /// Makes the same queries as Scylla::record_failed_login makes for an uncontended failure,
+    /// against the nil ID, which no user holds. This is done when no user holds a presented email
+    /// address or username, such that the time taken to respond to an unknown user doesn't differ
+    /// from that taken to respond to a known user presenting an incorrect password. The
+    /// conditional update never applies, as no count can be -1, so no row is written.
+    async fn record_unknown_failed_login(&self) -> IdentityResult<()> {
+        match self
+            .load_projection(
+                &UserQuery::Id(&Uuid::nil()),
+                &[UserColumn::FailedLoginAttempts, UserColumn::LockedUntil],
+            )
+            .await
+        {
+            Ok(_) | Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+            Err(e) => return Err(e),
+        }
+
+        self.execute_lwt(
+            &failed_login_update("-1"),
+            query_values!(1i32, None::<Timespec>, Uuid::nil()),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Resets the number of times that a user has failed to authenticate, and lifts any lock, as
+    /// should be done once they successfully authenticate.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user whose failed attempts should be reset
+    pub async fn reset_failed_logins(&self, id: &Uuid) -> IdentityResult<()> {
+        self.execute(
+            &format!(
+                "UPDATE identity.users SET {} = 0, {} = null WHERE {} = ?;",
+                UserColumn::FailedLoginAttempts.as_str(),
+                UserColumn::LockedUntil.as_str(),
+                UserColumn::Id.as_str()
+            ),
+            query_values!(*id),
+        )
+        .await
+    }
+
+    /// Authenticates a user by their email address and password, as is done upon login. Fails
+    /// with an AuthError::InvalidCredentials if no user holds the address or if the password is
+    /// incorrect. In each case, the user holding the address is looked up, a password is hashed,
+    /// and the failure is recorded via a conditional update (against the nil ID, which never
+    /// applies, if no user holds the address), such that neither the error nor the time taken to
+    /// respond reveals whether the address is registered. Failed attempts count towards the
+    /// connector's LockoutPolicy.
+    ///
+    /// A user that is locked out fails with an AuthError::AccountLocked, whether or not their
+    /// password is correct. This deliberately reveals that a locked out user's address is
+    /// registered: were the lock only reported to those presenting the correct password, the error
+    /// would confirm each correct guess, and the lock could be circumvented. A password is still
+    /// hashed, such that the time taken to respond doesn't differ. A user presenting their correct
+    /// password fails with an AuthError::AccountSuspended if they're suspended, or an
+    /// AuthError::EmailUnverified if the connector requires a verified email address that they
+    /// lack. Any other error is the fault of the database, rather than of the credentials.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The email address of the user
+    /// * `password` - The plaintext password presented by the user
+    pub async fn authenticate(&self, email: &str, password: &str) -> IdentityResult<OwnedUser> {
+        let owner = self.email_owner(&normalize_email(email)).await?;
+
+        self.authenticate_owner(owner, password).await
+    }
+
+    /// Authenticates a user by their username and password, failing in the same manner as
+    /// Scylla::authenticate.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The username of the user
+    /// * `password` - The plaintext password presented by the user
+    pub async fn authenticate_by_username(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> IdentityResult<OwnedUser> {
+        let owner = self.username_owner(&normalize_username(username)).await?;
+
+        self.authenticate_owner(owner, password).await
+    }
+
+    /// Authenticates the user holding an email address or username by their password.
+    ///
+    /// # Arguments
+    ///
+    /// * `owner` - The ID of the user holding the presented email address or username, if any
+    /// * `password` - The plaintext password presented by the user
+    async fn authenticate_owner(
+        &self,
+        owner: Option<Uuid>,
+        password: &str,
+    ) -> IdentityResult<OwnedUser> {
+        // No user holds the nil ID, so an unknown user is still looked up
+        let user: Option<OwnedUser> = match self
+            .load_record(&UserQuery::Id(&owner.unwrap_or_else(Uuid::nil)))
+            .await
+        {
+            Ok(u) => Some(u),
+            Err(IdentityError::QueryError(QueryError::NoResults)) => None,
+            Err(e) => return Err(e),
+        };
+
+        match user {
+            // The password isn't checked, such that a lock can't be circumvented by guessing it
+            Some(u) if u.has_password() && u.is_locked_out() => {
+                let _ = self.password_hasher().verify_salted(
+                    password,
+                    &DUMMY_PASSWORD_SALT,
+                    &DUMMY_PASSWORD_HASH,
+                );
+
+                return Err(AuthError::AccountLocked.into());
+            }
+            Some(u) if u.has_password() => {
+                if self.verify_and_maybe_upgrade(&u, password).await? {
+                    if u.failed_login_attempts() > 0 || u.locked_until().is_some() {
+                        self.reset_failed_logins(u.id()).await?;
+                    }
+
+                    // Only a user presenting their password learns that their account is barred
+                    if u.is_suspended() {
+                        return Err(AuthError::AccountSuspended.into());
+                    }
+                    if self.verified_email_required() && !u.email_verified() {
+                        return Err(AuthError::EmailUnverified.into());
+                    }
+
+                    return Ok(u);
+                }
+
+                // A contended count mustn't be distinguishable from an incorrect password
+                match self
+                    .record_failed_login(u.id(), self.lockout_policy())
+                    .await
+                {
+                    Ok(_) | Err(IdentityError::QueryError(QueryError::Contended)) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            _ => {
+                // Hash the password and record the failure all the same, as would be done for a
+                // known user presenting an incorrect password
+                let _ = self.password_hasher().verify_salted(
+                    password,
+                    &DUMMY_PASSWORD_SALT,
+                    &DUMMY_PASSWORD_HASH,
+                );
+                self.record_unknown_failed_login().await?;
+            }
+        }
+
+        Err(AuthError::InvalidCredentials.into())
+    }
+
+    /// Determines whether or not the provided password is a user's password, as hashed by the
+    /// connector's password hasher.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user whose password should be checked
+    /// * `password` - The plaintext password that should be verified
+    pub fn verify_password(&self, user: &OwnedUser, password: &str) -> bool {
+        user.verify_password_with(self.password_hasher(), password)
+    }
+
+    /// Determines whether or not the provided password is a user's password, as hashed by the
+    /// connector's password hasher. If the password matches a legacy hash, or a hash flagged for
+    /// rotation, the stored hash is replaced by a freshly salted hash of the password.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user whose password should be checked
+    /// * `password` - The plaintext password that should be verified
+    pub async fn verify_and_maybe_upgrade(
+        &self,
+        user: &OwnedUser,
+        password: &str,
+    ) -> IdentityResult<bool> {
+        match user.verify_and_maybe_upgrade(self.password_hasher(), password) {
+            PasswordVerification::Upgraded { hash, salt } => {
+                self.store_password_hash(user.id(), &hash, &salt).await?;
+
+                Ok(true)
+            }
+            verification => Ok(verification.is_accepted()),
+        }
+    }
+
+    /// Loads only the selected columns of a user. If no columns are selected, the user's existence
+    /// is still checked, but no columns are loaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query that the user should match
+    /// * `columns` - The columns that should be loaded
+    pub async fn load_projection(
+        &self,
+        query: &UserQuery<'_>,
+        columns: &[UserColumn],
+    ) -> IdentityResult<PartialUser> {
+        let selected = if columns.is_empty() {
+            UserColumn::Id.as_str().to_owned()
+        } else {
+            columns
+                .iter()
+                .map(UserColumn::as_str)
+                .collect::<Vec<&str>>()
+                .join(", ")
+        };
+
+        self.run(self.session().query(format!(
+            "SELECT {} FROM identity.users WHERE {}{};",
+            selected,
+            query.condition()?,
+            query.suffix()
+        )))
+        .await
+        .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+        .and_then(|resp| resp.into_rows().ok_or(QueryError::NoResults.into()))
+        .and_then(|rows| rows.into_iter().next().ok_or(QueryError::NoResults.into()))
+        .and_then(|row| PartialUser::from_row(&row, columns).map_err(|e| e.into()))
+    }
+
+    /// Deletes every user matching a filter, returning the number of users deleted. Since users
+    /// may only be deleted by their ID, the users table is scanned for matching users, which are
+    /// then deleted in batches. Any username or email reservations held by deleted users are
+    /// released.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter that deleted users should match
+    pub async fn delete_where(&self, filter: UserFilter) -> IdentityResult<u64> {
+        let matching = self
+            .run(self.session().query(format!(
+                "SELECT {} FROM identity.users;",
+                FILTER_COLUMNS
+                    .iter()
+                    .map(UserColumn::as_str)
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )))
+            .await
+            .and_then(|frame| frame.get_body().map_err(|e| e.into()))
+            .map(|resp| resp.into_rows().unwrap_or_default())
+            .and_then(|rows| self.check_result_size(rows.len()).map(|_| rows))?
+            .iter()
+            .map(|row| PartialUser::from_row(row, &FILTER_COLUMNS))
+            .filter(|u| u.as_ref().map_or(true, |u| filter.matches(u)))
+            .collect::<Result<Vec<PartialUser>, ConvertRowToUserError>>()?;
+
+        for batch in matching.chunks(DELETE_BATCH_SIZE) {
+            let query = batch
+                .iter()
+                .filter_map(PartialUser::id)
+                .fold(BatchQueryBuilder::new(), |query, id| {
+                    query.add_query(
+                        format!(
+                            "DELETE FROM identity.users WHERE {} = ?;",
+                            UserColumn::Id.as_str()
+                        ),
+                        query_values!(*id),
+                    )
+                })
+                .finalize()?;
+
+            self.run(self.session().batch_with_params(query)).await?;
+
+            for u in batch {
+                if let (Some(id), Some(username), Some(email)) = (u.id(), u.username(), u.email()) {
+                    self.release_username(username, id).await?;
+                    self.release_email(email, id).await?;
+                }
+            }
+
+            for id in batch.iter().filter_map(PartialUser::id) {
+                self.event_sink().on_user_deleted(id);
+            }
+        }
+
+        Ok(matching.len() as u64)
+    }
+
+    /// Deletes a user along with every row related to them: their linked external accounts,
+    /// provider tokens, sessions, username history, audit log, email addresses, and their username
+    /// and email address reservations. Every row is deleted atomically, by a single batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that should be deleted
+    pub async fn delete_user_cascade(&self, user_id: &Uuid) -> IdentityResult<()> {
+        let u: OwnedUser = self.load_record(&UserQuery::Id(user_id)).await?;
+
+        let mut query = BatchQueryBuilder::new()
+            .add_query(
+                format!(
+                    "DELETE FROM identity.users WHERE {} = ?;",
+                    UserColumn::Id.as_str()
+                ),
+                query_values!(*user_id),
+            )
+            .add_query(
+                "DELETE FROM identity.provider_tokens WHERE user_id = ?;",
+                query_values!(*user_id),
+            )
+            .add_query(
+                "DELETE FROM identity.username_history WHERE user_id = ?;",
+                query_values!(*user_id),
+            )
+            .add_query(
+                "DELETE FROM identity.audit_events WHERE user_id = ?;",
+                query_values!(*user_id),
+            );
+
+        for event in self.load_audit_log(user_id).await? {
+            let (deletion, values) = event.timeline_deletion();
+            query = query.add_query(deletion, values);
+        }
+
+        for session in self.load_user_sessions(user_id).await? {
+            query = query.add_query(
+                "DELETE FROM identity.sessions WHERE session_id = ?;",
+                query_values!(*session.session_id()),
+            );
+        }
+
+        for identity in self.load_linked_identities(user_id).await? {
+            query = query.add_query(
+                "DELETE FROM identity.provider_identities WHERE provider = ? AND provider_user_id = ?;",
+                query_values!(
+                    <&str as From<IdentityProvider>>::from(identity.provider()),
+                    identity.provider_user_id()
+                ),
+            );
+        }
+
+        // Conditional deletions can't span partitions, so reservations are only deleted once
+        // they're known to be held by the user
+        if self.username_owner(&u.username).await? == Some(u.id) {
+            query = query.add_query(
+                "DELETE FROM identity.usernames WHERE username = ?;",
+                query_values!(u.username.as_str()),
+            );
+
+            for prefix in username_prefixes(&u.username) {
+                query = query.add_query(
+                    "DELETE FROM identity.username_prefixes WHERE prefix = ? AND username = ?;",
+                    query_values!(prefix, u.username.as_str()),
+                );
+            }
+        }
+
+        let mut emails = self
+            .load_user_emails(user_id)
+            .await?
+            .into_iter()
+            .map(|record| record.email().to_owned())
+            .collect::<Vec<String>>();
+        for email in &emails {
+            query = query.add_query(
+                "DELETE FROM identity.user_emails WHERE email = ?;",
+                query_values!(email.as_str()),
+            );
+        }
+
+        if !emails.contains(&u.email) {
+            emails.push(u.email.clone());
+        }
+        for email in emails {
+            if self.email_owner(&email).await? == Some(u.id) {
+                query = query.add_query(
+                    "DELETE FROM identity.emails WHERE email = ?;",
+                    query_values!(email),
+                );
+            }
+        }
+
+        self.run(self.session().batch_with_params(query.finalize()?))
+            .await?;
+        self.event_sink().on_user_deleted(user_id);
+
+        Ok(())
+    }
+
+    /// Normalizes the email address of every user stored before email addresses were normalized,
+    /// reserving each normalized address for its user. Returns the number of users whose address
+    /// was rewritten. Users whose normalized address is already held by some other user are left
+    /// untouched, and reported in a QueryError::EmailConflicts once every other user has been
+    /// normalized; such conflicts must be resolved manually. Users are streamed a page at a time,
+    /// so the users table is never held in memory at once.
+    pub async fn normalize_existing_emails(&self) -> IdentityResult<u64> {
+        let (mut updated, mut conflicting) = (0, Vec::new());
+
+        let mut users = Box::pin(self.stream_users(None));
+        while let Some(u) = users.try_next().await? {
+            let email = normalize_email(&u.email);
+
+            if email == u.email {
+                continue;
+            }
+
+            // The user may already hold a reservation of their normalized address
+            match self.reserve_email(&email, &u.id).await? {
+                LwtOutcome::NotApplied(holder) if holder != u.id => {
+                    conflicting.push(u.id);
+
+                    continue;
+                }
+                _ => (),
+            }
+
+            // Unlike change_email, the user still owns the same address, so it stays verified
+            self.execute(
+                &format!(
+                    "UPDATE identity.users SET {} = ? WHERE {} = ?;",
+                    UserColumn::Email.as_str(),
+                    UserColumn::Id.as_str()
+                ),
+                query_values!(email.as_str(), u.id),
+            )
+            .await?;
+            self.release_email(&u.email, &u.id).await?;
+            self.event_sink().on_user_updated(&u.id);
+
+            updated += 1;
+        }
+
+        if conflicting.is_empty() {
+            Ok(updated)
+        } else {
+            Err(QueryError::EmailConflicts {
+                updated,
+                users: conflicting,
+            }
+            .into())
+        }
+    }
+
+    /// Populates a column of the users table that holds no value for some users, as is the case
+    /// for every existing user once a column is added. Users are paged through, and the value of
+    /// the column computed for each user is written by a single-row update, returning the number
+    /// of users updated. Updates are issued concurrently, a bounded number at a time, rather than
+    /// batched, as the users that they update lie in different partitions. Users already holding a
+    /// value are skipped, such that a backfill that was interrupted may simply be run again.
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - The name of the column that should be populated
+    /// * `compute` - Computes the value of the column for a user, or None if the user should be
+    /// left untouched
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async fn example(db: swaply_identity::db::scylla::Scylla) -> swaply_identity::result::IdentityResult<()> {
+    /// let updated = db
+    ///     .backfill("username_normalized", |u| Some(u.username().to_lowercase()))
+    ///     .await?;
+    /// println!("backfilled {} users", updated);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn backfill<T, F>(&self, column: &'static str, compute: F) -> IdentityResult<u64>
+    where
+        T: Into<Bytes>,
+        Row: IntoRustByName<T>,
+        F: Fn(&OwnedUser) -> Option<T>,
+    {
+        self.backfill_table("identity.users", column, compute).await
+    }
+
+    /// Populates a column of a table holding users, as is done by Scylla::backfill.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The qualified name of the table (e.g., "identity.users")
+    /// * `column` - The name of the column that should be populated
+    /// * `compute` - Computes the value of the column for a user, or None if the user should be
+    /// left untouched
+    async fn backfill_table<T, F>(
+        &self,
+        table: &str,
+        column: &'static str,
+        compute: F,
+    ) -> IdentityResult<u64>
+    where
+        T: Into<Bytes>,
+        Row: IntoRustByName<T>,
+        F: Fn(&OwnedUser) -> Option<T>,
+    {
+        let query = format!(
+            "UPDATE {} SET {} = ? WHERE {} = ?;",
+            table,
+            column,
+            UserColumn::Id.as_str()
+        );
+        let (query, compute) = (query.as_str(), &compute);
+
+        let (updated, pending) = self
+            .stream_pages(format!("SELECT * FROM {};", table))
+            .try_fold(
+                (0, Vec::new()),
+                move |(mut updated, mut pending), row| async move {
+                    // Users backfilled by an earlier, interrupted run are left alone
+                    if <Row as IntoRustByName<T>>::get_by_name(&row, column)?.is_some() {
+                        return Ok((updated, pending));
+                    }
+
+                    let u = <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(row)?;
+                    if let Some(value) = compute(&u) {
+                        pending.push(query_values!(value, u.id));
+                    }
+
+                    if pending.len() == BACKFILL_BATCH_SIZE {
+                        updated += self
+                            .write_backfill_values(query, std::mem::take(&mut pending))
+                            .await?;
+
+                        tracing::info!(column, updated, "backfilling users");
+                    }
+
+                    Ok((updated, pending))
+                },
+            )
+            .await?;
+
+        let updated = updated + self.write_backfill_values(query, pending).await?;
+        tracing::info!(column, updated, "backfilled users");
+
+        Ok(updated)
+    }
+
+    /// Concurrently writes the values computed for several users while backfilling a column,
+    /// returning the number of users updated. Each value is written by its own statement, which
+    /// is retried after timing out, since writing the same value twice is harmless.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The statement updating the column of a single user
+    /// * `values` - The values that should be bound to the statement, one set per user
+    async fn write_backfill_values(
+        &self,
+        query: &str,
+        values: Vec<QueryValues>,
+    ) -> IdentityResult<u64> {
+        let updated = values.len() as u64;
+
+        future::try_join_all(values.into_iter().map(|values| {
+            self.run_write(true, move || {
+                self.session().query_with_values(query, values.clone())
+            })
+        }))
+        .await
+        .map(|_| updated)
+    }
+
+    /// Marks a user's current email address as verified.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user that proved they own their email address
+    pub async fn mark_email_verified(&self, id: &Uuid) -> IdentityResult<()> {
+        self.execute(
+            &format!(
+                "UPDATE identity.users SET {} = true WHERE {} = ?;",
+                UserColumn::EmailVerified.as_str(),
+                UserColumn::Id.as_str()
+            ),
+            query_values!(*id),
+        )
+        .await?;
+        self.event_sink().on_user_updated(id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{collections::HashSet, error::Error};
+
+    use super::{
+        super::{
+            audit::{AuditEvent, AuditKind},
+            provider_identity::ProviderIdentity,
+            session::LoginSession,
+            token::OwnedProviderToken,
+            user_email::UserEmail,
+            username_history::UsernameChange,
+        },
+        *,
+    };
+    use crate::{clock::FixedClock, testing};
+    use cdrs::{
+        frame::frame_result::{BodyResResultRows, ColSpec, ColType, ColTypeOption, RowsMetadata},
+        types::{CBytes, CString},
+    };
+
+    #[tokio::test]
+    async fn test_insert_user() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+        testing::insert_user(&db, &testing::generate_user()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_user_id() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+
+        assert_eq!(loaded_u, u);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_user_nickname() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_random_user();
+        testing::insert_user(&db, &User::from(&u)).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Nickname(u.username())).await?;
+
+        assert_eq!(loaded_u.id(), u.id());
+        assert_eq!(loaded_u, u);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_user_if_absent() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_user();
+        assert!(db.insert_if_absent(&u).await?);
+
+        // A replayed registration under the same ID must not clobber the stored user
+        let replayed = User::new(
+            Some(*u.id()),
+            "replayed",
+            "replayed@test.com",
+            u.password_hash().cloned(),
+            None,
+        );
+        assert!(!db.insert_if_absent(&replayed).await?);
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u, u);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_table_schema_matches_table() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let schema = <User as InTable<Scylla, DbSession>>::table_schema();
+        let rows = session
+            .query_with_values(
+                "SELECT column_name, type FROM system_schema.columns WHERE keyspace_name = ? AND table_name = ?;",
+                query_values!(schema.keyspace, schema.name),
+            )
+            .await?
+            .get_body()?
+            .into_rows()
+            .unwrap_or_default();
+
+        let mut created = rows
+            .into_iter()
+            .map(|row| {
+                let name: String = row.get_r_by_name("column_name")?;
+                let cql_type: String = row.get_r_by_name("type")?;
+
+                Ok((name, cql_type.to_uppercase()))
+            })
+            .collect::<Result<Vec<(String, String)>, CDRSError>>()?;
+        let mut described = schema
+            .columns
+            .iter()
+            .map(|column| (column.name.to_owned(), column.cql_type.to_owned()))
+            .collect::<Vec<(String, String)>>();
+        created.sort();
+        described.sort();
+
+        assert_eq!(created, described);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_names_consistent() -> Result<(), Box<dyn Error>> {
+        assert_eq!(
+            <User as Insertable<Scylla, DbSession>>::INSERTION_QUERY,
+            users_insertion_query()
+        );
+        testing::assert_insertion_columns_match(&testing::generate_user())?;
+
+        let ddl = <User as InTable<Scylla, DbSession>>::table_schema().create_statement();
+        for column in UserColumn::ALL.iter() {
+            assert!(ddl.contains(&format!("{} {}", column.as_str(), column.cql_type())));
+            assert!(<User as Updatable<Scylla, DbSession>>::UPDATE_QUERY
+                .contains(&format!("{} = ?", column.as_str())));
+        }
+
+        // The serialized user must bind exactly the columns that the deserializer reads
+        match <User as Serializable<QueryValues>>::try_into(&testing::generate_user())? {
+            QueryValues::NamedValues(values) => {
+                let names: HashSet<&str> = values.keys().map(String::as_str).collect();
+                let expected: HashSet<&str> =
+                    UserColumn::ALL.iter().map(UserColumn::as_str).collect();
+
+                assert_eq!(names, expected);
+            }
+            values => panic!("expected named values, got {:?}", values),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_register_read_your_writes() -> Result<(), Box<dyn Error>> {
+        use cdrs::consistency::Consistency;
+
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+        assert_eq!(db.read_your_writes_consistency(), Consistency::One);
+
+        let db = db.with_read_your_writes(true);
+        assert_eq!(db.read_your_writes_consistency(), Consistency::Quorum);
+
+        let (username, email) = (unique_username(), format!("{}@test.com", unique_username()));
+        let u = User::new(None, &username, &email, None, None);
+
+        let registered_u = db.register(&u).await?;
+        assert_eq!(registered_u, u);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_register_idempotent() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session).with_read_your_writes(true);
+
+        let request = |username: &str| RegistrationRequest {
+            username: username.to_owned(),
+            email: format!("{}@test.com", username),
+            password: "Tr0ub4dour&3".to_owned(),
+        };
+
+        let (key, username) = (Uuid::new_v4().to_string(), unique_username());
+
+        let registered_u = db.register_idempotent(&key, request(&username)).await?;
+        assert_eq!(registered_u.username(), username);
+
+        // Repeating the request yields the same user, even if the request has changed since
+        let repeated_u = db.register_idempotent(&key, request(&username)).await?;
+        assert_eq!(repeated_u.id(), registered_u.id());
+
+        let changed_u = db
+            .register_idempotent(&key, request(&unique_username()))
+            .await?;
+        assert_eq!(changed_u.id(), registered_u.id());
+
+        // The key expires, such that it isn't held forever should registration be abandoned
+        let ttl: i32 = db
+            .session()
+            .query_with_values(
+                "SELECT TTL(user_id) AS ttl FROM identity.registration_idempotency WHERE key = ?;",
+                query_values!(key.as_str()),
+            )
+            .await?
+            .get_body()?
+            .into_rows()
+            .and_then(|rows| rows.into_iter().next())
+            .ok_or(QueryError::NoResults)?
+            .get_r_by_name("ttl")?;
+        assert!(ttl > 0 && ttl as u64 <= REGISTRATION_KEY_TTL.as_secs());
+
+        // A failed registration doesn't hold onto its key
+        let other_key = Uuid::new_v4().to_string();
+        match db.register_idempotent(&other_key, request(&username)).await {
+            Err(IdentityError::QueryError(QueryError::UsernameTaken)) => (),
+            res => panic!("expected the username to be taken, got {:?}", res),
+        }
+
+        let other_username = unique_username();
+        let other_u = db
+            .register_idempotent(&other_key, request(&other_username))
+            .await?;
+        assert_ne!(other_u.id(), registered_u.id());
+        assert_eq!(other_u.username(), other_username);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_with_ttl() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (ephemeral_u, permanent_u) = (testing::generate_user(), testing::generate_user());
+        db.insert_with_ttl(&ephemeral_u, Duration::from_secs(1))
+            .await?;
+        db.insert_with_ttl(&permanent_u, Duration::from_secs(0))
+            .await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(ephemeral_u.id())).await?;
+        assert_eq!(loaded_u, ephemeral_u);
+
+        tokio::time::delay_for(Duration::from_secs(2)).await;
+
+        let loaded_u: IdentityResult<OwnedUser> =
+            db.load_record(&UserQuery::Id(ephemeral_u.id())).await;
+        match loaded_u {
+            Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+            res => panic!("expected the user to have expired, got {:?}", res),
+        }
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(permanent_u.id())).await?;
+        assert_eq!(loaded_u, permanent_u);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_return() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+        let inserted_u = db.insert_and_return(testing::generate_user()).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(inserted_u.id())).await?;
+        assert_eq!(User::from(&loaded_u), inserted_u);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_owned_user() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        // A second keyspace holding a copy of the users table
+        session
+            .query(
+                "
+                    CREATE KEYSPACE IF NOT EXISTS identity_copy
+                        WITH REPLICATION = {
+                            'class': 'SimpleStrategy',
+                            'replication_factor': 1
+                    };
+                ",
+            )
+            .await?;
+        session
+            .query(
+                TableSchema {
+                    keyspace: "identity_copy",
+                    ..<User as InTable<Scylla, DbSession>>::table_schema()
+                }
+                .create_statement(),
+            )
+            .await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+
+        db.session()
+            .query_with_values(
+                <User as Insertable<Scylla, DbSession>>::INSERTION_QUERY
+                    .replace("identity.", "identity_copy."),
+                QueryValues::try_from(&loaded_u)?,
+            )
+            .await?;
+
+        let row = db
+            .session()
+            .query(format!(
+                "SELECT * FROM identity_copy.users WHERE id = {};",
+                u.id()
+            ))
+            .await?
+            .get_body()?
+            .into_rows()
+            .and_then(|rows| rows.into_iter().next())
+            .ok_or(QueryError::NoResults)?;
+        let copied_u = <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(row)?;
+
+        assert_eq!(User::from(&copied_u), loaded_u);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_projection() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+
+        let partial = db
+            .load_projection(
+                &UserQuery::Id(u.id()),
+                &[UserColumn::Username, UserColumn::Email],
+            )
+            .await?;
+        assert_eq!(partial.username(), Some(u.username()));
+        assert_eq!(partial.email(), Some(u.email()));
+        assert_eq!(partial.id(), None);
+        assert_eq!(partial.password_hash(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deserialize_missing_column() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+
+        // Select every column but the email, as though the schema had drifted
+        let row = db
+            .session()
+            .query(format!(
+                "SELECT id, username, password_hash, password_salt, registered_at, email_verified \
+                 FROM identity.users WHERE id = {};",
+                u.id()
+            ))
+            .await?
+            .get_body()?
+            .into_rows()
+            .and_then(|rows| rows.into_iter().next())
+            .ok_or(QueryError::NoResults)?;
+
+        match <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(row) {
+            Err(e) => assert_eq!(e.column(), Some("email")),
+            res => panic!("expected the email column to be missing, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    /// Builds a row holding the given columns, as though it had been read from the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - The name, type, and raw bytes of each column, or None if it holds null
+    fn build_row(columns: Vec<(&str, ColType, Option<Vec<u8>>)>) -> Row {
+        let (col_specs, values) = columns
+            .into_iter()
+            .map(|(name, id, value)| {
+                (
+                    ColSpec {
+                        table_spec: None,
+                        name: CString::new(name.to_owned()),
+                        col_type: ColTypeOption { id, value: None },
+                    },
+                    value.map(CBytes::new).unwrap_or_else(CBytes::new_empty),
+                )
+            })
+            .unzip::<_, _, Vec<ColSpec>, Vec<CBytes>>();
+
+        Row::from_frame_body(BodyResResultRows {
+            metadata: RowsMetadata {
+                flags: 0,
+                columns_count: col_specs.len() as i32,
+                paging_state: None,
+                global_table_spec: None,
+                col_specs,
+            },
+            rows_count: 1,
+            rows_content: vec![values],
+        })
+        .remove(0)
+    }
+
+    /// Builds a row holding only the given ID and a username of the given raw bytes, as though
+    /// the bytes had been read from the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user
+    /// * `username` - The raw bytes of the user's username
+    fn row_with_username(id: &Uuid, username: &[u8]) -> Row {
+        build_row(vec![
+            (
+                UserColumn::Id.as_str(),
+                ColType::Uuid,
+                Some(id.as_bytes().to_vec()),
+            ),
+            (
+                UserColumn::Username.as_str(),
+                ColType::Varchar,
+                Some(username.to_vec()),
+            ),
+        ])
+    }
+
+    /// Gets the columns of a row holding a user registered at the epoch, with no password, and
+    /// none of the columns added after the users table was first deployed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the user
+    fn original_columns(id: &Uuid) -> Vec<(&'static str, ColType, Option<Vec<u8>>)> {
+        vec![
+            (
+                UserColumn::Id.as_str(),
+                ColType::Uuid,
+                Some(id.as_bytes().to_vec()),
+            ),
+            (
+                UserColumn::Username.as_str(),
+                ColType::Varchar,
+                Some(b"test".to_vec()),
+            ),
+            (
+                UserColumn::Email.as_str(),
+                ColType::Varchar,
+                Some(b"test@test.com".to_vec()),
+            ),
+            (UserColumn::PasswordHash.as_str(), ColType::Blob, None),
+            (UserColumn::PasswordSalt.as_str(), ColType::Blob, None),
+            (
+                UserColumn::RegisteredAt.as_str(),
+                ColType::Timestamp,
+                Some(0i64.to_be_bytes().to_vec()),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_deserialize_invalid_hash_length() {
+        let id = Uuid::new_v4();
+
+        // Hashes too short to be a hash, as though the column was corrupted
+        let short_hash = vec![1; HASH_LENGTH - 1];
+        let mut hashes = vec![(ColType::Blob, short_hash.clone())];
+        if cfg!(feature = "legacy-base58-hashes") {
+            hashes.push((
+                ColType::Varchar,
+                bs58::encode(&short_hash).into_string().into_bytes(),
+            ));
+        }
+
+        for (col_type, hash) in hashes {
+            let mut columns = original_columns(&id);
+            columns[3] = (UserColumn::PasswordHash.as_str(), col_type, Some(hash));
+
+            match <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(build_row(columns)) {
+                Err(ConvertRowToUserError::InUser(_, e)) => match *e {
+                    ConvertRowToUserError::InvalidHashLength {
+                        column,
+                        expected,
+                        got,
+                    } => {
+                        assert_eq!(column, UserColumn::PasswordHash.as_str());
+                        assert_eq!((expected, got), (HASH_LENGTH, HASH_LENGTH - 1));
+                    }
+                    e => panic!("expected the hash to be too short, got {:?}", e),
+                },
+                res => panic!("expected the hash to be too short, got {:?}", res),
+            }
+        }
+
+        let mut columns = original_columns(&id);
+        columns[4].2 = Some(vec![0; SALT_LENGTH + 1]);
+        match <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(build_row(columns)) {
+            Err(e) => assert_eq!(e.column(), Some(UserColumn::PasswordSalt.as_str())),
+            res => panic!("expected the salt to be too long, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_extra_columns() -> Result<(), Box<dyn Error>> {
+        let id = Uuid::new_v4();
+
+        // Columns that the user doesn't know of, as though the table had been altered before the
+        // user was updated to match
+        let mut columns = original_columns(&id);
+        columns.push((
+            UserColumn::DisplayName.as_str(),
+            ColType::Varchar,
+            Some(b"Test".to_vec()),
+        ));
+        columns.push(("pronouns", ColType::Varchar, Some(b"they/them".to_vec())));
+        columns.push(("karma", ColType::Int, Some(42i32.to_be_bytes().to_vec())));
+
+        let u = <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(build_row(columns))?;
+        assert_eq!(u.id(), &id);
+        assert_eq!(u.username(), "test");
+        assert_eq!(u.display_name(), Some("Test"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_absent_optional_columns() -> Result<(), Box<dyn Error>> {
+        let id = Uuid::new_v4();
+
+        // None of the columns added since the table was first deployed are present, as though
+        // the user had been updated before the table was altered
+        let u = <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(build_row(
+            original_columns(&id),
+        ))?;
+        assert_eq!(u.id(), &id);
+        assert!(!u.email_verified());
+        assert_eq!(u.failed_login_attempts(), 0);
+        assert_eq!(u.locked_until(), None);
+        assert_eq!(u.display_name(), None);
+        assert_eq!(u.timezone(), None);
+
+        // A column holding a value of the wrong type still fails, rather than being read as absent
+        let mut columns = original_columns(&id);
+        columns.push((
+            UserColumn::DisplayName.as_str(),
+            ColType::Int,
+            Some(42i32.to_be_bytes().to_vec()),
+        ));
+        match <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(build_row(columns)) {
+            Err(e) => assert_eq!(e.column(), Some(UserColumn::DisplayName.as_str())),
+            res => panic!("expected the display name to be malformed, got {:?}", res),
+        }
+
+        // Columns present since the table was first deployed are still required
+        let columns = original_columns(&id)
+            .into_iter()
+            .filter(|(name, _, _)| *name != UserColumn::Email.as_str())
+            .collect();
+        match <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(build_row(columns)) {
+            Err(e) => assert_eq!(e.column(), Some(UserColumn::Email.as_str())),
+            res => panic!("expected the email column to be missing, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_column_absent() {
+        let row = build_row(vec![(
+            UserColumn::DisplayName.as_str(),
+            ColType::Int,
+            Some(42i32.to_be_bytes().to_vec()),
+        )]);
+
+        let absent = <Row as IntoRustByName<String>>::get_by_name(&row, "locale").unwrap_err();
+        assert!(is_column_absent(&absent, "locale"));
+
+        // A column of the wrong type isn't absent, nor is an absent column under another name
+        let mistyped =
+            <Row as IntoRustByName<String>>::get_by_name(&row, UserColumn::DisplayName.as_str())
+                .unwrap_err();
+        assert!(!is_column_absent(
+            &mistyped,
+            UserColumn::DisplayName.as_str()
+        ));
+        assert!(!is_column_absent(&absent, "timezone"));
+    }
+
+    #[test]
+    fn test_deserialize_invalid_utf8() {
+        let id = Uuid::new_v4();
+
+        // 0xff never appears in UTF-8
+        let row = row_with_username(&id, b"test\xff");
+        match <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(row) {
+            Err(ConvertRowToUserError::InUser(user_id, e)) => {
+                assert_eq!(user_id, id);
+                assert!(matches!(
+                    *e,
+                    ConvertRowToUserError::InvalidUtf8 { column: "username" }
+                ));
+                assert!(e.to_string().contains("username holds invalid UTF-8"));
+            }
+            res => panic!("expected the username to be invalid UTF-8, got {:?}", res),
+        }
+
+        // A valid username is decoded, so the absent email is reported instead
+        let row = row_with_username(&id, b"test");
+        match <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(row) {
+            Err(e) => assert_eq!(e.column(), Some("email")),
+            res => panic!("expected the email column to be missing, got {:?}", res),
+        }
+    }
+
+    #[cfg(feature = "legacy-base58-hashes")]
+    #[tokio::test]
+    async fn test_deserialize_blob_and_base58_hashes() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        // A copy of the users table whose hashes have yet to be migrated from base58 TEXT
+        session
+            .query(
+                "
+                    CREATE TABLE IF NOT EXISTS identity.users_base58_hashes (
+                        id UUID,
+                        username TEXT,
+                        email TEXT,
+                        password_hash TEXT,
+                        password_salt TEXT,
+                        registered_at TIMESTAMP,
+                        email_verified BOOLEAN,
+                        rotate_hash_on_next_login BOOLEAN,
+                        failed_login_attempts INT,
+                        locked_until TIMESTAMP,
+                        display_name TEXT,
+                        locale TEXT,
+                        timezone TEXT,
+                        suspended BOOLEAN,
+                        PRIMARY KEY (id)
+                    );
+                ",
+            )
+            .await?;
+
+        let db = Scylla::new(session);
+
+        let salt = password::generate_salt();
+        let u = User::new(
+            None,
+            "test",
+            "test@test.com",
+            Some(password::hash_password("123456", &salt)),
+            None,
+        )
+        .with_password_salt(salt);
+        testing::insert_user(&db, &u).await?;
+
+        let mut values = match <User as Serializable<QueryValues>>::try_into(&u)? {
+            QueryValues::NamedValues(values) => values,
+            values => panic!("expected named values, got {:?}", values),
+        };
+        values.insert(
+            UserColumn::PasswordHash.as_str().to_owned(),
+            u.password_hash().unwrap().to_base58().into(),
+        );
+        values.insert(
+            UserColumn::PasswordSalt.as_str().to_owned(),
+            bs58::encode(salt).into_string().into(),
+        );
+        db.session()
+            .query_with_values(
+                <User as Insertable<Scylla, DbSession>>::INSERTION_QUERY
+                    .replace("identity.users", "identity.users_base58_hashes"),
+                QueryValues::NamedValues(values),
+            )
+            .await?;
+
+        let mut rows = Vec::new();
+        for table in &["identity.users", "identity.users_base58_hashes"] {
+            rows.push(
+                db.session()
+                    .query(format!("SELECT * FROM {} WHERE id = {};", table, u.id()))
+                    .await?
+                    .get_body()?
+                    .into_rows()
+                    .and_then(|rows| rows.into_iter().next())
+                    .ok_or(QueryError::NoResults)?,
+            );
+        }
+
+        let base58_u = <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(rows.remove(1))?;
+        let blob_u = <OwnedUser as Deserializable<OwnedUser, Row>>::try_from(rows.remove(0))?;
+
+        assert_eq!(blob_u, base58_u);
+        assert_eq!(blob_u, u);
+        assert!(blob_u.verify_password("123456"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deserialize_corrupt_row() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+
+        // A single byte is too short to be a hash
+        db.execute(
+            "UPDATE identity.users SET password_hash = 0x00 WHERE id = ?;",
+            query_values!(*u.id()),
+        )
+        .await?;
+
+        let loaded_u: IdentityResult<OwnedUser> = db.load_record(&UserQuery::Id(u.id())).await;
+        match loaded_u {
+            Err(IdentityError::QueryError(QueryError::DeserializationError(e))) => {
+                assert_eq!(e.id(), Some(u.id()));
+                assert!(e.to_string().contains(&u.id().to_string()));
+            }
+            res => panic!("expected a deserialization error, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_registered_at_truncated() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let registered_at = DateTime::<Utc>::from_utc(
+            NaiveDateTime::from_timestamp(1_592_000_000, 123_456_789),
+            Utc,
+        );
+        let u = User::new(
+            None,
+            "test",
+            "test@test.com",
+            Some(blake3::hash(b"123456").into()),
+            Some(registered_at),
+        );
+        testing::insert_user(&db, &u).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(
+            loaded_u.registered_at(),
+            DateTime::<Utc>::from_utc(
+                NaiveDateTime::from_timestamp(1_592_000_000, 123_000_000),
+                Utc
+            )
+        );
+        assert_eq!(loaded_u.registered_at(), u.registered_at());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_registered_at() {
+        let now = Utc::now();
+        let mut u = User::new(None, "test", "test@test.com", None, Some(now));
+        assert_eq!(u.try_registered_at(), Ok(u.registered_at()));
+
+        // The last valid nanosecond of a second, truncated to the millisecond
+        u.registered_at = Timespec {
+            sec: 0,
+            nsec: NANOS_PER_SEC - 1,
+        }
+        .into();
+        assert_eq!(
+            u.try_registered_at()
+                .map(|registered_at| registered_at.timestamp_subsec_nanos()),
+            Ok(999_000_000)
+        );
+
+        // A whole second of nanoseconds, which would otherwise be read as a leap second
+        u.registered_at = RegistrationTimestamp {
+            sec: 0,
+            nsec: NANOS_PER_SEC,
+        };
+        assert_eq!(
+            u.try_registered_at(),
+            Err(TimestampError::InvalidNanoseconds(NANOS_PER_SEC))
+        );
+
+        u.registered_at = RegistrationTimestamp {
+            sec: 0,
+            nsec: -NANOS_PER_MILLI,
+        };
+        assert_eq!(
+            u.try_registered_at(),
+            Err(TimestampError::InvalidNanoseconds(-NANOS_PER_MILLI))
+        );
+
+        u.registered_at = RegistrationTimestamp {
+            sec: i64::MAX,
+            nsec: 0,
+        };
+        assert_eq!(
+            u.try_registered_at(),
+            Err(TimestampError::OutOfRange(i64::MAX))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_normalize_existing_emails() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (username, holder, conflicting) =
+            (unique_username(), unique_username(), unique_username());
+        let (email, conflicting_email) = (
+            format!(" {}@Test.COM", username.to_uppercase()),
+            format!("{}@Test.com", holder),
+        );
+
+        // Users stored before email addresses were normalized
+        let u = User::new(None, &username, &email, None, None);
+        let conflicting_u = User::new(None, &conflicting, &conflicting_email, None, None);
+        testing::insert_user(&db, &u).await?;
+        testing::insert_user(&db, &conflicting_u).await?;
+
+        let holder_u = User::new(None, &holder, &conflicting_email.to_lowercase(), None, None);
+        db.insert_unique(&holder_u).await?;
+
+        match db.normalize_existing_emails().await {
+            Err(IdentityError::QueryError(QueryError::EmailConflicts { updated, users })) => {
+                assert!(updated >= 1);
+                assert_eq!(users, vec![*conflicting_u.id()]);
+            }
+            res => panic!("expected an email conflict, got {:?}", res),
+        }
+
+        let normalized = normalize_email(&email);
+        assert_eq!(db.email_owner(&normalized).await?, Some(*u.id()));
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.email(), normalized);
+
+        // The conflicting user is left untouched until resolved
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(conflicting_u.id())).await?;
+        assert_eq!(loaded_u.email(), conflicting_email);
+
+        db.delete_where(UserFilter::default().username_prefix(&conflicting))
+            .await?;
+        assert_eq!(db.normalize_existing_emails().await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backfill() -> Result<(), Box<dyn Error>> {
+        let db = Scylla::new(testing::open_session().await?);
+        db.ensure_schema().await?;
+
+        // A copy of the users table with the column to backfill, holding only this test's users
+        let schema = <User as InTable<Scylla, DbSession>>::table_schema();
+        let schema = TableSchema {
+            name: "users_backfill",
+            columns: schema
+                .columns
+                .iter()
+                .copied()
+                .chain(std::iter::once(ColumnSchema::new("username_upper", "TEXT")))
+                .collect(),
+            ..schema
+        };
+        db.session()
+            .query("DROP TABLE IF EXISTS identity.users_backfill;")
+            .await?;
+        db.session().query(schema.create_statement()).await?;
+
+        let users = (0..3)
+            .map(|_| {
+                let username = unique_username();
+                let email = format!("{}@test.com", username);
+
+                (username, email)
+            })
+            .collect::<Vec<(String, String)>>();
+        let users = users
+            .iter()
+            .map(|(username, email)| User::new(None, username, email, None, None))
+            .collect::<Vec<User>>();
+        for u in &users {
+            db.execute(
+                &<User as Insertable<Scylla, DbSession>>::INSERTION_QUERY
+                    .replace("identity.users", "identity.users_backfill"),
+                Serializable::try_into(u)?,
+            )
+            .await?;
+        }
+
+        let username_upper = |id: &Uuid| {
+            let db = &db;
+            let id = *id;
+
+            async move {
+                db.run(db.session().query_with_values(
+                    "SELECT username_upper FROM identity.users_backfill WHERE id = ?;",
+                    query_values!(id),
+                ))
+                .await?
+                .get_body()?
+                .into_rows()
+                .unwrap_or_default()
+                .pop()
+                .map_or(Ok(None), |row| {
+                    <Row as IntoRustByName<String>>::get_by_name(&row, "username_upper")
+                })
+                .map_err(IdentityError::from)
+            }
+        };
+
+        assert_eq!(
+            db.backfill_table("identity.users_backfill", "username_upper", |u| {
+                Some(u.username().to_uppercase())
+            })
+            .await?,
+            users.len() as u64
+        );
+        for u in &users {
+            assert_eq!(
+                username_upper(u.id()).await?,
+                Some(u.username().to_uppercase())
+            );
+        }
+
+        // Users that were already backfilled are left untouched when the backfill is resumed
+        assert_eq!(
+            db.backfill_table("identity.users_backfill", "username_upper", |_| {
+                Some("overwritten".to_owned())
+            })
+            .await?,
+            0
+        );
+        for u in &users {
+            assert_eq!(
+                username_upper(u.id()).await?,
+                Some(u.username().to_uppercase())
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_where() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let prefix = unique_username();
+        let (doomed, survivor) = (
+            vec![format!("{}_a", prefix), format!("{}_b", prefix)],
+            unique_username(),
+        );
+
+        let hash = PasswordHash::from(blake3::hash(b"123456"));
+        let doomed_users = doomed
+            .iter()
+            .map(|username| User::new(None, username, "test@test.com", Some(hash.clone()), None))
+            .collect::<Vec<User>>();
+        let surviving_user = User::new(None, &survivor, "test@test.com", Some(hash), None);
+
+        for u in doomed_users.iter().chain(std::iter::once(&surviving_user)) {
+            testing::insert_user(&db, u).await?;
+        }
+
+        assert_eq!(
+            db.delete_where(UserFilter::default().username_prefix(&prefix))
+                .await?,
+            2
+        );
+
+        for u in doomed_users.iter() {
+            let loaded_u: IdentityResult<OwnedUser> = db.load_record(&UserQuery::Id(u.id())).await;
+
+            match loaded_u {
+                Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+                res => panic!("expected the user to be deleted, got {:?}", res),
+            }
+        }
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(surviving_user.id())).await?;
+        assert_eq!(loaded_u, surviving_user);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_users_paged() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session).with_page_size(2);
+
+        let users = (0..5)
+            .map(|_| testing::generate_user())
+            .collect::<Vec<User>>();
+        for u in users.iter() {
+            testing::insert_user(&db, u).await?;
+        }
+
+        let listed = db.list_users(None).await?;
+        let streamed = db
+            .stream_users(None)
+            .try_collect::<Vec<OwnedUser>>()
+            .await?;
+
+        for u in users.iter() {
+            assert!(listed.iter().any(|listed_u| listed_u.id() == u.id()));
+            assert!(streamed.iter().any(|streamed_u| streamed_u.id() == u.id()));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_users_unsupported_order() -> Result<(), Box<dyn Error>> {
+        use futures::stream::StreamExt;
+
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+        testing::insert_user(&db, &testing::generate_user()).await?;
+
+        // Users are keyed by their ID alone, so the users table isn't clustered by any column
+        let assert_unsupported = |res: IdentityResult<Vec<OwnedUser>>| match res {
+            Err(IdentityError::QueryError(QueryError::UnsupportedOrdering { table, column })) => {
+                assert_eq!(table, "identity.users");
+                assert_eq!(column, UserColumn::Username.as_str());
+            }
+            res => panic!("expected the ordering to be unsupported, got {:?}", res),
+        };
+
+        for order in &[UserOrder::UsernameAsc, UserOrder::UsernameDesc] {
+            assert_unsupported(db.list_users(Some(*order)).await);
+
+            // Nothing but the error is streamed
+            assert_unsupported(
+                db.stream_users(Some(*order))
+                    .collect::<Vec<IdentityResult<OwnedUser>>>()
+                    .await
+                    .into_iter()
+                    .collect(),
+            );
+            assert_eq!(db.stream_users(Some(*order)).count().await, 1);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_rows() -> Result<(), Box<dyn Error>> {
+        use futures::stream::StreamExt;
+
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session).with_page_size(2).with_max_rows(2);
+
+        let users = (0..3)
+            .map(|_| testing::generate_user())
+            .collect::<Vec<User>>();
+        for u in users.iter() {
+            testing::insert_user(&db, u).await?;
+        }
+
+        match db.list_users(None).await {
+            Err(IdentityError::QueryError(QueryError::ResultTooLarge { limit: 2 })) => (),
+            res => panic!("expected the result to be too large, got {:?}", res),
+        }
+
+        // The stream yields rows up to the limit, then reports the guard error and ends
+        let streamed = db
+            .stream_users(None)
+            .collect::<Vec<IdentityResult<OwnedUser>>>()
+            .await;
+        assert_eq!(streamed.len(), 3);
+        assert!(streamed[..2].iter().all(Result::is_ok));
+        assert!(matches!(
+            streamed[2],
+            Err(IdentityError::QueryError(QueryError::ResultTooLarge { .. }))
+        ));
+
+        let registered: IdentityResult<Vec<OwnedUser>> = db
+            .load_records(&UserQuery::RegisteredBetween(
+                Utc::now() - chrono::Duration::hours(1),
+                Utc::now(),
+            ))
+            .await;
+        match registered {
+            Err(IdentityError::QueryError(QueryError::ResultTooLarge { limit: 2 })) => (),
+            res => panic!("expected the result to be too large, got {:?}", res),
+        }
+
+        // The whole table is scanned for matching users, so the guard applies regardless of how
+        // few users match
+        match db
+            .delete_where(UserFilter::default().username_prefix(&unique_username()))
+            .await
+        {
+            Err(IdentityError::QueryError(QueryError::ResultTooLarge { limit: 2 })) => (),
+            res => panic!("expected the result to be too large, got {:?}", res),
+        }
+
+        // Loading a single record is unaffected
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(users[0].id())).await?;
+        assert_eq!(loaded_u, users[0]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_registered_between() -> Result<(), Box<dyn Error>> {
+        use chrono::{Duration, TimeZone};
+
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        // Register users hours apart, starting at some random instant in the past that no other
+        // test is likely to have registered users at
+        let base = Utc.timestamp(
+            1_000_000_000 + i64::from(rand::random::<u32>() % 100_000_000),
+            0,
+        );
+        let users = (0..4)
+            .map(|i| {
+                User::new(
+                    None,
+                    "test",
+                    "test@test.com",
+                    None,
+                    Some(base + Duration::hours(i)),
+                )
+            })
+            .collect::<Vec<User>>();
+        for u in users.iter() {
+            testing::insert_user(&db, u).await?;
+        }
+
+        let loaded: Vec<OwnedUser> = db
+            .load_records(&UserQuery::RegisteredBetween(
+                base + Duration::hours(1),
+                base + Duration::hours(2),
+            ))
+            .await?;
+
+        assert_eq!(
+            loaded.iter().map(OwnedUser::id).collect::<HashSet<&Uuid>>(),
+            users[1..3].iter().map(User::id).collect::<HashSet<&Uuid>>()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recent_registrations() -> Result<(), Box<dyn Error>> {
+        use chrono::TimeZone;
+
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        // As in test_load_registered_between, the window ends at some random instant in the past
+        let now = Utc.timestamp(
+            1_000_000_000 + i64::from(rand::random::<u32>() % 100_000_000),
+            0,
+        );
+        let users = [-30, -2, -1, 1]
+            .iter()
+            .map(|&hours| {
+                User::new(
+                    None,
+                    "test",
+                    "test@test.com",
+                    None,
+                    Some(now + chrono::Duration::hours(hours)),
+                )
+            })
+            .collect::<Vec<User>>();
+        for u in users.iter() {
+            testing::insert_user(&db, u).await?;
+        }
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        let recent = db.recent_registrations_at(now, day, 10).await?;
+        assert_eq!(
+            recent.iter().map(OwnedUser::id).collect::<Vec<&Uuid>>(),
+            vec![users[2].id(), users[1].id()]
+        );
+
+        let newest = db.recent_registrations_at(now, day, 1).await?;
+        assert_eq!(
+            newest.iter().map(OwnedUser::id).collect::<Vec<&Uuid>>(),
+            vec![users[2].id()]
+        );
+
+        // Every matching user is counted against the maximum result size, not just those returned
+        let bounded = Scylla::new(testing::open_session().await?).with_max_rows(1);
+        match bounded.recent_registrations_at(now, day, 1).await {
+            Err(IdentityError::QueryError(QueryError::ResultTooLarge { limit: 1 })) => (),
+            res => panic!("expected the scan to be bounded, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_deterministic() {
+        let id =
+            |seed: &[u8]| *User::new_deterministic(seed, "test", "test@test.com", None, None).id();
+
+        assert_eq!(id(b"alice"), id(b"alice"));
+        assert_ne!(id(b"alice"), id(b"bob"));
+
+        // The same seed yields a different ID in a different namespace
+        let namespaced =
+            User::new_deterministic_in(&Uuid::nil(), b"alice", "test", "test@test.com", None, None);
+        assert_ne!(namespaced.id(), &id(b"alice"));
+    }
+
+    #[test]
+    fn test_registered_between_invalid_range() {
+        let now = Utc::now();
+
+        assert!(UserQuery::RegisteredBetween(now, now).condition().is_ok());
+        assert!(matches!(
+            UserQuery::RegisteredBetween(now, now - chrono::Duration::seconds(1)).condition(),
+            Err(ValidationError::InvalidRange)
+        ));
+    }
+
+    /// Generates a valid username that is unlikely to have been used by any previous test.
+    fn unique_username() -> String {
+        format!("u{}", &Uuid::new_v4().to_simple().to_string()[..16])
+    }
+
+    #[tokio::test]
+    async fn test_change_username() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        UsernameChange::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+
+        let (first, second) = (unique_username(), unique_username());
+        db.change_username(u.id(), &first).await?;
+        db.change_username(u.id(), &second.to_uppercase()).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.username, second);
+
+        let history = db.load_username_history(u.id()).await?;
+        assert_eq!(
+            history
+                .iter()
+                .map(|change| change.username())
+                .collect::<Vec<&str>>(),
+            vec![u.username(), first.as_str()]
+        );
+
+        // The old username should be free for others to claim
+        assert!(db
+            .reserve_username(&first, &Uuid::new_v4())
+            .await?
+            .is_applied());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_username_prefixes() {
+        assert_eq!(username_prefixes("alice"), vec!["a", "al", "ali"]);
+        assert_eq!(username_prefixes("al"), vec!["a", "al"]);
+        assert!(username_prefixes("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_usernames() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        UsernameChange::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        // Inserted out of order, alongside usernames sharing all but the last character of the
+        // searched prefix
+        let stem = unique_username();
+        let usernames = ["a3", "b1", "a1", "", "a2"]
+            .iter()
+            .map(|suffix| format!("{}{}", stem, suffix))
+            .collect::<Vec<String>>();
+        let emails = usernames
+            .iter()
+            .map(|username| format!("{}@test.com", username))
+            .collect::<Vec<String>>();
+        let users = usernames
+            .iter()
+            .zip(emails.iter())
+            .map(|(username, email)| User::new(None, username, email, None, None))
+            .collect::<Vec<User>>();
+        for u in &users {
+            db.insert_unique(u).await?;
+        }
+
+        let found_usernames = |found: Vec<OwnedUser>| {
+            found
+                .into_iter()
+                .map(|u| u.username)
+                .collect::<Vec<String>>()
+        };
+        let prefix = format!("{}A", stem.to_uppercase());
+
+        assert_eq!(
+            found_usernames(
+                db.search_usernames(&prefix, 10, UserOrder::UsernameAsc)
+                    .await?
+            ),
+            vec![
+                format!("{}a1", stem),
+                format!("{}a2", stem),
+                format!("{}a3", stem)
+            ]
+        );
+        assert_eq!(
+            found_usernames(
+                db.search_usernames(&prefix, 2, UserOrder::UsernameAsc)
+                    .await?
+            ),
+            vec![format!("{}a1", stem), format!("{}a2", stem)]
+        );
+        assert!(db
+            .search_usernames("", 10, UserOrder::UsernameAsc)
+            .await?
+            .is_empty());
+
+        // A renamed user is no longer found by their old username
+        db.change_username(users[4].id(), &unique_username())
+            .await?;
+        assert_eq!(
+            found_usernames(
+                db.search_usernames(&prefix, 10, UserOrder::UsernameAsc)
+                    .await?
+            ),
+            vec![format!("{}a1", stem), format!("{}a3", stem)]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_usernames_ordered() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let stem = unique_username();
+        for suffix in &["b", "c", "a"] {
+            let username = format!("{}{}", stem, suffix);
+            let email = format!("{}@test.com", username);
+
+            db.insert_unique(&User::new(None, &username, &email, None, None))
+                .await?;
+        }
+
+        let found_usernames = |found: Vec<OwnedUser>| {
+            found
+                .into_iter()
+                .map(|u| u.username)
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            found_usernames(
+                db.search_usernames(&stem, 10, UserOrder::UsernameAsc)
+                    .await?
+            ),
+            vec![
+                format!("{}a", stem),
+                format!("{}b", stem),
+                format!("{}c", stem)
+            ]
+        );
+        assert_eq!(
+            found_usernames(
+                db.search_usernames(&stem, 2, UserOrder::UsernameDesc)
+                    .await?
+            ),
+            vec![format!("{}c", stem), format!("{}b", stem)]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_change_username_taken() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        UsernameChange::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (u1, u2) = (testing::generate_user(), testing::generate_user());
+        testing::insert_user(&db, &u1).await?;
+        testing::insert_user(&db, &u2).await?;
+
+        let username = unique_username();
+        db.change_username(u1.id(), &username).await?;
+
+        match db.change_username(u2.id(), &username).await {
+            Err(IdentityError::QueryError(QueryError::UsernameTaken)) => Ok(()),
+            res => panic!("expected the username to be taken, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_try_insert_unique_conflict() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (username, email) = (unique_username(), format!("{}@test.com", unique_username()));
+        let u1 = User::new(None, &username, &email, None, None);
+        assert_eq!(db.try_insert_unique(&u1).await?, LwtOutcome::Applied);
+
+        let other_email = format!("{}@test.com", unique_username());
+        let u2 = User::new(None, &username, &other_email, None, None);
+        assert_eq!(
+            db.try_insert_unique(&u2).await?,
+            LwtOutcome::NotApplied(UniqueConflict::Username {
+                username: username.clone(),
+                user_id: u1.id,
+            })
+        );
+
+        let u3 = User::new(None, &unique_username(), &email, None, None);
+        assert_eq!(
+            db.try_insert_unique(&u3).await?,
+            LwtOutcome::NotApplied(UniqueConflict::Email {
+                email: email.clone(),
+                user_id: u1.id,
+            })
+        );
+
+        // Neither conflicting user should have left a reservation behind
+        assert_eq!(db.email_owner(&other_email).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_change_email() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        UserEmail::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_user();
+        testing::insert_user(&db, &u).await?;
+        db.mark_email_verified(u.id()).await?;
+
+        let email = format!("{}@test.com", unique_username());
+        db.change_email(u.id(), &email.to_uppercase()).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.email, email);
+        assert!(!loaded_u.email_verified());
+
+        let emails = db.load_user_emails(u.id()).await?;
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].email(), email);
+        assert!(emails[0].is_primary());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_change_email_taken() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        UserEmail::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (u1, u2) = (testing::generate_user(), testing::generate_user());
+        testing::insert_user(&db, &u1).await?;
+        testing::insert_user(&db, &u2).await?;
+
+        let email = format!("{}@test.com", unique_username());
+        db.change_email(u1.id(), &email).await?;
+
+        match db.change_email(u2.id(), &email).await {
+            Err(IdentityError::QueryError(QueryError::EmailTaken)) => Ok(()),
+            res => panic!("expected the email to be taken, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_new_owned_user() {
+        let (id, registered_at) = (Uuid::new_v4(), Utc::now());
+        let hash = PasswordHash::from(blake3::hash(b"123456"));
+
+        let owned = OwnedUser::new(
+            Some(id),
+            "test".to_owned(),
+            "test@test.com",
+            Some(hash.clone()),
+            Some(registered_at),
+        );
+        let u = User::new(
+            Some(id),
+            "test",
+            "test@test.com",
+            Some(hash.clone()),
+            Some(registered_at),
+        );
+
+        assert_eq!(owned, u);
+        assert_eq!(owned.password_hash, Some(hash));
+    }
+
+    #[test]
+    fn test_hash_password_into_user() {
+        let salt = password::generate_salt();
+        let hash = password::hash_password("correct horse battery staple", &salt);
+
+        let u = User::new(None, "test", "test@test.com", Some(hash.clone()), None)
+            .with_password_salt(salt);
+        assert_eq!(u.password_hash(), Some(&hash));
+        assert!(u.verify_password("correct horse battery staple"));
+        assert!(!u.verify_password("correct horse battery"));
+
+        // Legacy users hold an unsalted, plain blake3 hash
+        assert!(testing::generate_user().verify_password("123456"));
+    }
+
+    #[test]
+    fn test_build_user_weak_password() {
+        match User::builder()
+            .username("test")
+            .email("test@test.com")
+            .password("123456")
+            .build()
+        {
+            Err(ValidationError::WeakPassword(password::PasswordError::TooShort(_))) => (),
+            res => panic!("expected the password to be rejected, got {:?}", res),
+        }
+
+        // The policy may be relaxed, e.g., for testing
+        assert!(User::builder()
+            .username("test")
+            .email("test@test.com")
+            .password("123456")
+            .password_policy(PasswordPolicy::permissive())
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_build_user_strong_password() -> Result<(), Box<dyn Error>> {
+        let u = User::builder()
+            .username("test")
+            .email("test@test.com")
+            .password("Tr0ub4dour&3")
+            .build()?;
+
+        assert_eq!(u.username(), "test");
+        assert_eq!(u.email(), "test@test.com");
+        assert!(u.password_salt().is_some());
+        assert!(u.verify_password("Tr0ub4dour&3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_registration_request() -> Result<(), Box<dyn Error>> {
+        let req: RegistrationRequest = serde_json::from_str(
+            r#"{"username": " Test ", "email": "Test@Test.com", "password": "Tr0ub4dour&3"}"#,
+        )?;
+
+        let u = req.into_user()?;
+        assert_eq!(u.username(), "test");
+        assert_eq!(u.email(), "test@test.com");
+        assert!(!u.email_verified());
+        assert!(u.verify_password("Tr0ub4dour&3"));
+        assert!(!u.verify_password("Tr0ub4dour&4"));
+
+        let weak: RegistrationRequest = serde_json::from_str(
+            r#"{"username": "test", "email": "test@test.com", "password": "123456"}"#,
+        )?;
+        assert!(weak.into_user().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_user_without_password() -> Result<(), Box<dyn Error>> {
+        let u = User::builder()
+            .username("test")
+            .email("test@test.com")
+            .build()?;
+
+        assert!(!u.has_password());
+        assert_eq!(u.password_hash(), None);
+        assert!(!u.verify_password(""));
+        assert!(!u.verify_password("Tr0ub4dour&3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_user_with_clock() -> Result<(), Box<dyn Error>> {
+        let pinned = DateTime::<Utc>::from_utc(
+            NaiveDateTime::from_timestamp(1_592_000_000, 123_000_000),
+            Utc,
+        );
+        let clock = FixedClock(pinned);
+
+        let u = User::builder()
+            .username("test")
+            .email("test@test.com")
+            .password("Tr0ub4dour&3x")
+            .clock(&clock)
+            .build()?;
+        assert_eq!(u.registered_at(), pinned);
+
+        // An explicit registration time takes precedence over the clock
+        let u = User::builder()
+            .username("test")
+            .email("test@test.com")
+            .registered_at(pinned + chrono::Duration::seconds(1))
+            .clock(&clock)
+            .build()?;
+        assert_eq!(u.registered_at(), pinned + chrono::Duration::seconds(1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_user_without_password() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = User::new(None, "test", "test@test.com", None, None);
+        testing::insert_user(&db, &u).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u, u);
+        assert!(!loaded_u.has_password());
+        assert!(!db.verify_password(&loaded_u, "123456"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_serde_owned_user() -> Result<(), Box<dyn Error>> {
+        let salt = password::generate_salt();
+        let u = OwnedUser::from(
+            User::new(
+                None,
+                "test",
+                "test@test.com",
+                Some(password::hash_password("123456", &salt)),
+                None,
+            )
+            .with_password_salt(salt)
+            .with_rotate_hash_on_next_login(true),
+        );
+
+        let json = serde_json::to_string(&u)?;
+        assert!(!json.contains(UserColumn::PasswordHash.as_str()));
+        assert!(!json.contains(UserColumn::PasswordSalt.as_str()));
+
+        let deserialized_u: OwnedUser = serde_json::from_str(&json)?;
+        assert!(!deserialized_u.has_password());
+
+        // Besides the redacted password, the user should survive the trip unchanged
+        let mut redacted_u = u.clone();
+        redacted_u.password_hash = None;
+        redacted_u.password_salt = None;
+        assert_eq!(User::from(&deserialized_u), redacted_u);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_owned_user_by_id() {
+        let u = OwnedUser::from(testing::generate_user());
+        let renamed = OwnedUser {
+            username: "renamed".to_owned(),
+            ..u.clone()
+        };
+
+        let users = vec![u, renamed, OwnedUser::from(testing::generate_user())]
+            .into_iter()
+            .collect::<HashSet<OwnedUser>>();
+        assert_eq!(users.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_change_password() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        LoginSession::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session).with_password_hasher(PasswordHasher::new(
+            password::DEFAULT_CONTEXT,
+            Some([1; 32]),
+        ));
+
+        let u = testing::generate_random_user();
+        testing::insert_user(&db, &User::from(&u)).await?;
+        let login = db
+            .create_session(u.id(), Duration::from_secs(60 * 60))
+            .await?;
+
+        db.change_password(u.id(), "correct horse battery staple")
+            .await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert!(db.verify_password(&loaded_u, "correct horse battery staple"));
+        assert!(!db.verify_password(&loaded_u, "123456"));
+
+        // Sessions established with the old password must no longer be accepted
+        assert!(db.resolve_session(login.session_id()).await.is_err());
+
+        Ok(())
     }
-}
 
-impl From<CDRSError> for ConvertRowToUserError {
-    fn from(e: CDRSError) -> Self {
-        Self::CDRSError(e)
+    #[cfg(feature = "provider-google")]
+    #[tokio::test]
+    async fn test_delete_user_cascade() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        UsernameChange::create_prerequisite_objects(&session).await?;
+        ProviderIdentity::create_prerequisite_objects(&session).await?;
+        AuditEvent::create_prerequisite_objects(&session).await?;
+        OwnedProviderToken::create_prerequisite_objects(&session).await?;
+        LoginSession::create_prerequisite_objects(&session).await?;
+        UserEmail::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (username, email) = (unique_username(), format!("{}@test.com", unique_username()));
+        let u = User::new(None, &username, &email, None, None);
+        db.insert_unique(&u).await?;
+
+        let external_id = Uuid::new_v4().to_string();
+        db.link_provider(u.id(), IdentityProvider::Google, &external_id)
+            .await?;
+        db.record_username_change(u.id(), "previous").await?;
+        db.record_audit_event(u.id(), AuditKind::Login, "127.0.0.1")
+            .await?;
+        let login = db
+            .create_session(u.id(), Duration::from_secs(60 * 60))
+            .await?;
+        let secondary = format!("{}@test.com", unique_username());
+        db.add_email(u.id(), &secondary).await?;
+
+        db.delete_user_cascade(u.id()).await?;
+
+        let loaded_u: IdentityResult<OwnedUser> = db.load_record(&UserQuery::Id(u.id())).await;
+        match loaded_u {
+            Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+            res => panic!("expected the user to be deleted, got {:?}", res),
+        }
+        assert!(db.load_linked_identities(u.id()).await?.is_empty());
+        assert!(db.load_username_history(u.id()).await?.is_empty());
+        assert!(db.load_audit_log(u.id()).await?.is_empty());
+        assert!(db.load_user_sessions(u.id()).await?.is_empty());
+        assert!(db.resolve_session(login.session_id()).await.is_err());
+        assert_eq!(db.username_owner(&username).await?, None);
+        assert_eq!(db.email_owner(&email).await?, None);
+        assert_eq!(db.email_owner(&secondary).await?, None);
+        assert!(db.load_user_emails(u.id()).await?.is_empty());
+
+        Ok(())
     }
-}
 
-impl From<Bs58DecodingError> for ConvertRowToUserError {
-    fn from(e: Bs58DecodingError) -> Self {
-        Self::DecodingError(e)
+    #[tokio::test]
+    async fn test_audit_lookup_consistency() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (username, email) = (unique_username(), format!("{}@test.com", unique_username()));
+        let u = User::new(None, &username, &email, None, None);
+        db.insert_unique(&u).await?;
+
+        // A reservation left behind by a user whose row was never written
+        let (orphaned_username, missing_id) = (unique_username(), Uuid::new_v4());
+        db.execute(
+            "INSERT INTO identity.usernames (username, user_id) VALUES (?, ?);",
+            query_values!(orphaned_username.as_str(), missing_id),
+        )
+        .await?;
+
+        // A user whose reservations were never written
+        let unreserved_u = testing::generate_random_user();
+        testing::insert_user(&db, &User::from(&unreserved_u)).await?;
+
+        let inconsistencies = db.audit_lookup_consistency().await?;
+        assert!(inconsistencies.contains(&Inconsistency::OrphanedUsername {
+            username: orphaned_username,
+            user_id: missing_id,
+        }));
+        assert!(
+            inconsistencies.contains(&Inconsistency::MissingUsernameReservation {
+                username: unreserved_u.username().to_owned(),
+                user_id: *unreserved_u.id(),
+            })
+        );
+        assert!(
+            inconsistencies.contains(&Inconsistency::MissingEmailReservation {
+                email: unreserved_u.email().to_owned(),
+                user_id: *unreserved_u.id(),
+            })
+        );
+        assert!(!inconsistencies
+            .iter()
+            .any(|inconsistency| match inconsistency {
+                Inconsistency::MissingUsernameReservation { user_id, .. }
+                | Inconsistency::MissingEmailReservation { user_id, .. } => user_id == u.id(),
+                _ => false,
+            }));
+
+        Ok(())
     }
-}
 
-impl Error for ConvertRowToUserError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        match self {
-            Self::CDRSError(ref e) => Some(e),
-            Self::DecodingError(ref e) => Some(e),
+    #[tokio::test]
+    async fn test_set_display_name() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_random_user();
+        testing::insert_user(&db, &User::from(&u)).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.display_name(), None);
+        assert_eq!(loaded_u.display_name_or_username(), u.username());
+
+        db.set_display_name(u.id(), Some("Jane Doe")).await?;
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.display_name(), Some("Jane Doe"));
+        assert_eq!(loaded_u.display_name_or_username(), "Jane Doe");
+        assert_eq!(loaded_u.username(), u.username());
+
+        let too_long = "a".repeat(MAX_DISPLAY_NAME_LENGTH + 1);
+        for invalid in &["", "   ", too_long.as_str()] {
+            match db.set_display_name(u.id(), Some(invalid)).await {
+                Err(IdentityError::ValidationError(ValidationError::InvalidDisplayName)) => (),
+                res => panic!("expected an invalid display name, got {:?}", res),
+            }
+        }
+
+        db.set_display_name(u.id(), None).await?;
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.display_name(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_locale_and_timezone() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_random_user();
+        testing::insert_user(&db, &User::from(&u)).await?;
+
+        db.set_locale(u.id(), Some("pt-BR")).await?;
+        db.set_timezone(u.id(), Some("America/Sao_Paulo")).await?;
+
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.locale(), Some("pt-BR"));
+        assert_eq!(loaded_u.timezone(), Some("America/Sao_Paulo"));
+        assert_eq!(loaded_u.tz(), Some(chrono_tz::America::Sao_Paulo));
+
+        match db.set_timezone(u.id(), Some("Mars/Phobos")).await {
+            Err(IdentityError::ValidationError(ValidationError::InvalidTimezone)) => (),
+            res => panic!("expected an invalid timezone, got {:?}", res),
+        }
+        match db.set_locale(u.id(), Some("not a locale")).await {
+            Err(IdentityError::ValidationError(ValidationError::InvalidLocale)) => (),
+            res => panic!("expected an invalid locale, got {:?}", res),
         }
+
+        // Rejected values must not have been stored
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.timezone(), Some("America/Sao_Paulo"));
+        assert_eq!(loaded_u.locale(), Some("pt-BR"));
+
+        db.set_timezone(u.id(), None).await?;
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.timezone(), None);
+
+        Ok(())
     }
-}
 
-impl From<ConvertRowToUserError> for IdentityError {
-    fn from(e: ConvertRowToUserError) -> Self {
-        IdentityError::QueryError(QueryError::DeserializationError(e))
+    #[test]
+    fn test_validate_locale() {
+        for valid in &[
+            "en",
+            "en-US",
+            "zh-Hant-TW",
+            "es-419",
+            "de-CH-1996",
+            "en-x-private",
+        ] {
+            assert!(validate_locale(valid).is_ok(), "{} should be valid", valid);
+        }
+
+        for invalid in &["", "e", "en_US", "en-", "abcdefghi", "en-US-"] {
+            assert!(
+                validate_locale(invalid).is_err(),
+                "{} should be invalid",
+                invalid
+            );
+        }
     }
-}
 
-impl Deserializable<OwnedUser, Row> for OwnedUser {
-    type Error = ConvertRowToUserError;
+    #[tokio::test]
+    async fn test_apply_update() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
 
-    fn try_from(value: Row) -> Result<OwnedUser, Self::Error> {
-        Ok(OwnedUser {
-            id: value.get_r_by_name("id")?,
-            username: value.get_r_by_name("username")?,
-            email: value.get_r_by_name("email")?,
-            password_hash: bs58::decode(<Row as IntoRustByName<String>>::get_r_by_name(
-                &value,
-                "password_hash",
-            )?)
-            .into_vec()?,
-            registered_at: <Row as IntoRustByName<Timespec>>::get_r_by_name(
-                &value,
-                "registered_at",
-            )
-            .map(|timespec| <Timespec as Into<RegistrationTimestamp>>::into(timespec))?,
-        })
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_random_user();
+        testing::insert_user(&db, &User::from(&u)).await?;
+
+        // A single column
+        db.apply_update(u.id(), &UserUpdate::default().email_verified(true))
+            .await?;
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert!(loaded_u.email_verified());
+        assert_eq!(loaded_u.display_name(), None);
+
+        // Several columns, leaving the rest untouched
+        let update = UserUpdate::default()
+            .display_name(Some("Jane Doe"))
+            .locale(Some("en-GB"))
+            .timezone(Some("Europe/London"));
+        db.apply_update(u.id(), &update).await?;
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.display_name(), Some("Jane Doe"));
+        assert_eq!(loaded_u.locale(), Some("en-GB"));
+        assert_eq!(loaded_u.timezone(), Some("Europe/London"));
+        assert!(loaded_u.email_verified());
+        assert_eq!(loaded_u.username(), u.username());
+
+        match db.apply_update(u.id(), &UserUpdate::default()).await {
+            Err(IdentityError::ValidationError(ValidationError::EmptyUpdate)) => (),
+            res => panic!("expected an empty update, got {:?}", res),
+        }
+
+        // Updates of users that don't exist mustn't create partial rows
+        match db
+            .apply_update(&Uuid::new_v4(), &UserUpdate::default().email_verified(true))
+            .await
+        {
+            Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+            res => panic!("expected the user to be missing, got {:?}", res),
+        }
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-pub mod test {
-    use std::error::Error;
+    #[test]
+    fn test_user_update_to_cql() {
+        let (query, values) = UserUpdate::default()
+            .rotate_hash_on_next_login(true)
+            .display_name(None)
+            .to_cql()
+            .unwrap();
+        assert_eq!(
+            query,
+            "UPDATE identity.users SET rotate_hash_on_next_login = ?, display_name = ? WHERE id = ?;"
+        );
+        assert_eq!(values.len(), 2);
 
-    use super::{super::super::db::Provider, *};
-    use crate::testing;
+        assert!(matches!(
+            UserUpdate::default().to_cql(),
+            Err(ValidationError::EmptyUpdate)
+        ));
+        assert!(matches!(
+            UserUpdate::default().timezone(Some("Mars/Phobos")).to_cql(),
+            Err(ValidationError::InvalidTimezone)
+        ));
+    }
 
     #[tokio::test]
-    async fn test_insert_user() -> Result<(), Box<dyn Error>> {
+    async fn test_authenticate() -> Result<(), Box<dyn Error>> {
         let session = testing::open_session().await?;
 
         crate::create_keyspace(&session).await?;
         User::create_prerequisite_objects(&session).await?;
 
         let db = Scylla::new(session);
-        testing::insert_user(&db, &testing::generate_user()).await?;
+
+        let (username, email) = (unique_username(), format!("{}@test.com", unique_username()));
+        let u = User::with_password(None, &username, &email, "correct horse", None);
+        db.insert_unique(&u).await?;
+
+        let authenticated_u = db
+            .authenticate(&email.to_uppercase(), "correct horse")
+            .await?;
+        assert_eq!(authenticated_u.id(), u.id());
+
+        // An unknown address and an incorrect password must be indistinguishable
+        let unknown_email = format!("{}@test.com", unique_username());
+        for (email, password) in &[(&unknown_email, "correct horse"), (&email, "123456")] {
+            match db.authenticate(email, password).await {
+                Err(IdentityError::Auth(AuthError::InvalidCredentials)) => (),
+                res => panic!("expected invalid credentials, got {:?}", res),
+            }
+        }
+
+        // The failure recorded for the unknown address never applies, so no row is written
+        match db.load_projection(&UserQuery::Id(&Uuid::nil()), &[]).await {
+            Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+            res => panic!("expected no user to hold the nil ID, got {:?}", res),
+        }
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_query_user_id() -> Result<(), Box<dyn Error>> {
+    async fn test_authenticate_by_username() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session).with_lockout_policy(LockoutPolicy {
+            threshold: 2,
+            window: Duration::from_secs(60 * 60),
+        });
+
+        let (username, email) = (unique_username(), format!("{}@test.com", unique_username()));
+        let u = User::with_password(None, &username, &email, "correct horse", None);
+        db.insert_unique(&u).await?;
+
+        let authenticated_u = db
+            .authenticate_by_username(&username.to_uppercase(), "correct horse")
+            .await?;
+        assert_eq!(authenticated_u.id(), u.id());
+
+        // An unknown username and an incorrect password must be indistinguishable
+        let unknown_username = unique_username();
+        for (username, password) in &[(&unknown_username, "correct horse"), (&username, "123456")] {
+            match db.authenticate_by_username(username, password).await {
+                Err(IdentityError::Auth(AuthError::InvalidCredentials)) => (),
+                res => panic!("expected invalid credentials, got {:?}", res),
+            }
+        }
+
+        // A successful attempt resets the count of failed attempts
+        db.authenticate_by_username(&username, "correct horse")
+            .await?;
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(loaded_u.failed_login_attempts(), 0);
+
+        // Once locked out, even the correct password is rejected
+        for _ in 0..2 {
+            assert!(db
+                .authenticate_by_username(&username, "123456")
+                .await
+                .is_err());
+        }
+        match db
+            .authenticate_by_username(&username, "correct horse")
+            .await
+        {
+            Err(IdentityError::Auth(AuthError::AccountLocked)) => (),
+            res => panic!("expected the user to be locked out, got {:?}", res),
+        }
+
+        // The lock is revealed to those that don't know the password too, such that it doesn't
+        // confirm a correct guess. Only a registered user may be locked out, however
+        match db.authenticate_by_username(&username, "123456").await {
+            Err(IdentityError::Auth(AuthError::AccountLocked)) => (),
+            res => panic!("expected the user to be locked out, got {:?}", res),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_suspended() -> Result<(), Box<dyn Error>> {
         let session = testing::open_session().await?;
 
         crate::create_keyspace(&session).await?;
@@ -658,32 +6867,307 @@ pub mod test {
 
         let db = Scylla::new(session);
 
-        let u = testing::generate_user();
-        testing::insert_user(&db, &u).await?;
+        let (username, email) = (unique_username(), format!("{}@test.com", unique_username()));
+        let u = User::with_password(None, &username, &email, "correct horse", None);
+        db.insert_unique(&u).await?;
 
+        db.set_suspended(u.id(), true).await?;
         let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert!(loaded_u.is_suspended());
 
-        assert_eq!(loaded_u, u);
+        match db.authenticate(&email, "correct horse").await {
+            Err(IdentityError::Auth(AuthError::AccountSuspended)) => (),
+            res => panic!("expected the user to be suspended, got {:?}", res),
+        }
+
+        // The suspension isn't revealed to those that don't know the password
+        match db.authenticate(&email, "123456").await {
+            Err(IdentityError::Auth(AuthError::InvalidCredentials)) => (),
+            res => panic!("expected invalid credentials, got {:?}", res),
+        }
+
+        db.set_suspended(u.id(), false).await?;
+        let authenticated_u = db.authenticate(&email, "correct horse").await?;
+        assert_eq!(authenticated_u.id(), u.id());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_query_user_nickname() -> Result<(), Box<dyn Error>> {
+    async fn test_authenticate_email_unverified() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let (username, email) = (unique_username(), format!("{}@test.com", unique_username()));
+        let u = User::with_password(None, &username, &email, "correct horse", None);
+        db.insert_unique(&u).await?;
+
+        // Unverified users may authenticate unless the connector requires otherwise
+        db.authenticate(&email, "correct horse").await?;
+
+        let db = db.with_verified_email_required(true);
+        match db
+            .authenticate_by_username(&username, "correct horse")
+            .await
+        {
+            Err(IdentityError::Auth(AuthError::EmailUnverified)) => (),
+            res => panic!("expected the email address to be unverified, got {:?}", res),
+        }
+
+        db.mark_email_verified(u.id()).await?;
+        let authenticated_u = db.authenticate(&email, "correct horse").await?;
+        assert_eq!(authenticated_u.id(), u.id());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_login_lockout() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_random_user();
+        testing::insert_user(&db, &User::from(&u)).await?;
+        assert!(!u.is_locked_out());
+
+        let policy = LockoutPolicy {
+            threshold: 3,
+            window: Duration::from_secs(60 * 60),
+        };
+        for expected in 1..=2 {
+            assert_eq!(db.record_failed_login(u.id(), &policy).await?, expected);
+        }
+
+        let failing_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(failing_u.failed_login_attempts(), 2);
+        assert!(!failing_u.is_locked_out());
+
+        assert_eq!(db.record_failed_login(u.id(), &policy).await?, 3);
+
+        let locked_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert!(locked_u.is_locked_out());
+        assert!(!locked_u.is_locked_out_at(Utc::now() + chrono::Duration::hours(2)));
+
+        // Authenticating successfully lifts the lock before it expires
+        assert!(db.verify_password(&locked_u, "123456"));
+        db.reset_failed_logins(u.id()).await?;
+
+        let unlocked_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(unlocked_u.failed_login_attempts(), 0);
+        assert_eq!(unlocked_u.locked_until(), None);
+        assert!(!unlocked_u.is_locked_out());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_login_lockout_expires() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_random_user();
+        testing::insert_user(&db, &User::from(&u)).await?;
+
+        let policy = LockoutPolicy {
+            threshold: 1,
+            window: Duration::from_secs(1),
+        };
+        db.record_failed_login(u.id(), &policy).await?;
+
+        let locked_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert!(locked_u.is_locked_out());
+
+        tokio::time::delay_for(Duration::from_secs(2)).await;
+
+        let expired_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert!(!expired_u.is_locked_out());
+
+        // Failures are counted afresh once the lock has expired
+        assert_eq!(db.record_failed_login(u.id(), &policy).await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rotate_hash_on_next_login() -> Result<(), Box<dyn Error>> {
         let session = testing::open_session().await?;
 
         crate::create_keyspace(&session).await?;
         User::create_prerequisite_objects(&session).await?;
+        LoginSession::create_prerequisite_objects(&session).await?;
 
         let db = Scylla::new(session);
 
         let u = testing::generate_user();
         testing::insert_user(&db, &u).await?;
+        db.change_password(u.id(), "correct horse battery staple")
+            .await?;
 
-        let loaded_u: OwnedUser = db.load_record(&UserQuery::Nickname("test")).await?;
+        // An up-to-date hash isn't replaced
+        let loaded_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert!(
+            db.verify_and_maybe_upgrade(&loaded_u, "correct horse battery staple")
+                .await?
+        );
+        let unchanged_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert_eq!(unchanged_u.password_salt, loaded_u.password_salt);
 
-        assert_eq!(loaded_u, u);
+        db.rotate_hash_on_next_login(u.id()).await?;
+        let flagged_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert!(flagged_u.rotate_hash_on_next_login());
+
+        // A failed login leaves the flag in place
+        assert!(!db.verify_and_maybe_upgrade(&flagged_u, "123456").await?);
+        assert!(
+            db.verify_and_maybe_upgrade(&flagged_u, "correct horse battery staple")
+                .await?
+        );
+
+        let rotated_u: OwnedUser = db.load_record(&UserQuery::Id(u.id())).await?;
+        assert!(!rotated_u.rotate_hash_on_next_login());
+        assert_ne!(rotated_u.password_salt, flagged_u.password_salt);
+        assert_ne!(rotated_u.password_hash, flagged_u.password_hash);
+        assert!(db.verify_password(&rotated_u, "correct horse battery staple"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_and_maybe_upgrade() {
+        let hasher = PasswordHasher::default();
+
+        // Legacy, unsalted hashes are always upgraded
+        let u = testing::generate_user();
+        match u.verify_and_maybe_upgrade(&hasher, "123456") {
+            PasswordVerification::Upgraded { hash, salt } => {
+                assert!(hasher.verify_salted("123456", &salt, &hash))
+            }
+            v => panic!("expected an upgraded hash, got {:?}", v),
+        }
+        assert_eq!(
+            u.verify_and_maybe_upgrade(&hasher, "1234567"),
+            PasswordVerification::Rejected
+        );
+
+        let salt = password::generate_salt();
+        let u = User::new(
+            None,
+            "test",
+            "test@test.com",
+            Some(hasher.hash_salted("123456", &salt)),
+            None,
+        )
+        .with_password_salt(salt);
+        assert_eq!(
+            u.verify_and_maybe_upgrade(&hasher, "123456"),
+            PasswordVerification::Accepted
+        );
+
+        match u
+            .with_rotate_hash_on_next_login(true)
+            .verify_and_maybe_upgrade(&hasher, "123456")
+        {
+            PasswordVerification::Upgraded {
+                hash,
+                salt: new_salt,
+            } => {
+                assert_ne!(new_salt, salt);
+                assert!(hasher.verify_salted("123456", &new_salt, &hash));
+            }
+            v => panic!("expected an upgraded hash, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_parse_provider_lenient() -> Result<(), Box<dyn Error>> {
+        let provider = ProviderKind::parse_lenient("myspace");
+        assert_eq!(provider, ProviderKind::Other("myspace".to_owned()));
+        assert_eq!(provider.known(), None);
+
+        // Unknown providers survive a round trip through their string form
+        assert_eq!(ProviderKind::from(provider.to_string()), provider);
+
+        let json = serde_json::to_string(&provider)?;
+        assert_eq!(json, "\"myspace\"");
+        assert_eq!(serde_json::from_str::<ProviderKind>(&json)?, provider);
+
+        // Strict parsing still rejects unknown providers
+        assert!("myspace".parse::<IdentityProvider>().is_err());
+        assert!(IdentityProvider::try_from(provider).is_err());
 
         Ok(())
     }
+
+    #[cfg(feature = "provider-google")]
+    #[test]
+    fn test_parse_provider_lenient_known() {
+        let provider = ProviderKind::from("google");
+
+        assert_eq!(provider, ProviderKind::Known(IdentityProvider::Google));
+        assert_eq!(provider.as_str(), "google");
+        assert_eq!(
+            IdentityProvider::try_from(provider).unwrap(),
+            IdentityProvider::Google
+        );
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[test]
+    fn test_parse_provider() {
+        assert_eq!(
+            IdentityProvider::try_from("google").unwrap(),
+            IdentityProvider::Google
+        );
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[test]
+    fn test_parse_provider_from_str() {
+        assert_eq!(
+            "google".parse::<IdentityProvider>().unwrap(),
+            IdentityProvider::Google
+        );
+
+        match "myspace".parse::<IdentityProvider>() {
+            Err(IntoIdentityProviderError::InvalidProvider) => (),
+            res => panic!("expected myspace to be rejected, got {:?}", res),
+        }
+    }
+
+    #[cfg(feature = "provider-google")]
+    #[test]
+    fn test_serde_provider() -> Result<(), Box<dyn Error>> {
+        assert_eq!(
+            serde_json::to_string(&IdentityProvider::Google)?,
+            r#""google""#
+        );
+        assert_eq!(
+            serde_json::from_str::<IdentityProvider>(r#""google""#)?,
+            IdentityProvider::Google
+        );
+        assert!(serde_json::from_str::<IdentityProvider>(r#""Google""#).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "provider-facebook"))]
+    #[test]
+    fn test_parse_disabled_provider() {
+        match IdentityProvider::try_from("facebook") {
+            Err(IntoIdentityProviderError::InvalidProvider) => (),
+            res => panic!("expected facebook to be disabled, got {:?}", res),
+        }
+    }
 }
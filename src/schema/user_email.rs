@@ -0,0 +1,483 @@
+use cdrs::{
+    error::Error as CDRSError,
+    query::{BatchExecutor, BatchQueryBuilder, QueryExecutor, QueryValues},
+    query_values,
+    types::{prelude::Row, IntoRustByName},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use time::Timespec;
+use uuid::Uuid;
+
+use super::{
+    super::{
+        db::{
+            scylla::{verify_table_schema, Scylla},
+            ColumnSchema, Deserializable, Idempotent, InTable, Insertable, LwtOutcome, Provider,
+            Queryable, Serializable, TableSchema,
+        },
+        error::{IdentityError, QueryError},
+        result::IdentityResult,
+        DbSession,
+    },
+    user::{normalize_email, validate_email, OwnedUser, RegistrationTimestamp, UserQuery},
+};
+
+use std::convert::TryInto;
+
+/// UserEmail represents one of the email addresses held by a user. Exactly one of a user's
+/// addresses is their primary address, which is mirrored on the user's row.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct UserEmail {
+    /// The normalized email address
+    email: String,
+
+    /// The ID of the user holding the email address
+    user_id: Uuid,
+
+    /// Whether or not the email address is the user's primary address
+    is_primary: bool,
+
+    /// The time at which the user added the email address
+    added_at: RegistrationTimestamp,
+}
+
+impl UserEmail {
+    /// Creates a new record of an email address held by a user, added at the current UTC time.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user holding the email address
+    /// * `email` - The normalized email address
+    /// * `is_primary` - Whether or not the email address is the user's primary address
+    pub fn new(user_id: Uuid, email: &str, is_primary: bool) -> Self {
+        Self {
+            email: email.to_owned(),
+            user_id,
+            is_primary,
+            added_at: Utc::now().try_into().unwrap_or_default(),
+        }
+    }
+
+    /// Gets the normalized email address.
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// Gets the ID of the user holding the email address.
+    pub fn user_id(&self) -> &Uuid {
+        &self.user_id
+    }
+
+    /// Determines whether or not the email address is the user's primary address.
+    pub fn is_primary(&self) -> bool {
+        self.is_primary
+    }
+
+    /// Gets the time at which the user added the email address.
+    pub fn added_at(&self) -> DateTime<Utc> {
+        self.added_at.into()
+    }
+}
+
+#[async_trait]
+impl InTable<Scylla, DbSession> for UserEmail {
+    async fn create_prerequisite_objects(session: &DbSession) -> IdentityResult<()> {
+        session
+            .query(Self::table_schema().create_statement())
+            .await
+            .and(
+                session
+                    .query(
+                        // Mappings from users to their email addresses, such that each of a
+                        // user's addresses may be listed
+                        "CREATE INDEX IF NOT EXISTS ON identity.user_emails (user_id);",
+                    )
+                    .await,
+            )
+            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))?;
+
+        verify_table_schema(session, &Self::table_schema()).await
+    }
+
+    fn table_schema() -> TableSchema {
+        // Every email address held by each user, primary or otherwise
+        TableSchema {
+            keyspace: "identity",
+            name: "user_emails",
+            columns: vec![
+                ColumnSchema::new("email", "TEXT"),
+                ColumnSchema::new("user_id", "UUID"),
+                ColumnSchema::new("is_primary", "BOOLEAN"),
+                ColumnSchema::new("added_at", "TIMESTAMP"),
+            ],
+            partition_key: vec!["email"],
+            clustering_key: Vec::new(),
+        }
+    }
+}
+
+impl Serializable<QueryValues> for UserEmail {
+    type Error = IdentityError;
+
+    fn try_into(&self) -> Result<QueryValues, Self::Error> {
+        Ok(query_values!(
+            "email" => self.email.as_str(),
+            "user_id" => self.user_id,
+            "is_primary" => self.is_primary,
+            "added_at" => <&RegistrationTimestamp as Into<Timespec>>::into(&self.added_at)
+        ))
+    }
+}
+
+impl Idempotent for UserEmail {
+    /// Records are keyed by their email address, so reinserting one overwrites the same row.
+    fn idempotent(&self) -> bool {
+        true
+    }
+}
+
+impl Insertable<Scylla, DbSession> for UserEmail {
+    const INSERTION_QUERY: &'static str = r#"INSERT INTO identity.user_emails (email, user_id, is_primary, added_at) VALUES (?, ?, ?, ?);"#;
+}
+
+impl Deserializable<UserEmail, Row> for UserEmail {
+    type Error = IdentityError;
+
+    fn try_from(value: Row) -> Result<UserEmail, Self::Error> {
+        Ok(UserEmail {
+            email: value.get_r_by_name("email")?,
+            user_id: value.get_r_by_name("user_id")?,
+            is_primary: value.get_r_by_name("is_primary")?,
+            added_at: <Row as IntoRustByName<Timespec>>::get_r_by_name(&value, "added_at")?.into(),
+        })
+    }
+}
+
+/// UserEmailQuery represents a query for the record of a single email address.
+#[derive(Debug)]
+pub struct UserEmailQuery<'a>(pub &'a str);
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for UserEmailQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.user_emails WHERE email = '{}';",
+            self.0.replace('\'', "''")
+        ))
+    }
+}
+
+/// UserEmailsQuery represents a query for every email address held by a user.
+#[derive(Debug)]
+pub struct UserEmailsQuery<'a>(pub &'a Uuid);
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for UserEmailsQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.user_emails WHERE user_id = {};",
+            self.0
+        ))
+    }
+}
+
+impl Scylla {
+    /// Loads the record of an email address, if any user has added it.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The normalized email address that should be loaded
+    async fn load_user_email(&self, email: &str) -> IdentityResult<Option<UserEmail>> {
+        match self.load_record(&UserEmailQuery(email)).await {
+            Ok(record) => Ok(Some(record)),
+            Err(IdentityError::QueryError(QueryError::NoResults)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Records a user's primary email address, unless it has already been recorded. Users that
+    /// have never added an email address have no records.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user whose primary email address should be recorded
+    async fn ensure_primary_email(&self, user: &OwnedUser) -> IdentityResult<()> {
+        self.insert_if_absent(&UserEmail::new(*user.id(), user.email(), true))
+            .await
+            .map(|_| ())
+    }
+
+    /// Loads every email address held by a user. Users that have never added an email address
+    /// have no records, though they still hold the primary address on their row.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose email addresses should be loaded
+    pub async fn load_user_emails(&self, user_id: &Uuid) -> IdentityResult<Vec<UserEmail>> {
+        self.load_records(&UserEmailsQuery(user_id)).await
+    }
+
+    /// Adds a secondary email address to a user, reserving it such that no other user may hold
+    /// it. Adding an address that the user already holds is a no-op.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that the email address should be added to
+    /// * `email` - The email address that should be added
+    pub async fn add_email(&self, user_id: &Uuid, email: &str) -> IdentityResult<UserEmail> {
+        let email = normalize_email(email);
+        validate_email(&email)?;
+
+        let u: OwnedUser = self.load_record(&UserQuery::Id(user_id)).await?;
+        self.ensure_primary_email(&u).await?;
+
+        if let Some(existing) = self.load_user_email(&email).await? {
+            return if existing.user_id == *u.id() {
+                Ok(existing)
+            } else {
+                Err(QueryError::EmailTaken.into())
+            };
+        }
+
+        match self.reserve_email(&email, user_id).await? {
+            LwtOutcome::Applied => (),
+            LwtOutcome::NotApplied(holder) if holder == *user_id => (),
+            LwtOutcome::NotApplied(_) => return Err(QueryError::EmailTaken.into()),
+        }
+
+        let added = UserEmail::new(*user_id, &email, false);
+
+        // Don't leave the address reserved if it can't actually be recorded
+        if let Err(e) = self.insert_record(&added).await {
+            self.release_email(&email, user_id).await?;
+
+            return Err(e);
+        }
+
+        Ok(added)
+    }
+
+    /// Removes a secondary email address from a user, releasing its reservation. A user's primary
+    /// address can't be removed, and must first be replaced via Scylla::promote_email.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that the email address should be removed from
+    /// * `email` - The email address that should be removed
+    pub async fn remove_email(&self, user_id: &Uuid, email: &str) -> IdentityResult<()> {
+        let email = normalize_email(email);
+
+        match self.load_user_email(&email).await? {
+            Some(existing) if existing.user_id == *user_id => {
+                if existing.is_primary {
+                    return Err(QueryError::PrimaryEmail.into());
+                }
+            }
+            _ => return Err(QueryError::NoResults.into()),
+        }
+
+        self.execute(
+            "DELETE FROM identity.user_emails WHERE email = ?;",
+            query_values!(email.as_str()),
+        )
+        .await?;
+
+        self.release_email(&email, user_id).await.map(|_| ())
+    }
+
+    /// Makes one of a user's secondary email addresses their primary address, mirroring it onto
+    /// the user's row. The previous primary address is kept as a secondary address. Since
+    /// secondary addresses aren't verified, the user's email is marked as unverified.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose primary email address should be changed
+    /// * `email` - The secondary email address that should become the primary address
+    pub async fn promote_email(&self, user_id: &Uuid, email: &str) -> IdentityResult<()> {
+        let email = normalize_email(email);
+
+        match self.load_user_email(&email).await? {
+            Some(existing) if existing.user_id == *user_id => {
+                if existing.is_primary {
+                    return Ok(());
+                }
+            }
+            _ => return Err(QueryError::NoResults.into()),
+        }
+
+        let u: OwnedUser = self.load_record(&UserQuery::Id(user_id)).await?;
+        self.ensure_primary_email(&u).await?;
+
+        let query = BatchQueryBuilder::new()
+            .add_query(
+                "UPDATE identity.users SET email = ?, email_verified = false WHERE id = ?;",
+                query_values!(email.as_str(), *user_id),
+            )
+            .add_query(
+                "UPDATE identity.user_emails SET is_primary = false WHERE email = ?;",
+                query_values!(u.email()),
+            )
+            .add_query(
+                "UPDATE identity.user_emails SET is_primary = true WHERE email = ?;",
+                query_values!(email.as_str()),
+            )
+            .finalize()?;
+
+        self.run(self.session().batch_with_params(query)).await?;
+        self.event_sink().on_user_updated(user_id);
+
+        Ok(())
+    }
+
+    /// Replaces the record of a user's primary email address once it has been changed via
+    /// Scylla::change_email.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose primary email address was changed
+    /// * `previous` - The user's previous primary email address
+    /// * `current` - The user's new primary email address
+    pub(crate) async fn replace_primary_email(
+        &self,
+        user_id: &Uuid,
+        previous: &str,
+        current: &str,
+    ) -> IdentityResult<()> {
+        let record = UserEmail::new(*user_id, current, true);
+
+        let query = BatchQueryBuilder::new()
+            .add_query(
+                "DELETE FROM identity.user_emails WHERE email = ?;",
+                query_values!(previous),
+            )
+            .add_query(
+                <UserEmail as Insertable<Scylla, DbSession>>::INSERTION_QUERY,
+                <UserEmail as Serializable<QueryValues>>::try_into(&record)?,
+            )
+            .finalize()?;
+
+        self.run(self.session().batch_with_params(query))
+            .await
+            .map(|_| ())
+    }
+
+    /// Loads the user holding an email address, whether it is their primary address or one of
+    /// their secondary addresses. Fails with a QueryError::NoResults if no user holds it.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The email address of the user
+    pub async fn resolve_user_by_email(&self, email: &str) -> IdentityResult<OwnedUser> {
+        let email = normalize_email(email);
+
+        // Users that have never added an email address only hold a reservation of their primary
+        // address
+        let owner = match self.load_user_email(&email).await? {
+            Some(record) => Some(record.user_id),
+            None => self.email_owner(&email).await?,
+        };
+
+        match owner {
+            Some(user_id) => self.load_record(&UserQuery::Id(&user_id)).await,
+            None => Err(QueryError::NoResults.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::error::Error;
+
+    use super::{super::user::User, *};
+    use crate::testing;
+
+    /// Generates an email address that no other test uses.
+    fn unique_email() -> String {
+        format!(
+            "e{}@test.com",
+            &Uuid::new_v4().to_simple().to_string()[..16]
+        )
+    }
+
+    #[test]
+    fn test_insertion_columns_match() -> Result<(), Box<dyn Error>> {
+        testing::assert_insertion_columns_match(&UserEmail::new(
+            Uuid::new_v4(),
+            "test@test.com",
+            false,
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_add_and_promote_email() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        User::create_prerequisite_objects(&session).await?;
+        UserEmail::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let u = testing::generate_random_user();
+        db.insert_unique(&User::from(&u)).await?;
+
+        let secondary = unique_email();
+        let added = db.add_email(u.id(), &secondary.to_uppercase()).await?;
+        assert_eq!(added.email(), secondary);
+        assert!(!added.is_primary());
+
+        // The user is resolved by either address, but their row still holds the primary address
+        assert_eq!(db.resolve_user_by_email(&secondary).await?.id(), u.id());
+        assert_eq!(db.resolve_user_by_email(u.email()).await?.id(), u.id());
+        assert_eq!(
+            db.resolve_user_by_email(&secondary).await?.email(),
+            u.email()
+        );
+
+        let mut emails = db.load_user_emails(u.id()).await?;
+        emails.sort_by_key(|record| !record.is_primary());
+        assert_eq!(
+            emails
+                .iter()
+                .map(|record| (record.email(), record.is_primary()))
+                .collect::<Vec<(&str, bool)>>(),
+            vec![(u.email(), true), (secondary.as_str(), false)]
+        );
+
+        // No other user may add the address
+        let other = testing::generate_random_user();
+        db.insert_unique(&User::from(&other)).await?;
+        match db.add_email(other.id(), &secondary).await {
+            Err(IdentityError::QueryError(QueryError::EmailTaken)) => (),
+            res => panic!("expected the email to be taken, got {:?}", res),
+        }
+
+        db.promote_email(u.id(), &secondary).await?;
+
+        let loaded_u = db.resolve_user_by_email(u.email()).await?;
+        assert_eq!(loaded_u.email(), secondary);
+        assert!(!loaded_u.email_verified());
+        assert!(db
+            .load_user_emails(u.id())
+            .await?
+            .iter()
+            .all(|record| { record.is_primary() == (record.email() == secondary) }));
+
+        // The previous primary address may now be removed, but the new one may not
+        match db.remove_email(u.id(), &secondary).await {
+            Err(IdentityError::QueryError(QueryError::PrimaryEmail)) => (),
+            res => panic!("expected the primary email to be kept, got {:?}", res),
+        }
+        db.remove_email(u.id(), u.email()).await?;
+
+        assert_eq!(db.load_user_emails(u.id()).await?.len(), 1);
+        assert_eq!(db.email_owner(u.email()).await?, None);
+        match db.resolve_user_by_email(u.email()).await {
+            Err(IdentityError::QueryError(QueryError::NoResults)) => (),
+            res => panic!("expected the email to be released, got {:?}", res),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use cdrs::{
+    error::Error as CDRSError,
+    query::{QueryExecutor, QueryValues},
+    query_values,
+    types::{prelude::Row, IntoRustByName},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use time::Timespec;
+use uuid::Uuid;
+
+use super::{
+    super::{
+        db::{
+            scylla::{verify_table_schema, Scylla},
+            ClusteringOrder, ColumnSchema, Deserializable, Idempotent, InTable, Insertable,
+            Provider, Queryable, Serializable, TableSchema,
+        },
+        error::IdentityError,
+        result::IdentityResult,
+        DbSession,
+    },
+    user::RegistrationTimestamp,
+};
+
+use std::convert::TryInto;
+
+/// UsernameChange represents a record of a username that a user held before renaming themselves.
+#[derive(Debug)]
+pub struct UsernameChange<'a> {
+    /// The ID of the user that changed their username
+    user_id: Uuid,
+
+    /// The username that the user held prior to the change
+    username: &'a str,
+
+    /// The time at which the user stopped using the username
+    changed_at: RegistrationTimestamp,
+}
+
+impl<'a> UsernameChange<'a> {
+    /// Creates a new record of a username change, occurring at the current UTC time.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that changed their username
+    /// * `username` - The username that the user held prior to the change
+    pub fn new(user_id: Uuid, username: &'a str) -> Self {
+        Self {
+            user_id,
+            username,
+            changed_at: Utc::now().try_into().unwrap_or_default(),
+        }
+    }
+}
+
+/// OwnedUsernameChange represents an allocated record of a username change.
+#[derive(Serialize, Debug)]
+pub struct OwnedUsernameChange {
+    user_id: Uuid,
+    username: String,
+    changed_at: RegistrationTimestamp,
+}
+
+impl OwnedUsernameChange {
+    /// Gets the ID of the user that changed their username.
+    pub fn user_id(&self) -> &Uuid {
+        &self.user_id
+    }
+
+    /// Gets the username that the user held prior to the change.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Gets the time at which the user stopped using the username.
+    pub fn changed_at(&self) -> DateTime<Utc> {
+        self.changed_at.into()
+    }
+}
+
+#[async_trait]
+impl InTable<Scylla, DbSession> for UsernameChange<'_> {
+    async fn create_prerequisite_objects(session: &DbSession) -> IdentityResult<()> {
+        session
+            .query(Self::table_schema().create_statement())
+            .await
+            .map_err(|e| <CDRSError as Into<IdentityError>>::into(e))?;
+
+        verify_table_schema(session, &Self::table_schema()).await
+    }
+
+    fn table_schema() -> TableSchema {
+        // Each user's previous usernames, oldest first
+        TableSchema {
+            keyspace: "identity",
+            name: "username_history",
+            columns: vec![
+                ColumnSchema::new("user_id", "UUID"),
+                ColumnSchema::new("changed_at", "TIMESTAMP"),
+                ColumnSchema::new("username", "TEXT"),
+            ],
+            partition_key: vec!["user_id"],
+            clustering_key: vec![
+                ("changed_at", ClusteringOrder::Asc),
+                ("username", ClusteringOrder::Asc),
+            ],
+        }
+    }
+}
+
+impl Serializable<QueryValues> for UsernameChange<'_> {
+    type Error = IdentityError;
+
+    fn try_into(&self) -> Result<QueryValues, Self::Error> {
+        Ok(query_values!(
+            "user_id" => self.user_id,
+            "changed_at" => <&RegistrationTimestamp as Into<Timespec>>::into(&self.changed_at),
+            "username" => self.username
+        ))
+    }
+}
+
+impl Idempotent for UsernameChange<'_> {
+    /// The time of the change is fixed before it is inserted, so reinserting it overwrites the
+    /// same row.
+    fn idempotent(&self) -> bool {
+        true
+    }
+}
+
+impl Insertable<Scylla, DbSession> for UsernameChange<'_> {
+    const INSERTION_QUERY: &'static str = r#"INSERT INTO identity.username_history (user_id, changed_at, username) VALUES (?, ?, ?);"#;
+}
+
+impl Deserializable<OwnedUsernameChange, Row> for OwnedUsernameChange {
+    type Error = IdentityError;
+
+    fn try_from(value: Row) -> Result<OwnedUsernameChange, Self::Error> {
+        Ok(OwnedUsernameChange {
+            user_id: value.get_r_by_name("user_id")?,
+            username: value.get_r_by_name("username")?,
+            changed_at: <Row as IntoRustByName<Timespec>>::get_r_by_name(&value, "changed_at")?
+                .into(),
+        })
+    }
+}
+
+/// UsernameHistoryQuery represents a query for every username previously held by a user.
+#[derive(Debug)]
+pub struct UsernameHistoryQuery<'a>(pub &'a Uuid);
+
+#[async_trait]
+impl Queryable<Scylla, DbSession> for UsernameHistoryQuery<'_> {
+    async fn to_query(&self, _session: &DbSession) -> IdentityResult<String> {
+        Ok(format!(
+            "SELECT * FROM identity.username_history WHERE user_id = {};",
+            self.0
+        ))
+    }
+}
+
+impl Scylla {
+    /// Records that a user has stopped using a username.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user that changed their username
+    /// * `previous_username` - The username that the user held prior to the change
+    pub async fn record_username_change(
+        &self,
+        user_id: &Uuid,
+        previous_username: &str,
+    ) -> IdentityResult<()> {
+        self.insert_record(&UsernameChange::new(*user_id, previous_username))
+            .await
+    }
+
+    /// Loads every username previously held by a user, ordered from oldest to most recent. The
+    /// user's current username is not included.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user whose history should be loaded
+    pub async fn load_username_history(
+        &self,
+        user_id: &Uuid,
+    ) -> IdentityResult<Vec<OwnedUsernameChange>> {
+        self.load_records(&UsernameHistoryQuery(user_id)).await
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::error::Error;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_insertion_columns_match() -> Result<(), Box<dyn Error>> {
+        testing::assert_insertion_columns_match(&UsernameChange::new(Uuid::new_v4(), "previous"))
+    }
+
+    #[tokio::test]
+    async fn test_load_username_history() -> Result<(), Box<dyn Error>> {
+        let session = testing::open_session().await?;
+
+        crate::create_keyspace(&session).await?;
+        UsernameChange::create_prerequisite_objects(&session).await?;
+
+        let db = Scylla::new(session);
+
+        let id = Uuid::new_v4();
+        db.record_username_change(&id, "first").await?;
+        db.record_username_change(&id, "second").await?;
+
+        let history = db.load_username_history(&id).await?;
+        assert_eq!(
+            history
+                .iter()
+                .map(|change| change.username())
+                .collect::<Vec<&str>>(),
+            vec!["first", "second"]
+        );
+
+        Ok(())
+    }
+}